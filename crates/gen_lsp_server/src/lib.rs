@@ -2,6 +2,9 @@
 //! This crate handles protocol handshaking and parsing messages, while you
 //! control the message dispatch loop yourself.
 //!
+//! Requests to the client, like `workspace/applyEdit`, are sent through
+//! `OutgoingRequests`, which routes the client's responses back to callbacks.
+//!
 //! Run with `RUST_LOG=sync_lsp_server=debug` to see all the messages.
 //!
 //! ```no_run
@@ -60,20 +63,26 @@
 //! ```
 
 use failure::{bail, format_err};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
 
 mod msg;
+mod outgoing;
 mod stdio;
 
 use crossbeam_channel::{Receiver, Sender};
 use lsp_types::{
     notification::{Exit, Initialized},
-    request::{Initialize, Shutdown},
-    InitializeParams, InitializeResult, ServerCapabilities,
+    request::{Initialize, Request, Shutdown},
 };
 
 pub type Result<T> = ::std::result::Result<T, failure::Error>;
 pub use crate::{
-    msg::{ErrorCode, RawMessage, RawNotification, RawRequest, RawResponse, RawResponseError},
+    msg::{
+        ErrorCode, RawMessage, RawNotification, RawRequest, RawResponse, RawResponseError,
+        RequestId,
+    },
+    outgoing::OutgoingRequests,
     stdio::{stdio_transport, Threads},
 };
 
@@ -83,11 +92,17 @@ pub use crate::{
 ///
 /// `server` should use the `handle_shutdown` function to handle the `Shutdown`
 /// request.
-pub fn run_server(
-    caps: ServerCapabilities,
+///
+/// `caps` are usually `ServerCapabilities`, but can be any type serializing to
+/// the capabilities object, to advertise capabilities `lsp_types` doesn't know.
+/// Likewise, the params passed to `server` are usually `InitializeParams`, but
+/// can be any type deserializing from them, to read client capabilities
+/// `lsp_types` doesn't know.
+pub fn run_server<P: DeserializeOwned>(
+    caps: impl Serialize,
     receiver: Receiver<RawMessage>,
     sender: Sender<RawMessage>,
-    server: impl FnOnce(InitializeParams, &Receiver<RawMessage>, &Sender<RawMessage>) -> Result<()>,
+    server: impl FnOnce(P, &Receiver<RawMessage>, &Sender<RawMessage>) -> Result<()>,
 ) -> Result<()> {
     log::info!("lsp server initializes");
     let params = initialize(&receiver, &sender, caps)?;
@@ -116,19 +131,19 @@ pub fn handle_shutdown(req: RawRequest, sender: &Sender<RawMessage>) -> Option<R
     }
 }
 
-fn initialize(
+fn initialize<P: DeserializeOwned>(
     receiver: &Receiver<RawMessage>,
     sender: &Sender<RawMessage>,
-    caps: ServerCapabilities,
-) -> Result<InitializeParams> {
+    caps: impl Serialize,
+) -> Result<P> {
     let (id, params) = match receiver.recv() {
-        Ok(RawMessage::Request(req)) => match req.cast::<Initialize>() {
-            Err(req) => bail!("expected initialize request, got {:?}", req),
-            Ok(req) => req,
-        },
+        Ok(RawMessage::Request(req)) if req.method == Initialize::METHOD => {
+            (req.id, serde_json::from_value(req.params)?)
+        }
         msg => bail!("expected initialize request, got {:?}", msg),
     };
-    let resp = RawResponse::ok::<Initialize>(id, &InitializeResult { capabilities: caps });
+    let result = json!({ "capabilities": caps });
+    let resp = RawResponse { id, result: Some(result), error: None };
     sender.send(RawMessage::Response(resp)).unwrap();
     match receiver.recv() {
         Ok(RawMessage::Notification(n)) => {
@@ -1,6 +1,9 @@
-use std::io::{BufRead, Write};
+use std::{
+    fmt,
+    io::{BufRead, Write},
+};
 
-use lsp_types::{notification::Notification, request::Request};
+use lsp_types::{notification::Notification, request::Request, NumberOrString};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_str, from_value, to_string, to_value, Value};
 use failure::{bail, format_err};
@@ -15,9 +18,22 @@ pub enum RawMessage {
     Response(RawResponse),
 }
 
+/// Id of a request. JSON RPC allows both numbers and strings, and clients use
+/// either.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct RequestId(IdRepr);
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+enum IdRepr {
+    Number(i64),
+    String(String),
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RawRequest {
-    pub id: u64,
+    pub id: RequestId,
     pub method: String,
     pub params: Value,
 }
@@ -27,7 +43,7 @@ pub struct RawResponse {
     // JSON RPC allows this to be null if it was impossible
     // to decode the request's id. Ignore this special case
     // and just die horribly.
-    pub id: u64,
+    pub id: RequestId,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub result: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -86,15 +102,45 @@ impl RawMessage {
     }
 }
 
+impl From<i64> for RequestId {
+    fn from(id: i64) -> RequestId {
+        RequestId(IdRepr::Number(id))
+    }
+}
+
+impl From<String> for RequestId {
+    fn from(id: String) -> RequestId {
+        RequestId(IdRepr::String(id))
+    }
+}
+
+impl From<NumberOrString> for RequestId {
+    fn from(id: NumberOrString) -> RequestId {
+        match id {
+            NumberOrString::Number(id) => (id as i64).into(),
+            NumberOrString::String(id) => id.into(),
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            IdRepr::Number(id) => write!(f, "{}", id),
+            IdRepr::String(id) => write!(f, "{:?}", id),
+        }
+    }
+}
+
 impl RawRequest {
-    pub fn new<R>(id: u64, params: &R::Params) -> RawRequest
+    pub fn new<R>(id: RequestId, params: &R::Params) -> RawRequest
     where
         R: Request,
         R::Params: serde::Serialize,
     {
         RawRequest { id, method: R::METHOD.to_string(), params: to_value(params).unwrap() }
     }
-    pub fn cast<R>(self) -> ::std::result::Result<(u64, R::Params), RawRequest>
+    pub fn cast<R>(self) -> ::std::result::Result<(RequestId, R::Params), RawRequest>
     where
         R: Request,
         R::Params: serde::de::DeserializeOwned,
@@ -109,17 +155,32 @@ impl RawRequest {
 }
 
 impl RawResponse {
-    pub fn ok<R>(id: u64, result: &R::Result) -> RawResponse
+    pub fn ok<R>(id: RequestId, result: &R::Result) -> RawResponse
     where
         R: Request,
         R::Result: serde::Serialize,
     {
         RawResponse { id, result: Some(to_value(&result).unwrap()), error: None }
     }
-    pub fn err(id: u64, code: i32, message: String) -> RawResponse {
+    pub fn err(id: RequestId, code: i32, message: String) -> RawResponse {
         let error = RawResponseError { code, message, data: None };
         RawResponse { id, result: None, error: Some(error) }
     }
+    /// Interprets the response as the answer to a request of type `R`.
+    pub fn into_result<R>(self) -> ::std::result::Result<R::Result, RawResponseError>
+    where
+        R: Request,
+        R::Result: serde::de::DeserializeOwned,
+    {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        from_value(self.result.unwrap_or(Value::Null)).map_err(|e| RawResponseError {
+            code: ErrorCode::ParseError as i32,
+            message: format!("invalid result of {}: {}", R::METHOD, e),
+            data: None,
+        })
+    }
 }
 
 impl RawNotification {
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use crossbeam_channel::Sender;
+use lsp_types::request::Request;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{RawMessage, RawRequest, RawResponse, RawResponseError, RequestId};
+
+// Boxed `FnOnce` can't be called on older compilers, so handlers are `FnMut`
+// which are only called once.
+type ResponseHandler<S> = Box<dyn FnMut(&mut S, RawResponse)>;
+
+/// Requests sent from the server to the client, like `workspace/applyEdit`,
+/// which wait for the client's response.
+///
+/// Every request gets a fresh id and a callback, which is called with the
+/// response and the server's state `S` once the client answers.
+pub struct OutgoingRequests<S> {
+    next_id: i64,
+    pending: HashMap<RequestId, ResponseHandler<S>>,
+}
+
+impl<S> Default for OutgoingRequests<S> {
+    fn default() -> OutgoingRequests<S> {
+        OutgoingRequests { next_id: 0, pending: HashMap::new() }
+    }
+}
+
+impl<S> OutgoingRequests<S> {
+    pub fn new() -> OutgoingRequests<S> {
+        OutgoingRequests::default()
+    }
+
+    /// Sends a request of type `R` to the client. `on_response` gets either
+    /// the result or the error the client has answered with.
+    pub fn send<R>(
+        &mut self,
+        sender: &Sender<RawMessage>,
+        params: &R::Params,
+        on_response: impl FnOnce(&mut S, Result<R::Result, RawResponseError>) + 'static,
+    ) -> RequestId
+    where
+        R: Request,
+        R::Params: Serialize,
+        R::Result: DeserializeOwned,
+    {
+        let id = RequestId::from(self.next_id);
+        self.next_id += 1;
+        let mut on_response = Some(on_response);
+        let handler: ResponseHandler<S> = Box::new(move |state, resp| {
+            if let Some(on_response) = on_response.take() {
+                on_response(state, resp.into_result::<R>())
+            }
+        });
+        self.pending.insert(id.clone(), handler);
+        let req = RawRequest::new::<R>(id.clone(), params);
+        sender.send(RawMessage::Request(req)).unwrap();
+        id
+    }
+
+    /// Calls the callback of the request `resp` answers. Returns `resp` back
+    /// if it isn't a response to any of the pending requests.
+    pub fn on_response(&mut self, state: &mut S, resp: RawResponse) -> Option<RawResponse> {
+        match self.pending.remove(&resp.id) {
+            Some(mut handler) => {
+                handler(state, resp);
+                None
+            }
+            None => Some(resp),
+        }
+    }
+}
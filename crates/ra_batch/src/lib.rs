@@ -8,8 +8,8 @@ use ra_db::{
     CrateGraph, FileId, SourceRoot, SourceRootId, SourceDatabase, salsa,
 };
 use ra_hir::{db, HirInterner};
-use ra_project_model::ProjectWorkspace;
-use ra_vfs::{Vfs, VfsChange};
+use ra_project_model::{ProjectWorkspace, CargoConfig, ProcMacroClient};
+use ra_vfs::{Vfs, VfsChange, Watch};
 
 type Result<T> = std::result::Result<T, failure::Error>;
 
@@ -68,6 +68,7 @@ impl BatchDatabase {
                         for (vfs_file, path, text) in files {
                             let file_id = vfs_file_to_id(vfs_file);
                             db.set_file_text(file_id, text);
+                            db.set_reparsed_file(file_id, Default::default());
                             db.set_file_relative_path(file_id, path.clone());
                             db.set_file_source_root(file_id, source_root_id);
                             file_map.insert(path, file_id);
@@ -96,22 +97,17 @@ impl BatchDatabase {
 
     pub fn load_cargo(root: impl AsRef<Path>) -> Result<(BatchDatabase, Vec<SourceRootId>)> {
         let root = std::env::current_dir()?.join(root);
-        let ws = ProjectWorkspace::discover(root.as_ref())?;
+        let ws = ProjectWorkspace::discover(root.as_ref(), &CargoConfig::default())?;
         let mut roots = Vec::new();
         roots.push(root.clone());
-        for pkg in ws.cargo.packages() {
-            roots.push(pkg.root(&ws.cargo).to_path_buf());
-        }
-        for krate in ws.sysroot.crates() {
-            roots.push(krate.root_dir(&ws.sysroot).to_path_buf())
-        }
-        let (mut vfs, roots) = Vfs::new(roots);
+        roots.extend(ws.to_roots());
+        let (mut vfs, roots) = Vfs::new(roots, Watch(false));
         let mut load = |path: &Path| {
             let vfs_file = vfs.load(path);
             log::debug!("vfs file {:?} -> {:?}", path, vfs_file);
             vfs_file.map(vfs_file_to_id)
         };
-        let crate_graph = ws.to_crate_graph(&mut load);
+        let crate_graph = ws.to_crate_graph(&mut load, &ProcMacroClient::dummy());
         log::debug!("crate graph: {:?}", crate_graph);
 
         let local_roots = roots
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use failure::format_err;
+use ra_ide_api::{
+    AnalysisChange, AnalysisHost, FileId, FilePosition, LibraryData, LineCol, SourceRootId,
+};
+use ra_project_model::{CargoConfig, ProcMacroClient, ProjectWorkspace};
+use ra_vfs::{Vfs, VfsChange, Watch};
+
+use crate::Result;
+
+/// Measures how long completion takes after an edit to `path`, which is what
+/// the user experiences while typing.
+pub fn run(position: &str, iterations: usize) -> Result<()> {
+    let (path, line, col) = parse_position(position)?;
+    let root = std::env::current_dir()?;
+
+    let start = Instant::now();
+    let (mut host, file_id) = load(&root, &path)?;
+    println!("Database loaded in {:?}", start.elapsed());
+
+    let line_index = host.analysis().file_line_index(file_id);
+    let offset = line_index.offset(LineCol { line, col_utf16: col });
+    let position = FilePosition { file_id, offset };
+
+    let start = Instant::now();
+    let n_items = complete(&host, position)?;
+    println!("Initial completion: {} items in {:?}", n_items, start.elapsed());
+
+    let mut total = Duration::default();
+    for i in 0..iterations {
+        // Whitespace at the end of the file doesn't move the cursor, but
+        // bumps the revision just like a keystroke.
+        let mut text = String::clone(&host.analysis().file_text(file_id));
+        text.push_str(if i % 2 == 0 { " " } else { "\n" });
+        let mut change = AnalysisChange::new();
+        change.change_file(file_id, Arc::new(text));
+        host.apply_change(change);
+
+        let start = Instant::now();
+        complete(&host, position)?;
+        let elapsed = start.elapsed();
+        println!("Completion after edit #{}: {:?}", i + 1, elapsed);
+        total += elapsed;
+    }
+    if iterations > 0 {
+        println!("Average completion after edit: {:?}", total / iterations as u32);
+    }
+    Ok(())
+}
+
+fn parse_position(position: &str) -> Result<(PathBuf, u32, u32)> {
+    let mut parts = position.rsplitn(3, ':');
+    let (col, line, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(col), Some(line), Some(path)) => (col, line, path),
+        _ => return Err(format_err!("expected `path:line:column`, got `{}`", position)),
+    };
+    let line: u32 = line.parse()?;
+    let col: u32 = col.parse()?;
+    if line == 0 || col == 0 {
+        return Err(format_err!("lines and columns are 1-based"));
+    }
+    Ok((PathBuf::from(path), line - 1, col - 1))
+}
+
+fn complete(host: &AnalysisHost, position: FilePosition) -> Result<usize> {
+    let items = host
+        .analysis()
+        .completions(position)
+        .map_err(|_| format_err!("completion was canceled"))?;
+    Ok(items.map_or(0, |items| items.len()))
+}
+
+fn load(root: &Path, path: &Path) -> Result<(AnalysisHost, FileId)> {
+    let ws = ProjectWorkspace::discover(root, &CargoConfig::default())?;
+    let mut roots = Vec::new();
+    roots.push(root.to_path_buf());
+    roots.extend(ws.to_roots());
+    let (mut vfs, roots) = Vfs::new(roots, Watch(false));
+    let crate_graph = ws.to_crate_graph(
+        &mut |path: &Path| vfs.load(path).map(|file| FileId(file.0.into())),
+        &ProcMacroClient::dummy(),
+    );
+    let file_id = vfs
+        .load(&root.join(path))
+        .map(|file| FileId(file.0.into()))
+        .ok_or_else(|| format_err!("{} is not in the workspace", path.display()))?;
+
+    let mut change = AnalysisChange::new();
+    for &vfs_root in roots.iter() {
+        let is_local = vfs.root2path(vfs_root).starts_with(root);
+        change.add_root(SourceRootId(vfs_root.0.into()), is_local);
+    }
+    change.set_crate_graph(crate_graph);
+
+    // Wait until the Vfs has loaded all roots.
+    let receiver = vfs.task_receiver().clone();
+    let mut roots_loaded = HashSet::new();
+    for task in receiver {
+        vfs.handle_task(task);
+        for vfs_change in vfs.commit_changes() {
+            if let VfsChange::AddRoot { root: vfs_root, files } = vfs_change {
+                let root_id = SourceRootId(vfs_root.0.into());
+                let is_local = vfs.root2path(vfs_root).starts_with(root);
+                let files = files
+                    .into_iter()
+                    .map(|(file, path, text)| (FileId(file.0.into()), path, text))
+                    .collect::<Vec<_>>();
+                if is_local {
+                    for (file_id, path, text) in files {
+                        change.add_file(root_id, file_id, path, text);
+                    }
+                } else {
+                    change.add_library(LibraryData::prepare(root_id, files));
+                }
+                roots_loaded.insert(root_id);
+            }
+        }
+        if roots_loaded.len() == vfs.n_roots() {
+            break;
+        }
+    }
+
+    let mut host = AnalysisHost::default();
+    host.apply_change(change);
+    Ok((host, file_id))
+}
@@ -1,4 +1,5 @@
 mod analysis_stats;
+mod analysis_bench;
 
 use std::{fs, io::Read, path::Path, time::Instant};
 
@@ -30,6 +31,11 @@ fn main() -> Result<()> {
         .subcommand(
             SubCommand::with_name("analysis-stats").arg(Arg::with_name("verbose").short("v")),
         )
+        .subcommand(
+            SubCommand::with_name("analysis-bench")
+                .arg(Arg::with_name("complete").long("--complete").required(true).takes_value(true))
+                .arg(Arg::with_name("iterations").long("--iterations").takes_value(true)),
+        )
         .get_matches();
     match matches.subcommand() {
         ("parse", Some(matches)) => {
@@ -67,6 +73,11 @@ fn main() -> Result<()> {
             let verbose = matches.is_present("verbose");
             analysis_stats::run(verbose)?;
         }
+        ("analysis-bench", Some(matches)) => {
+            let position = matches.value_of("complete").unwrap();
+            let iterations: usize = matches.value_of("iterations").unwrap_or("5").parse()?;
+            analysis_bench::run(position, iterations)?;
+        }
         _ => unreachable!(),
     }
     Ok(())
@@ -5,12 +5,16 @@
 /// Note that neither this module, nor any other part of the analyzer's core do
 /// actual IO. See `vfs` and `project_model` in the `ra_lsp_server` crate for how
 /// actual IO is done and lowered to input.
+use std::sync::Arc;
+
 use relative_path::RelativePathBuf;
 use rustc_hash::FxHashMap;
 
 use ra_syntax::SmolStr;
 use rustc_hash::FxHashSet;
 
+pub use tt::ProcMacroKind;
+
 /// `FileId` is an integer which uniquely identifies a file. File paths are
 /// messy and system-dependent, so most of the code should work directly with
 /// `FileId`, without inspecting the path. The mapping between `FileId` and path
@@ -36,7 +40,7 @@ pub struct SourceRoot {
 
 /// `CrateGraph` is a bit of information which turns a set of text files into a
 /// number of Rust crates. Each crate is defined by the `FileId` of its root module,
-/// the set of cfg flags (not yet implemented) and the set of dependencies. Note
+/// the set of cfg flags and the set of dependencies. Note
 /// that, due to cfg's, there might be several crates for a single `FileId`! As
 /// in the rust-lang proper, a crate does not have a name. Instead, names are
 /// specified on dependency edges. That is, a crate might be known under
@@ -71,16 +75,105 @@ impl Edition {
     }
 }
 
+/// A procedural macro exported by a proc-macro crate. Unlike `macro_rules`,
+/// the analyzer can't expand these itself: expansion is delegated to the
+/// `expander`, which usually talks to an external proc-macro server.
+#[derive(Debug, Clone)]
+pub struct ProcMacro {
+    pub name: SmolStr,
+    pub kind: ProcMacroKind,
+    pub expander: Arc<dyn tt::TokenExpander>,
+}
+
+impl Eq for ProcMacro {}
+impl PartialEq for ProcMacro {
+    fn eq(&self, other: &ProcMacro) -> bool {
+        self.name == other.name
+            && self.kind == other.kind
+            && Arc::ptr_eq(&self.expander, &other.expander)
+    }
+}
+
+/// The set of `cfg` options a crate is compiled with: atoms like `unix` and
+/// key-value pairs like `feature = "std"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgOptions {
+    atoms: FxHashSet<SmolStr>,
+    key_values: FxHashSet<(SmolStr, SmolStr)>,
+}
+
+impl CfgOptions {
+    pub fn insert_atom(&mut self, atom: SmolStr) {
+        self.atoms.insert(atom);
+    }
+
+    pub fn insert_key_value(&mut self, key: SmolStr, value: SmolStr) {
+        self.key_values.insert((key, value));
+    }
+
+    pub fn check_atom(&self, atom: &str) -> bool {
+        self.atoms.iter().any(|it| it == atom)
+    }
+
+    pub fn check_key_value(&self, key: &str, value: &str) -> bool {
+        self.key_values.iter().any(|(k, v)| k == key && v == value)
+    }
+}
+
+/// Environment variables visible to the crate at compile time, via `env!`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Env {
+    entries: FxHashMap<String, String>,
+}
+
+impl Env {
+    pub fn set(&mut self, key: &str, value: String) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(|it| it.as_str())
+    }
+}
+
+/// What the crate is compiled as. A single root file might be compiled both as
+/// a library and as its test harness, with `--cfg test`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrateKind {
+    Lib,
+    Bin,
+    Test,
+    ProcMacro,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct CrateData {
     file_id: FileId,
     edition: Edition,
+    /// Name of the crate for the humans, like the name of the cargo target.
+    /// Not to be confused with the names of dependency edges.
+    display_name: Option<String>,
+    kind: CrateKind,
+    cfg_options: CfgOptions,
+    env: Env,
     dependencies: Vec<Dependency>,
+    /// `None` for ordinary crates, `Some` (possibly empty, if we failed to load
+    /// the dylib) for `proc-macro = true` crates.
+    proc_macros: Option<Vec<ProcMacro>>,
 }
 
 impl CrateData {
     fn new(file_id: FileId, edition: Edition) -> CrateData {
-        CrateData { file_id, edition, dependencies: Vec::new() }
+        CrateData {
+            file_id,
+            edition,
+            display_name: None,
+            kind: CrateKind::Lib,
+            cfg_options: CfgOptions::default(),
+            env: Env::default(),
+            dependencies: Vec::new(),
+            proc_macros: None,
+        }
     }
 
     fn add_dep(&mut self, name: SmolStr, crate_id: CrateId) {
@@ -120,10 +213,39 @@ impl CrateGraph {
         Ok(self.arena.get_mut(&from).unwrap().add_dep(name, to))
     }
 
+    /// Marks the crate as a proc-macro crate, exporting the given macros.
+    pub fn set_proc_macros(&mut self, crate_id: CrateId, proc_macros: Vec<ProcMacro>) {
+        self.arena.get_mut(&crate_id).unwrap().proc_macros = Some(proc_macros);
+    }
+
+    pub fn set_display_name(&mut self, crate_id: CrateId, display_name: String) {
+        self.arena.get_mut(&crate_id).unwrap().display_name = Some(display_name);
+    }
+
+    pub fn set_kind(&mut self, crate_id: CrateId, kind: CrateKind) {
+        self.arena.get_mut(&crate_id).unwrap().kind = kind;
+    }
+
+    pub fn set_cfg_options(&mut self, crate_id: CrateId, cfg_options: CfgOptions) {
+        self.arena.get_mut(&crate_id).unwrap().cfg_options = cfg_options;
+    }
+
+    pub fn set_env(&mut self, crate_id: CrateId, env: Env) {
+        self.arena.get_mut(&crate_id).unwrap().env = env;
+    }
+
     pub fn is_empty(&self) -> bool {
         self.arena.is_empty()
     }
 
+    pub fn is_proc_macro(&self, crate_id: CrateId) -> bool {
+        self.arena[&crate_id].proc_macros.is_some()
+    }
+
+    pub fn proc_macros(&self, crate_id: CrateId) -> &[ProcMacro] {
+        self.arena[&crate_id].proc_macros.as_ref().map(|it| it.as_slice()).unwrap_or(&[])
+    }
+
     pub fn crate_root(&self, crate_id: CrateId) -> FileId {
         self.arena[&crate_id].file_id
     }
@@ -132,10 +254,42 @@ impl CrateGraph {
         self.arena[&crate_id].edition
     }
 
-    // TODO: this only finds one crate with the given root; we could have multiple
+    pub fn display_name(&self, crate_id: CrateId) -> Option<&str> {
+        self.arena[&crate_id].display_name.as_ref().map(|it| it.as_str())
+    }
+
+    pub fn kind(&self, crate_id: CrateId) -> CrateKind {
+        self.arena[&crate_id].kind
+    }
+
+    pub fn cfg_options(&self, crate_id: CrateId) -> &CfgOptions {
+        &self.arena[&crate_id].cfg_options
+    }
+
+    pub fn env(&self, crate_id: CrateId) -> &Env {
+        &self.arena[&crate_id].env
+    }
+
+    /// Returns all crates with the given root. Test harnesses come last, so
+    /// that the library or binary is preferred when one crate has to be picked.
+    pub fn crates_for_crate_root(&self, file_id: FileId) -> Vec<CrateId> {
+        let mut res = self
+            .arena
+            .iter()
+            .filter(|(_crate_id, data)| data.file_id == file_id)
+            .map(|(&crate_id, _data)| crate_id)
+            .collect::<Vec<_>>();
+        self.sort_by_preference(&mut res);
+        res
+    }
+
     pub fn crate_id_for_crate_root(&self, file_id: FileId) -> Option<CrateId> {
-        let (&crate_id, _) = self.arena.iter().find(|(_crate_id, data)| data.file_id == file_id)?;
-        Some(crate_id)
+        self.crates_for_crate_root(file_id).into_iter().next()
+    }
+
+    /// Orders crates deterministically, with test harnesses last.
+    pub fn sort_by_preference(&self, crates: &mut [CrateId]) {
+        crates.sort_by_key(|&it| (self.kind(it) == CrateKind::Test, it));
     }
 
     pub fn dependencies<'a>(
@@ -145,17 +299,41 @@ impl CrateGraph {
         self.arena[&crate_id].dependencies.iter()
     }
 
-    /// Extends this crate graph by adding a complete disjoint second crate
-    /// graph.
+    /// Extends this crate graph by adding a second crate graph. The crates
+    /// which are already in this graph, that is the ones with the same root
+    /// file and cfg options, are not added again: the dependencies on them are
+    /// redirected to the existing crates. This happens when several workspaces
+    /// depend on the sysroot or on the same crates.io crates.
     pub fn extend(&mut self, other: CrateGraph) {
-        let start = self.arena.len() as u32;
-        self.arena.extend(other.arena.into_iter().map(|(id, mut data)| {
-            let new_id = CrateId(id.0 + start);
+        let mut by_root: FxHashMap<FileId, Vec<CrateId>> = FxHashMap::default();
+        for (&crate_id, data) in self.arena.iter() {
+            by_root.entry(data.file_id).or_default().push(crate_id);
+        }
+        let mut other_ids = other.arena.keys().cloned().collect::<Vec<_>>();
+        other_ids.sort();
+        let mut next_id = self.arena.len() as u32;
+        let mut id_map = FxHashMap::default();
+        for id in other_ids {
+            let data = &other.arena[&id];
+            let existing = by_root.get(&data.file_id).and_then(|crates| {
+                crates.iter().cloned().find(|it| self.arena[it].cfg_options == data.cfg_options)
+            });
+            let new_id = existing.unwrap_or_else(|| {
+                next_id += 1;
+                CrateId(next_id - 1)
+            });
+            id_map.insert(id, new_id);
+        }
+        for (id, mut data) in other.arena {
+            let new_id = id_map[&id];
+            if self.arena.contains_key(&new_id) {
+                continue;
+            }
             for dep in &mut data.dependencies {
-                dep.crate_id = CrateId(dep.crate_id.0 + start);
+                dep.crate_id = id_map[&dep.crate_id];
             }
-            (new_id, data)
-        }));
+            self.arena.insert(new_id, data);
+        }
     }
 
     fn dfs_find(&self, target: CrateId, from: CrateId, visited: &mut FxHashSet<CrateId>) -> bool {
@@ -179,7 +357,12 @@ impl CrateGraph {
 
 #[cfg(test)]
 mod tests {
-    use super::{CrateGraph, FileId, SmolStr, Edition::Edition2018};
+    use std::sync::Arc;
+
+    use super::{
+        CrateGraph, CrateId, FileId, SmolStr, Edition::Edition2018, ProcMacro, ProcMacroKind,
+        CfgOptions, Env, CrateKind,
+    };
 
     #[test]
     fn it_should_panic_because_of_cycle_dependencies() {
@@ -201,4 +384,91 @@ mod tests {
         assert!(graph.add_dep(crate1, SmolStr::new("crate2"), crate2).is_ok());
         assert!(graph.add_dep(crate2, SmolStr::new("crate3"), crate3).is_ok());
     }
+
+    #[test]
+    fn extend_shares_crates_with_the_same_root_and_cfg() {
+        let workspace = |root: u32| {
+            let mut graph = CrateGraph::default();
+            let krate = graph.add_crate_root(FileId(root), Edition2018);
+            let std = graph.add_crate_root(FileId(10), Edition2018);
+            let mut test_cfg = CfgOptions::default();
+            test_cfg.insert_atom("test".into());
+            let std_test = graph.add_crate_root(FileId(10), Edition2018);
+            graph.set_cfg_options(std_test, test_cfg);
+            graph.add_dep(krate, SmolStr::new("std"), std).unwrap();
+            graph
+        };
+        let mut graph = workspace(1);
+        graph.extend(workspace(2));
+
+        assert_eq!(graph.crates_for_crate_root(FileId(10)).len(), 2);
+        let krate2 = graph.crate_id_for_crate_root(FileId(2)).unwrap();
+        let std_dep = graph.dependencies(krate2).next().unwrap().crate_id();
+        assert_eq!(graph.crate_root(std_dep), FileId(10));
+        assert!(!graph.cfg_options(std_dep).check_atom("test"));
+        assert_eq!(Some(std_dep), graph.dependencies(CrateId(0)).next().map(|it| it.crate_id()));
+    }
+
+    #[test]
+    fn cfg_options_and_env_are_per_crate() {
+        let mut graph = CrateGraph::default();
+        let crate1 = graph.add_crate_root(FileId(1u32), Edition2018);
+        let crate2 = graph.add_crate_root(FileId(2u32), Edition2018);
+        let mut cfg_options = CfgOptions::default();
+        cfg_options.insert_atom("unix".into());
+        cfg_options.insert_key_value("feature".into(), "std".into());
+        graph.set_cfg_options(crate1, cfg_options);
+        let mut env = Env::default();
+        env.set("OUT_DIR", "/tmp/out".to_string());
+        graph.set_env(crate1, env);
+
+        assert!(graph.cfg_options(crate1).check_atom("unix"));
+        assert!(graph.cfg_options(crate1).check_key_value("feature", "std"));
+        assert!(!graph.cfg_options(crate1).check_key_value("feature", "alloc"));
+        assert!(!graph.cfg_options(crate2).check_atom("unix"));
+        assert_eq!(graph.env(crate1).get("OUT_DIR"), Some("/tmp/out"));
+        assert_eq!(graph.env(crate2).get("OUT_DIR"), None);
+    }
+
+    #[derive(Debug)]
+    struct IdentityExpander;
+
+    impl tt::TokenExpander for IdentityExpander {
+        fn expand(
+            &self,
+            subtree: &tt::Subtree,
+            _attributes: Option<&tt::Subtree>,
+        ) -> Result<tt::Subtree, tt::ExpansionError> {
+            Ok(subtree.clone())
+        }
+    }
+
+    #[test]
+    fn proc_macro_crates_are_tracked() {
+        let mut graph = CrateGraph::default();
+        let crate1 = graph.add_crate_root(FileId(1u32), Edition2018);
+        let crate2 = graph.add_crate_root(FileId(2u32), Edition2018);
+        let expander: Arc<dyn tt::TokenExpander> = Arc::new(IdentityExpander);
+        let proc_macro =
+            ProcMacro { name: "Identity".into(), kind: ProcMacroKind::CustomDerive, expander };
+        graph.set_proc_macros(crate2, vec![proc_macro]);
+        assert!(!graph.is_proc_macro(crate1));
+        assert!(graph.proc_macros(crate1).is_empty());
+        assert!(graph.is_proc_macro(crate2));
+        assert_eq!(graph.proc_macros(crate2)[0].name, "Identity");
+    }
+
+    #[test]
+    fn test_harness_shares_root_with_lib() {
+        let mut graph = CrateGraph::default();
+        let test = graph.add_crate_root(FileId(1u32), Edition2018);
+        graph.set_kind(test, CrateKind::Test);
+        let lib = graph.add_crate_root(FileId(1u32), Edition2018);
+        graph.set_display_name(lib, "foo".to_string());
+        graph.add_crate_root(FileId(2u32), Edition2018);
+        assert_eq!(graph.crates_for_crate_root(FileId(1u32)), vec![lib, test]);
+        assert_eq!(graph.crate_id_for_crate_root(FileId(1u32)), Some(lib));
+        assert_eq!(graph.display_name(lib), Some("foo"));
+        assert_eq!(graph.display_name(test), None);
+    }
 }
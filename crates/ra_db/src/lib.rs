@@ -7,7 +7,8 @@ use std::{
     panic, sync::Arc,
 };
 
-use ra_syntax::{TextUnit, TextRange, SourceFile, TreeArc};
+use parking_lot::Mutex;
+use ra_syntax::{TextUnit, TextRange, SourceFile, TreeArc, AstNode};
 use relative_path::RelativePathBuf;
 
 pub use ::salsa as salsa;
@@ -15,6 +16,7 @@ pub use crate::{
     cancellation::Canceled,
     input::{
         FileId, CrateId, SourceRoot, SourceRootId, CrateGraph, Dependency, Edition,
+        ProcMacro, ProcMacroKind, CfgOptions, Env, CrateKind,
     },
     loc2id::LocationIntener,
 };
@@ -74,6 +76,10 @@ pub trait SourceDatabase: CheckCanceled + std::fmt::Debug {
     /// Text of the file.
     #[salsa::input]
     fn file_text(&self, file_id: FileId) -> Arc<String>;
+    /// The syntax tree of the file, if it was reparsed incrementally when the
+    /// text was changed. This is only a hint for `parse`, see `ReparsedFile`.
+    #[salsa::input]
+    fn reparsed_file(&self, file_id: FileId) -> ReparsedFile;
     // Parses the file into the syntax tree.
     fn parse(&self, file_id: FileId) -> TreeArc<SourceFile>;
     /// Path to a file, relative to the root of its source root.
@@ -94,12 +100,41 @@ pub trait SourceDatabase: CheckCanceled + std::fmt::Debug {
 fn source_root_crates(db: &impl SourceDatabase, id: SourceRootId) -> Arc<Vec<CrateId>> {
     let root = db.source_root(id);
     let graph = db.crate_graph();
-    let res =
-        root.files.values().filter_map(|&it| graph.crate_id_for_crate_root(it)).collect::<Vec<_>>();
+    let mut res =
+        root.files.values().flat_map(|&it| graph.crates_for_crate_root(it)).collect::<Vec<_>>();
+    graph.sort_by_preference(&mut res);
     Arc::new(res)
 }
 
+/// A syntax tree reparsed incrementally, which `parse` takes instead of
+/// parsing the text from scratch, if the tree matches the current text. The
+/// tree is taken out, so that afterwards it is retained only by the `parse`
+/// query, and is freed when the syntax tree is evicted.
+#[derive(Debug, Clone, Default)]
+pub struct ReparsedFile(Arc<Mutex<Option<TreeArc<SourceFile>>>>);
+
+impl ReparsedFile {
+    pub fn new(tree: Option<TreeArc<SourceFile>>) -> ReparsedFile {
+        ReparsedFile(Arc::new(Mutex::new(tree)))
+    }
+
+    pub fn take(&self) -> Option<TreeArc<SourceFile>> {
+        self.0.lock().take()
+    }
+}
+
+impl PartialEq for ReparsedFile {
+    fn eq(&self, other: &ReparsedFile) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ReparsedFile {}
+
 fn parse(db: &impl SourceDatabase, file_id: FileId) -> TreeArc<SourceFile> {
     let text = db.file_text(file_id);
-    SourceFile::parse(&*text)
+    match db.reparsed_file(file_id).take() {
+        Some(tree) if tree.syntax().text() == text.as_str() => tree,
+        _ => SourceFile::parse(&*text),
+    }
 }
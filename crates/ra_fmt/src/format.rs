@@ -0,0 +1,215 @@
+use ra_syntax::{
+    SyntaxNode,
+    SyntaxKind::{self, *},
+    TextRange, TextUnit,
+    algo::find_covering_node,
+};
+use ra_text_edit::{TextEdit, TextEditBuilder};
+
+/// The indentation of rustfmt, for when the editor doesn't tell it.
+pub const DEFAULT_INDENT: &str = "    ";
+
+/// Nodes whose contents are indented one level deeper than the node itself,
+/// when they don't start on the same line.
+const INDENTING: &[SyntaxKind] = &[
+    BLOCK,
+    ITEM_LIST,
+    MATCH_ARM_LIST,
+    NAMED_FIELD_DEF_LIST,
+    POS_FIELD_DEF_LIST,
+    ENUM_VARIANT_LIST,
+    NAMED_FIELD_LIST,
+    USE_TREE_LIST,
+    ARG_LIST,
+    PARAM_LIST,
+    ARRAY_EXPR,
+    TUPLE_EXPR,
+    TOKEN_TREE,
+];
+
+/// Fixes the indentation and the spacing between the tokens in `range`. This
+/// is a fallback for when `rustfmt` is not available: lines are never joined
+/// or broken, and comments and the insides of macro calls keep their spacing.
+/// Each level of indentation is one `indent`, e.g. four spaces or a tab.
+pub fn format_range(root: &SyntaxNode, range: TextRange, indent: &str) -> TextEdit {
+    let mut edit = TextEditBuilder::default();
+    let lines = Lines::new(root);
+    let mut prev: Option<&SyntaxNode> = None;
+    let mut ws: Option<&SyntaxNode> = None;
+    let leaves = find_covering_node(root, range)
+        .descendants()
+        .filter(|it| it.leaf_text().is_some() && it.range().is_subrange(&range));
+    for leaf in leaves {
+        if leaf.kind() == WHITESPACE {
+            ws = Some(leaf);
+            continue;
+        }
+        match ws {
+            Some(ws) if ws.leaf_text().unwrap().contains('\n') => {
+                fix_indent(ws, leaf, &lines, indent, &mut edit)
+            }
+            _ => {
+                if let Some(prev) = prev {
+                    fix_spacing(prev, ws, leaf, &mut edit)
+                }
+            }
+        }
+        prev = Some(leaf);
+        ws = None;
+    }
+    edit.finish()
+}
+
+fn fix_indent(
+    ws: &SyntaxNode,
+    next: &SyntaxNode,
+    lines: &Lines,
+    indent: &str,
+    edit: &mut TextEditBuilder,
+) {
+    let text = ws.leaf_text().unwrap();
+    // Keep at most one blank line.
+    let newlines = text.matches('\n').count().min(2);
+    let mut new_text = "\n".repeat(newlines);
+    for _ in 0..indent_level(ws, next, lines) {
+        new_text.push_str(indent);
+    }
+    if new_text != text.as_str() {
+        edit.replace(ws.range(), new_text);
+    }
+}
+
+/// Counts the indenting ancestors of `ws`, which start on different lines, so
+/// that `foo(bar(` only indents the arguments once.
+fn indent_level(ws: &SyntaxNode, next: &SyntaxNode, lines: &Lines) -> usize {
+    let mut level = 0;
+    let mut line = lines.line(next.range().start());
+    for node in ws.ancestors().skip(1) {
+        let is_chain = match node.kind() {
+            METHOD_CALL_EXPR | FIELD_EXPR => next.kind() == DOT && ws.parent() == Some(node),
+            _ => false,
+        };
+        if !is_chain && !INDENTING.contains(&node.kind()) {
+            continue;
+        }
+        let node_line = lines.line(node.range().start());
+        if node_line >= line {
+            continue;
+        }
+        line = node_line;
+        // The closing delimiter is aligned with the line the node starts on.
+        let is_closing = match next.kind() {
+            R_CURLY | R_PAREN | R_BRACK => next.parent() == Some(node),
+            _ => false,
+        };
+        if !is_closing {
+            level += 1;
+        }
+    }
+    level
+}
+
+fn fix_spacing(
+    prev: &SyntaxNode,
+    ws: Option<&SyntaxNode>,
+    next: &SyntaxNode,
+    edit: &mut TextEditBuilder,
+) {
+    if prev.kind() == COMMENT || next.kind() == COMMENT {
+        return;
+    }
+    let in_token_tree = |node: &SyntaxNode| node.parent().map(|it| it.kind()) == Some(TOKEN_TREE);
+    if in_token_tree(prev) || in_token_tree(next) {
+        return;
+    }
+    let current = ws.map_or("", |ws| ws.leaf_text().unwrap().as_str());
+    let expected = match spacing(prev, next) {
+        Some(it) => it,
+        None if current.len() > 1 => " ",
+        None => return,
+    };
+    if current == expected {
+        return;
+    }
+    match ws {
+        Some(ws) => edit.replace(ws.range(), expected.to_string()),
+        None => edit.insert(next.range().start(), expected.to_string()),
+    }
+}
+
+/// The whitespace between two adjacent tokens on the same line, or `None` if
+/// whatever the user wrote is fine.
+fn spacing(left: &SyntaxNode, right: &SyntaxNode) -> Option<&'static str> {
+    let parent = |node: &SyntaxNode| node.parent().map(|it| it.kind());
+    match right.kind() {
+        COMMA | SEMI | QUESTION | COLON | COLONCOLON | R_PAREN | R_BRACK => return Some(""),
+        // `1 .max(2)` is not the same as `1.max(2)`.
+        DOT if left.kind() == INT_NUMBER => return None,
+        DOT => return Some(""),
+        _ => (),
+    }
+    match left.kind() {
+        L_PAREN | L_BRACK | DOT | COLONCOLON => return Some(""),
+        _ => (),
+    }
+    match (left.kind(), right.kind()) {
+        (L_CURLY, R_CURLY) => return Some(""),
+        (L_CURLY, _) | (_, R_CURLY)
+            if parent(left) == Some(USE_TREE_LIST) || parent(right) == Some(USE_TREE_LIST) =>
+        {
+            return Some("")
+        }
+        (_, L_CURLY) | (L_CURLY, _) | (_, R_CURLY) => return Some(" "),
+        (_, L_PAREN) if parent(right) == Some(ARG_LIST) || parent(right) == Some(PARAM_LIST) => {
+            return Some("")
+        }
+        (_, L_BRACK) if parent(right) == Some(INDEX_EXPR) => return Some(""),
+        (_, L_ANGLE) | (_, R_ANGLE) | (L_ANGLE, _)
+            if parent(left) == Some(TYPE_ARG_LIST)
+                || parent(left) == Some(TYPE_PARAM_LIST)
+                || parent(right) == Some(TYPE_ARG_LIST)
+                || parent(right) == Some(TYPE_PARAM_LIST) =>
+        {
+            return Some("")
+        }
+        (COMMA, _) | (SEMI, _) | (COLON, _) => return Some(" "),
+        _ => (),
+    }
+    match left.kind() {
+        MINUS | EXCL | STAR | AMP
+            if parent(left) == Some(PREFIX_EXPR) || parent(left) == Some(REF_EXPR) =>
+        {
+            return Some("")
+        }
+        _ => (),
+    }
+    let is_operator = |node: &SyntaxNode| match node.kind() {
+        EQ | FAT_ARROW | THIN_ARROW => true,
+        _ => parent(node) == Some(BIN_EXPR),
+    };
+    if is_operator(left) || is_operator(right) {
+        return Some(" ");
+    }
+    None
+}
+
+/// Offsets of the newlines, to find out on which line a node starts.
+struct Lines {
+    newlines: Vec<TextUnit>,
+}
+
+impl Lines {
+    fn new(root: &SyntaxNode) -> Lines {
+        let start = root.range().start();
+        let text = root.text().to_string();
+        let newlines =
+            text.match_indices('\n').map(|(i, _)| start + TextUnit::from_usize(i)).collect();
+        Lines { newlines }
+    }
+
+    fn line(&self, offset: TextUnit) -> usize {
+        match self.newlines.binary_search(&offset) {
+            Ok(i) | Err(i) => i,
+        }
+    }
+}
@@ -1,5 +1,7 @@
 //! This crate provides some utilities for indenting rust code.
 //!
+mod format;
+
 use itertools::Itertools;
 use ra_syntax::{
     AstNode,
@@ -8,6 +10,8 @@ use ra_syntax::{
     algo::generate,
 };
 
+pub use crate::format::{format_range, DEFAULT_INDENT};
+
 pub fn reindent(text: &str, indent: &str) -> String {
     let indent = format!("\n{}", indent);
     text.lines().intersperse(&indent).collect()
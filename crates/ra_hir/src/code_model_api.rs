@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use relative_path::RelativePathBuf;
-use ra_db::{CrateId, SourceRootId, Edition};
+use ra_db::{CrateId, SourceRootId, Edition, CrateKind};
 use ra_syntax::{ast::self, TreeArc, SyntaxNode};
 
 use crate::{
@@ -52,6 +52,16 @@ impl Crate {
         crate_graph.edition(self.crate_id)
     }
 
+    pub fn display_name(&self, db: &impl PersistentHirDatabase) -> Option<String> {
+        let crate_graph = db.crate_graph();
+        crate_graph.display_name(self.crate_id).map(|it| it.to_string())
+    }
+
+    pub fn kind(&self, db: &impl PersistentHirDatabase) -> CrateKind {
+        let crate_graph = db.crate_graph();
+        crate_graph.kind(self.crate_id)
+    }
+
     // TODO: should this be in source_binder?
     pub fn source_root_crates(
         db: &impl PersistentHirDatabase,
@@ -440,6 +450,8 @@ pub struct FnSignature {
     /// True if the first param is `self`. This is relevant to decide whether this
     /// can be called as a method.
     pub(crate) has_self_param: bool,
+    /// True for `unsafe fn`, which can only be called in unsafe blocks.
+    pub(crate) is_unsafe: bool,
 }
 
 impl FnSignature {
@@ -460,6 +472,10 @@ impl FnSignature {
     pub fn has_self_param(&self) -> bool {
         self.has_self_param
     }
+
+    pub fn is_unsafe(&self) -> bool {
+        self.is_unsafe
+    }
 }
 
 impl Function {
@@ -47,7 +47,8 @@ impl FnSignature {
             TypeRef::unit()
         };
 
-        let sig = FnSignature { name, params, ret_type, has_self_param };
+        let is_unsafe = node.is_unsafe();
+        let sig = FnSignature { name, params, ret_type, has_self_param, is_unsafe };
         Arc::new(sig)
     }
 }
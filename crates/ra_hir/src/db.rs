@@ -1,14 +1,14 @@
 use std::sync::Arc;
 
 use ra_syntax::{SyntaxNode, TreeArc, SourceFile};
-use ra_db::{SourceDatabase, salsa};
+use ra_db::{SourceDatabase, FileId, salsa};
 
 use crate::{
     MacroCallId, HirFileId,
     SourceFileItems, SourceItemId, Crate, Module, HirInterner,
     Function, FnSignature, ExprScopes,
     Struct, Enum, StructField,
-    macros::MacroExpansion,
+    macros::{MacroExpansion, MacroRulesData, MacroError},
     module_tree::ModuleTree,
     nameres::{ItemMap, lower::{LoweredModule, ImportSourceMap}},
     ty::{InferenceResult, Ty, method_resolution::CrateImplBlocks, TypableDef},
@@ -24,7 +24,13 @@ pub trait PersistentHirDatabase: SourceDatabase + AsRef<HirInterner> {
     fn hir_parse(&self, file_id: HirFileId) -> TreeArc<SourceFile>;
 
     #[salsa::invoke(crate::macros::expand_macro_invocation)]
-    fn expand_macro_invocation(&self, invoc: MacroCallId) -> Option<Arc<MacroExpansion>>;
+    fn expand_macro_invocation(
+        &self,
+        invoc: MacroCallId,
+    ) -> Result<Arc<MacroExpansion>, MacroError>;
+
+    #[salsa::invoke(crate::macros::macro_rules_query)]
+    fn macro_rules(&self, file_id: HirFileId) -> Arc<Vec<MacroRulesData>>;
 
     #[salsa::invoke(crate::adt::StructData::struct_data_query)]
     fn struct_data(&self, s: Struct) -> Arc<StructData>;
@@ -60,6 +66,10 @@ pub trait PersistentHirDatabase: SourceDatabase + AsRef<HirInterner> {
     #[salsa::invoke(crate::module_tree::ModuleTree::module_tree_query)]
     fn module_tree(&self, krate: Crate) -> Arc<ModuleTree>;
 
+    /// All crates with a module in this file, test harnesses last.
+    #[salsa::invoke(crate::source_binder::crates_for_file_query)]
+    fn crates_for_file(&self, file_id: FileId) -> Arc<Vec<Crate>>;
+
     #[salsa::invoke(crate::impl_block::impls_in_module)]
     fn impls_in_module(&self, module: Module) -> Arc<ModuleImplBlocks>;
 
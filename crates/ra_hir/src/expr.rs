@@ -1,16 +1,18 @@
 use std::ops::Index;
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use ra_arena::{Arena, RawId, impl_arena_id, map::ArenaMap};
 use ra_syntax::{
     SyntaxNodePtr, AstNode,
-    ast::{self, LoopBodyOwner, ArgListOwner, NameOwner, LiteralFlavor}
+    ast::{self, LoopBodyOwner, ArgListOwner, NameOwner, LiteralFlavor},
+    SyntaxKind::EXPR_STMT,
 };
 
 use crate::{
-    Path, Name, HirDatabase, Function, Resolver,
+    Path, Name, HirDatabase, Function, Resolver, MacroExpansion,
+    source_binder,
     name::AsName,
     type_ref::{Mutability, TypeRef},
 };
@@ -41,6 +43,21 @@ pub struct Body {
     params: Vec<PatId>,
     /// The `ExprId` of the actual body expression.
     body_expr: ExprId,
+    /// Bindings whose names come from the definition of a macro, which is
+    /// called in the body.
+    def_site_pats: FxHashSet<PatId>,
+    /// Paths whose names come from the definition of a macro, which is
+    /// called in the body.
+    def_site_exprs: FxHashSet<ExprId>,
+}
+
+/// The syntax context of a name: whether it was written by the user or comes
+/// from the definition of a macro. Names only see bindings of their own
+/// context, which makes locals of `macro_rules` hygienic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxContext {
+    CallSite,
+    DefSite,
 }
 
 /// An item body together with the mapping from syntax nodes to HIR expression
@@ -78,6 +95,24 @@ impl Body {
         self.pats.iter()
     }
 
+    /// The syntax context of the name of the binding.
+    pub fn pat_context(&self, pat: PatId) -> SyntaxContext {
+        if self.def_site_pats.contains(&pat) {
+            SyntaxContext::DefSite
+        } else {
+            SyntaxContext::CallSite
+        }
+    }
+
+    /// The syntax context of the path of the expression.
+    pub fn expr_context(&self, expr: ExprId) -> SyntaxContext {
+        if self.def_site_exprs.contains(&expr) {
+            SyntaxContext::DefSite
+        } else {
+            SyntaxContext::CallSite
+        }
+    }
+
     pub fn syntax_mapping(&self, db: &impl HirDatabase) -> Arc<BodySyntaxMapping> {
         db.body_syntax_mapping(self.owner)
     }
@@ -86,19 +121,29 @@ impl Body {
 // needs arbitrary_self_types to be a method... or maybe move to the def?
 pub fn resolver_for_expr(body: Arc<Body>, db: &impl HirDatabase, expr_id: ExprId) -> Resolver {
     let scopes = db.expr_scopes(body.owner);
-    resolver_for_scope(body, db, scopes.scope_for(expr_id))
+    let context = body.expr_context(expr_id);
+    resolver_for_scope_in_context(body, db, scopes.scope_for(expr_id), context)
 }
 
 pub fn resolver_for_scope(
     body: Arc<Body>,
     db: &impl HirDatabase,
     scope_id: Option<scope::ScopeId>,
+) -> Resolver {
+    resolver_for_scope_in_context(body, db, scope_id, SyntaxContext::CallSite)
+}
+
+fn resolver_for_scope_in_context(
+    body: Arc<Body>,
+    db: &impl HirDatabase,
+    scope_id: Option<scope::ScopeId>,
+    context: SyntaxContext,
 ) -> Resolver {
     let mut r = body.owner.resolver(db);
     let scopes = db.expr_scopes(body.owner);
     let scope_chain = scopes.scope_chain_for(scope_id).collect::<Vec<_>>();
     for scope in scope_chain.into_iter().rev() {
-        r = r.push_expr_scope(Arc::clone(&scopes), scope);
+        r = r.push_expr_scope(Arc::clone(&scopes), scope, context);
     }
     r
 }
@@ -473,6 +518,11 @@ pub(crate) fn body_hir(db: &impl HirDatabase, func: Function) -> Arc<Body> {
 
 struct ExprCollector {
     owner: Function,
+    /// Expansions of the statement-level macro calls of the body.
+    macro_expansions: FxHashMap<SyntaxNodePtr, Arc<MacroExpansion>>,
+    /// The expansion we are currently lowering, if any. Its nodes are not
+    /// part of the source file, so they are not recorded in the syntax mapping.
+    expansion: Option<Arc<MacroExpansion>>,
     exprs: Arena<ExprId, Expr>,
     pats: Arena<PatId, Pat>,
     expr_syntax_mapping: FxHashMap<SyntaxNodePtr, ExprId>,
@@ -481,12 +531,19 @@ struct ExprCollector {
     pat_syntax_mapping_back: ArenaMap<PatId, SyntaxNodePtr>,
     params: Vec<PatId>,
     body_expr: Option<ExprId>,
+    def_site_pats: FxHashSet<PatId>,
+    def_site_exprs: FxHashSet<ExprId>,
 }
 
 impl ExprCollector {
-    fn new(owner: Function) -> Self {
+    fn new(
+        owner: Function,
+        macro_expansions: FxHashMap<SyntaxNodePtr, Arc<MacroExpansion>>,
+    ) -> Self {
         ExprCollector {
             owner,
+            macro_expansions,
+            expansion: None,
             exprs: Arena::default(),
             pats: Arena::default(),
             expr_syntax_mapping: FxHashMap::default(),
@@ -495,20 +552,26 @@ impl ExprCollector {
             pat_syntax_mapping_back: ArenaMap::default(),
             params: Vec::new(),
             body_expr: None,
+            def_site_pats: FxHashSet::default(),
+            def_site_exprs: FxHashSet::default(),
         }
     }
 
     fn alloc_expr(&mut self, expr: Expr, syntax_ptr: SyntaxNodePtr) -> ExprId {
         let id = self.exprs.alloc(expr);
-        self.expr_syntax_mapping.insert(syntax_ptr, id);
-        self.expr_syntax_mapping_back.insert(id, syntax_ptr);
+        if self.expansion.is_none() {
+            self.expr_syntax_mapping.insert(syntax_ptr, id);
+            self.expr_syntax_mapping_back.insert(id, syntax_ptr);
+        }
         id
     }
 
     fn alloc_pat(&mut self, pat: Pat, syntax_ptr: SyntaxNodePtr) -> PatId {
         let id = self.pats.alloc(pat);
-        self.pat_syntax_mapping.insert(syntax_ptr, id);
-        self.pat_syntax_mapping_back.insert(id, syntax_ptr);
+        if self.expansion.is_none() {
+            self.pat_syntax_mapping.insert(syntax_ptr, id);
+            self.pat_syntax_mapping_back.insert(id, syntax_ptr);
+        }
         id
     }
 
@@ -626,7 +689,13 @@ impl ExprCollector {
             ast::ExprKind::PathExpr(e) => {
                 let path =
                     e.path().and_then(Path::from_ast).map(Expr::Path).unwrap_or(Expr::Missing);
-                self.alloc_expr(path, syntax_ptr)
+                let id = self.alloc_expr(path, syntax_ptr);
+                if let Some(expansion) = &self.expansion {
+                    if expansion.is_def_site(e.syntax().range()) {
+                        self.def_site_exprs.insert(id);
+                    }
+                }
+                id
             }
             ast::ExprKind::ContinueExpr(_e) => {
                 // TODO: labels
@@ -785,24 +854,63 @@ impl ExprCollector {
     }
 
     fn collect_block(&mut self, block: &ast::Block) -> ExprId {
-        let statements = block
-            .statements()
-            .map(|s| match s.kind() {
-                ast::StmtKind::LetStmt(stmt) => {
-                    let pat = self.collect_pat_opt(stmt.pat());
-                    let type_ref = stmt.type_ref().map(TypeRef::from_ast);
-                    let initializer = stmt.initializer().map(|e| self.collect_expr(e));
-                    Statement::Let { pat, type_ref, initializer }
-                }
-                ast::StmtKind::ExprStmt(stmt) => {
-                    Statement::Expr(self.collect_expr_opt(stmt.expr()))
-                }
-            })
-            .collect();
+        let mut statements = Vec::new();
+        for stmt in block.statements() {
+            self.collect_stmt(stmt, &mut statements);
+        }
         let tail = block.expr().map(|e| self.collect_expr(e));
         self.alloc_expr(Expr::Block { statements, tail }, SyntaxNodePtr::new(block.syntax()))
     }
 
+    fn collect_stmt(&mut self, stmt: &ast::Stmt, statements: &mut Vec<Statement>) {
+        let statement = match stmt.kind() {
+            ast::StmtKind::LetStmt(stmt) => {
+                let pat = self.collect_pat_opt(stmt.pat());
+                let type_ref = stmt.type_ref().map(TypeRef::from_ast);
+                let initializer = stmt.initializer().map(|e| self.collect_expr(e));
+                Statement::Let { pat, type_ref, initializer }
+            }
+            ast::StmtKind::ExprStmt(stmt) => {
+                if let Some(expansion) = self.stmt_expansion(stmt) {
+                    let syntax = expansion.syntax();
+                    if let Some(block) = ast::Block::cast(&syntax) {
+                        self.collect_expansion(expansion, block, statements);
+                        return;
+                    }
+                }
+                Statement::Expr(self.collect_expr_opt(stmt.expr()))
+            }
+        };
+        statements.push(statement);
+    }
+
+    fn stmt_expansion(&self, stmt: &ast::ExprStmt) -> Option<Arc<MacroExpansion>> {
+        // Calls inside of expansions are not expanded.
+        if self.expansion.is_some() {
+            return None;
+        }
+        let macro_call = stmt.syntax().children().find_map(ast::MacroCall::cast)?;
+        self.macro_expansions.get(&SyntaxNodePtr::new(macro_call.syntax())).cloned()
+    }
+
+    /// Lowers the statements of the expansion as if they were written in place
+    /// of the call, so that `let`s of the macro are visible after the call.
+    fn collect_expansion(
+        &mut self,
+        expansion: Arc<MacroExpansion>,
+        block: &ast::Block,
+        statements: &mut Vec<Statement>,
+    ) {
+        let prev = mem::replace(&mut self.expansion, Some(expansion));
+        for stmt in block.statements() {
+            self.collect_stmt(stmt, statements);
+        }
+        if let Some(expr) = block.expr() {
+            statements.push(Statement::Expr(self.collect_expr(expr)));
+        }
+        self.expansion = prev;
+    }
+
     fn collect_block_opt(&mut self, block: Option<&ast::Block>) -> ExprId {
         if let Some(block) = block {
             self.collect_block(block)
@@ -817,7 +925,16 @@ impl ExprCollector {
                 let name = bp.name().map(|nr| nr.as_name()).unwrap_or_else(Name::missing);
                 let annotation = BindingAnnotation::new(bp.is_mutable(), bp.is_ref());
                 let subpat = bp.pat().map(|subpat| self.collect_pat(subpat));
-                Pat::Bind { name, mode: annotation, subpat }
+                let bind = Pat::Bind { name, mode: annotation, subpat };
+                let id = self.alloc_pat(bind, SyntaxNodePtr::new(pat.syntax()));
+                let is_def_site = match (&self.expansion, bp.name()) {
+                    (Some(expansion), Some(name)) => expansion.is_def_site(name.syntax().range()),
+                    _ => false,
+                };
+                if is_def_site {
+                    self.def_site_pats.insert(id);
+                }
+                return id;
             }
             ast::PatKind::TupleStructPat(p) => {
                 let path = p.path().and_then(Path::from_ast);
@@ -917,6 +1034,8 @@ impl ExprCollector {
             pats: self.pats,
             params: self.params,
             body_expr: self.body_expr.expect("A body should have been collected"),
+            def_site_pats: self.def_site_pats,
+            def_site_exprs: self.def_site_exprs,
         };
         BodySyntaxMapping {
             body: Arc::new(body),
@@ -929,17 +1048,30 @@ impl ExprCollector {
 }
 
 pub(crate) fn body_syntax_mapping(db: &impl HirDatabase, func: Function) -> Arc<BodySyntaxMapping> {
-    let mut collector = ExprCollector::new(func);
+    let (file_id, fn_def) = func.source(db);
+    let module = func.module(db);
+    let macro_expansions = fn_def
+        .syntax()
+        .descendants()
+        .filter_map(ast::MacroCall::cast)
+        .filter(|it| it.syntax().parent().map(|it| it.kind()) == Some(EXPR_STMT))
+        .filter_map(|macro_call| {
+            let id = source_binder::macro_call_id(db, module, file_id, macro_call);
+            let expansion = db.expand_macro_invocation(id).ok()?;
+            Some((SyntaxNodePtr::new(macro_call.syntax()), expansion))
+        })
+        .collect();
+    let mut collector = ExprCollector::new(func, macro_expansions);
 
     // TODO: consts, etc.
-    collector.collect_fn_body(&func.source(db).1);
+    collector.collect_fn_body(&fn_def);
 
     Arc::new(collector.into_body_syntax_mapping())
 }
 
 #[cfg(test)]
 pub(crate) fn collect_fn_body_syntax(function: Function, node: &ast::FnDef) -> BodySyntaxMapping {
-    let mut collector = ExprCollector::new(function);
+    let mut collector = ExprCollector::new(function, FxHashMap::default());
     collector.collect_fn_body(node);
     collector.into_body_syntax_mapping()
 }
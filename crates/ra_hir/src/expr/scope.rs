@@ -11,7 +11,7 @@ use ra_arena::{Arena, RawId, impl_arena_id};
 
 use crate::{
     Name, AsName, Function,
-    expr::{PatId, ExprId, Pat, Expr, Body, Statement, BodySyntaxMapping},
+    expr::{PatId, ExprId, Pat, Expr, Body, Statement, BodySyntaxMapping, SyntaxContext},
     HirDatabase,
 };
 
@@ -30,6 +30,7 @@ pub struct ExprScopes {
 pub struct ScopeEntry {
     name: Name,
     pat: PatId,
+    context: SyntaxContext,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -86,7 +87,8 @@ impl ExprScopes {
             Pat::Bind { name, .. } => {
                 // bind can have a sub pattern, but it's actually not allowed
                 // to bind to things in there
-                let entry = ScopeEntry { name: name.clone(), pat };
+                let context = body.pat_context(pat);
+                let entry = ScopeEntry { name: name.clone(), pat, context };
                 self.scopes[scope].entries.push(entry)
             }
             p => p.walk_child_pats(|pat| self.add_bindings(body, scope, pat)),
@@ -176,9 +178,12 @@ impl ScopesWithSyntaxMapping {
     pub fn resolve_local_name(&self, name_ref: &ast::NameRef) -> Option<ScopeEntryWithSyntax> {
         let mut shadowed = FxHashSet::default();
         let name = name_ref.as_name();
+        // Names of the source file are always written by the user, so they
+        // don't see the locals of macro definitions.
         let ret = self
             .scope_chain(name_ref.syntax())
             .flat_map(|scope| self.scopes.entries(scope).iter())
+            .filter(|entry| entry.context() == SyntaxContext::CallSite)
             .filter(|entry| shadowed.insert(entry.name()))
             .filter(|entry| entry.name() == &name)
             .nth(0);
@@ -224,6 +229,10 @@ impl ScopeEntry {
     pub fn pat(&self) -> PatId {
         self.pat
     }
+
+    pub fn context(&self) -> SyntaxContext {
+        self.context
+    }
 }
 
 fn compute_block_scopes(
@@ -9,7 +9,7 @@ use ra_syntax::{TreeArc, SyntaxNode, SourceFile, AstNode, SyntaxNodePtr, ast};
 use ra_arena::{Arena, RawId, ArenaId, impl_arena_id};
 
 use crate::{
-    Module,
+    Module, Name,
     PersistentHirDatabase,
 };
 
@@ -97,7 +97,7 @@ impl HirFileId {
         match file_id.0 {
             HirFileIdRepr::File(file_id) => db.parse(file_id),
             HirFileIdRepr::Macro(m) => {
-                if let Some(exp) = db.expand_macro_invocation(m) {
+                if let Ok(exp) = db.expand_macro_invocation(m) {
                     return exp.file();
                 }
                 // returning an empty string looks fishy...
@@ -135,6 +135,16 @@ impl_arena_id!(MacroCallId);
 pub struct MacroCallLoc {
     pub(crate) module: Module,
     pub(crate) source_item_id: SourceItemId,
+    pub(crate) kind: MacroCallKind,
+}
+
+/// For function-like macros, `MacroCallLoc::source_item_id` points to an
+/// `ast::MacroCall`. Derives don't have a call node of their own, so for them
+/// it points to the annotated item instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum MacroCallKind {
+    FnLike,
+    Derive(Name),
 }
 
 impl MacroCallId {
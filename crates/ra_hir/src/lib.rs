@@ -53,7 +53,7 @@ pub use self::{
     path::{Path, PathKind},
     name::Name,
     ids::{HirFileId, MacroCallId, MacroCallLoc, HirInterner},
-    macros::{MacroDef, MacroInput, MacroExpansion},
+    macros::{MacroDef, MacroInput, MacroExpansion, MacroError},
     nameres::{ItemMap, PerNs, Namespace},
     ty::Ty,
     impl_block::{ImplBlock, ImplItem},
@@ -5,16 +5,30 @@
 /// do we do that.
 ///
 /// When the file-management question is resolved, all that is left is a
-/// token-tree-to-token-tree transformation plus hygiene. We don't have either of
-/// those yet, so all macros are string based at the moment!
-use std::sync::Arc;
+/// token-tree-to-token-tree transformation plus hygiene. Built-in macros are
+/// still string based, `macro_rules!` macros are expanded by `ra_mbe`, and
+/// procedural macros from dependencies are expanded as token trees by an
+/// external process, see `ra_proc_macro`. Resulting token trees are parsed
+/// directly, without going through text, by `mbe::token_tree_to_ast_item_list`.
+///
+/// For hygiene, we rely on `tt::TokenId`s: `ra_mbe` tells us whether a token
+/// of the expansion was written in the macro definition or passed in by the
+/// caller, and `MacroExpansion` remembers this for every identifier. Function
+/// bodies use this to hide the `let`s of a macro definition from the caller,
+/// see `Body::is_def_site`.
+use std::{collections::hash_map::DefaultHasher, fmt, hash::Hasher, sync::Arc};
 
+use ra_db::{ProcMacro, ProcMacroKind};
 use ra_syntax::{
     TextRange, TextUnit, SourceFile, AstNode, SyntaxNode, TreeArc, SyntaxNodePtr,
-    ast,
+    ast::{self, AttrsOwner, NameOwner},
+    SyntaxKind::{BLOCK, ITEM_LIST, SOURCE_FILE},
 };
 
-use crate::{MacroCallId, PersistentHirDatabase};
+use crate::{
+    MacroCallId, PersistentHirDatabase, Module, Name, AsName, Crate, HirFileId, MacroCallLoc,
+    ids::MacroCallKind,
+};
 
 // Hard-coded defs for now :-(
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -55,6 +69,71 @@ impl MacroDef {
             MacroDef::Vec => self.expand_vec(input),
         }
     }
+    fn expand_proc_macro(
+        proc_macro: &ProcMacro,
+        input: &tt::Subtree,
+        token_map: &mbe::TokenMap,
+    ) -> Result<MacroExpansion, MacroError> {
+        let expansion = proc_macro.expander.expand(input, None).map_err(|err| {
+            log::warn!("failed to expand proc macro {}: {}", proc_macro.name, err);
+            MacroError::ProcMacro(err)
+        })?;
+        let (file, rev_token_map) = mbe::token_tree_to_ast_item_list(&expansion);
+        // Tokens created by the proc macro have unspecified ids, and are not in
+        // the token map.
+        let ranges_map = rev_token_map
+            .ranges
+            .iter()
+            .filter_map(|&(range, id)| Some((token_map.relative_range_of(id)?, range)))
+            .collect();
+        let ptr = SyntaxNodePtr::new(file.syntax());
+        Ok(MacroExpansion::new(file, ranges_map, Vec::new(), ptr))
+    }
+
+    fn expand_macro_rules(
+        rules: &mbe::MacroRules,
+        input: &tt::Subtree,
+        token_map: &mbe::TokenMap,
+        dollar_crate: &str,
+        is_item_level: bool,
+    ) -> Result<MacroExpansion, MacroError> {
+        let mut expansion = rules.expand(input).map_err(MacroError::Expand)?;
+        replace_dollar_crate(&mut expansion, dollar_crate);
+        if !is_item_level {
+            expansion = wrap_in_fn(expansion);
+        }
+        let (file, rev_token_map) = mbe::token_tree_to_ast_item_list(&expansion);
+        let mut ranges_map = Vec::new();
+        let mut def_site_ranges = Vec::new();
+        for &(range, id) in rev_token_map.ranges.iter() {
+            // Tokens of the dummy function come from neither side.
+            if id == tt::TokenId::unspecified() {
+                continue;
+            }
+            match rules.map_id_up(id) {
+                (id, mbe::Origin::Call) => {
+                    if let Some(src_range) = token_map.relative_range_of(id) {
+                        ranges_map.push((src_range, range));
+                    }
+                }
+                (_, mbe::Origin::Def) => def_site_ranges.push(range),
+            }
+        }
+        let ptr = if is_item_level {
+            SyntaxNodePtr::new(file.syntax())
+        } else {
+            let block = file
+                .syntax()
+                .descendants()
+                .find_map(ast::Block::cast)
+                .ok_or(MacroError::NotABlock)?;
+            SyntaxNodePtr::new(block.syntax())
+        };
+        Ok(MacroExpansion::new(file, ranges_map, def_site_ranges, ptr))
+    }
+
+    // `vec!` is still expanded textually: the whole argument is mapped to the
+    // array expression, which keeps ranges inside of it exact.
     fn expand_vec(self, input: MacroInput) -> Option<MacroExpansion> {
         let text = format!(r"fn dummy() {{ {}; }}", input.text);
         let file = SourceFile::parse(&text);
@@ -62,35 +141,111 @@ impl MacroDef {
         let ptr = SyntaxNodePtr::new(array_expr.syntax());
         let src_range = TextRange::offset_len(0.into(), TextUnit::of_str(&input.text));
         let ranges_map = vec![(src_range, array_expr.syntax().range())];
-        let res = MacroExpansion { text, ranges_map, ptr };
+        let res = MacroExpansion::new(file, ranges_map, Vec::new(), ptr);
         Some(res)
     }
 }
 
+/// The reason why a macro call could not be expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroError {
+    /// We don't know how to expand this call, because the macro is not found
+    /// or its input is not supported yet. This is not necessary an error in
+    /// the user's code.
+    Unresolved,
+    /// The called `macro_rules!` definition is malformed.
+    Parse(mbe::ParseError),
+    Expand(mbe::ExpandError),
+    ProcMacro(tt::ExpansionError),
+    /// The expansion does not parse as a list of items.
+    Syntax(String),
+    /// The expansion of a call in expression or statement position does not
+    /// parse as a block.
+    NotABlock,
+    /// The macro uses `$crate`, but the crate which defines it can't be
+    /// reached through the dependencies of the calling crate.
+    UnreachableDollarCrate,
+}
+
+impl MacroError {
+    /// Checks if the error should be reported to the user.
+    pub fn is_reportable(&self) -> bool {
+        *self != MacroError::Unresolved
+    }
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroError::Unresolved => f.write_str("unresolved macro"),
+            MacroError::Parse(it) => fmt::Display::fmt(it, f),
+            MacroError::Expand(it) => fmt::Display::fmt(it, f),
+            MacroError::ProcMacro(it) => write!(f, "proc macro {}", it),
+            MacroError::Syntax(it) => write!(f, "invalid macro expansion: {}", it),
+            MacroError::NotABlock => f.write_str("macro expansion is not a valid expression"),
+            MacroError::UnreachableDollarCrate => {
+                f.write_str("`$crate` of the macro is not a dependency of this crate")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MacroInput {
     // Should be token trees
     pub text: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct MacroExpansion {
-    /// The result of macro expansion. Should be token tree as well.
-    text: String,
+    /// The result of macro expansion.
+    file: TreeArc<SourceFile>,
     /// Correspondence between ranges in the original source code and ranges in
     /// the macro.
     ranges_map: Vec<(TextRange, TextRange)>,
+    /// Ranges of the identifiers which come from the macro definition rather
+    /// than from the macro call. Such identifiers live in the syntax context of
+    /// the macro and must not interact with identifiers of the caller.
+    def_site_ranges: Vec<TextRange>,
     /// Implementation detail: internally, a macro is expanded to the whole file,
     /// even if it is an expression. This `ptr` selects the actual expansion from
     /// the expanded file.
     ptr: SyntaxNodePtr,
+    /// Hash of the text of `file`: syntax trees compare by identity, so
+    /// expansions are compared by this hash instead.
+    text_hash: u64,
+}
+
+impl PartialEq for MacroExpansion {
+    fn eq(&self, other: &MacroExpansion) -> bool {
+        self.text_hash == other.text_hash
+            && self.ranges_map == other.ranges_map
+            && self.def_site_ranges == other.def_site_ranges
+            && self.ptr == other.ptr
+    }
 }
 
+impl Eq for MacroExpansion {}
+
 impl MacroExpansion {
+    fn new(
+        file: TreeArc<SourceFile>,
+        ranges_map: Vec<(TextRange, TextRange)>,
+        def_site_ranges: Vec<TextRange>,
+        ptr: SyntaxNodePtr,
+    ) -> MacroExpansion {
+        let mut hasher = DefaultHasher::new();
+        for chunk in file.syntax().text().chunks() {
+            hasher.write(chunk.as_bytes());
+        }
+        let text_hash = hasher.finish();
+        MacroExpansion { file, ranges_map, def_site_ranges, ptr, text_hash }
+    }
+
     // FIXME: does not really make sense, macro expansion is not necessary a
     // whole file. See `MacroExpansion::ptr` as well.
     pub(crate) fn file(&self) -> TreeArc<SourceFile> {
-        SourceFile::parse(&self.text)
+        self.file.clone()
     }
 
     pub fn syntax(&self) -> TreeArc<SyntaxNode> {
@@ -108,6 +263,11 @@ impl MacroExpansion {
         }
         None
     }
+    /// Checks if the identifier at `range` of the expanded code was written in
+    /// the macro definition, as opposed to being passed in by the caller.
+    pub fn is_def_site(&self, range: TextRange) -> bool {
+        self.def_site_ranges.iter().any(|it| range.is_subrange(it))
+    }
     /// Maps range in the expanded code to the range in the source code.
     pub fn map_range_back(&self, tgt_range: TextRange) -> Option<TextRange> {
         for (s_range, t_range) in self.ranges_map.iter() {
@@ -125,11 +285,364 @@ impl MacroExpansion {
 pub(crate) fn expand_macro_invocation(
     db: &impl PersistentHirDatabase,
     invoc: MacroCallId,
-) -> Option<Arc<MacroExpansion>> {
+) -> Result<Arc<MacroExpansion>, MacroError> {
     let loc = invoc.loc(db);
     let syntax = db.file_item(loc.source_item_id);
-    let macro_call = ast::MacroCall::cast(&syntax).unwrap();
+    // Items produced by item-level macros are collected by name resolution, so
+    // an expansion which does not parse is an error. Other calls are expanded
+    // to a block of statements, which is not checked.
+    let is_item_level = match syntax.parent() {
+        Some(parent) => parent.kind() == SOURCE_FILE || parent.kind() == ITEM_LIST,
+        None => false,
+    };
+    let res = match &loc.kind {
+        MacroCallKind::FnLike => {
+            let macro_call = ast::MacroCall::cast(&syntax).unwrap();
+            if let Some((def, input)) = MacroDef::from_call(macro_call) {
+                let res = def.expand(input).ok_or_else(|| {
+                    MacroError::Syntax("expected a comma-separated list of expressions".to_string())
+                })?;
+                return Ok(Arc::new(res));
+            }
+            let name = macro_call
+                .path()
+                .and_then(|it| it.segment())
+                .and_then(|it| it.name_ref())
+                .ok_or(MacroError::Unresolved)?
+                .as_name();
+            let token_tree = macro_call.token_tree().ok_or(MacroError::Unresolved)?;
+            if let Some((def_crate, rules)) = find_macro_rules(db, &loc, &name) {
+                let rules = rules.map_err(MacroError::Parse)?;
+                let (input, token_map) =
+                    mbe::ast_to_token_tree(token_tree).ok_or(MacroError::Unresolved)?;
+                let krate = loc.module.krate(db).ok_or(MacroError::Unresolved)?;
+                let dollar_crate = dollar_crate(db, krate, def_crate)?;
+                MacroDef::expand_macro_rules(
+                    &rules,
+                    &input,
+                    &token_map,
+                    &dollar_crate,
+                    is_item_level,
+                )?
+            } else {
+                let proc_macro = find_proc_macro(db, loc.module, &name, ProcMacroKind::FuncLike)
+                    .ok_or(MacroError::Unresolved)?;
+                let (mut input, token_map) =
+                    mbe::ast_to_token_tree(token_tree).ok_or(MacroError::Unresolved)?;
+                // Function-like proc macros see only the tokens inside the delimiters.
+                input.delimiter = tt::Delimiter::None;
+                MacroDef::expand_proc_macro(&proc_macro, &input, &token_map)?
+            }
+        }
+        MacroCallKind::Derive(name) => {
+            let proc_macro = find_proc_macro(db, loc.module, name, ProcMacroKind::CustomDerive)
+                .ok_or(MacroError::Unresolved)?;
+            let (mut input, token_map) =
+                mbe::syntax_node_to_token_tree(&syntax).ok_or(MacroError::Unresolved)?;
+            strip_derives(&mut input);
+            MacroDef::expand_proc_macro(&proc_macro, &input, &token_map)?
+        }
+    };
+    if is_item_level {
+        if let Some(err) = res.file().errors().into_iter().next() {
+            return Err(MacroError::Syntax(err.to_string()));
+        }
+    }
+    Ok(Arc::new(res))
+}
+
+/// Returns the names of all `#[derive(..)]`d traits of the item. A derive can
+/// be a path, like `serde::Serialize`, whose last segment is the name.
+pub(crate) fn derive_names(item: &impl AttrsOwner) -> Vec<Name> {
+    item.attrs()
+        .filter_map(|attr| attr.as_call())
+        .filter(|(name, _)| name.as_str() == "derive")
+        .flat_map(|(_, args)| {
+            let mut res = Vec::new();
+            let mut last_segment = None;
+            for text in args.syntax().children().filter_map(|it| it.leaf_text()) {
+                if text.as_str() == "," {
+                    res.extend(last_segment.take());
+                } else if text.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') {
+                    last_segment = Some(Name::new(text.clone()));
+                }
+            }
+            res.extend(last_segment);
+            res
+        })
+        .collect()
+}
+
+/// Like rustc, we don't pass the `#[derive]` attributes themselves to the
+/// derive macro.
+fn strip_derives(item: &mut tt::Subtree) {
+    let is_derive = |tt: &tt::TokenTree| match tt {
+        tt::TokenTree::Subtree(subtree) if subtree.delimiter == tt::Delimiter::Bracket => {
+            match subtree.token_trees.first() {
+                Some(tt::TokenTree::Leaf(tt::Leaf::Ident(ident))) => {
+                    ident.text.as_str() == "derive"
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    };
+    let old = std::mem::replace(&mut item.token_trees, Vec::new());
+    let mut token_trees = Vec::with_capacity(old.len());
+    let mut iter = old.into_iter().peekable();
+    while let Some(tt) = iter.next() {
+        let is_pound = match &tt {
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => punct.char == '#',
+            _ => false,
+        };
+        if is_pound && iter.peek().map_or(false, is_derive) {
+            iter.next();
+            continue;
+        }
+        token_trees.push(tt);
+    }
+    item.token_trees = token_trees;
+}
 
-    let (def, input) = MacroDef::from_call(macro_call)?;
-    def.expand(input).map(Arc::new)
+/// Finds a proc macro with the given name among the direct dependencies of
+/// the module's crate.
+fn find_proc_macro(
+    db: &impl PersistentHirDatabase,
+    module: Module,
+    name: &Name,
+    kind: ProcMacroKind,
+) -> Option<ProcMacro> {
+    let krate = module.krate(db)?;
+    let crate_graph = db.crate_graph();
+    let res = crate_graph
+        .dependencies(krate.crate_id)
+        .flat_map(|dep| crate_graph.proc_macros(dep.crate_id()))
+        .find(|it| it.kind == kind && it.name.as_str() == name.as_str())
+        .cloned();
+    res
+}
+
+/// Wraps the expansion of a call in statement or expression position into
+/// `fn expansion() { ... }`, so that it is parsed as a block.
+fn wrap_in_fn(mut expansion: tt::Subtree) -> tt::Subtree {
+    let body = if expansion.delimiter == tt::Delimiter::None {
+        expansion.delimiter = tt::Delimiter::Brace;
+        expansion
+    } else {
+        tt::Subtree { delimiter: tt::Delimiter::Brace, token_trees: vec![expansion.into()] }
+    };
+    let ident = |text: &str| {
+        let ident = tt::Ident { text: text.into(), id: tt::TokenId::unspecified() };
+        tt::TokenTree::from(tt::Leaf::from(ident))
+    };
+    let params = tt::Subtree { delimiter: tt::Delimiter::Parenthesis, token_trees: Vec::new() };
+    let token_trees = vec![ident("fn"), ident("expansion"), params.into(), body.into()];
+    tt::Subtree { delimiter: tt::Delimiter::None, token_trees }
+}
+
+/// Replaces `$crate` in the expansion with a path to the crate which defines
+/// the macro.
+fn replace_dollar_crate(subtree: &mut tt::Subtree, dollar_crate: &str) {
+    let mut token_trees = Vec::with_capacity(subtree.token_trees.len());
+    for tt in subtree.token_trees.drain(..) {
+        match tt {
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) if ident.text.as_str() == "$crate" => {
+                let name = if dollar_crate.starts_with("::") {
+                    for &spacing in [tt::Spacing::Joint, tt::Spacing::Alone].iter() {
+                        token_trees.push(tt::Leaf::from(tt::Punct { char: ':', spacing }).into());
+                    }
+                    &dollar_crate[2..]
+                } else {
+                    dollar_crate
+                };
+                let ident = tt::Ident { text: name.into(), id: ident.id };
+                token_trees.push(tt::Leaf::from(ident).into());
+            }
+            tt::TokenTree::Subtree(mut it) => {
+                replace_dollar_crate(&mut it, dollar_crate);
+                token_trees.push(it.into());
+            }
+            tt => token_trees.push(tt),
+        }
+    }
+    subtree.token_trees = token_trees;
+}
+
+/// A `macro_rules!` definition.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MacroRulesData {
+    pub(crate) name: Name,
+    /// `macro_rules!` macros are scoped textually: a definition is visible only
+    /// below this offset.
+    pub(crate) offset: TextUnit,
+    pub(crate) is_exported: bool,
+    pub(crate) rules: Result<Arc<mbe::MacroRules>, mbe::ParseError>,
+}
+
+/// Collects the item-level `macro_rules!` definitions of a file, in source
+/// order.
+pub(crate) fn macro_rules_query(
+    db: &impl PersistentHirDatabase,
+    file_id: HirFileId,
+) -> Arc<Vec<MacroRulesData>> {
+    let source_file = db.hir_parse(file_id);
+    let res = source_file
+        .syntax()
+        .descendants()
+        .filter_map(ast::MacroCall::cast)
+        .filter(|it| !it.syntax().ancestors().any(|node| node.kind() == BLOCK))
+        .filter_map(|macro_call| {
+            let path = macro_call.path()?;
+            if path.qualifier().is_some()
+                || path.segment()?.name_ref()?.text().as_str() != "macro_rules"
+            {
+                return None;
+            }
+            let name = macro_call.name()?.as_name();
+            let (tt, _) = mbe::ast_to_token_tree(macro_call.token_tree()?)?;
+            let rules = mbe::MacroRules::parse(&tt).map(Arc::new);
+            let is_exported = macro_call
+                .attrs()
+                .any(|attr| attr.as_atom().map_or(false, |it| it.as_str() == "macro_export"));
+            let offset = macro_call.syntax().range().start();
+            Some(MacroRulesData { name, offset, is_exported, rules })
+        })
+        .collect();
+    Arc::new(res)
+}
+
+/// Finds the `macro_rules!` definition a macro call refers to, together with
+/// the crate the macro was defined in.
+fn find_macro_rules(
+    db: &impl PersistentHirDatabase,
+    loc: &MacroCallLoc,
+    name: &Name,
+) -> Option<(Crate, Result<Arc<mbe::MacroRules>, mbe::ParseError>)> {
+    let krate = loc.module.krate(db)?;
+
+    // Definitions above the call in the same file. If the call itself comes
+    // from an expansion, definitions above the outer call are visible too ...
+    let mut source_item_id = loc.source_item_id;
+    loop {
+        let call_offset = db.file_item(source_item_id).range().start();
+        let local = db
+            .macro_rules(source_item_id.file_id)
+            .iter()
+            .rev()
+            .find(|it| it.name == *name && it.offset < call_offset)
+            .map(|it| it.rules.clone());
+        if let Some(rules) = local {
+            return Some((krate, rules));
+        }
+        match source_item_id.file_id.as_macro_call_id() {
+            Some(macro_call_id) => source_item_id = macro_call_id.loc(db).source_item_id,
+            None => break,
+        }
+    }
+
+    // ... then in the files of the parent modules ...
+    let (module_file, _) = loc.module.definition_source(db);
+    let mut module = loc.module;
+    while let Some(parent) = module.parent(db) {
+        let (file_id, _) = parent.definition_source(db);
+        if file_id != module_file {
+            let defs = db.macro_rules(file_id);
+            if let Some(def) = defs.iter().rev().find(|it| it.name == *name) {
+                return Some((krate, def.rules.clone()));
+            }
+        }
+        module = parent;
+    }
+
+    // ... and finally among the `#[macro_export]`ed macros of the dependencies.
+    krate.dependencies(db).into_iter().find_map(|dep| {
+        let rules = find_exported_macro_rules(db, dep.krate, name)?;
+        Some((dep.krate, rules))
+    })
+}
+
+fn find_exported_macro_rules(
+    db: &impl PersistentHirDatabase,
+    krate: Crate,
+    name: &Name,
+) -> Option<Result<Arc<mbe::MacroRules>, mbe::ParseError>> {
+    let mut files = Vec::new();
+    let mut modules = vec![krate.root_module(db)?];
+    while let Some(module) = modules.pop() {
+        let (file_id, _) = module.definition_source(db);
+        if !files.contains(&file_id) {
+            files.push(file_id);
+        }
+        modules.extend(module.children(db));
+    }
+    files.into_iter().find_map(|file_id| {
+        db.macro_rules(file_id)
+            .iter()
+            .find(|it| it.is_exported && it.name == *name)
+            .map(|it| it.rules.clone())
+    })
+}
+
+/// Text to substitute for `$crate` in the expansion of a macro from `def_crate`
+/// called from `krate`.
+fn dollar_crate(
+    db: &impl PersistentHirDatabase,
+    krate: Crate,
+    def_crate: Crate,
+) -> Result<String, MacroError> {
+    if krate == def_crate {
+        return Ok("crate".to_string());
+    }
+    // The crate which defines the macro may be a dependency of a dependency,
+    // so look for the shortest path to it through the crate graph.
+    let mut visited = vec![krate];
+    let mut queue = std::collections::VecDeque::new();
+    queue.push_back((krate, String::new()));
+    while let Some((from, path)) = queue.pop_front() {
+        for dep in from.dependencies(db) {
+            let path = format!("{}::{}", path, dep.name);
+            if dep.krate == def_crate {
+                return Ok(path);
+            }
+            if !visited.contains(&dep.krate) {
+                visited.push(dep.krate);
+                queue.push_back((dep.krate, path));
+            }
+        }
+    }
+    Err(MacroError::UnreachableDollarCrate)
+}
+
+#[cfg(test)]
+mod tests {
+    use ra_db::SourceDatabase;
+
+    use crate::{Crate, mock::MockDatabase};
+    use super::{dollar_crate, MacroError};
+
+    #[test]
+    fn dollar_crate_goes_through_dependencies() {
+        let mut db = MockDatabase::with_files(
+            "
+            //- /main.rs
+            //- /a.rs
+            //- /b.rs
+            ",
+        );
+        db.set_crate_graph_from_fixture(crate_graph! {
+            "main": ("/main.rs", ["a"]),
+            "a": ("/a.rs", ["b"]),
+            "b": ("/b.rs", []),
+        });
+        let krate = |path| {
+            let crate_id = db.crate_graph().crate_id_for_crate_root(db.file_id_of(path)).unwrap();
+            Crate { crate_id }
+        };
+        let (main, a, b) = (krate("/main.rs"), krate("/a.rs"), krate("/b.rs"));
+
+        assert_eq!(dollar_crate(&db, main, main), Ok("crate".to_string()));
+        assert_eq!(dollar_crate(&db, main, a), Ok("::a".to_string()));
+        assert_eq!(dollar_crate(&db, main, b), Ok("::a::b".to_string()));
+        assert_eq!(dollar_crate(&db, b, main), Err(MacroError::UnreachableDollarCrate));
+    }
 }
@@ -139,6 +139,7 @@ impl MockDatabase {
         assert!(prev.is_none(), "duplicate files in the text fixture");
         let text = Arc::new(text.to_string());
         self.set_file_text(file_id, text);
+        self.set_reparsed_file(file_id, Default::default());
         self.set_file_relative_path(file_id, rel_path.clone());
         self.set_file_source_root(file_id, source_root_id);
         source_root.files.insert(rel_path, file_id);
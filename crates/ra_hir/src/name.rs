@@ -43,6 +43,10 @@ impl Name {
         Name::new(idx.to_string().into())
     }
 
+    pub(crate) fn as_str(&self) -> &str {
+        self.text.as_str()
+    }
+
     pub(crate) fn as_known_name(&self) -> Option<KnownName> {
         let name = match self.text.as_str() {
             "isize" => KnownName::Isize,
@@ -11,7 +11,8 @@ use crate::{
     SourceItemId, Path, ModuleSource, Name,
     HirFileId, MacroCallLoc, AsName, PerNs, Function,
     ModuleDef, Module, Struct, Enum, Const, Static, Trait, Type,
-    ids::LocationCtx, PersistentHirDatabase,
+    ids::{LocationCtx, MacroCallKind}, PersistentHirDatabase,
+    macros::derive_names,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -115,11 +116,33 @@ impl LoweredModule {
             match item {
                 ast::ItemOrMacro::Item(it) => {
                     self.add_def_id(source_map, db, module, file_id, it);
+                    let derives = match it.kind() {
+                        ast::ModuleItemKind::StructDef(it) => derive_names(it),
+                        ast::ModuleItemKind::EnumDef(it) => derive_names(it),
+                        _ => Vec::new(),
+                    };
+                    // FIXME: derives mostly produce impls, which are collected
+                    // separately by `ModuleImplBlocks` and don't see expansions yet.
+                    for name in derives {
+                        let item_id = file_items.id_of_unchecked(it.syntax());
+                        let loc = MacroCallLoc {
+                            module,
+                            source_item_id: SourceItemId { file_id, item_id },
+                            kind: MacroCallKind::Derive(name),
+                        };
+                        let file_id = HirFileId::from(loc.id(db));
+                        for item in db.hir_parse(file_id).items() {
+                            self.add_def_id(source_map, db, module, file_id, item);
+                        }
+                    }
                 }
                 ast::ItemOrMacro::Macro(macro_call) => {
                     let item_id = file_items.id_of_unchecked(macro_call.syntax());
-                    let loc =
-                        MacroCallLoc { module, source_item_id: SourceItemId { file_id, item_id } };
+                    let loc = MacroCallLoc {
+                        module,
+                        source_item_id: SourceItemId { file_id, item_id },
+                        kind: MacroCallKind::FnLike,
+                    };
                     let id = loc.id(db);
                     let file_id = HirFileId::from(id);
                     //FIXME: expand recursively
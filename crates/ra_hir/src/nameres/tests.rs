@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use ra_db::SourceDatabase;
+use ra_db::{SourceDatabase, ProcMacro, ProcMacroKind};
 use test_utils::{assert_eq_text, covers};
 
 use crate::{
@@ -476,6 +476,129 @@ fn item_map_across_crates() {
     );
 }
 
+#[derive(Debug)]
+struct FixedExpander(&'static str);
+
+impl tt::TokenExpander for FixedExpander {
+    fn expand(
+        &self,
+        _subtree: &tt::Subtree,
+        _attributes: Option<&tt::Subtree>,
+    ) -> Result<tt::Subtree, tt::ExpansionError> {
+        Ok(mbe::text_to_token_tree(self.0).unwrap())
+    }
+}
+
+/// Checks that the derive gets the item without the `#[derive]` attributes.
+#[derive(Debug)]
+struct DeriveExpander(&'static str);
+
+impl tt::TokenExpander for DeriveExpander {
+    fn expand(
+        &self,
+        subtree: &tt::Subtree,
+        _attributes: Option<&tt::Subtree>,
+    ) -> Result<tt::Subtree, tt::ExpansionError> {
+        assert_eq!(subtree.to_string(), self.0);
+        Ok(mbe::text_to_token_tree("fn foo_name() {}").unwrap())
+    }
+}
+
+#[test]
+fn item_map_with_proc_macros() {
+    let mut db = MockDatabase::with_files(
+        "
+        //- /main.rs
+        make_struct!();
+
+        #[derive(test_macros::Named)]
+        #[allow(dead_code)]
+        struct Foo;
+
+        //- /lib.rs
+        ",
+    );
+    db.set_crate_graph_from_fixture(crate_graph! {
+        "main": ("/main.rs", ["test_macros"]),
+        "test_macros": ("/lib.rs", []),
+    });
+    let mut crate_graph = (*db.crate_graph()).clone();
+    let macros_crate = crate_graph.crate_id_for_crate_root(db.file_id_of("/lib.rs")).unwrap();
+    crate_graph.set_proc_macros(
+        macros_crate,
+        vec![
+            ProcMacro {
+                name: "make_struct".into(),
+                kind: ProcMacroKind::FuncLike,
+                expander: Arc::new(FixedExpander("struct Generated;")),
+            },
+            ProcMacro {
+                name: "Named".into(),
+                kind: ProcMacroKind::CustomDerive,
+                expander: Arc::new(DeriveExpander("# [allow (dead_code)] struct Foo ;")),
+            },
+        ],
+    );
+    db.set_crate_graph(Arc::new(crate_graph));
+    let main_id = db.file_id_of("/main.rs");
+
+    let module = crate::source_binder::module_from_file_id(&db, main_id).unwrap();
+    let krate = module.krate(&db).unwrap();
+    let item_map = db.item_map(krate);
+
+    check_module_item_map(
+        &item_map,
+        module.module_id,
+        "
+        Foo: t v
+        Generated: t v
+        foo_name: v
+        ",
+    );
+}
+
+#[test]
+fn item_map_with_macro_rules() {
+    let mut db = MockDatabase::with_files(
+        "
+        //- /main.rs
+        macro_rules! structs {
+            ($($i:ident),*) => {
+                $(struct $i { field: u32 })*
+            }
+        }
+        structs!(Foo, Bar);
+        import_baz!();
+
+        //- /lib.rs
+        #[macro_export]
+        macro_rules! import_baz {
+            () => { use $crate::Baz; }
+        }
+        pub struct Baz;
+        ",
+    );
+    db.set_crate_graph_from_fixture(crate_graph! {
+        "main": ("/main.rs", ["test_macros"]),
+        "test_macros": ("/lib.rs", []),
+    });
+    let main_id = db.file_id_of("/main.rs");
+
+    let module = crate::source_binder::module_from_file_id(&db, main_id).unwrap();
+    let krate = module.krate(&db).unwrap();
+    let item_map = db.item_map(krate);
+
+    check_module_item_map(
+        &item_map,
+        module.module_id,
+        "
+        Bar: t v
+        Baz: t v
+        Foo: t v
+        ",
+    );
+}
+
 #[test]
 fn extern_crate_rename() {
     let mut db = MockDatabase::with_files(
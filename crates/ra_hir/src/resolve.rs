@@ -9,7 +9,7 @@ use crate::{
     name::{Name, KnownName},
     nameres::{PerNs, ItemMap},
     generics::GenericParams,
-    expr::{scope::{ExprScopes, ScopeId}, PatId, Body},
+    expr::{scope::{ExprScopes, ScopeId}, PatId, Body, SyntaxContext},
     impl_block::ImplBlock,
     path::Path,
 };
@@ -30,6 +30,8 @@ pub(crate) struct ModuleItemMap {
 pub(crate) struct ExprScope {
     expr_scopes: Arc<ExprScopes>,
     scope_id: ScopeId,
+    /// Only the bindings of this context are visible.
+    context: SyntaxContext,
 }
 
 #[derive(Debug, Clone)]
@@ -214,8 +216,9 @@ impl Resolver {
         self,
         expr_scopes: Arc<ExprScopes>,
         scope_id: ScopeId,
+        context: SyntaxContext,
     ) -> Resolver {
-        self.push_scope(Scope::ExprScope(ExprScope { expr_scopes, scope_id }))
+        self.push_scope(Scope::ExprScope(ExprScope { expr_scopes, scope_id, context }))
     }
 }
 
@@ -241,8 +244,11 @@ impl Scope {
                 }
             }
             Scope::ExprScope(e) => {
-                let entry =
-                    e.expr_scopes.entries(e.scope_id).iter().find(|entry| entry.name() == name);
+                let entry = e
+                    .expr_scopes
+                    .entries(e.scope_id)
+                    .iter()
+                    .find(|entry| entry.name() == name && entry.context() == e.context);
                 match entry {
                     Some(e) => PerNs::values(Resolution::LocalBinding(e.pat())),
                     None => PerNs::none(),
@@ -283,8 +289,9 @@ impl Scope {
                 f(Name::self_type(), PerNs::types(Resolution::SelfType(i.clone())));
             }
             Scope::ExprScope(e) => {
-                e.expr_scopes.entries(e.scope_id).iter().for_each(|e| {
-                    f(e.name().clone(), PerNs::values(Resolution::LocalBinding(e.pat())));
+                let entries = e.expr_scopes.entries(e.scope_id).iter();
+                entries.filter(|entry| entry.context() == e.context).for_each(|entry| {
+                    f(entry.name().clone(), PerNs::values(Resolution::LocalBinding(entry.pat())));
                 });
             }
         }
@@ -5,6 +5,8 @@
 ///
 /// So, this modules should not be used during hir construction, it exists
 /// purely for "IDE needs".
+use std::sync::Arc;
+
 use ra_db::{FileId, FilePosition};
 use ra_syntax::{
     SmolStr, TextRange, SyntaxNode,
@@ -13,13 +15,31 @@ use ra_syntax::{
 };
 
 use crate::{
-    HirDatabase, Function, ModuleDef, Struct, Enum,
-    AsName, Module, HirFileId, Crate, Trait, Resolver,
-    ids::{LocationCtx, SourceFileItemId},
+    HirDatabase, PersistentHirDatabase, Function, ModuleDef, Struct, Enum,
+    AsName, Module, HirFileId, Crate, Trait, Resolver, MacroCallId, MacroCallLoc, MacroError,
+    ids::{LocationCtx, SourceFileItemId, SourceItemId, MacroCallKind},
     expr
 };
 
-/// Locates the module by `FileId`. Picks topmost module in the file.
+pub(crate) fn crates_for_file_query(
+    db: &impl PersistentHirDatabase,
+    file_id: FileId,
+) -> Arc<Vec<Crate>> {
+    let source_root_id = db.file_source_root(file_id);
+    let res = db
+        .source_root_crates(source_root_id)
+        .iter()
+        .map(|&crate_id| Crate { crate_id })
+        .filter(|&krate| {
+            db.module_tree(krate).find_module_by_source(file_id.into(), None).is_some()
+        })
+        .collect();
+    Arc::new(res)
+}
+
+/// Locates the module by `FileId`. Picks topmost module in the file. If the
+/// file belongs to several crates, like a library and its test harness, the
+/// module of the library is returned.
 pub fn module_from_file_id(db: &impl HirDatabase, file_id: FileId) -> Option<Module> {
     module_from_source(db, file_id.into(), None)
 }
@@ -152,6 +172,62 @@ pub fn trait_from_module(
     Trait { id: ctx.to_def(trait_def) }
 }
 
+/// Returns the id of a function-like macro call. `file_id` is the file which
+/// contains the call, which can be either a real file or an expansion of
+/// another macro.
+pub fn macro_call_id(
+    db: &impl HirDatabase,
+    module: Module,
+    file_id: HirFileId,
+    macro_call: &ast::MacroCall,
+) -> MacroCallId {
+    let item_id = db.file_items(file_id).id_of(file_id, macro_call.syntax());
+    let loc = MacroCallLoc {
+        module,
+        source_item_id: SourceItemId { file_id, item_id },
+        kind: MacroCallKind::FnLike,
+    };
+    loc.id(db)
+}
+
+/// Returns the macro calls of the file which failed to expand, together with
+/// the reason.
+pub fn macro_errors(db: &impl HirDatabase, file_id: FileId) -> Vec<(TextRange, MacroError)> {
+    let file = db.parse(file_id);
+    file.syntax()
+        .descendants()
+        .filter_map(ast::MacroCall::cast)
+        .filter_map(|macro_call| {
+            // Calls in inline modules are resolved in those modules.
+            let module = module_from_child_node(db, file_id, macro_call.syntax())?;
+            let id = macro_call_id(db, module, file_id.into(), macro_call);
+            let err = db.expand_macro_invocation(id).err()?;
+            if !err.is_reportable() {
+                return None;
+            }
+            let range = match macro_call.path() {
+                Some(path) => path.syntax().range(),
+                None => macro_call.syntax().range(),
+            };
+            // Point at the token no rule expected, if we know it.
+            let range = match &err {
+                MacroError::Expand(mbe::ExpandError::NoMatchingRule(Some(id))) => {
+                    token_range(macro_call, *id).unwrap_or(range)
+                }
+                _ => range,
+            };
+            Some((range, err))
+        })
+        .collect()
+}
+
+fn token_range(macro_call: &ast::MacroCall, id: tt::TokenId) -> Option<TextRange> {
+    let token_tree = macro_call.token_tree()?;
+    let (_, token_map) = mbe::ast_to_token_tree(token_tree)?;
+    let range = token_map.relative_range_of(id)?;
+    Some(range + token_tree.syntax().range().start())
+}
+
 pub fn macro_symbols(db: &impl HirDatabase, file_id: FileId) -> Vec<(SmolStr, TextRange)> {
     let module = match module_from_file_id(db, file_id) {
         Some(it) => it,
@@ -170,10 +246,13 @@ pub fn macro_symbols(db: &impl HirDatabase, file_id: FileId) -> Vec<(SmolStr, Te
         })
         .filter_map(|it| it.source(db).0.as_macro_call_id())
     {
-        if let Some(exp) = db.expand_macro_invocation(macro_call_id) {
+        if let Ok(exp) = db.expand_macro_invocation(macro_call_id) {
             let loc = macro_call_id.loc(db);
             let syntax = db.file_item(loc.source_item_id);
-            let macro_call = ast::MacroCall::cast(&syntax).unwrap();
+            let macro_call = match ast::MacroCall::cast(&syntax) {
+                Some(it) => it,
+                None => continue,
+            };
             let off = macro_call.token_tree().unwrap().syntax().range().start();
             let file = exp.file();
             for trait_def in file.syntax().descendants().filter_map(ast::TraitDef::cast) {
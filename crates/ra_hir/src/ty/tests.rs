@@ -8,6 +8,7 @@ use test_utils::covers;
 use crate::{
     source_binder,
     mock::MockDatabase,
+    expr::Pat,
 };
 
 // These tests compare the inference results for all expressions in a file
@@ -972,6 +973,36 @@ mod foo {
     assert_eq!("i128", type_at_pos(&db, pos));
 }
 
+#[test]
+fn infer_macro_using_its_own_local() {
+    let (db, pos) = MockDatabase::with_position(
+        r#"
+//- /lib.rs
+macro_rules! m {
+    () => { let x = 1u8; let y = x; }
+}
+fn foo() {
+    let x = 1u32;
+    m!();
+    x<|>;
+}
+"#,
+    );
+    let func = source_binder::function_from_position(&db, pos).unwrap();
+    let body = func.body(&db);
+    let inference_result = func.infer(&db);
+    let y = body
+        .pats()
+        .find(|(_, pat)| match pat {
+            Pat::Bind { name, .. } => name.to_string() == "y",
+            _ => false,
+        })
+        .map(|(id, _)| id)
+        .unwrap();
+    assert_eq!("u8", inference_result[y].to_string());
+    assert_eq!("u32", type_at_pos(&db, pos));
+}
+
 fn type_at_pos(db: &MockDatabase, pos: FilePosition) -> String {
     let func = source_binder::function_from_position(db, pos).unwrap();
     let body_syntax_mapping = func.body_syntax_mapping(db);
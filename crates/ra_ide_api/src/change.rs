@@ -3,17 +3,19 @@ use std::{
     sync::Arc,
 };
 
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use ra_db::{
-    SourceRootId, FileId, CrateGraph, SourceDatabase, SourceRoot,
+    SourceRootId, FileId, CrateGraph, SourceDatabase, SourceRoot, ReparsedFile,
     salsa::{Database, SweepStrategy},
 };
-use ra_syntax::SourceFile;
+use ra_syntax::{SourceFile, TreeArc, AstNode};
+use ra_text_edit::AtomTextEdit;
 use relative_path::RelativePathBuf;
 use rayon::prelude::*;
 
 use crate::{
     db::RootDatabase,
+    library_cache,
     symbol_index::{SymbolIndex, SymbolsDatabase},
     status::syntax_tree_stats,
 };
@@ -22,7 +24,7 @@ use crate::{
 pub struct AnalysisChange {
     new_roots: Vec<(SourceRootId, bool)>,
     roots_changed: FxHashMap<SourceRootId, RootChange>,
-    files_changed: Vec<(FileId, Arc<String>)>,
+    files_changed: Vec<(FileId, Arc<String>, Option<Vec<AtomTextEdit>>)>,
     libraries_added: Vec<LibraryData>,
     crate_graph: Option<CrateGraph>,
 }
@@ -70,7 +72,14 @@ impl AnalysisChange {
     }
 
     pub fn change_file(&mut self, file_id: FileId, new_text: Arc<String>) {
-        self.files_changed.push((file_id, new_text))
+        self.files_changed.push((file_id, new_text, None))
+    }
+
+    /// Like `change_file`, but also records the `edits` which turn the old
+    /// text into the new one, so that the syntax tree can be reparsed
+    /// incrementally.
+    pub fn edit_file(&mut self, file_id: FileId, new_text: Arc<String>, edits: Vec<AtomTextEdit>) {
+        self.files_changed.push((file_id, new_text, Some(edits)))
     }
 
     pub fn remove_file(&mut self, root_id: SourceRootId, file_id: FileId, path: RelativePathBuf) {
@@ -88,10 +97,10 @@ impl AnalysisChange {
 }
 
 #[derive(Debug)]
-struct AddFile {
-    file_id: FileId,
-    path: RelativePathBuf,
-    text: Arc<String>,
+pub(crate) struct AddFile {
+    pub(crate) file_id: FileId,
+    pub(crate) path: RelativePathBuf,
+    pub(crate) text: Arc<String>,
 }
 
 #[derive(Debug)]
@@ -101,8 +110,8 @@ struct RemoveFile {
 }
 
 #[derive(Default)]
-struct RootChange {
-    added: Vec<AddFile>,
+pub(crate) struct RootChange {
+    pub(crate) added: Vec<AddFile>,
     removed: Vec<RemoveFile>,
 }
 
@@ -116,9 +125,9 @@ impl fmt::Debug for RootChange {
 }
 
 pub struct LibraryData {
-    root_id: SourceRootId,
-    root_change: RootChange,
-    symbol_index: SymbolIndex,
+    pub(crate) root_id: SourceRootId,
+    pub(crate) root_change: RootChange,
+    pub(crate) symbol_index: SymbolIndex,
 }
 
 impl fmt::Debug for LibraryData {
@@ -147,6 +156,29 @@ impl LibraryData {
             .collect();
         LibraryData { root_id, root_change, symbol_index }
     }
+
+    /// Returns a key which identifies the contents of the library, to look
+    /// up `LibraryData` previously saved with `to_cache`. It changes whenever
+    /// any file or the version of the analyzer changes.
+    pub fn cache_key(files: &[(FileId, RelativePathBuf, Arc<String>)]) -> String {
+        library_cache::cache_key(files)
+    }
+
+    /// Serializes the library index, to be loaded with `from_cache`.
+    pub fn to_cache(&self) -> Vec<u8> {
+        library_cache::encode(self)
+    }
+
+    /// Restores the index saved with `to_cache`. Returns `None` if `bytes`
+    /// are corrupted or don't match the `files`, in which case the library
+    /// should be `prepare`d from scratch.
+    pub fn from_cache(
+        root_id: SourceRootId,
+        files: Vec<(FileId, RelativePathBuf, Arc<String>)>,
+        bytes: &[u8],
+    ) -> Option<LibraryData> {
+        library_cache::decode(root_id, files, bytes)
+    }
 }
 
 const GC_COOLDOWN: time::Duration = time::Duration::from_millis(100);
@@ -165,37 +197,76 @@ impl RootDatabase {
             self.set_local_roots(Arc::new(local_roots));
         }
 
+        // The symbols of libraries are indexed up front, by `LibraryData`.
+        // The changed ones are indexed again lazily, see `library_symbols`.
+        let mut libraries_changed = FxHashSet::default();
         for (root_id, root_change) in change.roots_changed {
+            if self.library_roots().contains(&root_id) {
+                libraries_changed.insert(root_id);
+            }
             self.apply_root_change(root_id, root_change);
         }
-        for (file_id, text) in change.files_changed {
-            self.set_file_text(file_id, text)
+        for (file_id, text, edits) in change.files_changed {
+            if self.library_files.contains(&file_id) {
+                libraries_changed.insert(self.file_source_root(file_id));
+            }
+            let reparsed = edits.and_then(|edits| self.reparse(file_id, &edits));
+            self.set_file_text(file_id, text);
+            self.set_reparsed_file(file_id, ReparsedFile::new(reparsed));
+        }
+        for root_id in libraries_changed {
+            log::info!("library {:?} changed, its symbols will be indexed again", root_id);
+            self.set_prepared_library_symbols(root_id, None);
         }
         if !change.libraries_added.is_empty() {
             let mut libraries = Vec::clone(&self.library_roots());
+            libraries.extend(change.libraries_added.iter().map(|library| library.root_id));
+            self.set_library_roots(Arc::new(libraries));
             for library in change.libraries_added {
-                libraries.push(library.root_id);
+                let symbols = Some(Arc::new(library.symbol_index));
+                self.set_prepared_library_symbols(library.root_id, symbols);
                 self.set_source_root(library.root_id, Default::default());
-                self.set_constant_library_symbols(library.root_id, Arc::new(library.symbol_index));
                 self.apply_root_change(library.root_id, library.root_change);
             }
-            self.set_library_roots(Arc::new(libraries));
         }
         if let Some(crate_graph) = change.crate_graph {
             self.set_crate_graph(Arc::new(crate_graph))
         }
     }
 
+    /// Applies `edits` to the current syntax tree of the file. Returns `None`
+    /// if an edit can't be reparsed incrementally, in which case the new text
+    /// is simply parsed from scratch.
+    fn reparse(&self, file_id: FileId, edits: &[AtomTextEdit]) -> Option<TreeArc<SourceFile>> {
+        let mut tree = self.parse(file_id);
+        for edit in edits {
+            if edit.delete.end() > tree.syntax().range().end() {
+                return None;
+            }
+            tree = tree.incremental_reparse(edit)?;
+        }
+        Some(tree)
+    }
+
     fn apply_root_change(&mut self, root_id: SourceRootId, root_change: RootChange) {
+        let is_library = self.library_roots().contains(&root_id);
         let mut source_root = SourceRoot::clone(&self.source_root(root_id));
         for add_file in root_change.added {
+            if is_library {
+                Arc::make_mut(&mut self.library_files).insert(add_file.file_id);
+            }
             self.set_file_text(add_file.file_id, add_file.text);
+            self.set_reparsed_file(add_file.file_id, Default::default());
             self.set_file_relative_path(add_file.file_id, add_file.path.clone());
             self.set_file_source_root(add_file.file_id, root_id);
             source_root.files.insert(add_file.path, add_file.file_id);
         }
         for remove_file in root_change.removed {
+            if is_library {
+                Arc::make_mut(&mut self.library_files).remove(&remove_file.file_id);
+            }
             self.set_file_text(remove_file.file_id, Default::default());
+            self.set_reparsed_file(remove_file.file_id, Default::default());
             source_root.files.remove(&remove_file.path);
         }
         self.set_source_root(root_id, Arc::new(source_root));
@@ -228,3 +299,142 @@ impl RootDatabase {
         self.query(hir::db::BodySyntaxMappingQuery).sweep(sweep);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ra_db::{
+        SourceDatabase, ParseQuery, FileId, SourceRootId,
+        salsa::{Database, debug::DebugQueryTable},
+    };
+    use ra_syntax::{AstNode, SourceFile, TextRange, TextUnit};
+    use ra_text_edit::AtomTextEdit;
+    use relative_path::RelativePathBuf;
+
+    use crate::{
+        AnalysisChange, AnalysisHost, LibraryData, mock_analysis::MockAnalysis,
+        status::LiveEntries, symbol_index::SymbolsDatabase,
+    };
+
+    #[test]
+    fn evicts_least_recently_used_syntax_trees() {
+        let mut text = String::new();
+        for i in 0..10 {
+            text += &format!("//- /f{}.rs\nfn f{}() {{}}\n", i, i);
+        }
+        let mock = MockAnalysis::with_files(&text);
+        let files: Vec<FileId> = (0..10).map(|i| mock.id_of(&format!("/f{}.rs", i))).collect();
+        let mut host = mock.analysis_host();
+        host.set_lru_capacity(Some(4));
+        for &file_id in files.iter() {
+            host.db.parse(file_id);
+            // The first file is used all the time, so it is never evicted.
+            host.db.parse(files[0]);
+        }
+
+        let retained = host
+            .db
+            .query(ParseQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .filter(|entry| entry.value.is_some())
+            .map(|entry| entry.key)
+            .collect::<Vec<_>>();
+        assert!(retained.len() <= 4, "{} syntax trees retained", retained.len());
+        assert!(retained.contains(&files[0]));
+        assert!(retained.contains(&files[9]));
+
+        // Evicted trees are recomputed on demand.
+        assert!(host.db.parse(files[1]).syntax().text().to_string().contains("fn f1"));
+    }
+
+    #[test]
+    fn changes_to_libraries_are_applied() {
+        let (local_root, lib_root) = (SourceRootId(0), SourceRootId(1));
+        let (local_file, lib_file, new_lib_file) = (FileId(1), FileId(2), FileId(3));
+        let mut host = AnalysisHost::default();
+        let mut change = AnalysisChange::new();
+        change.add_root(local_root, true);
+        change.add_root(lib_root, false);
+        change.add_file(
+            local_root,
+            local_file,
+            RelativePathBuf::from("lib.rs"),
+            Arc::new("fn foo() {}".to_string()),
+        );
+        change.add_library(LibraryData::prepare(
+            lib_root,
+            vec![(lib_file, RelativePathBuf::from("lib.rs"), Arc::new("fn bar() {}".to_string()))],
+        ));
+        host.apply_change(change);
+        let lib_symbols = |host: &AnalysisHost| {
+            let symbols = host.db.library_symbols(lib_root);
+            let mut names =
+                symbols.symbols().iter().map(|it| it.name.to_string()).collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(lib_symbols(&host), ["bar"]);
+
+        let mut change = AnalysisChange::new();
+        change.change_file(local_file, Arc::new("fn foo2() {}".to_string()));
+        change.change_file(lib_file, Arc::new("fn bar2() {}".to_string()));
+        change.add_file(
+            lib_root,
+            new_lib_file,
+            RelativePathBuf::from("baz.rs"),
+            Arc::new("fn baz() {}".to_string()),
+        );
+        host.apply_change(change);
+
+        assert_eq!(host.db.file_text(local_file).as_str(), "fn foo2() {}");
+        assert_eq!(host.db.file_text(lib_file).as_str(), "fn bar2() {}");
+        assert_eq!(host.db.source_root(lib_root).files.len(), 2);
+        assert_eq!(host.db.file_source_root(new_lib_file), lib_root);
+        assert!(host.db.prepared_library_symbols(lib_root).is_none());
+        assert_eq!(lib_symbols(&host), ["bar2", "baz"]);
+
+        // The change is applied to the existing database, which keeps the
+        // values computed before.
+        host.db.parse(local_file);
+        let mut change = AnalysisChange::new();
+        change.remove_file(lib_root, lib_file, RelativePathBuf::from("lib.rs"));
+        host.apply_change(change);
+        assert_eq!(host.db.query(ParseQuery).entries::<LiveEntries>().live, 1);
+
+        assert_eq!(host.db.source_root(lib_root).files.len(), 1);
+        assert_eq!(lib_symbols(&host), ["baz"]);
+        assert_eq!(host.db.file_text(local_file).as_str(), "fn foo2() {}");
+    }
+
+    #[test]
+    fn edits_are_reparsed_incrementally() {
+        let mock = MockAnalysis::with_files(
+            "
+            //- /lib.rs
+            fn foo() {
+                1 + 1
+            }
+            ",
+        );
+        let file_id = mock.id_of("/lib.rs");
+        let mut host = mock.analysis_host();
+        let text = host.db.file_text(file_id);
+        host.db.parse(file_id);
+
+        let offset = TextUnit::of_str(&text[..text.find("1 + 1").unwrap()]);
+        let edit = AtomTextEdit::replace(TextRange::offset_len(offset, 5.into()), "92".to_string());
+        let new_text = edit.apply(text.to_string());
+        let mut change = AnalysisChange::new();
+        change.edit_file(file_id, Arc::new(new_text.clone()), vec![edit]);
+        host.apply_change(change);
+
+        assert_eq!(
+            host.db.parse(file_id).syntax().debug_dump(),
+            SourceFile::parse(&new_text).syntax().debug_dump()
+        );
+        // The reparsed tree is retained only by `parse`.
+        assert!(host.db.reparsed_file(file_id).take().is_none());
+    }
+}
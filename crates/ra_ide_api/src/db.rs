@@ -1,11 +1,12 @@
 use std::{
-    sync::Arc,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
     time,
 };
 
+use rustc_hash::FxHashSet;
 use ra_db::{
     CheckCanceled, FileId, Canceled, SourceDatabase,
-    salsa,
+    salsa::{self, Database},
 };
 
 use crate::{LineIndex, symbol_index::{self, SymbolsDatabase}};
@@ -23,6 +24,15 @@ pub(crate) struct RootDatabase {
     interner: Arc<hir::HirInterner>,
     pub(crate) last_gc: time::Instant,
     pub(crate) last_gc_check: time::Instant,
+    /// How many values salsa retains for each of the memory-hungry queries,
+    /// evicting the least recently used ones.
+    pub(crate) lru_capacity: usize,
+    /// Files of the libraries. A change to any of them means that the symbols
+    /// of the library have to be indexed again.
+    pub(crate) library_files: Arc<FxHashSet<FileId>>,
+    /// Set to cancel the queries of this particular snapshot, see
+    /// `Analysis::cancellation_handle`.
+    pub(crate) canceled: Arc<AtomicBool>,
 }
 
 impl salsa::Database for RootDatabase {
@@ -32,21 +42,49 @@ impl salsa::Database for RootDatabase {
     fn on_propagated_panic(&self) -> ! {
         Canceled::throw()
     }
+    fn salsa_event(&self, _event: impl Fn() -> salsa::Event<RootDatabase>) {
+        // Every query which is executed or validated goes through here, so
+        // this is a good spot to notice that the snapshot has been canceled.
+        if self.canceled.load(Ordering::SeqCst) {
+            Canceled::throw()
+        }
+    }
 }
 
+pub(crate) const DEFAULT_LRU_CAPACITY: usize = 128;
+
 impl Default for RootDatabase {
     fn default() -> RootDatabase {
+        RootDatabase::new(None)
+    }
+}
+
+impl RootDatabase {
+    pub(crate) fn new(lru_capacity: Option<usize>) -> RootDatabase {
         let mut db = RootDatabase {
             runtime: salsa::Runtime::default(),
             interner: Default::default(),
             last_gc: time::Instant::now(),
             last_gc_check: time::Instant::now(),
+            lru_capacity: DEFAULT_LRU_CAPACITY,
+            library_files: Default::default(),
+            canceled: Default::default(),
         };
         db.set_crate_graph(Default::default());
         db.set_local_roots(Default::default());
         db.set_library_roots(Default::default());
+        db.set_lru_capacity(lru_capacity);
         db
     }
+
+    pub(crate) fn set_lru_capacity(&mut self, lru_capacity: Option<usize>) {
+        let lru_capacity = lru_capacity.unwrap_or(DEFAULT_LRU_CAPACITY);
+        self.lru_capacity = lru_capacity;
+        self.query_mut(ra_db::ParseQuery).set_lru_capacity(lru_capacity);
+        self.query_mut(hir::db::HirParseQuery).set_lru_capacity(lru_capacity);
+        self.query_mut(hir::db::ExpandMacroInvocationQuery).set_lru_capacity(lru_capacity);
+        self.query_mut(hir::db::BodyHirQuery).set_lru_capacity(lru_capacity);
+    }
 }
 
 impl salsa::ParallelDatabase for RootDatabase {
@@ -56,6 +94,9 @@ impl salsa::ParallelDatabase for RootDatabase {
             interner: Arc::clone(&self.interner),
             last_gc: self.last_gc.clone(),
             last_gc_check: self.last_gc_check.clone(),
+            lru_capacity: self.lru_capacity,
+            library_files: Arc::clone(&self.library_files),
+            canceled: Default::default(),
         })
     }
 }
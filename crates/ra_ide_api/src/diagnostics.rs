@@ -61,5 +61,13 @@ pub(crate) fn diagnostics(db: &RootDatabase, file_id: FileId) -> Vec<Diagnostic>
             res.push(diag)
         }
     };
+    for (range, err) in source_binder::macro_errors(db, file_id) {
+        res.push(Diagnostic {
+            range,
+            message: err.to_string(),
+            severity: Severity::Error,
+            fix: None,
+        })
+    }
     res
 }
@@ -0,0 +1,187 @@
+use hir::{db::PersistentHirDatabase, source_binder, HirFileId, MacroCallId, Module};
+use ra_db::{FilePosition, SourceDatabase};
+use ra_syntax::{
+    algo::find_node_at_offset,
+    ast::{self, AstNode},
+    tokenize,
+    SyntaxKind::*,
+    TextRange, TextUnit,
+};
+
+use crate::db::RootDatabase;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedMacro {
+    pub name: String,
+    pub expansion: String,
+}
+
+pub(crate) fn expand_macro(db: &RootDatabase, position: FilePosition) -> Option<ExpandedMacro> {
+    let file = db.parse(position.file_id);
+    let name_ref = find_node_at_offset::<ast::NameRef>(file.syntax(), position.offset)?;
+    let macro_call = name_ref.syntax().ancestors().find_map(ast::MacroCall::cast)?;
+    let name = macro_call.path()?.segment()?.name_ref()?.text().to_string();
+
+    let module = source_binder::module_from_position(db, position)?;
+    let macro_call_id =
+        source_binder::macro_call_id(db, module, position.file_id.into(), macro_call);
+    let expansion = expand_macro_recur(db, module, macro_call_id)?;
+
+    Some(ExpandedMacro { name, expansion: insert_whitespaces(&expansion) })
+}
+
+/// Returns the text of the expansion, with all nested macro calls replaced by
+/// their own expansions.
+fn expand_macro_recur(
+    db: &RootDatabase,
+    module: Module,
+    macro_call_id: MacroCallId,
+) -> Option<String> {
+    let expansion = db.expand_macro_invocation(macro_call_id).ok()?;
+    let file_id = HirFileId::from(macro_call_id);
+    let syntax = expansion.syntax();
+    let base = syntax.range().start();
+
+    let mut replacements = Vec::new();
+    for child in syntax.descendants().filter_map(ast::MacroCall::cast) {
+        // Calls nested inside of other calls are expanded by the recursion.
+        let is_outermost = child
+            .syntax()
+            .ancestors()
+            .skip(1)
+            .take_while(|it| it.range() != syntax.range())
+            .all(|it| ast::MacroCall::cast(it).is_none());
+        if !is_outermost {
+            continue;
+        }
+        let child_id = source_binder::macro_call_id(db, module, file_id, child);
+        if let Some(text) = expand_macro_recur(db, module, child_id) {
+            replacements.push((child.syntax().range() - base, text));
+        }
+    }
+
+    let mut text = syntax.text().to_string();
+    // Apply the replacements back to front, so that the ranges stay valid.
+    for (range, replacement) in replacements.into_iter().rev() {
+        text.replace_range(range.start().to_usize()..range.end().to_usize(), &replacement);
+    }
+    // Calls in statement position are expanded to a block, show its contents.
+    if syntax.kind() == BLOCK {
+        text = text[1..text.len() - 1].trim().to_string();
+    }
+    Some(text)
+}
+
+/// Pretty-prints the expansion, which is produced from token trees and thus
+/// has a single space between all tokens.
+fn insert_whitespaces(text: &str) -> String {
+    let mut tokens = Vec::new();
+    let mut offset = TextUnit::from(0);
+    for token in tokenize(text) {
+        let range = TextRange::offset_len(offset, token.len);
+        offset += token.len;
+        if token.kind != WHITESPACE {
+            tokens.push((token.kind, &text[range]));
+        }
+    }
+
+    let mut res = String::new();
+    let mut indent = 0;
+    let mut line_start = true;
+    let mut prev = None;
+    let mut iter = tokens.iter().peekable();
+    while let Some(&(kind, token_text)) = iter.next() {
+        if kind == R_CURLY {
+            indent = indent.max(1) - 1;
+            if !line_start {
+                res.push('\n');
+                line_start = true;
+            }
+        }
+        if line_start {
+            res.push_str(&"    ".repeat(indent));
+        } else {
+            let space = match (prev, kind) {
+                (_, R_PAREN) | (_, R_BRACK) | (_, SEMI) | (_, COMMA) | (_, DOT) => false,
+                (_, COLONCOLON) | (_, L_BRACK) | (_, QUESTION) => false,
+                (Some(IDENT), L_PAREN) | (Some(IDENT), EXCL) | (Some(R_ANGLE), L_PAREN) => false,
+                (Some(L_PAREN), _) | (Some(L_BRACK), _) | (Some(DOT), _) => false,
+                (Some(COLONCOLON), _) | (Some(POUND), _) | (Some(EXCL), _) => false,
+                _ => true,
+            };
+            if space {
+                res.push(' ');
+            }
+        }
+        res.push_str(token_text);
+        line_start = false;
+        prev = Some(kind);
+
+        match kind {
+            L_CURLY if iter.peek().map(|&&(kind, _)| kind) == Some(R_CURLY) => {
+                iter.next();
+                res.push_str("}\n");
+                line_start = true;
+                prev = Some(R_CURLY);
+            }
+            L_CURLY => {
+                indent += 1;
+                res.push('\n');
+                line_start = true;
+            }
+            R_CURLY | SEMI => {
+                res.push('\n');
+                line_start = true;
+            }
+            _ => (),
+        }
+    }
+    res.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::assert_eq_text;
+
+    use crate::mock_analysis::analysis_and_position;
+
+    fn check_expand_macro(fixture: &str, expected: (&str, &str)) {
+        let (analysis, pos) = analysis_and_position(fixture);
+        let result = analysis.expand_macro(pos).unwrap().unwrap();
+        assert_eq!(result.name, expected.0);
+        assert_eq_text!(expected.1, &result.expansion);
+    }
+
+    #[test]
+    fn macro_expand_recursive_expansion() {
+        check_expand_macro(
+            r#"
+            //- /lib.rs
+            macro_rules! bar {
+                () => { fn b() {} }
+            }
+            macro_rules! foo {
+                () => { bar!(); }
+            }
+            f<|>oo!();
+            "#,
+            ("foo", "fn b() {}"),
+        );
+    }
+
+    #[test]
+    fn macro_expand_with_arguments() {
+        check_expand_macro(
+            r#"
+            //- /lib.rs
+            macro_rules! foo {
+                ($ i:ident) => {
+                    fn $ i() { let x = 1; }
+                }
+            }
+            fo<|>o! { bar }
+            "#,
+            ("foo", "fn bar() {\n    let x = 1;\n}"),
+        );
+    }
+}
@@ -5,7 +5,7 @@ use ra_syntax::{
 };
 
 use crate::{
-    TextRange, FileRange,
+    TextRange, FileRange, FilePosition,
     db::RootDatabase,
 };
 
@@ -17,6 +17,22 @@ pub(crate) fn extend_selection(db: &RootDatabase, frange: FileRange) -> TextRang
     ra_ide_api_light::extend_selection(source_file.syntax(), frange.range).unwrap_or(frange.range)
 }
 
+/// All the ranges `extend_selection` goes through from the empty selection at
+/// the position, from the innermost to the whole file.
+pub(crate) fn selection_ranges(db: &RootDatabase, position: FilePosition) -> Vec<TextRange> {
+    let mut res = Vec::new();
+    let mut range = TextRange::offset_len(position.offset, 0.into());
+    loop {
+        let next = extend_selection(db, FileRange { file_id: position.file_id, range });
+        if next == range || !range.is_subrange(&next) {
+            break;
+        }
+        res.push(next);
+        range = next;
+    }
+    res
+}
+
 fn extend_selection_in_macro(
     _db: &RootDatabase,
     source_file: &SourceFile,
@@ -38,7 +54,7 @@ fn find_macro_call(node: &SyntaxNode, range: TextRange) -> Option<&ast::MacroCal
 mod tests {
     use ra_syntax::TextRange;
 
-    use crate::mock_analysis::single_file_with_range;
+    use crate::mock_analysis::{single_file_with_position, single_file_with_range};
 
     #[test]
     fn extend_selection_inside_macros() {
@@ -52,4 +68,31 @@ mod tests {
         let r = analysis.extend_selection(frange).unwrap();
         assert_eq!(r, TextRange::from_to(50.into(), 55.into()));
     }
+
+    #[test]
+    fn selection_ranges_go_from_token_to_file() {
+        let (analysis, position) = single_file_with_position(
+            "
+            fn main() {
+                vec![foo(|x| x<|>)];
+            }
+        ",
+        );
+        let text = analysis.file_text(position.file_id);
+        let ranges = analysis.selection_ranges(position).unwrap();
+        let texts: Vec<&str> = ranges.iter().map(|&it| &text[it]).collect();
+        assert_eq!(&texts[..2], &["x", "|x| x"]);
+        assert_eq!(ranges.last().map(|&it| &text[it]), Some(&text[..]));
+        assert!(ranges.windows(2).all(|it| it[0].is_subrange(&it[1])));
+    }
+
+    #[test]
+    fn selection_ranges_inside_strings() {
+        let (analysis, position) =
+            single_file_with_position(r#"fn main() { let s = "hello wo<|>rld"; }"#);
+        let text = analysis.file_text(position.file_id);
+        let ranges = analysis.selection_ranges(position).unwrap();
+        let texts: Vec<&str> = ranges.iter().map(|&it| &text[it]).collect();
+        assert_eq!(&texts[..2], &["world", r#""hello world""#]);
+    }
 }
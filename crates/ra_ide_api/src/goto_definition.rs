@@ -86,12 +86,13 @@ pub(crate) fn reference_definition(
             Some(Resolution::LocalBinding(pat)) => {
                 let body = resolver.body().expect("no body for local binding");
                 let syntax_mapping = body.syntax_mapping(db);
-                let ptr =
-                    syntax_mapping.pat_syntax(pat).expect("pattern not found in syntax mapping");
-                let name =
-                    path.as_ident().cloned().expect("local binding from a multi-segment path");
-                let nav = NavigationTarget::from_scope_entry(file_id, name, ptr);
-                return Exact(nav);
+                // Bindings from macro expansions have no syntax in this file.
+                if let Some(ptr) = syntax_mapping.pat_syntax(pat) {
+                    let name =
+                        path.as_ident().cloned().expect("local binding from a multi-segment path");
+                    let nav = NavigationTarget::from_scope_entry(file_id, name, ptr);
+                    return Exact(nav);
+                }
             }
             Some(Resolution::GenericParam(..)) => {
                 // TODO: go to the generic param def
@@ -255,6 +256,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn goto_definition_ignores_bindings_of_macro_definition() {
+        check_goto(
+            r#"
+            //- /lib.rs
+            macro_rules! m {
+                () => { let x = ""; }
+            }
+            fn foo() {
+                let x = 92u8;
+                m!();
+                x<|>;
+            }
+            "#,
+            "x NAME FileId(1) [64; 65)",
+        );
+    }
+
     #[test]
     fn goto_definition_works_when_used_on_definition_name_itself() {
         check_goto(
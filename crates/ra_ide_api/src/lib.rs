@@ -18,6 +18,7 @@ pub mod mock_analysis;
 mod symbol_index;
 mod navigation_target;
 mod change;
+mod library_cache;
 
 mod status;
 mod completion;
@@ -32,17 +33,18 @@ mod references;
 mod impls;
 mod assists;
 mod diagnostics;
+mod expand_macro;
 
 #[cfg(test)]
 mod marks;
 
-use std::sync::Arc;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 
 use ra_syntax::{SourceFile, TreeArc, TextRange, TextUnit, AstNode};
 use ra_text_edit::TextEdit;
 use ra_db::{
     SourceDatabase, CheckCanceled,
-    salsa::{self, ParallelDatabase},
+    salsa::{self, Database, ParallelDatabase},
 };
 use relative_path::RelativePathBuf;
 
@@ -57,6 +59,8 @@ pub use crate::{
     runnables::{Runnable, RunnableKind},
     navigation_target::NavigationTarget,
     references::ReferenceSearchResult,
+    expand_macro::ExpandedMacro,
+    syntax_highlighting::{HighlightedToken, HighlightKind, HighlightModifiers},
 };
 pub use ra_ide_api_light::{
     Fold, FoldKind, HighlightedRange, Severity, StructureNode, LocalEdit,
@@ -64,7 +68,7 @@ pub use ra_ide_api_light::{
 };
 pub use ra_db::{
     Canceled, CrateGraph, CrateId, FileId, FilePosition, FileRange, SourceRootId,
-    Edition
+    Edition, CrateKind
 };
 pub use hir::Documentation;
 
@@ -104,7 +108,7 @@ pub struct Diagnostic {
     pub severity: Severity,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Query {
     query: String,
     lowercased: String,
@@ -171,6 +175,17 @@ pub struct AnalysisHost {
 }
 
 impl AnalysisHost {
+    /// Creates a host which retains at most `lru_capacity` syntax trees,
+    /// macro expansions and function bodies of each kind. The default is used
+    /// for `None`.
+    pub fn new(lru_capacity: Option<usize>) -> AnalysisHost {
+        AnalysisHost { db: db::RootDatabase::new(lru_capacity) }
+    }
+
+    pub fn set_lru_capacity(&mut self, lru_capacity: Option<usize>) {
+        self.db.set_lru_capacity(lru_capacity);
+    }
+
     /// Returns a snapshot of the current state, which you can query for
     /// semantic information.
     pub fn analysis(&self) -> Analysis {
@@ -201,6 +216,19 @@ pub struct Analysis {
     db: salsa::Snapshot<db::RootDatabase>,
 }
 
+/// Cancels the queries of a single `Analysis`, for example when the client is
+/// no longer interested in the result of a request. Unlike a change applied
+/// to the `AnalysisHost`, this doesn't affect other snapshots: those which
+/// were waiting for a query of the canceled one compute the query themselves.
+#[derive(Debug, Clone)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
 // As a general design guideline, `Analysis` API are intended to be independent
 // from the language server protocol. That is, when exposing some functionality
 // we should think in terms of "what API makes most sense" and not in terms of
@@ -208,6 +236,12 @@ pub struct Analysis {
 // API, the API should in theory be usable as a library, or via a different
 // protocol.
 impl Analysis {
+    /// Returns a handle which makes the queries of this snapshot fail with
+    /// `Canceled`.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        CancellationHandle(Arc::clone(&self.db.canceled))
+    }
+
     /// Debug info about the current state of the analysis
     pub fn status(&self) -> String {
         status::status(&*self.db)
@@ -234,6 +268,12 @@ impl Analysis {
         self.with_db(|db| extend_selection::extend_selection(db, frange))
     }
 
+    /// Returns all the ranges `extend_selection` goes through from the
+    /// position, innermost first.
+    pub fn selection_ranges(&self, position: FilePosition) -> Cancelable<Vec<TextRange>> {
+        self.with_db(|db| extend_selection::selection_ranges(db, position))
+    }
+
     /// Returns position of the matching brace (all types of braces are
     /// supported).
     pub fn matching_brace(&self, position: FilePosition) -> Option<TextUnit> {
@@ -247,6 +287,12 @@ impl Analysis {
         self.db.parse(file_id).syntax().debug_dump()
     }
 
+    /// Returns the recursive expansion of the macro call at the position,
+    /// pretty-printed as Rust code. For debug purposes.
+    pub fn expand_macro(&self, position: FilePosition) -> Cancelable<Option<ExpandedMacro>> {
+        self.with_db(|db| expand_macro::expand_macro(db, position))
+    }
+
     /// Returns an edit to remove all newlines in the range, cleaning up minor
     /// stuff like trailing commas.
     pub fn join_lines(&self, frange: FileRange) -> SourceChange {
@@ -281,6 +327,23 @@ impl Analysis {
         Some(SourceChange::from_local_edit(position.file_id, edit))
     }
 
+    /// Returns an edit which should be applied after `}` was typed, reindenting
+    /// its line. `indent` is a single level of indentation.
+    pub fn on_rcurly_typed(&self, position: FilePosition, indent: &str) -> Option<SourceChange> {
+        let file = self.db.parse(position.file_id);
+        let edit = ra_ide_api_light::on_rcurly_typed(&file, position.offset, indent)?;
+        Some(SourceChange::from_local_edit(position.file_id, edit))
+    }
+
+    /// Formats the file, or the items and statements which intersect `range`,
+    /// with the built-in formatter. This only fixes indentation and spacing,
+    /// and is meant for when `rustfmt` is not available. `indent` is a single
+    /// level of indentation, like four spaces or a tab.
+    pub fn format(&self, file_id: FileId, range: Option<TextRange>, indent: &str) -> TextEdit {
+        let file = self.db.parse(file_id);
+        ra_ide_api_light::format(&file, range, indent)
+    }
+
     /// Returns a tree representation of symbols in the file. Useful to draw a
     /// file outline.
     pub fn file_structure(&self, file_id: FileId) -> Vec<StructureNode> {
@@ -297,7 +360,7 @@ impl Analysis {
     /// Fuzzy searches for a symbol.
     pub fn symbol_search(&self, query: Query) -> Cancelable<Vec<NavigationTarget>> {
         self.with_db(|db| {
-            symbol_index::world_symbols(db, query)
+            symbol_index::world_symbols(db, query.clone())
                 .into_iter()
                 .map(NavigationTarget::from_symbol)
                 .collect::<Vec<_>>()
@@ -341,7 +404,8 @@ impl Analysis {
         self.with_db(|db| parent_module::parent_module(db, position))
     }
 
-    /// Returns crates this file belongs too.
+    /// Returns crates this file belongs to. If the file is part of both a
+    /// library and its test harness, the library comes first.
     pub fn crate_for(&self, file_id: FileId) -> Cancelable<Vec<CrateId>> {
         self.with_db(|db| parent_module::crate_for(db, file_id))
     }
@@ -351,6 +415,16 @@ impl Analysis {
         self.with_db(|db| db.crate_graph().crate_root(crate_id))
     }
 
+    /// Returns whether the given crate is a library, a binary, etc.
+    pub fn crate_kind(&self, crate_id: CrateId) -> Cancelable<CrateKind> {
+        self.with_db(|db| db.crate_graph().kind(crate_id))
+    }
+
+    /// Returns the edition of the given crate.
+    pub fn crate_edition(&self, crate_id: CrateId) -> Cancelable<Edition> {
+        self.with_db(|db| db.crate_graph().edition(crate_id))
+    }
+
     /// Returns the set of possible targets to run for the current file.
     pub fn runnables(&self, file_id: FileId) -> Cancelable<Vec<Runnable>> {
         self.with_db(|db| runnables::runnables(db, file_id))
@@ -361,6 +435,16 @@ impl Analysis {
         self.with_db(|db| syntax_highlighting::highlight(db, file_id))
     }
 
+    /// Computes semantic highlighting for the given file, or for the part of
+    /// it which intersects `range`.
+    pub fn highlight_tokens(
+        &self,
+        file_id: FileId,
+        range: Option<TextRange>,
+    ) -> Cancelable<Vec<HighlightedToken>> {
+        self.with_db(|db| syntax_highlighting::highlight_tokens(db, file_id, range))
+    }
+
     /// Computes completions at the given position.
     pub fn completions(&self, position: FilePosition) -> Cancelable<Option<Vec<CompletionItem>>> {
         self.with_db(|db| completion::completions(db, position).map(Into::into))
@@ -392,11 +476,24 @@ impl Analysis {
         self.with_db(|db| references::rename(db, position, new_name))
     }
 
-    fn with_db<F: FnOnce(&db::RootDatabase) -> T + std::panic::UnwindSafe, T>(
+    fn with_db<F: Fn(&db::RootDatabase) -> T + std::panic::RefUnwindSafe, T>(
         &self,
         f: F,
     ) -> Cancelable<T> {
-        self.db.catch_canceled(f)
+        loop {
+            match self.db.catch_canceled(|db| f(db)) {
+                // Another snapshot was canceled while computing a query this
+                // one was waiting for. The query is no longer in progress, so
+                // this snapshot can compute it itself.
+                Err(_)
+                    if !self.db.canceled.load(Ordering::SeqCst)
+                        && !self.db.salsa_runtime().is_current_revision_canceled() =>
+                {
+                    continue
+                }
+                res => return res,
+            }
+        }
     }
 }
 
@@ -417,3 +514,34 @@ fn analysis_is_send() {
     fn is_send<T: Send>() {}
     is_send::<Analysis>();
 }
+
+#[test]
+fn cancellation_handle_cancels_only_its_snapshot() {
+    let mock = mock_analysis::MockAnalysis::with_files("//- /lib.rs\nfn foo() {}");
+    let file_id = mock.id_of("/lib.rs");
+    let host = mock.analysis_host();
+    let (canceled, other) = (host.analysis(), host.analysis());
+    canceled.cancellation_handle().cancel();
+    assert!(canceled.diagnostics(file_id).is_err());
+    assert!(other.diagnostics(file_id).is_ok());
+}
+
+#[test]
+fn canceled_snapshot_doesnt_cancel_snapshots_waiting_for_its_queries() {
+    let mut text = "//- /lib.rs\n".to_string();
+    for i in 0..2000 {
+        text += &format!("fn foo{}() {{ let x = {}; x + 1; }}\n", i, i);
+    }
+    let mock = mock_analysis::MockAnalysis::with_files(&text);
+    let file_id = mock.id_of("/lib.rs");
+    let host = mock.analysis_host();
+    let (canceled, other) = (host.analysis(), host.analysis());
+    let handle = canceled.cancellation_handle();
+    // Both snapshots compute the same queries, so one of them waits for the
+    // other most of the time.
+    let canceled = std::thread::spawn(move || canceled.diagnostics(file_id).map(drop));
+    let other = std::thread::spawn(move || other.diagnostics(file_id).map(drop));
+    handle.cancel();
+    let _ = canceled.join().unwrap();
+    assert!(other.join().unwrap().is_ok());
+}
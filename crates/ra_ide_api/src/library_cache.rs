@@ -0,0 +1,321 @@
+//! Serialization of `LibraryData`, so that the symbol index of a library
+//! doesn't have to be rebuilt on each startup. The analyzer does no IO itself:
+//! it's up to the client to store the bytes, under the `cache_key`.
+//!
+//! The format is a simple little-endian binary one. Files are referred to by
+//! their relative paths, because `FileId`s are not stable across sessions.
+//! Everything which is read is validated: the entry ends with a checksum, the
+//! files must have the same texts, and the symbols must be within them. So a
+//! corrupted or stale cache entry results in `None`, and never in a panic or
+//! a bogus index.
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use ra_db::{FileId, SourceRootId};
+use ra_syntax::{
+    SmolStr,
+    SyntaxKind::{self, *},
+    SyntaxNodePtr, TextRange, TextUnit,
+};
+use relative_path::RelativePathBuf;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    change::{AddFile, LibraryData, RootChange},
+    symbol_index::{FileSymbol, SymbolIndex},
+};
+
+const MAGIC: &[u8; 4] = b"RALC";
+/// Bump this when the encoding changes.
+const FORMAT_VERSION: u32 = 3;
+/// How the `SymbolIndex` is computed may change between analyzer versions, so
+/// the entries of one version are never used by another.
+const ANALYZER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The kinds of nodes in the symbol index, see `symbol_index::to_symbol`.
+const SYMBOL_KINDS: &[SyntaxKind] =
+    &[FN_DEF, STRUCT_DEF, ENUM_DEF, TRAIT_DEF, MODULE, TYPE_DEF, CONST_DEF, STATIC_DEF];
+
+pub(crate) fn cache_key(files: &[(FileId, RelativePathBuf, Arc<String>)]) -> String {
+    let mut files = files.iter().map(|(_, path, text)| (path, text)).collect::<Vec<_>>();
+    files.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+    let mut hasher = DefaultHasher::new();
+    FORMAT_VERSION.hash(&mut hasher);
+    ANALYZER_VERSION.hash(&mut hasher);
+    for (path, text) in files.iter() {
+        path.as_str().hash(&mut hasher);
+        text.hash(&mut hasher);
+    }
+    format!("{:016x}-{}", hasher.finish(), files.len())
+}
+
+/// A hash of the text of a file, which `decode` checks against the actual
+/// text, so that a collision of the cache keys can't go unnoticed.
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+pub(crate) fn encode(data: &LibraryData) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(MAGIC);
+    w.u32(FORMAT_VERSION);
+    w.str(ANALYZER_VERSION);
+
+    let files = &data.root_change.added;
+    let file_idx: FxHashMap<FileId, u32> =
+        files.iter().enumerate().map(|(idx, file)| (file.file_id, idx as u32)).collect();
+    w.u32(files.len() as u32);
+    for file in files.iter() {
+        w.str(file.path.as_str());
+        w.u32(file.text.len() as u32);
+        w.u64(text_hash(&file.text));
+    }
+
+    let symbols = data.symbol_index.symbols();
+    w.u32(symbols.len() as u32);
+    for symbol in symbols {
+        w.u32(file_idx[&symbol.file_id]);
+        w.str(symbol.name.as_str());
+        let kind = SYMBOL_KINDS.iter().position(|&it| it == symbol.ptr.kind()).unwrap();
+        w.u32(kind as u32);
+        w.range(symbol.ptr.range());
+        match symbol.name_range {
+            Some(range) => {
+                w.u32(1);
+                w.range(range);
+            }
+            None => w.u32(0),
+        }
+        match &symbol.container_name {
+            Some(name) => {
+                w.u32(1);
+                w.str(name.as_str());
+            }
+            None => w.u32(0),
+        }
+    }
+    let checksum = checksum(&w.buf);
+    w.u64(checksum);
+    w.buf
+}
+
+pub(crate) fn decode(
+    root_id: SourceRootId,
+    files: Vec<(FileId, RelativePathBuf, Arc<String>)>,
+    bytes: &[u8],
+) -> Option<LibraryData> {
+    let mut r = Reader { buf: bytes };
+    let payload = r.bytes(bytes.len().checked_sub(8)?)?;
+    if r.u64()? != checksum(payload) {
+        return None;
+    }
+    let mut r = Reader { buf: payload };
+    if r.bytes(MAGIC.len())? != MAGIC || r.u32()? != FORMAT_VERSION || r.str()? != ANALYZER_VERSION
+    {
+        return None;
+    }
+
+    let by_path: FxHashMap<&str, (FileId, &str)> = files
+        .iter()
+        .map(|(file_id, path, text)| (path.as_str(), (*file_id, text.as_str())))
+        .collect();
+    let n_files = r.u32()? as usize;
+    if n_files != files.len() {
+        return None;
+    }
+    let mut file_lens = Vec::with_capacity(n_files);
+    for _ in 0..n_files {
+        let path = r.str()?;
+        let len = r.u32()? as usize;
+        let hash = r.u64()?;
+        let &(file_id, text) = by_path.get(path)?;
+        if len != text.len() || hash != text_hash(text) {
+            return None;
+        }
+        file_lens.push((file_id, TextUnit::of_str(text)));
+    }
+
+    let n_symbols = r.u32()? as usize;
+    let mut symbols = Vec::with_capacity(n_symbols.min(bytes.len()));
+    for _ in 0..n_symbols {
+        let (file_id, text_len) = *file_lens.get(r.u32()? as usize)?;
+        // Ranges past the end of the file would make `SyntaxNodePtr::to_node`
+        // panic.
+        let range_in_file = |range: TextRange| Some(range).filter(|it| it.end() <= text_len);
+        let name = SmolStr::new(r.str()?);
+        let kind = *SYMBOL_KINDS.get(r.u32()? as usize)?;
+        let ptr = SyntaxNodePtr::from_parts(range_in_file(r.range()?)?, kind);
+        let name_range = match r.u32()? {
+            0 => None,
+            1 => Some(range_in_file(r.range()?)?),
+            _ => return None,
+        };
+        let container_name = match r.u32()? {
+            0 => None,
+            1 => Some(SmolStr::new(r.str()?)),
+            _ => return None,
+        };
+        symbols.push(FileSymbol { file_id, name, ptr, name_range, container_name });
+    }
+    if !r.buf.is_empty() {
+        return None;
+    }
+
+    let mut root_change = RootChange::default();
+    root_change.added =
+        files.into_iter().map(|(file_id, path, text)| AddFile { file_id, path, text }).collect();
+    Some(LibraryData { root_id, root_change, symbol_index: SymbolIndex::new(symbols) })
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn range(&mut self, range: TextRange) {
+        self.u32(range.start().to_usize() as u32);
+        self.u32(range.end().to_usize() as u32);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (res, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(res)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.bytes(len)?).ok()
+    }
+
+    fn range(&mut self) -> Option<TextRange> {
+        let start = self.u32()?;
+        let end = self.u32()?;
+        if start > end {
+            return None;
+        }
+        Some(TextRange::from_to(TextUnit::from(start), TextUnit::from(end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ra_db::{FileId, SourceRootId};
+    use ra_syntax::{SyntaxNodePtr, TextRange, TextUnit};
+    use relative_path::RelativePathBuf;
+
+    use crate::{symbol_index::SymbolIndex, LibraryData};
+
+    use super::{checksum, MAGIC};
+
+    fn files() -> Vec<(FileId, RelativePathBuf, Arc<String>)> {
+        vec![
+            (
+                FileId(1),
+                RelativePathBuf::from("lib.rs"),
+                Arc::new("mod foo; struct Bar; impl Bar { fn baz() {} }".to_string()),
+            ),
+            (FileId(2), RelativePathBuf::from("foo.rs"), Arc::new("trait Quux {}".to_string())),
+        ]
+    }
+
+    #[test]
+    fn library_data_roundtrips_through_cache() {
+        let data = LibraryData::prepare(SourceRootId(0), files());
+        let bytes = data.to_cache();
+        let restored = LibraryData::from_cache(SourceRootId(0), files(), &bytes).unwrap();
+        assert_eq!(restored.symbol_index, data.symbol_index);
+        assert_eq!(restored.to_cache(), bytes);
+    }
+
+    #[test]
+    fn corrupted_or_stale_cache_is_rejected() {
+        let bytes = LibraryData::prepare(SourceRootId(0), files()).to_cache();
+        for len in 0..bytes.len() {
+            assert!(LibraryData::from_cache(SourceRootId(0), files(), &bytes[..len]).is_none());
+        }
+        for idx in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[idx] ^= 1;
+            assert!(LibraryData::from_cache(SourceRootId(0), files(), &corrupted).is_none());
+        }
+
+        // Entries written with another `FORMAT_VERSION` are ignored, even with
+        // a valid checksum.
+        let mut old_format = bytes[..bytes.len() - 8].to_vec();
+        old_format[MAGIC.len()] ^= 1;
+        let old_checksum = checksum(&old_format);
+        old_format.extend_from_slice(&old_checksum.to_le_bytes());
+        assert!(LibraryData::from_cache(SourceRootId(0), files(), &old_format).is_none());
+
+        let mut changed = files();
+        changed[1].2 = Arc::new("trait Quux {} trait Spam {}".to_string());
+        assert_ne!(LibraryData::cache_key(&files()), LibraryData::cache_key(&changed));
+        assert!(LibraryData::from_cache(SourceRootId(0), changed, &bytes).is_none());
+
+        // A file with the same length but another text is detected too.
+        let mut same_len = files();
+        same_len[1].2 = Arc::new("trait Spam {}".to_string());
+        assert!(LibraryData::from_cache(SourceRootId(0), same_len, &bytes).is_none());
+    }
+
+    #[test]
+    fn symbols_outside_of_their_files_are_rejected() {
+        let mut data = LibraryData::prepare(SourceRootId(0), files());
+        let mut symbols = data.symbol_index.symbols().to_vec();
+        let symbol = symbols.iter_mut().find(|it| it.file_id == FileId(2)).unwrap();
+        let past_end = TextRange::offset_len(TextUnit::from(10), TextUnit::from(10));
+        symbol.ptr = SyntaxNodePtr::from_parts(past_end, symbol.ptr.kind());
+        data.symbol_index = SymbolIndex::new(symbols);
+        let bytes = data.to_cache();
+        assert!(LibraryData::from_cache(SourceRootId(0), files(), &bytes).is_none());
+    }
+}
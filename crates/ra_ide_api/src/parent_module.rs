@@ -1,3 +1,4 @@
+use hir::db::PersistentHirDatabase;
 use ra_db::{FilePosition, FileId, CrateId};
 
 use crate::{NavigationTarget, db::RootDatabase};
@@ -13,17 +14,10 @@ pub(crate) fn parent_module(db: &RootDatabase, position: FilePosition) -> Vec<Na
     vec![nav]
 }
 
-/// Returns `Vec` for the same reason as `parent_module`
+/// Returns `Vec` because a file might belong both to a library and its test
+/// harness, or be shared between several crates.
 pub(crate) fn crate_for(db: &RootDatabase, file_id: FileId) -> Vec<CrateId> {
-    let module = match hir::source_binder::module_from_file_id(db, file_id) {
-        Some(it) => it,
-        None => return Vec::new(),
-    };
-    let krate = match module.krate(db) {
-        Some(it) => it,
-        None => return Vec::new(),
-    };
-    vec![krate.crate_id()]
+    db.crates_for_file(file_id).iter().map(|krate| krate.crate_id()).collect()
 }
 
 #[cfg(test)]
@@ -3,7 +3,8 @@ use ra_syntax::{
     TextRange, SyntaxNode,
     ast::{self, AstNode, NameOwner, ModuleItemOwner},
 };
-use ra_db::SourceDatabase;
+use ra_db::{SourceDatabase, CrateId, CrateKind};
+use hir::{Crate, db::PersistentHirDatabase};
 
 use crate::{db::RootDatabase, FileId};
 
@@ -11,6 +12,9 @@ use crate::{db::RootDatabase, FileId};
 pub struct Runnable {
     pub range: TextRange,
     pub kind: RunnableKind,
+    /// The crate to run in: tests are run by the test harness, if the file
+    /// belongs to one.
+    pub krate: Option<CrateId>,
 }
 
 #[derive(Debug)]
@@ -23,7 +27,27 @@ pub enum RunnableKind {
 
 pub(crate) fn runnables(db: &RootDatabase, file_id: FileId) -> Vec<Runnable> {
     let source_file = db.parse(file_id);
-    source_file.syntax().descendants().filter_map(|i| runnable(db, file_id, i)).collect()
+    let crates = db.crates_for_file(file_id);
+    source_file
+        .syntax()
+        .descendants()
+        .filter_map(|i| runnable(db, file_id, i))
+        .map(|mut runnable| {
+            runnable.krate = runnable_crate(db, &crates, &runnable.kind);
+            runnable
+        })
+        .collect()
+}
+
+fn runnable_crate(db: &RootDatabase, crates: &[Crate], kind: &RunnableKind) -> Option<CrateId> {
+    let preferred = match kind {
+        RunnableKind::Bin => CrateKind::Bin,
+        RunnableKind::Test { .. } | RunnableKind::TestMod { .. } | RunnableKind::Bench { .. } => {
+            CrateKind::Test
+        }
+    };
+    let krate = crates.iter().find(|it| it.kind(db) == preferred).or_else(|| crates.first())?;
+    Some(krate.crate_id())
 }
 
 fn runnable(db: &RootDatabase, file_id: FileId, item: &SyntaxNode) -> Option<Runnable> {
@@ -47,7 +71,7 @@ fn runnable_fn(fn_def: &ast::FnDef) -> Option<Runnable> {
     } else {
         return None;
     };
-    Some(Runnable { range: fn_def.syntax().range(), kind })
+    Some(Runnable { range: fn_def.syntax().range(), kind, krate: None })
 }
 
 fn runnable_mod(db: &RootDatabase, file_id: FileId, module: &ast::Module) -> Option<Runnable> {
@@ -67,7 +91,7 @@ fn runnable_mod(db: &RootDatabase, file_id: FileId, module: &ast::Module) -> Opt
 
     // FIXME: thread cancellation instead of `.ok`ing
     let path = module.path_to_root(db).into_iter().rev().filter_map(|it| it.name(db)).join("::");
-    Some(Runnable { range, kind: RunnableKind::TestMod { path } })
+    Some(Runnable { range, kind: RunnableKind::TestMod { path }, krate: None })
 }
 
 #[cfg(test)]
@@ -22,17 +22,26 @@ pub(crate) fn syntax_tree_stats(db: &RootDatabase) -> SyntaxTreeStats {
 pub(crate) fn status(db: &RootDatabase) -> String {
     let files_stats = db.query(FileTextQuery).entries::<FilesStats>();
     let syntax_tree_stats = syntax_tree_stats(db);
+    let lru_stats = format!(
+        "lru (capacity {}): {} parse, {} hir_parse, {} expand_macro_invocation, {} body_hir",
+        db.lru_capacity,
+        db.query(ParseQuery).entries::<LiveEntries>(),
+        db.query(hir::db::HirParseQuery).entries::<LiveEntries>(),
+        db.query(hir::db::ExpandMacroInvocationQuery).entries::<LiveEntries>(),
+        db.query(hir::db::BodyHirQuery).entries::<LiveEntries>(),
+    );
     let symbols_stats = db.query(LibrarySymbolsQuery).entries::<LibrarySymbolsStats>();
     let n_defs = {
         let interner: &hir::HirInterner = db.as_ref();
         interner.len()
     };
     format!(
-        "{}\n{}\n{}\n{} defs\n\nmemory:\n{}\ngc {:?} seconds ago",
+        "{}\n{}\n{}\n{} defs\n{}\n\nmemory:\n{}\ngc {:?} seconds ago",
         files_stats,
         symbols_stats,
         syntax_tree_stats,
         n_defs,
+        lru_stats,
         MemoryStats::current(),
         db.last_gc.elapsed().as_secs(),
     )
@@ -64,6 +73,35 @@ impl FromIterator<TableEntry<FileId, Arc<String>>> for FilesStats {
     }
 }
 
+/// Counts the entries of any query, and those of them which retain values.
+#[derive(Default)]
+pub(crate) struct LiveEntries {
+    total: usize,
+    pub(crate) live: usize,
+}
+
+impl fmt::Display for LiveEntries {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}/{}", self.live, self.total)
+    }
+}
+
+impl<K, V> FromIterator<TableEntry<K, V>> for LiveEntries {
+    fn from_iter<T>(iter: T) -> LiveEntries
+    where
+        T: IntoIterator<Item = TableEntry<K, V>>,
+    {
+        let mut res = LiveEntries::default();
+        for entry in iter {
+            res.total += 1;
+            if entry.value.is_some() {
+                res.live += 1;
+            }
+        }
+        res
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct SyntaxTreeStats {
     total: usize,
@@ -112,8 +150,8 @@ impl FromIterator<TableEntry<SourceRootId, Arc<SymbolIndex>>> for LibrarySymbols
         T: IntoIterator<Item = TableEntry<SourceRootId, Arc<SymbolIndex>>>,
     {
         let mut res = LibrarySymbolsStats::default();
-        for entry in iter {
-            let value = entry.value.unwrap();
+        // Libraries are only in the table once their symbols are looked up.
+        for value in iter.into_iter().filter_map(|entry| entry.value) {
             res.total += value.len();
             res.size += value.memory_size();
         }
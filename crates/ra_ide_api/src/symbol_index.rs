@@ -16,9 +16,8 @@
 //!
 //! `fst` does not support cheap updating of the index, but it supports unioning
 //! of state machines. So, to account for changing source code, we build an FST
-//! for each library (which is assumed to never change) and an FST for each Rust
-//! file in the current workspace, and run a query against the union of all
-//! those FSTs.
+//! for each library (which rarely changes) and an FST for each Rust file in
+//! the current workspace, and run a query against the union of all those FSTs.
 use std::{
     cmp::Ordering,
     hash::{Hash, Hasher},
@@ -49,14 +48,18 @@ use crate::{
 #[salsa::query_group(SymbolsDatabaseStorage)]
 pub(crate) trait SymbolsDatabase: hir::db::HirDatabase {
     fn file_symbols(&self, file_id: FileId) -> Arc<SymbolIndex>;
-    #[salsa::input]
     fn library_symbols(&self, id: SourceRootId) -> Arc<SymbolIndex>;
+    /// The index of a library which was built in the background, when the
+    /// library was added, see `LibraryData`. Reset to `None` when any of the
+    /// files of the library change.
+    #[salsa::input]
+    fn prepared_library_symbols(&self, id: SourceRootId) -> Option<Arc<SymbolIndex>>;
     /// The set of "local" (that is, from the current workspace) roots.
     /// Files in local roots are assumed to change frequently.
     #[salsa::input]
     fn local_roots(&self) -> Arc<Vec<SourceRootId>>;
     /// The set of roots for crates.io libraries.
-    /// Files in libraries are assumed to never change.
+    /// Files in libraries are assumed to change rarely.
     #[salsa::input]
     fn library_roots(&self) -> Arc<Vec<SourceRootId>>;
 }
@@ -77,6 +80,21 @@ fn file_symbols(db: &impl SymbolsDatabase, file_id: FileId) -> Arc<SymbolIndex>
     Arc::new(SymbolIndex::new(symbols))
 }
 
+fn library_symbols(db: &impl SymbolsDatabase, id: SourceRootId) -> Arc<SymbolIndex> {
+    if let Some(symbols) = db.prepared_library_symbols(id) {
+        return symbols;
+    }
+    let files = db
+        .source_root(id)
+        .files
+        .values()
+        .map(|&file_id| (file_id, db.file_text(file_id)))
+        .collect::<Vec<_>>();
+    db.check_canceled();
+    let files = files.par_iter().map(|(file_id, text)| (*file_id, SourceFile::parse(text)));
+    Arc::new(SymbolIndex::for_files(files))
+}
+
 pub(crate) fn world_symbols(db: &RootDatabase, query: Query) -> Vec<FileSymbol> {
     /// Need to wrap Snapshot to provide `Clone` impl for `map_with`
     struct Snap(salsa::Snapshot<RootDatabase>);
@@ -134,7 +152,7 @@ impl Hash for SymbolIndex {
 }
 
 impl SymbolIndex {
-    fn new(mut symbols: Vec<FileSymbol>) -> SymbolIndex {
+    pub(crate) fn new(mut symbols: Vec<FileSymbol>) -> SymbolIndex {
         fn cmp(s1: &FileSymbol, s2: &FileSymbol) -> Ordering {
             unicase::Ascii::new(s1.name.as_str()).cmp(&unicase::Ascii::new(s2.name.as_str()))
         }
@@ -149,6 +167,10 @@ impl SymbolIndex {
         self.symbols.len()
     }
 
+    pub(crate) fn symbols(&self) -> &[FileSymbol] {
+        &self.symbols
+    }
+
     pub(crate) fn memory_size(&self) -> usize {
         self.map.as_fst().size() + self.symbols.len() * mem::size_of::<FileSymbol>()
     }
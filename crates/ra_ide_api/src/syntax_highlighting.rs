@@ -1,4 +1,9 @@
-use ra_syntax::{ast, AstNode,};
+use hir::{ModuleDef, Resolution, source_binder};
+use ra_syntax::{
+    ast, AstNode, SourceFile, SyntaxNode, TextRange, TextUnit, Direction,
+    SyntaxKind::*,
+    algo::find_covering_node,
+};
 use ra_db::SourceDatabase;
 
 use crate::{
@@ -6,6 +11,73 @@ use crate::{
     db::RootDatabase,
 };
 
+/// What a highlighted token is, as far as name resolution can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    Comment,
+    String,
+    Number,
+    Keyword,
+    Attribute,
+    Macro,
+    Lifetime,
+    /// A `{}` placeholder in the format string of `format!` and friends.
+    FormatSpecifier,
+    Module,
+    Function,
+    Method,
+    Struct,
+    Enum,
+    EnumVariant,
+    Trait,
+    TypeAlias,
+    TypeParam,
+    Field,
+    Local,
+    Param,
+    Const,
+    Static,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HighlightModifiers {
+    /// The token is the name of the item or binding it defines.
+    pub definition: bool,
+    /// A `mut` binding or a `static mut`.
+    pub mutable: bool,
+    /// An `unsafe fn`, a `static mut` or a reference to them, which are only
+    /// allowed in unsafe code.
+    pub unsafe_: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightedToken {
+    pub range: TextRange,
+    pub kind: HighlightKind,
+    pub modifiers: HighlightModifiers,
+}
+
+impl HighlightedToken {
+    fn new(range: TextRange, kind: HighlightKind) -> HighlightedToken {
+        HighlightedToken { range, kind, modifiers: HighlightModifiers::default() }
+    }
+}
+
+/// Macros whose first string literal is a format string.
+const FORMAT_MACROS: &[&str] = &[
+    "format",
+    "format_args",
+    "print",
+    "println",
+    "eprint",
+    "eprintln",
+    "write",
+    "writeln",
+    "panic",
+    "unreachable",
+    "unimplemented",
+];
+
 pub(crate) fn highlight(db: &RootDatabase, file_id: FileId) -> Vec<HighlightedRange> {
     let source_file = db.parse(file_id);
     let mut res = ra_ide_api_light::highlight(source_file.syntax());
@@ -23,6 +95,266 @@ pub(crate) fn highlight(db: &RootDatabase, file_id: FileId) -> Vec<HighlightedRa
     res
 }
 
+/// Classifies the tokens of the file, or of the part of it which intersects
+/// `range`. Unlike `highlight`, names are classified by what they resolve to.
+///
+/// The tokens are sorted and don't overlap.
+pub(crate) fn highlight_tokens(
+    db: &RootDatabase,
+    file_id: FileId,
+    range: Option<TextRange>,
+) -> Vec<HighlightedToken> {
+    let source_file = db.parse(file_id);
+    let root = match range {
+        Some(range) => find_covering_node(source_file.syntax(), range),
+        None => source_file.syntax(),
+    };
+    let mut res = Vec::new();
+    for node in root.descendants() {
+        match ast::NameRef::cast(node) {
+            Some(name_ref) => res.extend(highlight_name_ref(db, file_id, &source_file, name_ref)),
+            None => highlight_syntax(node, &mut res),
+        }
+    }
+    // Names inside of macro calls are not resolved, but at least keywords and
+    // literals of the expansion are highlighted.
+    for macro_call in root.descendants().filter_map(ast::MacroCall::cast) {
+        if let Some((off, exp)) = hir::MacroDef::ast_expand(macro_call) {
+            let mut expanded = Vec::new();
+            for node in exp.syntax().descendants() {
+                highlight_syntax(node, &mut expanded);
+            }
+            res.extend(expanded.into_iter().filter_map(|mut token| {
+                token.range = exp.map_range_back(token.range)? + off;
+                Some(token)
+            }));
+        }
+    }
+
+    // Nodes are visited before their children, so the stable sort keeps the
+    // token of an attribute before the tokens inside of it.
+    res.sort_by_key(|token| token.range.start());
+    let mut end = TextUnit::from(0);
+    res.retain(|token| {
+        if token.range.start() < end {
+            return false;
+        }
+        end = token.range.end();
+        true
+    });
+    if let Some(range) = range {
+        res.retain(|token| token.range.intersection(&range).is_some());
+    }
+    res
+}
+
+/// Highlights the tokens which can be classified without name resolution.
+fn highlight_syntax(node: &SyntaxNode, acc: &mut Vec<HighlightedToken>) {
+    let kind = match node.kind() {
+        COMMENT => HighlightKind::Comment,
+        STRING | RAW_STRING => {
+            if is_format_string(node) {
+                highlight_format_string(node, acc);
+                return;
+            }
+            HighlightKind::String
+        }
+        BYTE_STRING | RAW_BYTE_STRING | CHAR | BYTE => HighlightKind::String,
+        INT_NUMBER | FLOAT_NUMBER => HighlightKind::Number,
+        ATTR => HighlightKind::Attribute,
+        LIFETIME => HighlightKind::Lifetime,
+        NAME => {
+            if let Some(token) = highlight_name(node) {
+                acc.push(token);
+            }
+            return;
+        }
+        k if k.is_keyword() => HighlightKind::Keyword,
+        _ => return,
+    };
+    acc.push(HighlightedToken::new(node.range(), kind))
+}
+
+fn highlight_name(name: &SyntaxNode) -> Option<HighlightedToken> {
+    let parent = name.parent()?;
+    let mut modifiers = HighlightModifiers { definition: true, ..Default::default() };
+    let kind = match parent.kind() {
+        FN_DEF => {
+            let fn_def = ast::FnDef::cast(parent)?;
+            modifiers.unsafe_ = fn_def.is_unsafe();
+            match fn_def.param_list().and_then(|it| it.self_param()) {
+                Some(_) => HighlightKind::Method,
+                None => HighlightKind::Function,
+            }
+        }
+        STRUCT_DEF => HighlightKind::Struct,
+        ENUM_DEF => HighlightKind::Enum,
+        ENUM_VARIANT => HighlightKind::EnumVariant,
+        TRAIT_DEF => HighlightKind::Trait,
+        TYPE_DEF => HighlightKind::TypeAlias,
+        TYPE_PARAM => HighlightKind::TypeParam,
+        MODULE => HighlightKind::Module,
+        CONST_DEF => HighlightKind::Const,
+        STATIC_DEF => {
+            modifiers.mutable = ast::StaticDef::cast(parent)?.is_mutable();
+            modifiers.unsafe_ = modifiers.mutable;
+            HighlightKind::Static
+        }
+        NAMED_FIELD_DEF => HighlightKind::Field,
+        MACRO_CALL => HighlightKind::Macro,
+        BIND_PAT => {
+            modifiers.mutable = ast::BindPat::cast(parent)?.is_mutable();
+            binding_kind(parent)
+        }
+        _ => return None,
+    };
+    Some(HighlightedToken { range: name.range(), kind, modifiers })
+}
+
+fn highlight_name_ref(
+    db: &RootDatabase,
+    file_id: FileId,
+    source_file: &SourceFile,
+    name_ref: &ast::NameRef,
+) -> Option<HighlightedToken> {
+    if let Some(range) = macro_name_range(name_ref) {
+        return Some(HighlightedToken::new(range, HighlightKind::Macro));
+    }
+    let mut token = HighlightedToken::new(name_ref.syntax().range(), HighlightKind::Field);
+    let parent = name_ref.syntax().parent()?;
+    if parent.kind() == NAMED_FIELD {
+        return Some(token);
+    }
+    if let Some(function) = source_binder::function_from_child_node(db, file_id, parent) {
+        if let Some(method_call) = ast::MethodCallExpr::cast(parent) {
+            let infer_result = function.infer(db);
+            let syntax_mapping = function.body_syntax_mapping(db);
+            let expr = ast::Expr::cast(method_call.syntax()).unwrap();
+            let method =
+                syntax_mapping.node_expr(expr).and_then(|it| infer_result.method_resolution(it))?;
+            token.kind = HighlightKind::Method;
+            token.modifiers.unsafe_ = method.signature(db).is_unsafe();
+            return Some(token);
+        }
+        if ast::FieldExpr::cast(parent).is_some() {
+            return Some(token);
+        }
+    }
+
+    let resolver = source_binder::resolver_for_node(db, file_id, name_ref.syntax());
+    let path = name_ref.syntax().ancestors().find_map(ast::Path::cast)?;
+    let path = hir::Path::from_ast(path)?;
+    let resolved = resolver.resolve_path(db, &path);
+    token.kind = match resolved.clone().take_types().or_else(|| resolved.take_values())? {
+        Resolution::Def(def) => match def {
+            ModuleDef::Module(_) => HighlightKind::Module,
+            ModuleDef::Function(function) => {
+                token.modifiers.unsafe_ = function.signature(db).is_unsafe();
+                HighlightKind::Function
+            }
+            ModuleDef::Struct(_) => HighlightKind::Struct,
+            ModuleDef::Enum(_) => HighlightKind::Enum,
+            ModuleDef::EnumVariant(_) => HighlightKind::EnumVariant,
+            ModuleDef::Const(_) => HighlightKind::Const,
+            ModuleDef::Static(it) => {
+                token.modifiers.mutable = it.source(db).1.is_mutable();
+                token.modifiers.unsafe_ = token.modifiers.mutable;
+                HighlightKind::Static
+            }
+            ModuleDef::Trait(_) => HighlightKind::Trait,
+            ModuleDef::Type(_) => HighlightKind::TypeAlias,
+        },
+        Resolution::LocalBinding(pat) => {
+            let body = resolver.body()?;
+            let ptr = body.syntax_mapping(db).pat_syntax(pat)?;
+            let pat = ptr.to_node(source_file);
+            token.modifiers.mutable = ast::BindPat::cast(pat).map_or(false, |it| it.is_mutable());
+            binding_kind(pat)
+        }
+        Resolution::GenericParam(_) => HighlightKind::TypeParam,
+        Resolution::SelfType(_) => HighlightKind::Keyword,
+    };
+    Some(token)
+}
+
+fn binding_kind(pat: &SyntaxNode) -> HighlightKind {
+    if pat.ancestors().any(|it| it.kind() == PARAM) {
+        HighlightKind::Param
+    } else {
+        HighlightKind::Local
+    }
+}
+
+/// The range of `foo!` if `name_ref` is the name of a macro call.
+fn macro_name_range(name_ref: &ast::NameRef) -> Option<TextRange> {
+    let path = name_ref.syntax().ancestors().find_map(ast::Path::cast)?;
+    let macro_call = path.syntax().parent().and_then(ast::MacroCall::cast)?;
+    if macro_call.path()?.syntax() != path.syntax() {
+        return None;
+    }
+    let mut end = name_ref.syntax().range().end();
+    for sibling in path.syntax().siblings(Direction::Next) {
+        match sibling.kind() {
+            EXCL | IDENT => end = sibling.range().end(),
+            _ => (),
+        }
+    }
+    Some(TextRange::from_to(name_ref.syntax().range().start(), end))
+}
+
+fn is_format_string(string: &SyntaxNode) -> bool {
+    let token_tree = match string.parent() {
+        Some(it) if it.kind() == TOKEN_TREE => it,
+        _ => return false,
+    };
+    let name = token_tree
+        .parent()
+        .and_then(ast::MacroCall::cast)
+        .and_then(|it| it.path())
+        .and_then(|it| it.segment())
+        .and_then(|it| it.name_ref());
+    match name {
+        Some(name) if FORMAT_MACROS.contains(&name.text().as_str()) => {}
+        _ => return false,
+    }
+    token_tree.children().find(|it| it.kind() == STRING || it.kind() == RAW_STRING) == Some(string)
+}
+
+/// Splits the format string into `String` tokens and `FormatSpecifier` tokens
+/// for the `{...}` placeholders. Escaped braces are part of the string.
+fn highlight_format_string(string: &SyntaxNode, acc: &mut Vec<HighlightedToken>) {
+    let start = string.range().start();
+    let text = string.text().to_string();
+    let mut push = |from: usize, to: usize, kind: HighlightKind| {
+        if from < to {
+            let range = TextRange::from_to(
+                start + TextUnit::from_usize(from),
+                start + TextUnit::from_usize(to),
+            );
+            acc.push(HighlightedToken::new(range, kind))
+        }
+    };
+    let bytes = text.as_bytes();
+    let mut string_start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'}' if bytes.get(i + 1) == Some(&bytes[i]) => i += 2,
+            b'{' => match text[i..].find('}') {
+                Some(len) => {
+                    push(string_start, i, HighlightKind::String);
+                    push(i, i + len + 1, HighlightKind::FormatSpecifier);
+                    i += len + 1;
+                    string_start = i;
+                }
+                None => break,
+            },
+            _ => i += 1,
+        }
+    }
+    push(string_start, text.len(), HighlightKind::String);
+}
+
 #[cfg(test)]
 mod tests {
     use crate::mock_analysis::single_file;
@@ -41,4 +373,86 @@ mod tests {
         let highlights = analysis.highlight(file_id).unwrap();
         assert_debug_snapshot_matches!("highlights_code_inside_macros", &highlights);
     }
+
+    fn highlight_tokens(code: &str) -> Vec<String> {
+        let (analysis, file_id) = single_file(code);
+        analysis
+            .highlight_tokens(file_id, None)
+            .unwrap()
+            .into_iter()
+            .map(|token| {
+                let mut res = format!("{} {:?}", &code[token.range], token.kind);
+                if token.modifiers.definition {
+                    res.push_str(" definition");
+                }
+                if token.modifiers.mutable {
+                    res.push_str(" mutable");
+                }
+                if token.modifiers.unsafe_ {
+                    res.push_str(" unsafe");
+                }
+                res
+            })
+            .collect()
+    }
+
+    #[test]
+    fn highlight_tokens_use_name_resolution() {
+        let tokens = highlight_tokens(
+            r#"
+trait Shape {}
+struct Square<'a> { side: &'a u32 }
+impl<'a> Shape for Square<'a> {}
+unsafe fn danger() {}
+static mut COUNTER: u32 = 0;
+#[inline]
+fn main() {
+    let mut total = 0;
+    let fixed = 1;
+    total += fixed;
+    unsafe { danger(); COUNTER += 1; }
+    println!("{}", total);
+}
+"#,
+        );
+        let expected = [
+            "Shape Trait definition",
+            "Square Struct definition",
+            "'a Lifetime",
+            "Shape Trait",
+            "Square Struct",
+            "danger Function definition unsafe",
+            "COUNTER Static definition mutable unsafe",
+            "#[inline] Attribute",
+            "total Local definition mutable",
+            "fixed Local definition",
+            "total Local mutable",
+            "fixed Local",
+            "danger Function unsafe",
+            "COUNTER Static mutable unsafe",
+            "println! Macro",
+        ];
+        for token in expected.iter() {
+            assert!(tokens.contains(&token.to_string()), "missing `{}` in {:#?}", token, tokens);
+        }
+    }
+
+    #[test]
+    fn highlight_tokens_split_format_strings() {
+        let tokens = highlight_tokens(r#"fn main() { println!("{} and {{}} {x:?}!", 1); }"#);
+        let string_tokens = tokens
+            .into_iter()
+            .filter(|it| it.ends_with(" String") || it.ends_with(" FormatSpecifier"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            string_tokens,
+            vec![
+                "\" String",
+                "{} FormatSpecifier",
+                " and {{}}  String",
+                "{x:?} FormatSpecifier",
+                "!\" String",
+            ]
+        );
+    }
 }
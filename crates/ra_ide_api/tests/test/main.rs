@@ -1,7 +1,7 @@
 use insta::assert_debug_snapshot_matches;
 use ra_ide_api::{
     mock_analysis::{single_file, single_file_with_position, MockAnalysis},
-    AnalysisChange, CrateGraph, Edition::Edition2018, Query, NavigationTarget,
+    AnalysisChange, CrateGraph, CrateKind, Edition::Edition2018, Query, NavigationTarget,
     ReferenceSearchResult,
 };
 use ra_syntax::SmolStr;
@@ -21,6 +21,54 @@ fn test_unresolved_module_diagnostic_no_diag_for_inline_mode() {
     assert!(diagnostics.is_empty());
 }
 
+#[test]
+fn test_macro_expansion_diagnostic() {
+    let (analysis, file_id) = single_file(
+        "
+        macro_rules! foo {
+            ($ i:ident) => { struct $ i; }
+        }
+        foo!(92);
+        println!(\"unresolved macros are not reported\");
+        ",
+    );
+    let diagnostics = analysis.diagnostics(file_id).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "no rules expected this token in macro call");
+}
+
+#[test]
+fn test_macro_expansion_diagnostic_points_at_token() {
+    let text = "
+        macro_rules! foo {
+            ($ i:ident) => { struct $ i; }
+        }
+        foo!(Bar Baz);
+        ";
+    let (analysis, file_id) = single_file(text);
+    let diagnostics = analysis.diagnostics(file_id).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    let range = diagnostics[0].range;
+    assert_eq!(&text[range.start().to_usize()..range.end().to_usize()], "Baz");
+}
+
+#[test]
+fn test_macro_expansion_diagnostic_in_inline_module() {
+    let (analysis, file_id) = single_file(
+        "
+        mod inner {
+            macro_rules! foo {
+                ($ i:ident) => { struct $ i; }
+            }
+            foo!(92);
+        }
+        ",
+    );
+    let diagnostics = analysis.diagnostics(file_id).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "no rules expected this token in macro call");
+}
+
 #[test]
 fn test_resolve_crate_root() {
     let mock = MockAnalysis::with_files(
@@ -45,6 +93,39 @@ fn test_resolve_crate_root() {
     assert_eq!(host.analysis().crate_for(mod_file).unwrap(), vec![crate_id]);
 }
 
+#[test]
+fn test_resolve_crate_root_with_test_harness() {
+    let mock = MockAnalysis::with_files(
+        "
+        //- /lib.rs
+        mod foo;
+        mod bar;
+        //- /foo.rs
+        #[test]
+        fn test_foo() {}
+    ",
+    );
+    let root_file = mock.id_of("/lib.rs");
+    let mod_file = mock.id_of("/foo.rs");
+    let mut host = mock.analysis_host();
+
+    let mut crate_graph = CrateGraph::default();
+    let harness = crate_graph.add_crate_root(root_file, Edition2018);
+    crate_graph.set_kind(harness, CrateKind::Test);
+    let lib = crate_graph.add_crate_root(root_file, Edition2018);
+    let mut change = AnalysisChange::new();
+    change.set_crate_graph(crate_graph);
+    host.apply_change(change);
+
+    let analysis = host.analysis();
+    assert_eq!(analysis.crate_for(mod_file).unwrap(), vec![lib, harness]);
+    let runnables = analysis.runnables(mod_file).unwrap();
+    assert_eq!(runnables.len(), 1);
+    assert_eq!(runnables[0].krate, Some(harness));
+    // `mod bar;` is unresolved in both crates, but is reported once.
+    assert_eq!(analysis.diagnostics(root_file).unwrap().len(), 1);
+}
+
 fn get_all_refs(text: &str) -> ReferenceSearchResult {
     let (analysis, position) = single_file_with_position(text);
     analysis.find_all_refs(position).unwrap().unwrap()
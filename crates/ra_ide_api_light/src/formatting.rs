@@ -0,0 +1,182 @@
+use ra_syntax::{
+    SourceFile, SyntaxNode, TextRange, AstNode,
+    SyntaxKind::*,
+    algo::find_covering_node,
+};
+use ra_text_edit::TextEdit;
+
+/// Formats the whole file, or only the items and statements which intersect
+/// `range`, with the built-in formatter, which fixes indentation and spacing.
+/// `indent` is a single level of indentation.
+pub fn format(file: &SourceFile, range: Option<TextRange>, indent: &str) -> TextEdit {
+    let range = match range {
+        Some(range) => selected_items(file.syntax(), range),
+        None => file.syntax().range(),
+    };
+    ra_fmt::format_range(file.syntax(), range, indent)
+}
+
+/// The range of the items or statements intersecting `range`, which includes
+/// the whitespace before the first of them to fix its indentation.
+fn selected_items(root: &SyntaxNode, range: TextRange) -> TextRange {
+    let covering = find_covering_node(root, range);
+    let list = covering
+        .ancestors()
+        .find(|it| match it.kind() {
+            SOURCE_FILE | ITEM_LIST | BLOCK => true,
+            _ => false,
+        })
+        .unwrap_or(root);
+    let mut items = list.children().filter(|it| match it.kind() {
+        WHITESPACE | COMMENT | L_CURLY | R_CURLY => false,
+        _ => it.range().intersection(&range).is_some(),
+    });
+    let first = match items.next() {
+        Some(it) => it,
+        None => return TextRange::offset_len(range.start(), 0.into()),
+    };
+    let last = items.last().unwrap_or(first);
+    let start = match first.prev_sibling() {
+        Some(ws) if ws.kind() == WHITESPACE => ws.range().start(),
+        _ => first.range().start(),
+    };
+    TextRange::from_to(start, last.range().end())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{assert_eq_text, extract_range};
+
+    use super::*;
+
+    fn check_format(before: &str, after: &str) {
+        check_format_with_indent(before, after, ra_fmt::DEFAULT_INDENT);
+    }
+
+    fn check_format_with_indent(before: &str, after: &str, indent: &str) {
+        let file = SourceFile::parse(before);
+        let actual = format(&file, None, indent).apply(before);
+        assert_eq_text!(after, &actual);
+    }
+
+    fn check_format_range(before: &str, after: &str) {
+        let (range, before) = extract_range(before);
+        let file = SourceFile::parse(&before);
+        let actual = format(&file, Some(range), ra_fmt::DEFAULT_INDENT).apply(&before);
+        assert_eq_text!(after, &actual);
+    }
+
+    #[test]
+    fn formats_indentation() {
+        check_format(
+            r"
+fn main() {
+let x = foo(bar(
+1,
+2,
+));
+      if x {
+  x.baz()
+.quux();
+        }
+
+
+    // comment
+}
+",
+            r"
+fn main() {
+    let x = foo(bar(
+        1,
+        2,
+    ));
+    if x {
+        x.baz()
+            .quux();
+    }
+
+    // comment
+}
+",
+        );
+    }
+
+    #[test]
+    fn formats_indentation_with_tabs() {
+        check_format_with_indent(
+            "
+fn main() {
+    if x {
+  foo(
+1,
+        );
+    }
+}
+",
+            "
+fn main() {
+\tif x {
+\t\tfoo(
+\t\t\t1,
+\t\t);
+\t}
+}
+",
+            "\t",
+        );
+    }
+
+    #[test]
+    fn formats_spacing() {
+        check_format(
+            r"
+fn foo( a:u32,b :&mut  Vec<u32> )->u32{
+    let x=- a+b [0] ;
+    x  .max( 1 )
+}
+",
+            r"
+fn foo(a: u32, b: &mut Vec<u32>) -> u32 {
+    let x = -a + b[0];
+    x.max(1)
+}
+",
+        );
+    }
+
+    #[test]
+    fn keeps_macro_calls_and_comments() {
+        check_format(
+            r"
+fn foo() {
+    println!( 'a' , 92 ); // comment
+}
+",
+            r"
+fn foo() {
+    println!( 'a' , 92 ); // comment
+}
+",
+        );
+    }
+
+    #[test]
+    fn formats_only_selected_statements() {
+        check_format_range(
+            r"
+fn foo() {
+  let x=1;
+      <|>let y=2;<|>
+  let z=3;
+}
+",
+            r"
+fn foo() {
+  let x=1;
+    let y = 2;
+  let z=3;
+}
+",
+        );
+    }
+}
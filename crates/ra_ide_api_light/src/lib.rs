@@ -12,6 +12,7 @@ mod structure;
 mod test_utils;
 mod join_lines;
 mod typing;
+mod formatting;
 mod diagnostics;
 
 #[derive(Debug)]
@@ -29,7 +30,8 @@ pub use self::{
     structure::{file_structure, StructureNode},
     diagnostics::diagnostics,
     join_lines::join_lines,
-    typing::{on_enter, on_dot_typed, on_eq_typed},
+    typing::{on_enter, on_dot_typed, on_eq_typed, on_rcurly_typed},
+    formatting::format,
 
 };
 use ra_text_edit::TextEditBuilder;
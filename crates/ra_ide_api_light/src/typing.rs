@@ -116,6 +116,33 @@ pub fn on_dot_typed(file: &SourceFile, dot_offset: TextUnit) -> Option<LocalEdit
     Some(res)
 }
 
+/// Reindents the line of the typed `}`, so that it is aligned with the line
+/// which opens the block or the item list. The lines before it are left as
+/// they are. Each level of indentation is one `indent`.
+pub fn on_rcurly_typed(
+    file: &SourceFile,
+    rcurly_offset: TextUnit,
+    indent: &str,
+) -> Option<LocalEdit> {
+    assert_eq!(file.syntax().text().char_at(rcurly_offset), Some('}'));
+    let rcurly = find_leaf_at_offset(file.syntax(), rcurly_offset).right_biased()?;
+    if rcurly.kind() != R_CURLY {
+        return None;
+    }
+    let whitespace = find_leaf_at_offset(file.syntax(), rcurly_offset)
+        .left_biased()
+        .and_then(ast::Whitespace::cast)?;
+    if !whitespace.text().contains('\n') {
+        return None;
+    }
+    let line = TextRange::from_to(whitespace.syntax().range().start(), rcurly.range().end());
+    let edit = ra_fmt::format_range(file.syntax(), line, indent);
+    if edit.as_atoms().is_empty() {
+        return None;
+    }
+    Some(LocalEdit { label: "reindent closing brace".to_string(), edit, cursor_position: None })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test_utils::{add_cursor, assert_eq_text, extract_offset};
@@ -404,4 +431,86 @@ impl S {
         );
         do_check_noop(r"<|>//! docz");
     }
+
+    #[test]
+    fn reindents_rcurly_line() {
+        fn type_rcurly(indent: &str, before: &str, after: &str) {
+            let (offset, before) = extract_offset(before);
+            let mut edit = TextEditBuilder::default();
+            edit.insert(offset, "}".to_string());
+            let before = edit.finish().apply(&before);
+            let file = SourceFile::parse(&before);
+            if let Some(result) = on_rcurly_typed(&file, offset, indent) {
+                let actual = result.edit.apply(&before);
+                assert_eq_text!(after, &actual);
+            } else {
+                assert_eq_text!(&before, after)
+            };
+        }
+
+        type_rcurly(
+            "    ",
+            r"
+fn foo() {
+    if true {
+    bar(1,2);
+      <|>
+}
+",
+            r"
+fn foo() {
+    if true {
+    bar(1,2);
+    }
+}
+",
+        );
+        type_rcurly(
+            "    ",
+            r"
+fn foo() {
+    if true {
+        bar();
+    <|>
+}
+",
+            r"
+fn foo() {
+    if true {
+        bar();
+    }
+}
+",
+        );
+        type_rcurly(
+            "\t",
+            "
+fn foo() {
+\tif true {
+\t\tbar();
+<|>
+}
+",
+            "
+fn foo() {
+\tif true {
+\t\tbar();
+\t}
+}
+",
+        );
+        type_rcurly(
+            "    ",
+            r"
+fn foo() {
+    if true { bar(); <|>
+}
+",
+            r"
+fn foo() {
+    if true { bar(); }
+}
+",
+        );
+    }
 }
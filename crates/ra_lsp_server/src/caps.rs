@@ -2,14 +2,33 @@ use lsp_types::{
     CodeActionProviderCapability, CodeLensOptions, CompletionOptions, DocumentOnTypeFormattingOptions,
     ExecuteCommandOptions, FoldingRangeProviderCapability, RenameOptions, RenameProviderCapability,
     ServerCapabilities, SignatureHelpOptions, TextDocumentSyncCapability, TextDocumentSyncKind,
-    TextDocumentSyncOptions, ImplementationProviderCapability,
+    TextDocumentSyncOptions, ImplementationProviderCapability, WorkspaceCapability,
+    WorkspaceFolderCapability, WorkspaceFolderCapabilityChangeNotifications,
 };
+use serde::Serialize;
+use serde_json::Value;
 
-pub fn server_capabilities() -> ServerCapabilities {
-    ServerCapabilities {
+use crate::{
+    req::{SemanticTokensFullOptions, SemanticTokensOptions},
+    semantic_tokens,
+};
+
+/// `ServerCapabilities` extended with the capabilities `lsp_types` doesn't
+/// know about yet.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedServerCapabilities {
+    #[serde(flatten)]
+    pub base: ServerCapabilities,
+    pub semantic_tokens_provider: SemanticTokensOptions,
+    pub selection_range_provider: bool,
+}
+
+pub fn server_capabilities() -> ExtendedServerCapabilities {
+    let base = ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
             open_close: Some(true),
-            change: Some(TextDocumentSyncKind::Full),
+            change: Some(TextDocumentSyncKind::Incremental),
             will_save: None,
             will_save_wait_until: None,
             save: None,
@@ -32,10 +51,10 @@ pub fn server_capabilities() -> ServerCapabilities {
         code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(true) }),
         document_formatting_provider: Some(true),
-        document_range_formatting_provider: None,
+        document_range_formatting_provider: Some(true),
         document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
             first_trigger_character: "=".to_string(),
-            more_trigger_character: Some(vec![".".to_string()]),
+            more_trigger_character: Some(vec![".".to_string(), "}".to_string()]),
         }),
         folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
         rename_provider: Some(RenameProviderCapability::Options(RenameOptions {
@@ -45,6 +64,46 @@ pub fn server_capabilities() -> ServerCapabilities {
         execute_command_provider: Some(ExecuteCommandOptions {
             commands: vec!["apply_code_action".to_string()],
         }),
-        workspace: None,
+        workspace: Some(WorkspaceCapability {
+            workspace_folders: Some(WorkspaceFolderCapability {
+                supported: Some(true),
+                change_notifications: Some(WorkspaceFolderCapabilityChangeNotifications::Bool(
+                    true,
+                )),
+            }),
+        }),
+    };
+    ExtendedServerCapabilities {
+        base,
+        semantic_tokens_provider: SemanticTokensOptions {
+            legend: semantic_tokens::legend(),
+            range: true,
+            full: SemanticTokensFullOptions { delta: true },
+        },
+        selection_range_provider: true,
+    }
+}
+
+/// The capabilities of the client the server cares about, including the ones
+/// `lsp_types` doesn't know about yet.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClientCaps {
+    /// `window.workDoneProgress`: the client shows `$/progress` notifications.
+    pub work_done_progress: bool,
+    /// `workspace.didChangeWatchedFiles.dynamicRegistration`: the client can
+    /// watch the files the server asks for.
+    pub watched_files_dynamic_registration: bool,
+}
+
+impl ClientCaps {
+    /// Reads the `capabilities` of the `initialize` request.
+    pub fn from_json(caps: &Value) -> ClientCaps {
+        let flag = |pointer: &str| caps.pointer(pointer).and_then(Value::as_bool) == Some(true);
+        ClientCaps {
+            work_done_progress: flag("/window/workDoneProgress"),
+            watched_files_dynamic_registration: flag(
+                "/workspace/didChangeWatchedFiles/dynamicRegistration",
+            ),
+        }
     }
 }
@@ -1,19 +1,18 @@
 use crate::{
-    project_model::TargetKind,
+    project_model::{ProjectWorkspace, TargetKind},
     server_world::ServerWorld,
     Result
 };
 
-use ra_ide_api::{FileId, RunnableKind};
+use ra_ide_api::{CrateId, FileId, Runnable, RunnableKind};
 
-pub(crate) fn runnable_args(
-    world: &ServerWorld,
-    file_id: FileId,
-    kind: &RunnableKind,
-) -> Result<Vec<String>> {
-    let spec = CargoTargetSpec::for_file(world, file_id)?;
+pub(crate) fn runnable_args(world: &ServerWorld, runnable: &Runnable) -> Result<Vec<String>> {
+    let spec = match runnable.krate {
+        Some(crate_id) => CargoTargetSpec::for_crate(world, crate_id)?,
+        None => None,
+    };
     let mut res = Vec::new();
-    match kind {
+    match &runnable.kind {
         RunnableKind::Test { name } => {
             res.push("test".to_string());
             if let Some(spec) = spec {
@@ -59,20 +58,26 @@ pub struct CargoTargetSpec {
 
 impl CargoTargetSpec {
     pub fn for_file(world: &ServerWorld, file_id: FileId) -> Result<Option<CargoTargetSpec>> {
-        let &crate_id = match world.analysis().crate_for(file_id)?.first() {
-            Some(crate_id) => crate_id,
-            None => return Ok(None),
-        };
+        match world.analysis().crate_for(file_id)?.first() {
+            Some(&crate_id) => CargoTargetSpec::for_crate(world, crate_id),
+            None => Ok(None),
+        }
+    }
+
+    pub fn for_crate(world: &ServerWorld, crate_id: CrateId) -> Result<Option<CargoTargetSpec>> {
         let file_id = world.analysis().crate_root(crate_id)?;
         let path = world.vfs.read().file2path(ra_vfs::VfsFile(file_id.0.into()));
-        let res = world.workspaces.iter().find_map(|ws| {
-            let tgt = ws.cargo.target_by_root(&path)?;
-            let res = CargoTargetSpec {
-                package: tgt.package(&ws.cargo).name(&ws.cargo).to_string(),
-                target: tgt.name(&ws.cargo).to_string(),
-                target_kind: tgt.kind(&ws.cargo),
-            };
-            Some(res)
+        let res = world.workspaces.iter().find_map(|ws| match ws {
+            ProjectWorkspace::Cargo { cargo, .. } => {
+                let tgt = cargo.target_by_root(&path)?;
+                let res = CargoTargetSpec {
+                    package: tgt.package(cargo).name(cargo).to_string(),
+                    target: tgt.name(cargo).to_string(),
+                    target_kind: tgt.kind(cargo),
+                };
+                Some(res)
+            }
+            ProjectWorkspace::Json { .. } => None,
         });
         Ok(res)
     }
@@ -100,7 +105,7 @@ impl CargoTargetSpec {
             TargetKind::Lib => {
                 buf.push("--lib".to_string());
             }
-            TargetKind::Other => (),
+            TargetKind::BuildScript | TargetKind::Other => (),
         }
     }
 }
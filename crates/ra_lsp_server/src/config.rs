@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::project_model::{CargoConfig, CargoFeatures};
+
+/// Settings of the server, which are read from the `initializationOptions`
+/// and can be changed at runtime through `workspace/didChangeConfiguration`.
+///
+/// The names of the fields match the settings of the VS Code extension, in the
+/// `rust-analyzer` section, so that the section can be used as is.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ServerConfig {
+    /// Path to the `ra_proc_macro_srv` binary. Procedural macros are not
+    /// expanded unless this is set.
+    #[serde(alias = "procMacroSrvPath")]
+    pub proc_macro_srv: Option<PathBuf>,
+    /// Features of the workspace members to enable when loading the project.
+    pub cargo_features: CargoFeatures,
+    /// Whether to run `cargo check` to learn the outputs of build scripts, like
+    /// the code they generate into `OUT_DIR`.
+    pub load_out_dirs_from_check: bool,
+    /// Whether to keep the indices of the libraries on disk between sessions.
+    pub library_cache: bool,
+    /// Path to the sources of the standard library, takes precedence over the
+    /// `RUST_SRC_PATH` environment variable.
+    pub rust_src_path: Option<PathBuf>,
+    /// Whether to link only `core` and `alloc`, for `#![no_std]` projects.
+    pub no_std: bool,
+    /// How many syntax trees, macro expansions and function bodies of each
+    /// kind to keep in memory.
+    pub lru_capacity: Option<usize>,
+    /// Directories, relative to the workspace folders, whose files are not
+    /// analyzed.
+    pub exclude_dirs: Vec<PathBuf>,
+    /// Whether to publish diagnostics for the open files.
+    pub enable_diagnostics: bool,
+    /// The `rustfmt` binary to use for formatting, and extra arguments to it.
+    pub rustfmt_path: Option<PathBuf>,
+    pub rustfmt_args: Vec<String>,
+    /// Whether completions may insert snippets, like function calls with
+    /// placeholders for the arguments or postfix templates. Otherwise, the
+    /// placeholders are stripped, and completions insert plain text.
+    pub completion_snippets: bool,
+    /// Whether to ask the client to watch the files of the workspace and to
+    /// apply the changes it reports, which works better than the server's own
+    /// watcher for huge trees and network mounts. The server's watcher is not
+    /// started then. Ignored if the client can't register file watchers.
+    pub use_client_watching: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            proc_macro_srv: None,
+            cargo_features: CargoFeatures::default(),
+            load_out_dirs_from_check: false,
+            library_cache: true,
+            rust_src_path: None,
+            no_std: false,
+            lru_capacity: None,
+            exclude_dirs: Vec::new(),
+            enable_diagnostics: true,
+            rustfmt_path: None,
+            rustfmt_args: Vec::new(),
+            completion_snippets: true,
+            use_client_watching: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn cargo_config(&self) -> CargoConfig {
+        let mut config = CargoConfig::default();
+        config.load_proc_macros = self.proc_macro_srv.is_some();
+        config.load_out_dirs_from_check = self.load_out_dirs_from_check;
+        config.features = self.cargo_features.clone();
+        config.sysroot.src = self.rust_src_path.clone();
+        config.sysroot.no_std = self.no_std;
+        config
+    }
+
+    pub fn is_excluded(&self, folders: &[PathBuf], path: &Path) -> bool {
+        folders
+            .iter()
+            .flat_map(|folder| self.exclude_dirs.iter().map(move |dir| folder.join(dir)))
+            .any(|dir| path.starts_with(dir))
+    }
+
+    /// Settings which are only read at startup.
+    pub fn requires_restart(&self, new: &ServerConfig) -> bool {
+        self.proc_macro_srv != new.proc_macro_srv
+            || self.library_cache != new.library_cache
+            || self.exclude_dirs != new.exclude_dirs
+            || self.use_client_watching != new.use_client_watching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn missing_settings_use_defaults() {
+        let config = ServerConfig::deserialize(json!({
+            "procMacroSrvPath": "/bin/srv",
+            "cargoFeatures": { "allFeatures": true },
+            "highlightingOn": false,
+        }))
+        .unwrap();
+        assert_eq!(config.proc_macro_srv, Some(PathBuf::from("/bin/srv")));
+        assert!(config.cargo_features.all_features);
+        assert!(config.cargo_config().load_proc_macros);
+        assert!(config.library_cache && config.enable_diagnostics && config.completion_snippets);
+    }
+}
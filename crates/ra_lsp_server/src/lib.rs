@@ -1,11 +1,19 @@
 mod caps;
 mod cargo_target_spec;
+mod config;
 mod conv;
+mod library_cache;
 mod main_loop;
 mod markdown;
 mod project_model;
 pub mod req;
+mod semantic_tokens;
 mod server_world;
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
-pub use crate::{caps::server_capabilities, main_loop::main_loop, main_loop::LspError};
+pub use crate::{
+    caps::server_capabilities, caps::ClientCaps, main_loop::main_loop, main_loop::LspError,
+    library_cache::LibraryCache,
+    config::ServerConfig,
+    project_model::{CargoConfig, CargoFeatures},
+};
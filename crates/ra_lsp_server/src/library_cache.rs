@@ -0,0 +1,127 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use filetime::FileTime;
+use ra_ide_api::{FileId, LibraryData, SourceRootId};
+use relative_path::RelativePathBuf;
+
+/// Keeps the indices of the libraries on disk, so that the sysroot and the
+/// crates.io dependencies don't have to be indexed from scratch on each
+/// startup. Entries are keyed by the hash of the library sources, so a new
+/// toolchain or an updated dependency simply results in a cache miss.
+///
+/// The modification time of an entry is bumped whenever it is loaded, and
+/// `evict_unused` removes the entries which were not used for a while, so
+/// that the old versions of the libraries don't pile up.
+#[derive(Debug)]
+pub struct LibraryCache {
+    dir: PathBuf,
+}
+
+impl LibraryCache {
+    pub fn new(dir: PathBuf) -> LibraryCache {
+        LibraryCache { dir }
+    }
+
+    /// The per-user cache directory, like `~/.cache/rust-analyzer/libraries`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(base.join("rust-analyzer").join("libraries"))
+    }
+
+    pub fn load_or_prepare(
+        &self,
+        root: SourceRootId,
+        files: Vec<(FileId, RelativePathBuf, Arc<String>)>,
+    ) -> LibraryData {
+        let path = self.dir.join(LibraryData::cache_key(&files));
+        if let Ok(bytes) = fs::read(&path) {
+            match LibraryData::from_cache(root, files.clone(), &bytes) {
+                Some(data) => {
+                    log::info!("loaded {:?} from {}", root, path.display());
+                    let now = FileTime::from_system_time(SystemTime::now());
+                    if let Err(e) = filetime::set_file_mtime(&path, now) {
+                        log::warn!("failed to touch {}: {}", path.display(), e);
+                    }
+                    return data;
+                }
+                None => {
+                    log::warn!("invalid library cache entry: {}", path.display());
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        let data = LibraryData::prepare(root, files);
+        if let Err(e) = self.store(&path, &data.to_cache()) {
+            log::warn!("failed to write {}: {}", path.display(), e);
+        }
+        data
+    }
+
+    /// Removes the entries, and the temporary files left by crashed servers,
+    /// which were neither written nor loaded during the last `max_age`.
+    pub fn evict_unused(&self, max_age: Duration) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(it) => it,
+            Err(_) => return,
+        };
+        let now = SystemTime::now();
+        for entry in entries.filter_map(Result::ok) {
+            let modified = match entry.metadata().and_then(|it| it.modified()) {
+                Ok(it) => it,
+                Err(_) => continue,
+            };
+            if now.duration_since(modified).map_or(false, |age| age > max_age) {
+                log::info!("evicting unused library cache entry {}", entry.path().display());
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Writes the entry to a temporary file first, so that a crash or a
+    /// concurrent server never leaves a half-written entry behind.
+    fn store(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension(format!("tmp{}", process::id()));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_only_unused_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LibraryCache::new(dir.path().to_path_buf());
+        let root = SourceRootId(0);
+        let text = Arc::new("fn foo() {}".to_string());
+        let files = vec![(FileId(0), RelativePathBuf::from("lib.rs"), text)];
+        cache.load_or_prepare(root, files.clone());
+        let entry = dir.path().join(LibraryData::cache_key(&files));
+        assert!(entry.exists());
+
+        let stale = dir.path().join("stale");
+        fs::write(&stale, b"").unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(60 * 60 * 24 * 60);
+        for path in [&entry, &stale].iter() {
+            filetime::set_file_mtime(path, FileTime::from_system_time(long_ago)).unwrap();
+        }
+        // Loading the entry marks it as used again.
+        cache.load_or_prepare(root, files);
+
+        cache.evict_unused(Duration::from_secs(60 * 60 * 24 * 30));
+        assert!(entry.exists());
+        assert!(!stale.exists());
+    }
+}
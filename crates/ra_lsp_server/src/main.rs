@@ -2,7 +2,9 @@ use serde::Deserialize;
 use flexi_logger::{Duplicate, Logger};
 use gen_lsp_server::{run_server, stdio_transport};
 
-use ra_lsp_server::Result;
+use lsp_types::InitializeParams;
+use ra_lsp_server::{ClientCaps, Result, ServerConfig};
+use serde_json::Value;
 
 fn main() -> Result<()> {
     ::std::env::set_var("RUST_BACKTRACE", "short");
@@ -31,19 +33,38 @@ struct InitializationOptions {
     // This is different to the highlightingOn setting, which is whether the user
     // wants our custom highlighting to be used.
     publish_decorations: Option<bool>,
+    #[serde(flatten)]
+    config: ServerConfig,
 }
 
 fn main_inner() -> Result<()> {
     let (receiver, sender, threads) = stdio_transport();
     let cwd = ::std::env::current_dir()?;
-    run_server(ra_lsp_server::server_capabilities(), receiver, sender, |params, r, s| {
-        let root = params.root_uri.and_then(|it| it.to_file_path().ok()).unwrap_or(cwd);
-        let supports_decorations = params
-            .initialization_options
-            .and_then(|v| InitializationOptions::deserialize(v).ok())
-            .and_then(|it| it.publish_decorations)
-            == Some(true);
-        ra_lsp_server::main_loop(false, root, supports_decorations, r, s)
+    run_server(ra_lsp_server::server_capabilities(), receiver, sender, |params: Value, r, s| {
+        // `InitializeParams` drop the client capabilities `lsp_types` doesn't
+        // know, so they are read from the raw params first.
+        let client_caps = ClientCaps::from_json(&params["capabilities"]);
+        let params = InitializeParams::deserialize(params)?;
+        // Clients which support several workspace folders send all of them,
+        // older ones only send the root.
+        let mut folders = params
+            .workspace_folders
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|it| it.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        if folders.is_empty() {
+            folders.push(params.root_uri.and_then(|it| it.to_file_path().ok()).unwrap_or(cwd));
+        }
+        let opts = params.initialization_options.and_then(|v| {
+            InitializationOptions::deserialize(v)
+                .map_err(|e| log::error!("invalid initialization options: {}", e))
+                .ok()
+        });
+        let supports_decorations =
+            opts.as_ref().and_then(|it| it.publish_decorations) == Some(true);
+        let config = opts.map(|it| it.config).unwrap_or_default();
+        ra_lsp_server::main_loop(false, folders, supports_decorations, client_caps, config, r, s)
     })?;
     log::info!("shutting down IO...");
     threads.join()?;
@@ -1,24 +1,31 @@
 mod handlers;
+mod progress;
 mod subscriptions;
 
-use std::{fmt, path::PathBuf, sync::Arc};
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
 
 use crossbeam_channel::{select, unbounded, Receiver, RecvError, Sender};
 use failure::{bail, format_err};
 use failure_derive::Fail;
 use gen_lsp_server::{
-    handle_shutdown, ErrorCode, RawMessage, RawNotification, RawRequest, RawResponse,
+    handle_shutdown, ErrorCode, OutgoingRequests, RawMessage, RawNotification, RawRequest,
+    RawResponse, RequestId,
 };
-use lsp_types::NumberOrString;
-use ra_ide_api::{Canceled, FileId, LibraryData};
+use lsp_types::{MessageType, ShowMessageParams};
+use ra_ide_api::{Canceled, CancellationHandle, FileId, LibraryData};
 use ra_vfs::VfsTask;
-use rustc_hash::FxHashSet;
-use serde::{de::DeserializeOwned, Serialize};
+use rustc_hash::FxHashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use thread_worker::Worker;
 use threadpool::ThreadPool;
 
 use crate::{
-    main_loop::subscriptions::Subscriptions,
-    project_model::workspace_loader,
+    caps::ClientCaps,
+    config::ServerConfig,
+    library_cache::LibraryCache,
+    main_loop::{progress::LoadingProgress, subscriptions::Subscriptions},
+    project_model::{workspace_loader, CargoConfig, ProcMacroClient, ProjectWorkspace},
     req,
     server_world::{ServerWorld, ServerWorldState},
     Result,
@@ -44,35 +51,68 @@ enum Task {
 }
 
 const THREADPOOL_SIZE: usize = 8;
+/// Library cache entries which were not used for this long are removed.
+const LIBRARY_CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Requests which are being handled on the threadpool. Canceling the handle
+/// stops the analysis of a request the client is no longer interested in.
+type PendingRequests = FxHashMap<RequestId, CancellationHandle>;
 
 pub fn main_loop(
     internal_mode: bool,
-    ws_root: PathBuf,
+    folders: Vec<PathBuf>,
     supports_decorations: bool,
+    client_caps: ClientCaps,
+    mut config: ServerConfig,
     msg_receiver: &Receiver<RawMessage>,
     msg_sender: &Sender<RawMessage>,
 ) -> Result<()> {
     let pool = ThreadPool::new(THREADPOOL_SIZE);
     let (task_sender, task_receiver) = unbounded::<Task>();
 
-    // FIXME: support dynamic workspace loading.
+    let proc_macro_client = match &config.proc_macro_srv {
+        None => ProcMacroClient::dummy(),
+        Some(path) => ProcMacroClient::extern_process(path).unwrap_or_else(|e| {
+            log::error!("failed to run proc-macro server {}: {}", path.display(), e);
+            ProcMacroClient::dummy()
+        }),
+    };
+
+    // The worker is kept around to reload the workspaces when manifests change.
+    let ws_worker = workspace_loader();
+    let mut client_requests = OutgoingRequests::new();
+    let mut progress = LoadingProgress::new(client_caps.work_done_progress);
+    let stage = Some(("loading cargo metadata".to_string(), None));
+    progress.update(stage, &mut client_requests, msg_sender);
     let workspaces = {
-        let ws_worker = workspace_loader();
-        ws_worker.sender().send(ws_root.clone()).unwrap();
-        match ws_worker.receiver().recv().unwrap() {
-            Ok(ws) => vec![ws],
-            Err(e) => {
-                log::error!("loading workspace failed: {}", e);
-                Vec::new()
-            }
+        for folder in folders.iter() {
+            ws_worker.sender().send((folder.clone(), config.cargo_config())).unwrap();
         }
+        folders.iter().map(|_| ws_worker.receiver().recv().unwrap()).collect()
     };
 
-    let mut state = ServerWorldState::new(ws_root.clone(), workspaces);
+    let library_cache = if config.library_cache {
+        LibraryCache::default_dir().map(LibraryCache::new)
+    } else {
+        None
+    };
+    if let Some(library_cache) = &library_cache {
+        library_cache.evict_unused(LIBRARY_CACHE_MAX_AGE);
+    }
+
+    if config.use_client_watching && !client_caps.watched_files_dynamic_registration {
+        log::warn!("the client can't watch files for the server, using the server's watcher");
+        config.use_client_watching = false;
+    }
+    let mut state = ServerWorldState::new(folders, workspaces, &proc_macro_client, config);
+
+    if state.config.use_client_watching {
+        register_file_watchers(&mut client_requests, msg_sender);
+    }
 
     log::info!("server initialized, serving requests");
 
-    let mut pending_requests = FxHashSet::default();
+    let mut pending_requests = PendingRequests::default();
     let mut subs = Subscriptions::new();
     let main_res = main_loop_inner(
         internal_mode,
@@ -82,9 +122,14 @@ pub fn main_loop(
         msg_receiver,
         task_sender,
         task_receiver.clone(),
+        &ws_worker,
+        &proc_macro_client,
+        library_cache.map(Arc::new),
         &mut state,
         &mut pending_requests,
         &mut subs,
+        &mut client_requests,
+        &mut progress,
     );
 
     log::info!("waiting for tasks to finish...");
@@ -105,6 +150,7 @@ enum Event {
     Task(Task),
     Vfs(VfsTask),
     Lib(LibraryData),
+    Workspace((PathBuf, Result<ProjectWorkspace>)),
 }
 
 impl fmt::Debug for Event {
@@ -138,6 +184,7 @@ impl fmt::Debug for Event {
             Event::Task(it) => fmt::Debug::fmt(it, f),
             Event::Vfs(it) => fmt::Debug::fmt(it, f),
             Event::Lib(it) => fmt::Debug::fmt(it, f),
+            Event::Workspace(_) => f.write_str("Workspace"),
         }
     }
 }
@@ -150,15 +197,25 @@ fn main_loop_inner(
     msg_receiver: &Receiver<RawMessage>,
     task_sender: Sender<Task>,
     task_receiver: Receiver<Task>,
+    ws_worker: &Worker<(PathBuf, CargoConfig), (PathBuf, Result<ProjectWorkspace>)>,
+    proc_macro_client: &ProcMacroClient,
+    library_cache: Option<Arc<LibraryCache>>,
     state: &mut ServerWorldState,
-    pending_requests: &mut FxHashSet<u64>,
+    pending_requests: &mut PendingRequests,
     subs: &mut Subscriptions,
+    client_requests: &mut OutgoingRequests<ServerWorldState>,
+    progress: &mut LoadingProgress,
 ) -> Result<()> {
     // We try not to index more than THREADPOOL_SIZE - 3 libraries at the same
     // time to always have a thread ready to react to input.
     let mut in_flight_libraries = 0;
     let mut pending_libraries = Vec::new();
 
+    // Workspaces are reloaded for all folders at once, and the results are
+    // applied together.
+    let mut workspaces_in_flight = 0;
+    let mut reloaded_workspaces = Vec::new();
+
     let (libdata_sender, libdata_receiver) = unbounded();
     loop {
         state.maybe_collect_garbage();
@@ -173,7 +230,8 @@ fn main_loop_inner(
                 Ok(task) => Event::Vfs(task),
                 Err(RecvError) => bail!("vfs died"),
             },
-            recv(libdata_receiver) -> data => Event::Lib(data.unwrap())
+            recv(libdata_receiver) -> data => Event::Lib(data.unwrap()),
+            recv(ws_worker.receiver()) -> ws => Event::Workspace(ws.unwrap())
         };
         log::info!("loop_turn = {:?}", event);
         let start = std::time::Instant::now();
@@ -189,6 +247,16 @@ fn main_loop_inner(
                 state.add_lib(lib);
                 in_flight_libraries -= 1;
             }
+            Event::Workspace(ws) => {
+                workspaces_in_flight -= 1;
+                reloaded_workspaces.push(ws);
+                if workspaces_in_flight == 0 {
+                    let loaded = std::mem::replace(&mut reloaded_workspaces, Vec::new());
+                    state.reload_workspaces(loaded, proc_macro_client);
+                    feedback(internal_mode, "workspace reloaded", msg_sender);
+                    state_changed = true;
+                }
+            }
             Event::Msg(msg) => match msg {
                 RawMessage::Request(req) => {
                     let req = match handle_shutdown(req, msg_sender) {
@@ -218,28 +286,60 @@ fn main_loop_inner(
                     }
                 }
                 RawMessage::Notification(not) => {
-                    on_notification(msg_sender, state, pending_requests, subs, not)?;
+                    on_notification(
+                        msg_sender,
+                        state,
+                        pending_requests,
+                        subs,
+                        client_requests,
+                        not,
+                    )?;
+                    state_changed = true;
+                }
+                RawMessage::Response(resp) => {
+                    if let Some(resp) = client_requests.on_response(state, resp) {
+                        log::error!("unexpected response: {:?}", resp)
+                    }
                     state_changed = true;
                 }
-                RawMessage::Response(resp) => log::error!("unexpected response: {:?}", resp),
             },
         };
 
+        if workspaces_in_flight == 0 && state.take_workspace_reload_request() {
+            if state.folders.is_empty() {
+                // There is nothing to load, but the crates of the removed
+                // folders have to go.
+                state.reload_workspaces(Vec::new(), proc_macro_client);
+                feedback(internal_mode, "workspace reloaded", msg_sender);
+            }
+            for folder in state.folders.iter() {
+                ws_worker.sender().send((folder.clone(), state.config.cargo_config())).unwrap();
+            }
+            workspaces_in_flight = state.folders.len();
+        }
+
         pending_libraries.extend(state.process_changes());
         while in_flight_libraries < THREADPOOL_SIZE - 3 && !pending_libraries.is_empty() {
             let (root, files) = pending_libraries.pop().unwrap();
             in_flight_libraries += 1;
             let sender = libdata_sender.clone();
+            let library_cache = library_cache.clone();
             pool.execute(move || {
                 let start = ::std::time::Instant::now();
                 log::info!("indexing {:?} ... ", root);
-                let data = LibraryData::prepare(root, files);
+                let data = match library_cache {
+                    Some(cache) => cache.load_or_prepare(root, files),
+                    None => LibraryData::prepare(root, files),
+                };
                 log::info!("indexed {:?} {:?}", start.elapsed(), root);
                 sender.send(data).unwrap();
             });
         }
 
-        if state.roots_to_scan == 0 && pending_libraries.is_empty() && in_flight_libraries == 0 {
+        let libraries_to_index = pending_libraries.len() + in_flight_libraries;
+        let stage = loading_stage(state, workspaces_in_flight, libraries_to_index);
+        progress.update(stage, client_requests, msg_sender);
+        if state.roots_to_scan == 0 && libraries_to_index == 0 {
             feedback(internal_mode, "workspace loaded", msg_sender);
         }
 
@@ -256,10 +356,34 @@ fn main_loop_inner(
     }
 }
 
-fn on_task(task: Task, msg_sender: &Sender<RawMessage>, pending_requests: &mut FxHashSet<u64>) {
+/// Describes what is being loaded, for the work done progress. Libraries are
+/// only indexed after they are scanned, so scanned roots include the libraries
+/// waiting to be indexed.
+fn loading_stage(
+    state: &ServerWorldState,
+    workspaces_in_flight: usize,
+    libraries_to_index: usize,
+) -> Option<(String, Option<u32>)> {
+    let counted = |done: usize, total: usize| Some((done * 100 / total.max(1)) as u32);
+    if workspaces_in_flight > 0 {
+        return Some(("loading cargo metadata".to_string(), None));
+    }
+    let roots_to_scan = state.roots_to_scan - libraries_to_index;
+    if roots_to_scan > 0 {
+        let (total, done) = (state.n_roots(), state.n_roots() - roots_to_scan);
+        return Some((format!("scanning roots {}/{}", done, total), counted(done, total)));
+    }
+    if libraries_to_index > 0 {
+        let (total, done) = (state.n_libraries(), state.n_libraries() - libraries_to_index);
+        return Some((format!("indexing libraries {}/{}", done, total), counted(done, total)));
+    }
+    None
+}
+
+fn on_task(task: Task, msg_sender: &Sender<RawMessage>, pending_requests: &mut PendingRequests) {
     match task {
         Task::Respond(response) => {
-            if pending_requests.remove(&response.id) {
+            if pending_requests.remove(&response.id).is_some() {
                 msg_sender.send(RawMessage::Response(response)).unwrap();
             }
         }
@@ -271,7 +395,7 @@ fn on_task(task: Task, msg_sender: &Sender<RawMessage>, pending_requests: &mut F
 
 fn on_request(
     world: &mut ServerWorldState,
-    pending_requests: &mut FxHashSet<u64>,
+    pending_requests: &mut PendingRequests,
     pool: &ThreadPool,
     sender: &Sender<Task>,
     req: RawRequest,
@@ -280,7 +404,9 @@ fn on_request(
     let req = pool_dispatcher
         .on::<req::AnalyzerStatus>(handlers::handle_analyzer_status)?
         .on::<req::SyntaxTree>(handlers::handle_syntax_tree)?
+        .on::<req::ExpandMacro>(handlers::handle_expand_macro)?
         .on::<req::ExtendSelection>(handlers::handle_extend_selection)?
+        .on::<req::SelectionRangeRequest>(handlers::handle_selection_range)?
         .on::<req::FindMatchingBrace>(handlers::handle_find_matching_brace)?
         .on::<req::JoinLines>(handlers::handle_join_lines)?
         .on::<req::OnEnter>(handlers::handle_on_enter)?
@@ -292,6 +418,9 @@ fn on_request(
         .on::<req::ParentModule>(handlers::handle_parent_module)?
         .on::<req::Runnables>(handlers::handle_runnables)?
         .on::<req::DecorationsRequest>(handlers::handle_decorations)?
+        .on::<req::SemanticTokensRequest>(handlers::handle_semantic_tokens)?
+        .on::<req::SemanticTokensDeltaRequest>(handlers::handle_semantic_tokens_delta)?
+        .on::<req::SemanticTokensRangeRequest>(handlers::handle_semantic_tokens_range)?
         .on::<req::Completion>(handlers::handle_completion)?
         .on::<req::CodeActionRequest>(handlers::handle_code_action)?
         .on::<req::CodeLensRequest>(handlers::handle_code_lens)?
@@ -303,12 +432,13 @@ fn on_request(
         .on::<req::Rename>(handlers::handle_rename)?
         .on::<req::References>(handlers::handle_references)?
         .on::<req::Formatting>(handlers::handle_formatting)?
+        .on::<req::RangeFormatting>(handlers::handle_range_formatting)?
         .on::<req::DocumentHighlightRequest>(handlers::handle_document_highlight)?
         .finish();
     match req {
-        Ok(id) => {
-            let inserted = pending_requests.insert(id);
-            assert!(inserted, "duplicate request: {}", id);
+        Ok((id, cancellation_handle)) => {
+            let previous = pending_requests.insert(id.clone(), cancellation_handle);
+            assert!(previous.is_none(), "duplicate request: {}", id);
             Ok(None)
         }
         Err(req) => Ok(Some(req)),
@@ -318,19 +448,16 @@ fn on_request(
 fn on_notification(
     msg_sender: &Sender<RawMessage>,
     state: &mut ServerWorldState,
-    pending_requests: &mut FxHashSet<u64>,
+    pending_requests: &mut PendingRequests,
     subs: &mut Subscriptions,
+    client_requests: &mut OutgoingRequests<ServerWorldState>,
     not: RawNotification,
 ) -> Result<()> {
     let not = match not.cast::<req::Cancel>() {
         Ok(params) => {
-            let id = match params.id {
-                NumberOrString::Number(id) => id,
-                NumberOrString::String(id) => {
-                    panic!("string id's not supported: {:?}", id);
-                }
-            };
-            if pending_requests.remove(&id) {
+            let id = RequestId::from(params.id);
+            if let Some(cancellation_handle) = pending_requests.remove(&id) {
+                cancellation_handle.cancel();
                 let response = RawResponse::err(
                     id,
                     ErrorCode::RequestCanceled as i32,
@@ -346,22 +473,18 @@ fn on_notification(
         Ok(params) => {
             let uri = params.text_document.uri;
             let path = uri.to_file_path().map_err(|()| format_err!("invalid uri: {}", uri))?;
-            if let Some(file_id) =
-                state.vfs.write().add_file_overlay(&path, params.text_document.text)
-            {
-                subs.add_sub(FileId(file_id.0.into()));
+            if let Some(file_id) = state.open_document(path, params.text_document.text) {
+                subs.add_sub(file_id);
             }
             return Ok(());
         }
         Err(not) => not,
     };
     let not = match not.cast::<req::DidChangeTextDocument>() {
-        Ok(mut params) => {
+        Ok(params) => {
             let uri = params.text_document.uri;
             let path = uri.to_file_path().map_err(|()| format_err!("invalid uri: {}", uri))?;
-            let text =
-                params.content_changes.pop().ok_or_else(|| format_err!("empty changes"))?.text;
-            state.vfs.write().change_file_overlay(path.as_path(), text);
+            state.change_document(&path, params.content_changes);
             return Ok(());
         }
         Err(not) => not,
@@ -370,8 +493,8 @@ fn on_notification(
         Ok(params) => {
             let uri = params.text_document.uri;
             let path = uri.to_file_path().map_err(|()| format_err!("invalid uri: {}", uri))?;
-            if let Some(file_id) = state.vfs.write().remove_file_overlay(path.as_path()) {
-                subs.remove_sub(FileId(file_id.0.into()));
+            if let Some(file_id) = state.close_document(&path) {
+                subs.remove_sub(file_id);
             }
             let params = req::PublishDiagnosticsParams { uri, diagnostics: Vec::new() };
             let not = RawNotification::new::<req::PublishDiagnostics>(&params);
@@ -380,13 +503,82 @@ fn on_notification(
         }
         Err(not) => not,
     };
+    let not = match not.cast::<req::DidChangeConfiguration>() {
+        Ok(params) => {
+            // Clients using the push model, like VS Code, send the settings
+            // keyed by the section, possibly along with the settings of other
+            // sections, which don't concern us. Clients using the pull model
+            // send nothing, and are asked for the settings.
+            let mut settings = params.settings;
+            if let Some(section) = settings.get_mut("rust-analyzer") {
+                let section = std::mem::replace(section, serde_json::Value::Null);
+                apply_settings(state, section, msg_sender);
+                return Ok(());
+            }
+            let is_empty = settings.as_object().map_or(settings.is_null(), |it| it.is_empty());
+            if !is_empty {
+                log::info!("no rust-analyzer settings changed");
+                return Ok(());
+            }
+            let params = req::ConfigurationParams {
+                items: vec![req::ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("rust-analyzer".to_string()),
+                }],
+            };
+            let sender = msg_sender.clone();
+            client_requests.send::<req::WorkspaceConfiguration>(
+                msg_sender,
+                &params,
+                move |state, result| match result {
+                    Ok(mut settings) => match settings.pop() {
+                        Some(settings) => apply_settings(state, settings, &sender),
+                        None => log::error!("no settings in the response"),
+                    },
+                    Err(e) => log::error!("failed to get the settings: {}", e.message),
+                },
+            );
+            return Ok(());
+        }
+        Err(not) => not,
+    };
+    let not = match not.cast::<req::DidChangeWorkspaceFolders>() {
+        Ok(params) => {
+            let to_paths = |folders: Vec<lsp_types::WorkspaceFolder>| {
+                folders.into_iter().filter_map(|it| it.uri.to_file_path().ok()).collect::<Vec<_>>()
+            };
+            let added = to_paths(params.event.added);
+            let removed = to_paths(params.event.removed);
+            state.change_folders(added, &removed);
+            return Ok(());
+        }
+        Err(not) => not,
+    };
+    let not = match not.cast::<req::DidChangeWatchedFiles>() {
+        Ok(params) => {
+            let is_manifest = |uri: &lsp_types::Url| {
+                let path = uri.path();
+                ["Cargo.toml", "Cargo.lock", "rust-project.json"]
+                    .iter()
+                    .any(|name| path.ends_with(&format!("/{}", name)))
+            };
+            if params.changes.iter().any(|change| is_manifest(&change.uri)) {
+                state.request_workspace_reload();
+            }
+            if state.config.use_client_watching {
+                state.apply_watched_file_changes(params.changes);
+            }
+            return Ok(());
+        }
+        Err(not) => not,
+    };
     log::error!("unhandled notification: {:?}", not);
     Ok(())
 }
 
 struct PoolDispatcher<'a> {
     req: Option<RawRequest>,
-    res: Option<u64>,
+    res: Option<(RequestId, CancellationHandle)>,
     pool: &'a ThreadPool,
     world: &'a ServerWorldState,
     sender: &'a Sender<Task>,
@@ -406,7 +598,9 @@ impl<'a> PoolDispatcher<'a> {
         match req.cast::<R>() {
             Ok((id, params)) => {
                 let world = self.world.snapshot();
+                let cancellation_handle = world.analysis().cancellation_handle();
                 let sender = self.sender.clone();
+                self.res = Some((id.clone(), cancellation_handle));
                 self.pool.execute(move || {
                     let resp = match f(world, params) {
                         Ok(resp) => RawResponse::ok::<R>(id, &resp),
@@ -434,14 +628,13 @@ impl<'a> PoolDispatcher<'a> {
                     let task = Task::Respond(resp);
                     sender.send(task).unwrap();
                 });
-                self.res = Some(id);
             }
             Err(req) => self.req = Some(req),
         }
         Ok(self)
     }
 
-    fn finish(&mut self) -> ::std::result::Result<u64, RawRequest> {
+    fn finish(&mut self) -> ::std::result::Result<(RequestId, CancellationHandle), RawRequest> {
         match (self.res.take(), self.req.take()) {
             (Some(res), None) => Ok(res),
             (None, Some(req)) => Err(req),
@@ -487,6 +680,46 @@ fn update_file_notifications_on_threadpool(
     });
 }
 
+/// Asks the client to watch the files of the workspace, see
+/// `ServerConfig::use_client_watching`.
+fn register_file_watchers(
+    client_requests: &mut OutgoingRequests<ServerWorldState>,
+    msg_sender: &Sender<RawMessage>,
+) {
+    let watchers = ["**/*.rs", "**/Cargo.toml", "**/Cargo.lock", "**/rust-project.json"]
+        .iter()
+        .map(|glob| req::FileSystemWatcher { glob_pattern: glob.to_string(), kind: None })
+        .collect();
+    let options = req::DidChangeWatchedFilesRegistrationOptions { watchers };
+    let method = <req::DidChangeWatchedFiles as req::Notification>::METHOD.to_string();
+    let registration = req::Registration {
+        id: method.clone(),
+        method,
+        register_options: Some(serde_json::to_value(options).unwrap()),
+    };
+    let params = req::RegistrationParams { registrations: vec![registration] };
+    client_requests.send::<req::RegisterCapability>(msg_sender, &params, |_, result| {
+        if let Err(e) = result {
+            log::error!("failed to register file watchers: {}", e.message);
+        }
+    });
+}
+
+fn apply_settings(state: &mut ServerWorldState, settings: Value, msg_sender: &Sender<RawMessage>) {
+    match ServerConfig::deserialize(settings) {
+        Ok(config) => {
+            if !state.update_config(config) {
+                show_message(
+                    MessageType::Info,
+                    "rust-analyzer needs to be restarted to apply the new settings",
+                    msg_sender,
+                );
+            }
+        }
+        Err(e) => log::error!("invalid settings: {}", e),
+    }
+}
+
 fn feedback(intrnal_mode: bool, msg: &str, sender: &Sender<RawMessage>) {
     if !intrnal_mode {
         return;
@@ -495,6 +728,12 @@ fn feedback(intrnal_mode: bool, msg: &str, sender: &Sender<RawMessage>) {
     sender.send(RawMessage::Notification(not)).unwrap();
 }
 
+fn show_message(typ: MessageType, message: &str, sender: &Sender<RawMessage>) {
+    let params = ShowMessageParams { typ, message: message.to_string() };
+    let not = RawNotification::new::<req::ShowMessage>(&params);
+    sender.send(RawMessage::Notification(not)).unwrap();
+}
+
 fn is_canceled(e: &failure::Error) -> bool {
     e.downcast_ref::<Canceled>().is_some()
 }
@@ -1,26 +1,31 @@
 use gen_lsp_server::ErrorCode;
 use lsp_types::{
     CodeActionResponse, CodeLens, Command, Diagnostic, DiagnosticSeverity,
-    DocumentFormattingParams, DocumentHighlight, DocumentSymbol, FoldingRange,
-    FoldingRangeKind, FoldingRangeParams, Hover, HoverContents, Location, MarkupContent,
-    MarkupKind, ParameterInformation, ParameterLabel, Position, PrepareRenameResponse, Range,
-    RenameParams, SignatureInformation, SymbolInformation, TextDocumentIdentifier, TextEdit,
-    WorkspaceEdit,
+    DocumentFormattingParams, DocumentHighlight, DocumentRangeFormattingParams, DocumentSymbol,
+    FoldingRange, FoldingRangeKind, FoldingRangeParams, FormattingOptions, Hover, HoverContents,
+    Location, MarkupContent, MarkupKind, ParameterInformation, ParameterLabel, Position,
+    PrepareRenameResponse, Range, RenameParams, SignatureInformation, SymbolInformation,
+    TextDocumentIdentifier, TextEdit, WorkspaceEdit,
 };
 use ra_ide_api::{
-    FileId, FilePosition, FileRange, FoldKind, Query, RangeInfo, RunnableKind, Severity, Cancelable,
+    FileId, FilePosition, FileRange, FoldKind, Query, RangeInfo, RunnableKind, Severity,
+    Cancelable, InsertTextFormat, Edition,
 };
-use ra_syntax::{AstNode, SyntaxKind, TextUnit};
+use ra_syntax::{AstNode, SyntaxKind, TextRange, TextUnit};
 use rustc_hash::FxHashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::to_value;
-use std::io::Write;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 use url_serde::Ser;
 
 use crate::{
     cargo_target_spec::{runnable_args, CargoTargetSpec},
     conv::{to_location, to_location_link, Conv, ConvWith, MapConvWith, TryConvWith},
     req::{self, Decoration},
+    semantic_tokens,
     server_world::ServerWorld,
     LspError, Result,
 };
@@ -35,6 +40,23 @@ pub fn handle_syntax_tree(world: ServerWorld, params: req::SyntaxTreeParams) ->
     Ok(res)
 }
 
+pub fn handle_expand_macro(
+    world: ServerWorld,
+    params: req::ExpandMacroParams,
+) -> Result<Option<req::ExpandedMacro>> {
+    let file_id = params.text_document.try_conv_with(&world)?;
+    let line_index = world.analysis().file_line_index(file_id);
+    let offset = params.position.map(|p| p.conv_with(&line_index));
+
+    match offset {
+        None => Ok(None),
+        Some(offset) => {
+            let res = world.analysis().expand_macro(FilePosition { file_id, offset })?;
+            Ok(res.map(|it| req::ExpandedMacro { name: it.name, expansion: it.expansion }))
+        }
+    }
+}
+
 pub fn handle_extend_selection(
     world: ServerWorld,
     params: req::ExtendSelectionParams,
@@ -51,6 +73,33 @@ pub fn handle_extend_selection(
     Ok(req::ExtendSelectionResult { selections })
 }
 
+pub fn handle_selection_range(
+    world: ServerWorld,
+    params: req::SelectionRangeParams,
+) -> Result<Vec<req::SelectionRange>> {
+    let file_id = params.text_document.try_conv_with(&world)?;
+    let line_index = world.analysis().file_line_index(file_id);
+    let mut res = Vec::with_capacity(params.positions.len());
+    for position in params.positions {
+        let offset = position.conv_with(&line_index);
+        let ranges = world.analysis().selection_ranges(FilePosition { file_id, offset })?;
+        // The chain is innermost first, so build the nesting from the outside.
+        let mut selection_range = None;
+        for range in ranges.into_iter().rev() {
+            selection_range = Some(req::SelectionRange {
+                range: range.conv_with(&line_index),
+                parent: selection_range.map(Box::new),
+            });
+        }
+        let selection_range = selection_range.unwrap_or_else(|| req::SelectionRange {
+            range: Range::new(position, position),
+            parent: None,
+        });
+        res.push(selection_range);
+    }
+    Ok(res)
+}
+
 pub fn handle_find_matching_brace(
     world: ServerWorld,
     params: req::FindMatchingBraceParams,
@@ -104,6 +153,7 @@ pub fn handle_on_type_formatting(
     let edit = match params.ch.as_str() {
         "=" => world.analysis().on_eq_typed(position),
         "." => world.analysis().on_dot_typed(position),
+        "}" => world.analysis().on_rcurly_typed(position, &indent(&params.options)),
         _ => return Ok(None),
     };
     let mut edit = match edit {
@@ -267,7 +317,7 @@ pub fn handle_runnables(
             }
         }
 
-        let args = runnable_args(&world, file_id, &runnable.kind)?;
+        let args = runnable_args(&world, &runnable)?;
 
         let r = req::Runnable {
             range: runnable.range.conv_with(&line_index),
@@ -318,6 +368,45 @@ pub fn handle_decorations(
     highlight(&world, file_id)
 }
 
+pub fn handle_semantic_tokens(
+    world: ServerWorld,
+    params: req::SemanticTokensParams,
+) -> Result<Option<req::SemanticTokens>> {
+    let file_id = params.text_document.try_conv_with(&world)?;
+    let tokens = semantic_tokens(&world, file_id, None)?;
+    world.semantic_tokens.lock().insert(file_id, tokens.clone());
+    Ok(Some(tokens))
+}
+
+pub fn handle_semantic_tokens_delta(
+    world: ServerWorld,
+    params: req::SemanticTokensDeltaParams,
+) -> Result<Option<req::SemanticTokensDeltaResult>> {
+    let file_id = params.text_document.try_conv_with(&world)?;
+    let tokens = semantic_tokens(&world, file_id, None)?;
+    let previous = world.semantic_tokens.lock().insert(file_id, tokens.clone());
+    let res = match previous {
+        Some(previous) if previous.result_id.as_ref() == Some(&params.previous_result_id) => {
+            let edits = semantic_tokens::diff(&previous, &tokens);
+            req::SemanticTokensDeltaResult::Delta(req::SemanticTokensDelta {
+                result_id: tokens.result_id,
+                edits,
+            })
+        }
+        _ => req::SemanticTokensDeltaResult::Tokens(tokens),
+    };
+    Ok(Some(res))
+}
+
+pub fn handle_semantic_tokens_range(
+    world: ServerWorld,
+    params: req::SemanticTokensRangeParams,
+) -> Result<Option<req::SemanticTokens>> {
+    let frange = (&params.text_document, params.range).try_conv_with(&world)?;
+    let tokens = semantic_tokens(&world, frange.file_id, Some(frange.range))?;
+    Ok(Some(req::SemanticTokens { result_id: None, ..tokens }))
+}
+
 pub fn handle_completion(
     world: ServerWorld,
     params: req::CompletionParams,
@@ -355,11 +444,56 @@ pub fn handle_completion(
         Some(items) => items,
     };
     let line_index = world.analysis().file_line_index(position.file_id);
-    let items = items.into_iter().map(|item| item.conv_with(&line_index)).collect();
+    let items = items
+        .into_iter()
+        .map(|item| {
+            let is_snippet = item.insert_text_format() == InsertTextFormat::Snippet;
+            let mut item = item.conv_with(&line_index);
+            if is_snippet && !world.config.completion_snippets {
+                if let Some(text_edit) = &mut item.text_edit {
+                    text_edit.new_text = snippet_to_plain_text(&text_edit.new_text);
+                }
+                item.insert_text_format = Some(lsp_types::InsertTextFormat::PlainText);
+            }
+            item
+        })
+        .collect();
 
     Ok(Some(req::CompletionResponse::Array(items)))
 }
 
+/// Returns the text which the snippet inserts, for clients which don't support
+/// snippets: tab stops are removed, and placeholders are replaced with their
+/// default text.
+fn snippet_to_plain_text(snippet: &str) -> String {
+    let mut res = String::with_capacity(snippet.len());
+    let mut open_placeholders = 0;
+    let mut chars = snippet.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            '$' => {
+                let is_placeholder = chars.peek() == Some(&'{');
+                if is_placeholder {
+                    chars.next();
+                }
+                while chars.peek().map_or(false, |c| c.is_ascii_digit()) {
+                    chars.next();
+                }
+                if is_placeholder {
+                    if chars.peek() == Some(&':') {
+                        chars.next();
+                    }
+                    open_placeholders += 1;
+                }
+            }
+            '}' if open_placeholders > 0 => open_placeholders -= 1,
+            c => res.push(c),
+        }
+    }
+    res
+}
+
 pub fn handle_folding_range(
     world: ServerWorld,
     params: FoldingRangeParams,
@@ -534,15 +668,42 @@ pub fn handle_formatting(
     let end_position = TextUnit::of_str(&file).conv_with(&file_line_index);
 
     use std::process;
-    let mut rustfmt = process::Command::new("rustfmt");
+    let mut rustfmt = match &world.config.rustfmt_path {
+        Some(path) => process::Command::new(path),
+        None => process::Command::new("rustfmt"),
+    };
+    rustfmt.args(&world.config.rustfmt_args);
+    let has_arg = |arg: &str| world.config.rustfmt_args.iter().any(|it| it.starts_with(arg));
+    if !has_arg("--edition") {
+        if let Some(&krate) = world.analysis().crate_for(file_id)?.first() {
+            let edition = match world.analysis().crate_edition(krate)? {
+                Edition::Edition2015 => "2015",
+                Edition::Edition2018 => "2018",
+            };
+            rustfmt.args(&["--edition", edition]);
+        }
+    }
     rustfmt.stdin(process::Stdio::piped()).stdout(process::Stdio::piped());
 
     if let Ok(path) = params.text_document.uri.to_file_path() {
         if let Some(parent) = path.parent() {
             rustfmt.current_dir(parent);
+            if !has_arg("--config-path") {
+                if let Some(config) = find_rustfmt_config(parent) {
+                    rustfmt.arg("--config-path").arg(config);
+                }
+            }
         }
     }
-    let mut rustfmt = rustfmt.spawn()?;
+    let mut rustfmt = match rustfmt.spawn() {
+        Ok(it) => it,
+        // Without rustfmt, at least fix the indentation and the spacing.
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let edit = world.analysis().format(file_id, None, &indent(&params.options));
+            return Ok(Some(edit.conv_with(&file_line_index)));
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     rustfmt.stdin.as_mut().unwrap().write_all(file.as_bytes())?;
 
@@ -567,6 +728,41 @@ pub fn handle_formatting(
     }]))
 }
 
+/// A level of indentation for the built-in formatter, as the editor is
+/// configured to indent.
+fn indent(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    }
+}
+
+/// The `.rustfmt.toml` or `rustfmt.toml` closest to `dir`, checked in the same
+/// order as rustfmt does. rustfmt finds the same file itself when it runs in
+/// `dir`, as it looks the configuration up from its working directory.
+fn find_rustfmt_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| vec![dir.join(".rustfmt.toml"), dir.join("rustfmt.toml")])
+        .find(|it| it.is_file())
+}
+
+/// Formats the items and statements which intersect the range with the
+/// built-in formatter, as rustfmt can't format a part of a file.
+pub fn handle_range_formatting(
+    world: ServerWorld,
+    params: DocumentRangeFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let file_id = params.text_document.try_conv_with(&world)?;
+    let line_index = world.analysis().file_line_index(file_id);
+    let range = params.range.conv_with(&line_index);
+    let edit = world.analysis().format(file_id, Some(range), &indent(&params.options));
+    if edit.as_atoms().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(edit.conv_with(&line_index)))
+}
+
 pub fn handle_code_action(
     world: ServerWorld,
     params: req::CodeActionParams,
@@ -618,7 +814,7 @@ pub fn handle_code_lens(
         };
 
         if let Some(title) = title {
-            let args = runnable_args(&world, file_id, &runnable.kind)?;
+            let args = runnable_args(&world, &runnable)?;
             let range = runnable.range.conv_with(&line_index);
 
             // This represents the actual command that will be run.
@@ -744,6 +940,9 @@ pub fn publish_diagnostics(
     file_id: FileId,
 ) -> Result<req::PublishDiagnosticsParams> {
     let uri = world.file_id_to_uri(file_id)?;
+    if !world.config.enable_diagnostics {
+        return Ok(req::PublishDiagnosticsParams { uri, diagnostics: Vec::new() });
+    }
     let line_index = world.analysis().file_line_index(file_id);
     let diagnostics = world
         .analysis()
@@ -780,6 +979,18 @@ fn highlight(world: &ServerWorld, file_id: FileId) -> Result<Vec<Decoration>> {
     Ok(res)
 }
 
+fn semantic_tokens(
+    world: &ServerWorld,
+    file_id: FileId,
+    range: Option<TextRange>,
+) -> Result<req::SemanticTokens> {
+    let text = world.analysis().file_text(file_id);
+    let line_index = world.analysis().file_line_index(file_id);
+    let tokens = world.analysis().highlight_tokens(file_id, range)?;
+    let data = semantic_tokens::encode(&tokens, &text, &line_index);
+    Ok(req::SemanticTokens { result_id: Some(semantic_tokens::next_result_id()), data })
+}
+
 fn to_diagnostic_severity(severity: Severity) -> DiagnosticSeverity {
     use ra_ide_api::Severity::*;
 
@@ -0,0 +1,94 @@
+use std::{cell::Cell, rc::Rc};
+
+use crossbeam_channel::Sender;
+use gen_lsp_server::{OutgoingRequests, RawMessage, RawNotification};
+use lsp_types::NumberOrString;
+
+use crate::{
+    req::{self, ProgressParams, WorkDoneProgress, WorkDoneProgressCreateParams},
+    server_world::ServerWorldState,
+};
+
+/// Reports the loading of the workspaces as work done progress, so that users
+/// know why completion and friends are incomplete right after startup.
+///
+/// Progress is only reported to clients which advertise the
+/// `window.workDoneProgress` capability, the others only get the internal
+/// feedback of the tests.
+///
+/// Every period of loading gets a fresh progress token. Progress is reported
+/// right after `window/workDoneProgress/create` is sent; if the client answers
+/// with an error it doesn't support progress, and nothing is reported anymore.
+pub struct LoadingProgress {
+    /// Set from the client capabilities, cleared when the client answers
+    /// `window/workDoneProgress/create` with an error.
+    supported: Rc<Cell<bool>>,
+    next_token: u64,
+    /// The token and the last message of the progress being reported.
+    active: Option<(NumberOrString, String)>,
+}
+
+impl LoadingProgress {
+    pub fn new(supported: bool) -> LoadingProgress {
+        LoadingProgress { supported: Rc::new(Cell::new(supported)), next_token: 0, active: None }
+    }
+
+    /// Reports the current stage of loading, `None` meaning that loading has
+    /// finished.
+    pub fn update(
+        &mut self,
+        stage: Option<(String, Option<u32>)>,
+        requests: &mut OutgoingRequests<ServerWorldState>,
+        sender: &Sender<RawMessage>,
+    ) {
+        if !self.supported.get() {
+            return;
+        }
+        match (stage, self.active.take()) {
+            (Some((message, percentage)), None) => {
+                let token = self.create(requests, sender);
+                let title = "rust-analyzer".to_string();
+                let value =
+                    WorkDoneProgress::Begin { title, message: Some(message.clone()), percentage };
+                notify(&token, value, sender);
+                self.active = Some((token, message));
+            }
+            (Some((message, percentage)), Some((token, last_message))) => {
+                if message != last_message {
+                    let value =
+                        WorkDoneProgress::Report { message: Some(message.clone()), percentage };
+                    notify(&token, value, sender);
+                }
+                self.active = Some((token, message));
+            }
+            (None, Some((token, _))) => {
+                notify(&token, WorkDoneProgress::End { message: None }, sender)
+            }
+            (None, None) => (),
+        }
+    }
+
+    fn create(
+        &mut self,
+        requests: &mut OutgoingRequests<ServerWorldState>,
+        sender: &Sender<RawMessage>,
+    ) -> NumberOrString {
+        let token = NumberOrString::String(format!("rustAnalyzer/loading/{}", self.next_token));
+        self.next_token += 1;
+        let params = WorkDoneProgressCreateParams { token: token.clone() };
+        let supported = Rc::clone(&self.supported);
+        requests.send::<req::WorkDoneProgressCreate>(sender, &params, move |_, result| {
+            if let Err(err) = result {
+                log::info!("client doesn't support work done progress: {}", err.message);
+                supported.set(false);
+            }
+        });
+        token
+    }
+}
+
+fn notify(token: &NumberOrString, value: WorkDoneProgress, sender: &Sender<RawMessage>) {
+    let params = ProgressParams { token: token.clone(), value };
+    let not = RawNotification::new::<req::Progress>(&params);
+    sender.send(RawMessage::Notification(not)).unwrap();
+}
@@ -5,17 +5,22 @@ use thread_worker::Worker;
 use crate::Result;
 
 pub use ra_project_model::{
-    ProjectWorkspace, CargoWorkspace, Package, Target, TargetKind, Sysroot,
+    ProjectWorkspace, CargoWorkspace, CargoConfig, CargoFeatures, Package, Target, TargetKind,
+    Sysroot, ProcMacroClient,
 };
 
-pub fn workspace_loader() -> Worker<PathBuf, Result<ProjectWorkspace>> {
-    Worker::<PathBuf, Result<ProjectWorkspace>>::spawn(
+/// Discovers the workspace of a folder, the result is tagged with the folder.
+pub fn workspace_loader() -> Worker<(PathBuf, CargoConfig), (PathBuf, Result<ProjectWorkspace>)> {
+    Worker::<(PathBuf, CargoConfig), (PathBuf, Result<ProjectWorkspace>)>::spawn(
         "workspace loader",
         1,
         |input_receiver, output_sender| {
             input_receiver
                 .into_iter()
-                .map(|path| ProjectWorkspace::discover(path.as_path()))
+                .map(|(path, config)| {
+                    let ws = ProjectWorkspace::discover(path.as_path(), &config);
+                    (path, ws)
+                })
                 .try_for_each(|it| output_sender.send(it))
                 .unwrap()
         },
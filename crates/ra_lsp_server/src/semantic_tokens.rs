@@ -0,0 +1,205 @@
+//! Encoding of `ra_ide_api::HighlightedToken`s for the semantic tokens
+//! requests of the protocol.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ra_ide_api::{HighlightKind, HighlightModifiers, HighlightedToken, LineIndex};
+
+use crate::req::{SemanticTokens, SemanticTokensEdit, SemanticTokensLegend};
+
+/// Token types of the legend; the index of a type is its number in the
+/// encoded tokens. The names of the protocol are used where they exist.
+const TOKEN_TYPES: &[&str] = &[
+    "comment",
+    "string",
+    "number",
+    "keyword",
+    "attribute",
+    "macro",
+    "lifetime",
+    "formatSpecifier",
+    "namespace",
+    "function",
+    "method",
+    "struct",
+    "enum",
+    "enumMember",
+    "interface",
+    "typeAlias",
+    "typeParameter",
+    "property",
+    "variable",
+    "parameter",
+];
+
+/// Token modifiers of the legend; the index of a modifier is its bit in the
+/// encoded tokens.
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "mutable", "unsafe", "readonly", "static"];
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.iter().map(|it| it.to_string()).collect(),
+        token_modifiers: TOKEN_MODIFIERS.iter().map(|it| it.to_string()).collect(),
+    }
+}
+
+/// A fresh id for every set of tokens, which clients pass back to ask only
+/// for the changes since.
+pub fn next_result_id() -> String {
+    static NEXT_RESULT_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_RESULT_ID.fetch_add(1, Ordering::SeqCst).to_string()
+}
+
+/// Encodes the tokens of a file with the given text. Tokens spanning several
+/// lines, like block comments, are split into one token per line, as clients
+/// aren't required to support multiline tokens.
+pub fn encode(tokens: &[HighlightedToken], text: &str, line_index: &LineIndex) -> Vec<u32> {
+    let mut builder = Builder::default();
+    for token in tokens {
+        let (token_type, mut modifiers) = token_type(token.kind);
+        modifiers |= modifiers_bits(token.modifiers);
+        let start = line_index.line_col(token.range.start());
+        let token_text = &text[token.range];
+        for (i, line) in token_text.split('\n').enumerate() {
+            let col = if i == 0 { start.col_utf16 } else { 0 };
+            let len = line.trim_end_matches('\r').encode_utf16().count() as u32;
+            builder.push(start.line + i as u32, col, len, token_type, modifiers);
+        }
+    }
+    builder.data
+}
+
+/// The edits turning `old` into `new`, as a single replacement of everything
+/// between the common prefix and suffix.
+pub fn diff(old: &SemanticTokens, new: &SemanticTokens) -> Vec<SemanticTokensEdit> {
+    let (old, new) = (&old.data[..], &new.data[..]);
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    if old.is_empty() && new.is_empty() {
+        return Vec::new();
+    }
+    vec![SemanticTokensEdit {
+        start: prefix as u32,
+        delete_count: old.len() as u32,
+        data: if new.is_empty() { None } else { Some(new.to_vec()) },
+    }]
+}
+
+#[derive(Default)]
+struct Builder {
+    prev_line: u32,
+    prev_col: u32,
+    data: Vec<u32>,
+}
+
+impl Builder {
+    fn push(&mut self, line: u32, col: u32, len: u32, token_type: u32, modifiers: u32) {
+        if len == 0 {
+            return;
+        }
+        let delta_line = line - self.prev_line;
+        let delta_col = if delta_line == 0 { col - self.prev_col } else { col };
+        self.data.extend_from_slice(&[delta_line, delta_col, len, token_type, modifiers]);
+        self.prev_line = line;
+        self.prev_col = col;
+    }
+}
+
+/// The type of the token, and the modifiers implied by it.
+fn token_type(kind: HighlightKind) -> (u32, u32) {
+    let (name, modifier) = match kind {
+        HighlightKind::Comment => ("comment", None),
+        HighlightKind::String => ("string", None),
+        HighlightKind::Number => ("number", None),
+        HighlightKind::Keyword => ("keyword", None),
+        HighlightKind::Attribute => ("attribute", None),
+        HighlightKind::Macro => ("macro", None),
+        HighlightKind::Lifetime => ("lifetime", None),
+        HighlightKind::FormatSpecifier => ("formatSpecifier", None),
+        HighlightKind::Module => ("namespace", None),
+        HighlightKind::Function => ("function", None),
+        HighlightKind::Method => ("method", None),
+        HighlightKind::Struct => ("struct", None),
+        HighlightKind::Enum => ("enum", None),
+        HighlightKind::EnumVariant => ("enumMember", None),
+        HighlightKind::Trait => ("interface", None),
+        HighlightKind::TypeAlias => ("typeAlias", None),
+        HighlightKind::TypeParam => ("typeParameter", None),
+        HighlightKind::Field => ("property", None),
+        HighlightKind::Local => ("variable", None),
+        HighlightKind::Param => ("parameter", None),
+        HighlightKind::Const => ("variable", Some("readonly")),
+        HighlightKind::Static => ("variable", Some("static")),
+    };
+    let modifiers = modifier.map_or(0, modifier_bit);
+    (index(TOKEN_TYPES, name), modifiers)
+}
+
+fn modifiers_bits(modifiers: HighlightModifiers) -> u32 {
+    let mut res = 0;
+    if modifiers.definition {
+        res |= modifier_bit("declaration");
+    }
+    if modifiers.mutable {
+        res |= modifier_bit("mutable");
+    }
+    if modifiers.unsafe_ {
+        res |= modifier_bit("unsafe");
+    }
+    res
+}
+
+fn modifier_bit(name: &str) -> u32 {
+    1 << index(TOKEN_MODIFIERS, name)
+}
+
+fn index(legend: &[&str], name: &str) -> u32 {
+    legend.iter().position(|it| *it == name).expect("not in the legend") as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use ra_syntax::TextRange;
+
+    use super::*;
+
+    fn token(from: u32, to: u32, kind: HighlightKind) -> HighlightedToken {
+        let range = TextRange::from_to(from.into(), to.into());
+        HighlightedToken { range, kind, modifiers: HighlightModifiers::default() }
+    }
+
+    #[test]
+    fn encodes_positions_relative_to_previous_token() {
+        let text = "/* a\nbc */ fn f() {}";
+        let tokens = [
+            token(0, 10, HighlightKind::Comment),
+            token(11, 13, HighlightKind::Keyword),
+            HighlightedToken {
+                modifiers: HighlightModifiers { definition: true, ..Default::default() },
+                ..token(14, 15, HighlightKind::Function)
+            },
+        ];
+        let data = encode(&tokens, text, &LineIndex::new(text));
+        assert_eq!(
+            data,
+            vec![
+                0, 0, 4, 0, 0, //
+                1, 0, 5, 0, 0, //
+                0, 6, 2, 3, 0, //
+                0, 3, 1, 9, 1, //
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_tokens() {
+        let tokens = |data: Vec<u32>| SemanticTokens { result_id: None, data };
+        let old = tokens(vec![0, 0, 4, 0, 0, 1, 0, 5, 0, 0]);
+        let new = tokens(vec![0, 0, 4, 0, 0, 2, 0, 5, 0, 0]);
+        let edit = SemanticTokensEdit { start: 5, delete_count: 1, data: Some(vec![2]) };
+        assert_eq!(diff(&old, &new), vec![edit]);
+        assert!(diff(&old, &old).is_empty());
+    }
+}
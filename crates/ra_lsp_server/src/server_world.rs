@@ -1,79 +1,271 @@
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
-use lsp_types::Url;
+use lsp_types::{FileEvent, Position, TextDocumentContentChangeEvent, Url};
 use ra_ide_api::{
-    Analysis, AnalysisChange, AnalysisHost, CrateGraph, FileId, LibraryData,
-    SourceRootId
+    Analysis, AnalysisChange, AnalysisHost, CrateGraph, FileId, LibraryData, SourceRootId,
 };
-use ra_vfs::{Vfs, VfsChange, VfsFile, VfsRoot};
+use ra_syntax::{TextRange, TextUnit};
+use ra_text_edit::AtomTextEdit;
+use ra_vfs::{Vfs, VfsChange, VfsFile, VfsRoot, Watch};
 use relative_path::RelativePathBuf;
-use parking_lot::RwLock;
+use rustc_hash::{FxHashMap, FxHashSet};
+use parking_lot::{Mutex, RwLock};
 use failure::format_err;
 
 use crate::{
-    project_model::ProjectWorkspace,
+    config::ServerConfig,
+    project_model::{ProjectWorkspace, ProcMacroClient},
+    req::SemanticTokens,
     Result,
 };
 
 #[derive(Debug)]
 pub struct ServerWorldState {
     pub roots_to_scan: usize,
-    pub root: PathBuf,
+    /// The workspace folders opened in the editor.
+    pub folders: Vec<PathBuf>,
     pub workspaces: Arc<Vec<ProjectWorkspace>>,
     pub analysis_host: AnalysisHost,
     pub vfs: Arc<RwLock<Vfs>>,
+    pub config: Arc<ServerConfig>,
+    /// The semantic tokens last sent for each open document, which
+    /// `textDocument/semanticTokens/full/delta` computes the changes against.
+    pub semantic_tokens: Arc<Mutex<FxHashMap<FileId, SemanticTokens>>>,
+    /// Paths of the vfs roots. Roots are added when new folders or
+    /// dependencies appear, but never removed.
+    vfs_roots: Vec<PathBuf>,
+    /// Source roots inside of the workspace folders, as opposed to libraries.
+    local_roots: FxHashSet<SourceRootId>,
+    /// The last successfully loaded workspace of each folder, which is kept
+    /// when a reload fails, e.g. because of a syntax error in `Cargo.toml`.
+    folder_workspaces: FxHashMap<PathBuf, ProjectWorkspace>,
+    /// The last crate graph given to the `analysis_host`, to avoid needless
+    /// invalidation when a reloaded workspace produces the same graph.
+    crate_graph: CrateGraph,
+    /// Set when a manifest has changed and the workspaces should be reloaded.
+    workspace_reload_requested: bool,
+    /// Texts of the documents opened in the editor, which incremental changes
+    /// are applied to.
+    open_documents: FxHashMap<PathBuf, String>,
+    /// Edits to the open documents which were not yet passed to the
+    /// `analysis_host`. `None` means that the whole text was replaced.
+    pending_edits: FxHashMap<PathBuf, Option<Vec<AtomTextEdit>>>,
 }
 
 pub struct ServerWorld {
     pub workspaces: Arc<Vec<ProjectWorkspace>>,
     pub analysis: Analysis,
     pub vfs: Arc<RwLock<Vfs>>,
+    pub config: Arc<ServerConfig>,
+    pub semantic_tokens: Arc<Mutex<FxHashMap<FileId, SemanticTokens>>>,
 }
 
 impl ServerWorldState {
-    pub fn new(root: PathBuf, workspaces: Vec<ProjectWorkspace>) -> ServerWorldState {
+    pub fn new(
+        folders: Vec<PathBuf>,
+        loaded: Vec<(PathBuf, Result<ProjectWorkspace>)>,
+        proc_macro_client: &ProcMacroClient,
+        config: ServerConfig,
+    ) -> ServerWorldState {
         let mut change = AnalysisChange::new();
 
+        let mut folder_workspaces = FxHashMap::default();
+        let workspaces = merge_workspaces(&mut folder_workspaces, &folders, loaded);
+
         let mut roots = Vec::new();
-        roots.push(root.clone());
+        roots.extend(folders.iter().cloned());
         for ws in workspaces.iter() {
-            for pkg in ws.cargo.packages() {
-                roots.push(pkg.root(&ws.cargo).to_path_buf());
-            }
-            for krate in ws.sysroot.crates() {
-                roots.push(krate.root_dir(&ws.sysroot).to_path_buf())
-            }
+            roots.extend(ws.to_roots());
         }
-        let (mut vfs, roots) = Vfs::new(roots);
+        // The files are watched either by the client or by the vfs.
+        let (mut vfs, roots) = Vfs::new(roots, Watch(!config.use_client_watching));
         let roots_to_scan = roots.len();
+        let vfs_roots = roots.iter().map(|&r| vfs.root2path(r)).collect();
+        let mut local_roots = FxHashSet::default();
         for r in roots {
-            let is_local = vfs.root2path(r).starts_with(&root);
+            let root_path = vfs.root2path(r);
+            let is_local = folders.iter().any(|folder| root_path.starts_with(folder));
+            if is_local {
+                local_roots.insert(SourceRootId(r.0.into()));
+            }
             change.add_root(SourceRootId(r.0.into()), is_local);
         }
 
-        // Create crate graph from all the workspaces
-        let mut crate_graph = CrateGraph::default();
-        let mut load = |path: &std::path::Path| {
-            let vfs_file = vfs.load(path);
-            vfs_file.map(|f| FileId(f.0.into()))
-        };
-        for ws in workspaces.iter() {
-            crate_graph.extend(ws.to_crate_graph(&mut load));
-        }
-        change.set_crate_graph(crate_graph);
+        let crate_graph = build_crate_graph(&mut vfs, &workspaces, proc_macro_client);
+        change.set_crate_graph(crate_graph.clone());
 
-        let mut analysis_host = AnalysisHost::default();
+        let mut analysis_host = AnalysisHost::new(config.lru_capacity);
         analysis_host.apply_change(change);
         ServerWorldState {
             roots_to_scan,
-            root,
+            folders,
             workspaces: Arc::new(workspaces),
             analysis_host,
             vfs: Arc::new(RwLock::new(vfs)),
+            config: Arc::new(config),
+            semantic_tokens: Default::default(),
+            vfs_roots,
+            local_roots,
+            folder_workspaces,
+            crate_graph,
+            workspace_reload_requested: false,
+            open_documents: FxHashMap::default(),
+            pending_edits: FxHashMap::default(),
+        }
+    }
+
+    pub fn open_document(&mut self, path: PathBuf, text: String) -> Option<FileId> {
+        let file = self.vfs.write().add_file_overlay(&path, text.clone());
+        self.open_documents.insert(path, text);
+        file.map(|it| FileId(it.0.into()))
+    }
+
+    /// Applies the changes of a `textDocument/didChange` to the overlay of the
+    /// document. Ranged changes are recorded as edits as well, so that the
+    /// document can be reparsed incrementally. Positions outside of the text
+    /// are clamped to it, and changes to documents which are not open are
+    /// logged and ignored, so that a confused client can't stop the server.
+    pub fn change_document(&mut self, path: &Path, changes: Vec<TextDocumentContentChangeEvent>) {
+        let mut text = match self.open_documents.get(path) {
+            Some(text) => text.clone(),
+            None => {
+                log::error!("change to a document which is not open: {}", path.display());
+                return;
+            }
+        };
+        let mut edits = Some(Vec::new());
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = TextUnit::from_usize(clamped_offset(&text, range.start));
+                    let end = TextUnit::from_usize(clamped_offset(&text, range.end)).max(start);
+                    let range = TextRange::from_to(start, end);
+                    text.replace_range(start.to_usize()..end.to_usize(), &change.text);
+                    if let Some(edits) = &mut edits {
+                        edits.push(AtomTextEdit::replace(range, change.text));
+                    }
+                }
+                None => {
+                    text = change.text;
+                    edits = None;
+                }
+            }
+        }
+        let pending =
+            self.pending_edits.entry(path.to_path_buf()).or_insert_with(|| Some(Vec::new()));
+        match (pending, edits) {
+            (Some(pending), Some(edits)) => pending.extend(edits),
+            (pending, _) => *pending = None,
+        }
+        self.vfs.write().change_file_overlay(path, text.clone());
+        self.open_documents.insert(path.to_path_buf(), text);
+    }
+
+    pub fn close_document(&mut self, path: &Path) -> Option<FileId> {
+        self.open_documents.remove(path);
+        self.pending_edits.remove(path);
+        let file_id = self.vfs.write().remove_file_overlay(path).map(|it| FileId(it.0.into()))?;
+        self.semantic_tokens.lock().remove(&file_id);
+        Some(file_id)
+    }
+
+    /// Applies the file changes reported by the client's file watcher. The
+    /// vfs reads the files again, except for the ones open in the editor,
+    /// whose overlays take precedence.
+    pub fn apply_watched_file_changes(&mut self, changes: Vec<FileEvent>) {
+        let mut vfs = self.vfs.write();
+        for change in changes {
+            if let Ok(path) = change.uri.to_file_path() {
+                vfs.notify_changed(&path);
+            }
+        }
+    }
+
+    /// Applies new settings, reloading the workspaces if the project model is
+    /// affected. Returns `false` if some of the settings can only take effect
+    /// after a restart.
+    pub fn update_config(&mut self, config: ServerConfig) -> bool {
+        if config.cargo_config() != self.config.cargo_config() {
+            self.request_workspace_reload();
+        }
+        if config.lru_capacity != self.config.lru_capacity {
+            self.analysis_host.set_lru_capacity(config.lru_capacity);
+        }
+        let applied = !self.config.requires_restart(&config);
+        self.config = Arc::new(config);
+        applied
+    }
+
+    /// Applies a `workspace/didChangeWorkspaceFolders` notification and
+    /// reloads the workspaces of the new set of folders. The added folders
+    /// get their source roots in `reload_workspaces`. The roots of removed
+    /// folders are kept, so their files are still analyzed until a restart.
+    pub fn change_folders(&mut self, added: Vec<PathBuf>, removed: &[PathBuf]) {
+        self.folders.retain(|folder| !removed.contains(folder));
+        for folder in added {
+            if !self.folders.contains(&folder) {
+                self.folders.push(folder);
+            }
+        }
+        self.request_workspace_reload();
+    }
+
+    /// Schedules a reload of the workspaces, because `Cargo.toml` or a
+    /// similar file has changed.
+    pub fn request_workspace_reload(&mut self) {
+        self.workspace_reload_requested = true;
+    }
+
+    pub fn take_workspace_reload_request(&mut self) -> bool {
+        std::mem::replace(&mut self.workspace_reload_requested, false)
+    }
+
+    /// Replaces the workspaces with the freshly loaded ones. Existing source
+    /// roots are kept as is, so the libraries don't have to be indexed again,
+    /// and roots are added for the new folders and dependencies, including the
+    /// ones nested in existing roots. The crate graph is only updated if it has
+    /// actually changed.
+    pub fn reload_workspaces(
+        &mut self,
+        loaded: Vec<(PathBuf, Result<ProjectWorkspace>)>,
+        proc_macro_client: &ProcMacroClient,
+    ) {
+        let workspaces = merge_workspaces(&mut self.folder_workspaces, &self.folders, loaded);
+
+        let mut change = AnalysisChange::new();
+        let new_roots = self
+            .folders
+            .iter()
+            .cloned()
+            .chain(workspaces.iter().flat_map(|ws| ws.to_roots()))
+            .collect::<Vec<_>>();
+        for path in new_roots {
+            // The roots must exist before the crate graph is built, so that
+            // the crate roots in them can be loaded.
+            let root = match self.vfs.write().add_root(path.clone()) {
+                Some(root) => root,
+                None => continue,
+            };
+            let root_id = SourceRootId(root.0.into());
+            let is_local = self.folders.iter().any(|folder| path.starts_with(folder));
+            if is_local {
+                self.local_roots.insert(root_id);
+            }
+            change.add_root(root_id, is_local);
+            self.vfs_roots.push(path);
+            self.roots_to_scan += 1;
         }
+
+        let crate_graph = build_crate_graph(&mut self.vfs.write(), &workspaces, proc_macro_client);
+        if crate_graph != self.crate_graph {
+            change.set_crate_graph(crate_graph.clone());
+            self.crate_graph = crate_graph;
+        }
+        self.analysis_host.apply_change(change);
+        self.workspaces = Arc::new(workspaces);
     }
 
     /// Returns a vec of libraries
@@ -87,11 +279,18 @@ impl ServerWorldState {
         }
         let mut libs = Vec::new();
         let mut change = AnalysisChange::new();
+        let vfs = Arc::clone(&self.vfs);
+        let (config, folders) = (Arc::clone(&self.config), self.folders.clone());
+        let is_excluded = |path: &Path| config.is_excluded(&folders, path);
         for c in changes {
             match c {
                 VfsChange::AddRoot { root, files } => {
-                    let root_path = self.vfs.read().root2path(root);
-                    if root_path.starts_with(&self.root) {
+                    let root_path = vfs.read().root2path(root);
+                    let files = files
+                        .into_iter()
+                        .filter(|(_, path, _)| !is_excluded(&path.to_path(&root_path)))
+                        .collect::<Vec<_>>();
+                    if self.local_roots.contains(&SourceRootId(root.0.into())) {
                         self.roots_to_scan -= 1;
                         for (file, path, text) in files {
                             change.add_file(
@@ -110,20 +309,39 @@ impl ServerWorldState {
                     }
                 }
                 VfsChange::AddFile { root, file, path, text } => {
+                    if is_excluded(&path.to_path(vfs.read().root2path(root))) {
+                        continue;
+                    }
                     change.add_file(SourceRootId(root.0.into()), FileId(file.0.into()), path, text);
                 }
                 VfsChange::RemoveFile { root, file, path } => {
                     change.remove_file(SourceRootId(root.0.into()), FileId(file.0.into()), path)
                 }
                 VfsChange::ChangeFile { file, text } => {
-                    change.change_file(FileId(file.0.into()), text);
+                    let path = vfs.read().file2path(file);
+                    if is_excluded(&path) {
+                        continue;
+                    }
+                    match self.pending_edits.remove(&path) {
+                        Some(Some(edits)) => change.edit_file(FileId(file.0.into()), text, edits),
+                        _ => change.change_file(FileId(file.0.into()), text),
+                    }
                 }
             }
         }
+        self.pending_edits.clear();
         self.analysis_host.apply_change(change);
         libs
     }
 
+    pub fn n_roots(&self) -> usize {
+        self.vfs_roots.len()
+    }
+
+    pub fn n_libraries(&self) -> usize {
+        self.vfs_roots.len() - self.local_roots.len()
+    }
+
     pub fn add_lib(&mut self, data: LibraryData) {
         self.roots_to_scan -= 1;
         let mut change = AnalysisChange::new();
@@ -136,6 +354,8 @@ impl ServerWorldState {
             workspaces: Arc::clone(&self.workspaces),
             analysis: self.analysis_host.analysis(),
             vfs: Arc::clone(&self.vfs),
+            config: Arc::clone(&self.config),
+            semantic_tokens: Arc::clone(&self.semantic_tokens),
         }
     }
 
@@ -148,6 +368,77 @@ impl ServerWorldState {
     }
 }
 
+/// The offset of `pos` in `text`. As the LSP spec says, a position past the end
+/// of a line is at the end of the line, and a line past the end of the text
+/// is at the end of the text.
+fn clamped_offset(text: &str, pos: Position) -> usize {
+    let line_start = match pos.line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line as usize - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return text.len(),
+        },
+    };
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut col_utf16 = 0;
+    for (offset, c) in line.char_indices() {
+        if col_utf16 >= pos.character as usize {
+            return line_start + offset;
+        }
+        col_utf16 += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+/// Records the newly loaded workspaces of the folders and returns the
+/// workspaces to analyze. Folders of the same cargo workspace share a single
+/// one, so that its crates are not added to the crate graph twice.
+fn merge_workspaces(
+    folder_workspaces: &mut FxHashMap<PathBuf, ProjectWorkspace>,
+    folders: &[PathBuf],
+    loaded: Vec<(PathBuf, Result<ProjectWorkspace>)>,
+) -> Vec<ProjectWorkspace> {
+    for (folder, ws) in loaded {
+        match ws {
+            Ok(ws) => {
+                folder_workspaces.insert(folder, ws);
+            }
+            Err(e) => log::error!("loading workspace of {} failed: {}", folder.display(), e),
+        }
+    }
+    folder_workspaces.retain(|folder, _| folders.contains(folder));
+
+    let mut workspaces = Vec::new();
+    let mut seen_roots = Vec::new();
+    for folder in folders {
+        if let Some(ws) = folder_workspaces.get(folder) {
+            let roots = ws.to_roots();
+            if !seen_roots.contains(&roots) {
+                seen_roots.push(roots);
+                workspaces.push(ws.clone());
+            }
+        }
+    }
+    workspaces
+}
+
+/// Creates the crate graph from all the workspaces.
+fn build_crate_graph(
+    vfs: &mut Vfs,
+    workspaces: &[ProjectWorkspace],
+    proc_macro_client: &ProcMacroClient,
+) -> CrateGraph {
+    let mut crate_graph = CrateGraph::default();
+    let mut load = |path: &Path| {
+        let vfs_file = vfs.load(path);
+        vfs_file.map(|f| FileId(f.0.into()))
+    };
+    for ws in workspaces.iter() {
+        crate_graph.extend(ws.to_crate_graph(&mut load, proc_macro_client));
+    }
+    crate_graph
+}
+
 impl ServerWorld {
     pub fn analysis(&self) -> &Analysis {
         &self.analysis
@@ -185,7 +476,7 @@ impl ServerWorld {
         } else {
             res.push_str("workspaces:\n");
             for w in self.workspaces.iter() {
-                res += &format!("{} packages loaded\n", w.cargo.packages().count());
+                res += &format!("{} packages loaded\n", w.n_packages());
             }
         }
         res.push_str("\nanalysis:\n");
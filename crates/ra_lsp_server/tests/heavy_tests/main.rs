@@ -6,14 +6,29 @@ use std::{
 };
 
 use lsp_types::{
-    CodeActionContext, DocumentFormattingParams, FormattingOptions, Position, Range,
+    CodeActionContext, DocumentFormattingParams, DocumentRangeFormattingParams, FormattingOptions,
+    Position, Range, DidChangeWatchedFilesParams, FileEvent, FileChangeType,
+    DidOpenTextDocumentParams, DidChangeTextDocumentParams, TextDocumentItem,
+    VersionedTextDocumentIdentifier, TextDocumentContentChangeEvent, DidChangeConfigurationParams,
+    CancelParams, NumberOrString, DidChangeWorkspaceFoldersParams, WorkspaceFoldersChangeEvent,
+    WorkspaceFolder, SymbolInformation,
 };
 use ra_lsp_server::req::{
-    CodeActionParams, CodeActionRequest, Formatting, Runnables, RunnablesParams, CompletionParams, Completion,
+    CodeActionParams, CodeActionRequest, Formatting, Runnables, RunnablesParams, CompletionParams,
+    Completion, DidChangeWatchedFiles, GotoDefinition, TextDocumentPositionParams,
+    DidOpenTextDocument, DidChangeTextDocument, DidChangeConfiguration, Progress, WorkDoneProgress,
+    Cancel, WorkspaceConfiguration, RegisterCapability, SemanticTokens, SemanticTokensRequest,
+    SemanticTokensParams, SemanticTokensDeltaRequest, SemanticTokensDeltaParams,
+    SemanticTokensDeltaResult, SemanticTokensDelta, RangeFormatting, SelectionRangeRequest,
+    SelectionRangeParams, SelectionRange, InternalFeedback, DidChangeWorkspaceFolders,
+    WorkspaceSymbol, WorkspaceSymbolParams,
 };
+use ra_lsp_server::{ClientCaps, ServerConfig};
 use serde_json::json;
 
-use crate::support::project;
+use crate::support::{
+    project, project_with_client_caps, project_with_config, project_with_folders, test_config,
+};
 
 const LOG: &'static str = "";
 
@@ -201,6 +216,101 @@ pub use std::collections::HashMap;
     );
 }
 
+#[test]
+fn test_format_document_with_rustfmt_toml() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- rustfmt.toml
+hard_tabs = true
+
+//- src/lib.rs
+fn main() {
+let x = 1;
+}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.request::<Formatting>(
+        DocumentFormattingParams {
+            text_document: server.doc_id("src/lib.rs"),
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: false,
+                properties: HashMap::new(),
+            },
+        },
+        json!([
+            {
+                "newText": "fn main() {\n\tlet x = 1;\n}\n",
+                "range": {
+                    "end": { "character": 0, "line": 3 },
+                    "start": { "character": 0, "line": 0 }
+                }
+            }
+        ]),
+    );
+}
+
+#[test]
+fn test_format_range() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+fn main() {
+  let x=1;
+      let y=2;
+}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.request::<RangeFormatting>(
+        DocumentRangeFormattingParams {
+            text_document: server.doc_id("src/lib.rs"),
+            range: Range::new(Position::new(2, 6), Position::new(2, 9)),
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: false,
+                properties: HashMap::new(),
+            },
+        },
+        json!([
+            {
+                "newText": "\n\t",
+                "range": {
+                    "end": { "character": 6, "line": 2 },
+                    "start": { "character": 10, "line": 1 }
+                }
+            },
+            {
+                "newText": " ",
+                "range": {
+                    "end": { "character": 11, "line": 2 },
+                    "start": { "character": 11, "line": 2 }
+                }
+            },
+            {
+                "newText": " ",
+                "range": {
+                    "end": { "character": 12, "line": 2 },
+                    "start": { "character": 12, "line": 2 }
+                }
+            }
+        ]),
+    );
+}
+
 #[test]
 fn test_missing_module_code_action() {
     let server = project(
@@ -255,3 +365,760 @@ fn main() {}
         json!([]),
     );
 }
+
+#[test]
+fn test_missing_module_code_action_in_json_project() {
+    let server = project(
+        r#"
+//- rust-project.json
+{
+    "roots": [{ "path": "src" }],
+    "crates": [
+        {
+            "root_module": "src/lib.rs",
+            "edition": "2018",
+            "deps": []
+        }
+    ]
+}
+
+//- src/lib.rs
+mod bar;
+
+fn main() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+    let empty_context = || CodeActionContext { diagnostics: Vec::new(), only: None };
+    server.request::<CodeActionRequest>(
+        CodeActionParams {
+            text_document: server.doc_id("src/lib.rs"),
+            range: Range::new(Position::new(0, 4), Position::new(0, 7)),
+            context: empty_context(),
+        },
+        json!([
+            {
+              "arguments": [
+                {
+                  "cursorPosition": null,
+                  "workspaceEdit": {
+                    "documentChanges": [
+                      {
+                        "kind": "create",
+                        "uri": "file:///[..]/src/bar.rs"
+                      }
+                    ]
+                  },
+                  "label": "create module"
+                }
+              ],
+              "command": "rust-analyzer.applySourceChange",
+              "title": "create module"
+            }
+        ]),
+    );
+
+    server.request::<CodeActionRequest>(
+        CodeActionParams {
+            text_document: server.doc_id("src/lib.rs"),
+            range: Range::new(Position::new(2, 4), Position::new(2, 7)),
+            context: empty_context(),
+        },
+        json!([]),
+    );
+}
+
+#[test]
+fn reloads_workspace_when_cargo_toml_changes() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() { bar::baz(); }
+
+//- bar/Cargo.toml
+[package]
+name = "bar"
+version = "0.0.0"
+
+//- bar/src/lib.rs
+pub fn baz() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.write_file(
+        "Cargo.toml",
+        r#"
+[package]
+name = "foo"
+version = "0.0.0"
+
+[dependencies]
+bar = { path = "bar" }
+"#,
+    );
+    server.notification::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+        changes: vec![FileEvent {
+            uri: server.doc_id("Cargo.toml").uri,
+            typ: FileChangeType::Changed,
+        }],
+    });
+    server.wait_for_feedback("workspace reloaded");
+
+    server.request::<GotoDefinition>(
+        TextDocumentPositionParams {
+            text_document: server.doc_id("src/lib.rs"),
+            position: Position { line: 0, character: 21 },
+        },
+        json!([{
+            "originSelectionRange": "{...}",
+            "targetUri": "file:///[..]bar/src/lib.rs",
+            "targetRange": "{...}",
+            "targetSelectionRange": "{...}"
+        }]),
+    );
+}
+
+#[test]
+fn indexes_dependencies_added_outside_of_the_workspace() {
+    let server = project_with_folders(
+        r#"
+//- foo/Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- foo/src/lib.rs
+pub fn foo() { bar::baz(); }
+
+//- bar/Cargo.toml
+[package]
+name = "bar"
+version = "0.0.0"
+
+//- bar/src/lib.rs
+pub fn baz() {}
+"#,
+        &["foo"],
+    );
+    server.wait_for_feedback("workspace loaded");
+    let libraries_loaded = server
+        .notifications::<InternalFeedback>()
+        .iter()
+        .filter(|it| *it == "library loaded")
+        .count();
+
+    server.write_file(
+        "foo/Cargo.toml",
+        r#"
+[package]
+name = "foo"
+version = "0.0.0"
+
+[dependencies]
+bar = { path = "../bar" }
+"#,
+    );
+    server.notification::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+        changes: vec![FileEvent {
+            uri: server.doc_id("foo/Cargo.toml").uri,
+            typ: FileChangeType::Changed,
+        }],
+    });
+    server.wait_for_feedback("workspace reloaded");
+    // `bar` is outside of the folder, so it is indexed as a new library.
+    server.wait_for_feedback_n("library loaded", libraries_loaded + 1);
+
+    server.request::<GotoDefinition>(
+        TextDocumentPositionParams {
+            text_document: server.doc_id("foo/src/lib.rs"),
+            position: Position { line: 0, character: 21 },
+        },
+        json!([{
+            "originSelectionRange": "{...}",
+            "targetUri": "file:///[..]bar/src/lib.rs",
+            "targetRange": "{...}",
+            "targetSelectionRange": "{...}"
+        }]),
+    );
+}
+
+#[test]
+fn analyzes_folders_added_to_the_workspace() {
+    let server = project_with_folders(
+        r#"
+//- foo/Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- foo/src/lib.rs
+pub fn foo() {}
+
+//- bar/Cargo.toml
+[package]
+name = "bar"
+version = "0.0.0"
+
+//- bar/src/lib.rs
+pub fn baz() {}
+"#,
+        &["foo"],
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.notification::<DidChangeWorkspaceFolders>(DidChangeWorkspaceFoldersParams {
+        event: WorkspaceFoldersChangeEvent {
+            added: vec![WorkspaceFolder { uri: server.doc_id("bar").uri, name: "bar".to_string() }],
+            removed: Vec::new(),
+        },
+    });
+    server.wait_for_feedback("workspace reloaded");
+    // The new folder has to be scanned before the workspace is loaded again.
+    let loaded = server
+        .notifications::<InternalFeedback>()
+        .iter()
+        .filter(|it| *it == "workspace loaded")
+        .count();
+    server.wait_for_feedback_n("workspace loaded", loaded + 1);
+
+    // Symbols of libraries are not searched by default, so `baz` is only
+    // found if `bar` is a local folder.
+    let res =
+        server.send_request::<WorkspaceSymbol>(WorkspaceSymbolParams { query: "baz".to_string() });
+    let symbols: Vec<SymbolInformation> = serde_json::from_value(res).unwrap();
+    assert_eq!(symbols.len(), 1);
+    assert!(symbols[0].location.uri.path().ends_with("bar/src/lib.rs"));
+}
+
+#[test]
+fn drops_crates_when_the_last_folder_is_removed() {
+    let server = project_with_folders(
+        r#"
+//- foo/Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- foo/tests/spam.rs
+#[test]
+fn test_eggs() {}
+"#,
+        &["foo"],
+    );
+    server.wait_for_feedback("workspace loaded");
+    let runnables = || {
+        let params =
+            RunnablesParams { text_document: server.doc_id("foo/tests/spam.rs"), position: None };
+        server.send_request::<Runnables>(params).to_string()
+    };
+    assert!(runnables().contains("--package"));
+
+    server.notification::<DidChangeWorkspaceFolders>(DidChangeWorkspaceFoldersParams {
+        event: WorkspaceFoldersChangeEvent {
+            added: Vec::new(),
+            removed: vec![WorkspaceFolder {
+                uri: server.doc_id("foo").uri,
+                name: "foo".to_string(),
+            }],
+        },
+    });
+    server.wait_for_feedback("workspace reloaded");
+    assert!(!runnables().contains("--package"));
+}
+
+#[test]
+fn applies_incremental_document_changes() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    let uri = server.doc_id("src/lib.rs").uri;
+    server.notification::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 0,
+            text: "pub fn foo() {}\npub fn bar() { }\n".to_string(),
+        },
+    });
+    server.notification::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier { uri, version: Some(1) },
+        content_changes: vec![
+            TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(1, 15), Position::new(1, 15))),
+                range_length: Some(0),
+                text: "foo();".to_string(),
+            },
+            TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(1, 14), Position::new(1, 15))),
+                range_length: Some(1),
+                text: String::new(),
+            },
+        ],
+    });
+
+    server.request::<GotoDefinition>(
+        TextDocumentPositionParams {
+            text_document: server.doc_id("src/lib.rs"),
+            position: Position { line: 1, character: 15 },
+        },
+        json!([{
+            "originSelectionRange": "{...}",
+            "targetUri": "file:///[..]src/lib.rs",
+            "targetRange": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 15 }
+            },
+            "targetSelectionRange": "{...}"
+        }]),
+    );
+}
+
+#[test]
+fn clamps_document_changes_to_the_text() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    // Changes to documents which are not open are ignored.
+    server.notification::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier {
+            uri: server.doc_id("src/main.rs").uri,
+            version: Some(1),
+        },
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(0, 0), Position::new(0, 0))),
+            range_length: Some(0),
+            text: "fn main() {}".to_string(),
+        }],
+    });
+
+    let uri = server.doc_id("src/lib.rs").uri;
+    server.notification::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 0,
+            text: "pub fn foo() {}\n".to_string(),
+        },
+    });
+    // Both positions are past the end of the text, which means the end.
+    server.notification::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier { uri, version: Some(1) },
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(1, 20), Position::new(7, 0))),
+            range_length: Some(0),
+            text: "pub fn bar() { foo(); }\n".to_string(),
+        }],
+    });
+
+    server.request::<GotoDefinition>(
+        TextDocumentPositionParams {
+            text_document: server.doc_id("src/lib.rs"),
+            position: Position { line: 1, character: 16 },
+        },
+        json!([{
+            "originSelectionRange": "{...}",
+            "targetUri": "file:///[..]src/lib.rs",
+            "targetRange": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 15 }
+            },
+            "targetSelectionRange": "{...}"
+        }]),
+    );
+}
+
+#[test]
+fn reloads_workspace_when_cargo_features_change() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+[features]
+bar = []
+
+//- src/lib.rs
+#[cfg(feature = "bar")]
+pub fn bar() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.notification::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: json!({
+            "rust-analyzer": {
+                "cargoFeatures": { "features": ["bar"] }
+            }
+        }),
+    });
+    server.wait_for_feedback("workspace reloaded");
+}
+
+#[test]
+fn completes_plain_text_without_snippets() {
+    let server = project_with_config(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+fn foo(x: u32) {}
+fn main() { fo }
+"#,
+        ServerConfig { completion_snippets: false, ..test_config() },
+    );
+    server.wait_for_feedback("workspace loaded");
+    // The settings of other sections don't reset ours.
+    server.notification::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: json!({ "editor": { "tabSize": 4 } }),
+    });
+    let res = server.send_request::<Completion>(CompletionParams {
+        text_document: server.doc_id("src/lib.rs"),
+        context: None,
+        position: Position::new(1, 14),
+    });
+    let foo = res.as_array().unwrap().iter().find(|it| it["label"] == "foo").unwrap();
+    assert_eq!(foo["textEdit"]["newText"], "foo()");
+    assert_eq!(foo["insertTextFormat"], 1);
+}
+
+#[test]
+fn loads_a_workspace_per_folder() {
+    let server = project_with_folders(
+        r#"
+//- foo/Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- foo/src/lib.rs
+pub fn foo() {}
+
+//- bar/Cargo.toml
+[package]
+name = "bar"
+version = "0.0.0"
+
+//- bar/tests/spam.rs
+#[test]
+fn test_eggs() {}
+"#,
+        &["foo", "bar"],
+    );
+    server.wait_for_feedback("workspace loaded");
+    server.request::<Runnables>(
+        RunnablesParams { text_document: server.doc_id("bar/tests/spam.rs"), position: None },
+        json!([
+          {
+            "args": [ "test", "--package", "bar", "--test", "spam", "--", "test_eggs", "--nocapture" ],
+            "bin": "cargo",
+            "env": { "RUST_BACKTRACE": "short" },
+            "label": "test test_eggs",
+            "range": {
+              "end": { "character": 17, "line": 1 },
+              "start": { "character": 0, "line": 0 }
+            }
+          },
+          {
+            "args": [ "check", "--package", "bar", "--test", "spam" ],
+            "bin": "cargo",
+            "env": {},
+            "label": "cargo check -p bar",
+            "range": {
+              "end": { "character": 0, "line": 0 },
+              "start": { "character": 0, "line": 0 }
+            }
+          }
+        ])
+    );
+}
+
+#[test]
+fn reports_loading_progress() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+    let progress =
+        server.notifications::<Progress>().into_iter().map(|it| it.value).collect::<Vec<_>>();
+    assert_eq!(
+        progress.first(),
+        Some(&WorkDoneProgress::Begin {
+            title: "rust-analyzer".to_string(),
+            message: Some("loading cargo metadata".to_string()),
+            percentage: None,
+        })
+    );
+    assert!(progress.iter().any(|it| match it {
+        WorkDoneProgress::Report { message: Some(message), .. } => {
+            message.starts_with("scanning roots")
+        }
+        _ => false,
+    }));
+    assert_eq!(progress.last(), Some(&WorkDoneProgress::End { message: None }));
+}
+
+#[test]
+fn reports_no_progress_to_clients_without_support() {
+    let server = project_with_client_caps(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+        test_config(),
+        ClientCaps::default(),
+    );
+    server.wait_for_feedback("workspace loaded");
+    assert!(server.notifications::<Progress>().is_empty());
+}
+
+#[test]
+fn accepts_string_and_negative_request_ids() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+    // Canceling a request which has already finished is a no-op.
+    server.notification::<Cancel>(CancelParams {
+        id: NumberOrString::String("finished".to_string()),
+    });
+    // The response is matched to the request by its id.
+    let res = server.send_request_with_id::<Runnables>(
+        "runnables".to_string().into(),
+        RunnablesParams { text_document: server.doc_id("src/lib.rs"), position: None },
+    );
+    assert!(res.is_array());
+    let res = server.send_request_with_id::<Runnables>(
+        (-1).into(),
+        RunnablesParams { text_document: server.doc_id("src/lib.rs"), position: None },
+    );
+    assert!(res.is_array());
+}
+
+#[test]
+fn pulls_settings_from_the_client() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+[features]
+bar = []
+
+//- src/lib.rs
+#[cfg(feature = "bar")]
+pub fn bar() {}
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+
+    server.notification::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::Value::Null,
+    });
+    server.answer_request::<WorkspaceConfiguration>(vec![json!({
+        "cargoFeatures": { "features": ["bar"] }
+    })]);
+    server.wait_for_feedback("workspace reloaded");
+}
+
+#[test]
+fn applies_changes_reported_by_client_watcher() {
+    let server = project_with_config(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+mod spam;
+"#,
+        ServerConfig { use_client_watching: true, ..test_config() },
+    );
+    let params = server.answer_request::<RegisterCapability>(());
+    assert_eq!(params.registrations[0].method, "workspace/didChangeWatchedFiles");
+    server.wait_for_feedback("workspace loaded");
+
+    server.write_file("src/spam.rs", "#[test]\nfn test_eggs() {}\n");
+    server.notification::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+        changes: vec![FileEvent {
+            uri: server.doc_id("src/spam.rs").uri,
+            typ: FileChangeType::Created,
+        }],
+    });
+    // The server's own watcher is not running, so the file is only known
+    // because the client has reported it.
+    let res = server.send_request::<Runnables>(RunnablesParams {
+        text_document: server.doc_id("src/spam.rs"),
+        position: None,
+    });
+    assert!(format!("{}", res).contains("test_eggs"));
+}
+
+#[test]
+fn does_not_register_file_watchers_without_client_support() {
+    let server = project_with_client_caps(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+pub fn foo() {}
+"#,
+        ServerConfig { use_client_watching: true, ..test_config() },
+        ClientCaps { work_done_progress: true, watched_files_dynamic_registration: false },
+    );
+    server.wait_for_feedback("workspace loaded");
+    assert!(server.requests::<RegisterCapability>().is_empty());
+}
+
+#[test]
+fn provides_semantic_tokens() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+fn foo() { let mut x = 1; x += 1; }
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+    let res = server.send_request::<SemanticTokensRequest>(SemanticTokensParams {
+        text_document: server.doc_id("src/lib.rs"),
+    });
+    let tokens: SemanticTokens = serde_json::from_value(res).unwrap();
+    assert_eq!(
+        tokens.data,
+        vec![
+            0, 0, 2, 3, 0, // fn
+            0, 3, 3, 9, 1, // foo: function, declaration
+            0, 8, 3, 3, 0, // let
+            0, 4, 3, 3, 0, // mut
+            0, 4, 1, 18, 3, // x: variable, declaration | mutable
+            0, 4, 1, 2, 0, // 1
+            0, 3, 1, 18, 2, // x: variable, mutable
+            0, 5, 1, 2, 0, // 1
+        ]
+    );
+
+    // Nothing has changed since the last request.
+    let res = server.send_request::<SemanticTokensDeltaRequest>(SemanticTokensDeltaParams {
+        text_document: server.doc_id("src/lib.rs"),
+        previous_result_id: tokens.result_id.unwrap(),
+    });
+    match serde_json::from_value(res).unwrap() {
+        SemanticTokensDeltaResult::Delta(SemanticTokensDelta { edits, .. }) => {
+            assert!(edits.is_empty())
+        }
+        SemanticTokensDeltaResult::Tokens(_) => panic!("expected a delta"),
+    }
+}
+
+#[test]
+fn provides_selection_ranges() {
+    let server = project(
+        r#"
+//- Cargo.toml
+[package]
+name = "foo"
+version = "0.0.0"
+
+//- src/lib.rs
+fn foo() { 92; }
+// hello world
+"#,
+    );
+    server.wait_for_feedback("workspace loaded");
+    let res = server.send_request::<SelectionRangeRequest>(SelectionRangeParams {
+        text_document: server.doc_id("src/lib.rs"),
+        positions: vec![Position::new(0, 12), Position::new(1, 5)],
+    });
+    let selection_ranges: Vec<SelectionRange> = serde_json::from_value(res).unwrap();
+    let chains: Vec<Vec<Range>> = selection_ranges
+        .into_iter()
+        .map(|it| {
+            let mut chain = vec![it.range];
+            let mut parent = it.parent;
+            while let Some(it) = parent {
+                chain.push(it.range);
+                parent = it.parent;
+            }
+            chain
+        })
+        .collect();
+    let range = |l1, c1, l2, c2| Range::new(Position::new(l1, c1), Position::new(l2, c2));
+    assert_eq!(
+        &chains[0][..4],
+        &[
+            range(0, 11, 0, 13), // 92
+            range(0, 11, 0, 14), // 92;
+            range(0, 9, 0, 16),  // { 92; }
+            range(0, 0, 0, 16),  // fn foo() { 92; }
+        ]
+    );
+    assert_eq!(
+        &chains[1][..2],
+        &[
+            range(1, 3, 1, 8),  // hello
+            range(1, 0, 1, 14), // // hello world
+        ]
+    );
+}
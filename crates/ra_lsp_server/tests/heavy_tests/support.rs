@@ -8,23 +8,62 @@ use std::{
 
 use crossbeam_channel::{after, select, Receiver};
 use flexi_logger::Logger;
-use gen_lsp_server::{RawMessage, RawNotification, RawRequest};
+use gen_lsp_server::{RawMessage, RawNotification, RawRequest, RawResponse, RequestId};
 use lsp_types::{
-    notification::DidOpenTextDocument,
+    notification::{DidOpenTextDocument, Notification},
     request::{Request, Shutdown},
     DidOpenTextDocumentParams, TextDocumentIdentifier, TextDocumentItem, Url,
 };
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{to_string_pretty, Value};
 use tempfile::TempDir;
 use thread_worker::Worker;
 use test_utils::{parse_fixture, find_mismatch};
 
 use ra_lsp_server::{
-    main_loop, req,
+    main_loop, req, ClientCaps, ServerConfig,
 };
 
 pub fn project(fixture: &str) -> Server {
+    project_with_folders(fixture, &[])
+}
+
+/// Like `project`, but the editor opens the given subdirectories of the
+/// fixture as workspace folders, instead of the fixture itself.
+pub fn project_with_folders(fixture: &str, folders: &[&str]) -> Server {
+    project_(fixture, folders, test_config(), test_client_caps())
+}
+
+/// Like `project`, but with custom settings.
+pub fn project_with_config(fixture: &str, config: ServerConfig) -> Server {
+    project_(fixture, &[], config, test_client_caps())
+}
+
+/// Like `project_with_config`, but the client has custom capabilities.
+pub fn project_with_client_caps(
+    fixture: &str,
+    config: ServerConfig,
+    client_caps: ClientCaps,
+) -> Server {
+    project_(fixture, &[], config, client_caps)
+}
+
+/// The settings of the test servers, which don't share the library cache.
+pub fn test_config() -> ServerConfig {
+    ServerConfig { library_cache: false, ..ServerConfig::default() }
+}
+
+/// The test client supports everything the server can use.
+fn test_client_caps() -> ClientCaps {
+    ClientCaps { work_done_progress: true, watched_files_dynamic_registration: true }
+}
+
+fn project_(
+    fixture: &str,
+    folders: &[&str],
+    config: ServerConfig,
+    client_caps: ClientCaps,
+) -> Server {
     static INIT: Once = Once::new();
     INIT.call_once(|| Logger::with_env_or_str(crate::LOG).start().unwrap());
 
@@ -37,24 +76,42 @@ pub fn project(fixture: &str) -> Server {
         fs::write(path.as_path(), entry.text.as_bytes()).unwrap();
         paths.push((path, entry.text));
     }
-    Server::new(tmp_dir, paths)
+    let mut folders = folders.iter().map(|it| tmp_dir.path().join(it)).collect::<Vec<_>>();
+    if folders.is_empty() {
+        folders.push(tmp_dir.path().to_path_buf());
+    }
+    Server::new(tmp_dir, folders, config, client_caps, paths)
 }
 
 pub struct Server {
-    req_id: Cell<u64>,
+    req_id: Cell<i64>,
     messages: RefCell<Vec<RawMessage>>,
     dir: TempDir,
     worker: Option<Worker<RawMessage, RawMessage>>,
 }
 
 impl Server {
-    fn new(dir: TempDir, files: Vec<(PathBuf, String)>) -> Server {
-        let path = dir.path().to_path_buf();
+    fn new(
+        dir: TempDir,
+        folders: Vec<PathBuf>,
+        config: ServerConfig,
+        client_caps: ClientCaps,
+        files: Vec<(PathBuf, String)>,
+    ) -> Server {
         let worker = Worker::<RawMessage, RawMessage>::spawn(
             "test server",
             128,
             move |mut msg_receiver, mut msg_sender| {
-                main_loop(true, path, true, &mut msg_receiver, &mut msg_sender).unwrap()
+                main_loop(
+                    true,
+                    folders,
+                    true,
+                    client_caps,
+                    config,
+                    &mut msg_receiver,
+                    &mut msg_sender,
+                )
+                .unwrap()
             },
         );
         let res = Server {
@@ -84,6 +141,19 @@ impl Server {
         TextDocumentIdentifier { uri: Url::from_file_path(path).unwrap() }
     }
 
+    /// Writes the file to disk, without notifying the server.
+    pub fn write_file(&self, rel_path: &str, text: &str) {
+        fs::write(self.dir.path().join(rel_path), text.as_bytes()).unwrap();
+    }
+
+    pub fn notification<N>(&self, params: N::Params)
+    where
+        N: Notification,
+        N::Params: Serialize,
+    {
+        self.send_notification(RawNotification::new::<N>(&params))
+    }
+
     pub fn request<R>(&self, params: R::Params, expected_resp: Value)
     where
         R: Request,
@@ -109,16 +179,27 @@ impl Server {
     {
         let id = self.req_id.get();
         self.req_id.set(id + 1);
+        self.send_request_with_id::<R>(id.into(), params)
+    }
 
+    pub fn send_request_with_id<R>(&self, id: RequestId, params: R::Params) -> Value
+    where
+        R: Request,
+        R::Params: Serialize,
+    {
         let r = RawRequest::new::<R>(id, &params);
         self.send_request_(r)
     }
     fn send_request_(&self, r: RawRequest) -> Value {
-        let id = r.id;
+        let id = r.id.clone();
         self.worker.as_ref().unwrap().sender().send(RawMessage::Request(r)).unwrap();
         while let Some(msg) = self.recv() {
             match msg {
-                RawMessage::Request(req) => panic!("unexpected request: {:?}", req),
+                RawMessage::Request(req) => {
+                    if req.method != req::WorkDoneProgressCreate::METHOD {
+                        panic!("unexpected request: {:?}", req)
+                    }
+                }
                 RawMessage::Notification(_) => (),
                 RawMessage::Response(res) => {
                     assert_eq!(res.id, id);
@@ -131,6 +212,24 @@ impl Server {
         }
         panic!("no response");
     }
+    /// Waits for the server to send a request of type `R`, answers it with
+    /// `result` and returns the parameters of the request.
+    pub fn answer_request<R>(&self, result: R::Result) -> R::Params
+    where
+        R: Request,
+        R::Params: DeserializeOwned,
+        R::Result: Serialize,
+    {
+        while let Some(msg) = self.recv() {
+            if let RawMessage::Request(req) = msg {
+                if let Ok((id, params)) = req.cast::<R>() {
+                    self.send_response(RawResponse::ok::<R>(id, &result));
+                    return params;
+                }
+            }
+        }
+        panic!("no request");
+    }
     pub fn wait_for_feedback(&self, feedback: &str) {
         self.wait_for_feedback_n(feedback, 1)
     }
@@ -154,8 +253,45 @@ impl Server {
             }
         }
     }
+    /// Returns the parameters of all notifications of kind `N` received so far.
+    pub fn notifications<N>(&self) -> Vec<N::Params>
+    where
+        N: Notification,
+        N::Params: DeserializeOwned,
+    {
+        self.messages
+            .borrow()
+            .iter()
+            .filter_map(|msg| match msg {
+                RawMessage::Notification(n) => n.clone().cast::<N>().ok(),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Returns the parameters of all requests of kind `R` received so far.
+    pub fn requests<R>(&self) -> Vec<R::Params>
+    where
+        R: Request,
+        R::Params: DeserializeOwned,
+    {
+        self.messages
+            .borrow()
+            .iter()
+            .filter_map(|msg| match msg {
+                RawMessage::Request(req) => req.clone().cast::<R>().ok().map(|(_, params)| params),
+                _ => None,
+            })
+            .collect()
+    }
     fn recv(&self) -> Option<RawMessage> {
         recv_timeout(&self.worker.as_ref().unwrap().receiver()).map(|msg| {
+            if let RawMessage::Request(req) = &msg {
+                // Like a real client, accept the server's progress.
+                if req.method == req::WorkDoneProgressCreate::METHOD {
+                    let resp = RawResponse::ok::<req::WorkDoneProgressCreate>(req.id, &());
+                    self.send_response(resp);
+                }
+            }
             self.messages.borrow_mut().push(msg.clone());
             msg
         })
@@ -163,6 +299,9 @@ impl Server {
     fn send_notification(&self, not: RawNotification) {
         self.worker.as_ref().unwrap().sender().send(RawMessage::Notification(not)).unwrap();
     }
+    fn send_response(&self, resp: RawResponse) {
+        self.worker.as_ref().unwrap().sender().send(RawMessage::Response(resp)).unwrap();
+    }
 }
 
 impl Drop for Server {
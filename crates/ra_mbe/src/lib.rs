@@ -20,11 +20,72 @@ mod mbe_parser;
 mod mbe_expander;
 mod syntax_bridge;
 
+use std::fmt;
+
 use ra_syntax::SmolStr;
 
 pub use tt::{Delimiter, Punct};
 
-pub use crate::syntax_bridge::ast_to_token_tree;
+/// An error in a `macro_rules` definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Expected(String),
+    InvalidRepeat(char),
+}
+
+/// An error during expansion of a macro call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpandError {
+    /// None of the rules matches the invocation. Holds the id of the token at
+    /// which the rule that got furthest failed. Only identifiers have ids, so
+    /// this is `None` if the failing token is a punctuation or a literal, or if
+    /// the invocation ended too early.
+    NoMatchingRule(Option<tt::TokenId>),
+    UnexpectedToken(Option<tt::TokenId>),
+    BindingError(String),
+    UnsupportedFragment(SmolStr),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Expected(it) => write!(f, "invalid macro definition: expected {}", it),
+            ParseError::InvalidRepeat(it) => {
+                write!(f, "invalid macro definition: `{}` is not a repetition operator", it)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpandError::NoMatchingRule(_) => {
+                f.write_str("no rules expected this token in macro call")
+            }
+            ExpandError::UnexpectedToken(_) => f.write_str("unexpected token in macro call"),
+            ExpandError::BindingError(it) => write!(f, "{}", it),
+            ExpandError::UnsupportedFragment(it) => {
+                write!(f, "unsupported fragment specifier `{}`", it)
+            }
+        }
+    }
+}
+
+pub use crate::syntax_bridge::{
+    ast_to_token_tree, syntax_node_to_token_tree, text_to_token_tree, token_tree_to_ast_item_list,
+    TokenMap, RevTokenMap,
+};
+
+/// Tells whether a token of the expansion was written in the macro definition
+/// or was passed to the macro at the invocation site. This is the basis of
+/// hygiene: identifiers from different origins live in different syntax
+/// contexts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Origin {
+    Def,
+    Call,
+}
 
 /// This struct contains AST for a single `macro_rules` definition. What might
 /// be very confusing is that AST has almost exactly the same shape as
@@ -33,14 +94,92 @@ pub use crate::syntax_bridge::ast_to_token_tree;
 #[derive(Debug, PartialEq, Eq)]
 pub struct MacroRules {
     pub(crate) rules: Vec<Rule>,
+    /// Offset for the ids of the invocation tokens, see `Shift`.
+    pub(crate) shift: Shift,
 }
 
 impl MacroRules {
-    pub fn parse(tt: &tt::Subtree) -> Option<MacroRules> {
+    pub fn parse(tt: &tt::Subtree) -> Result<MacroRules, ParseError> {
         mbe_parser::parse(tt)
     }
-    pub fn expand(&self, tt: &tt::Subtree) -> Option<tt::Subtree> {
-        mbe_expander::exapnd(self, tt)
+    pub fn expand(&self, tt: &tt::Subtree) -> Result<tt::Subtree, ExpandError> {
+        // Identifiers of the invocation and of the definition are numbered
+        // independently, so we shift the former to keep them apart in the
+        // expansion.
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        mbe_expander::exapnd(self, &tt).map_err(|err| match err {
+            ExpandError::NoMatchingRule(id) => {
+                ExpandError::NoMatchingRule(id.and_then(|id| self.shift.unshift(id)))
+            }
+            err => err,
+        })
+    }
+
+    /// Maps the id of an invocation token to the id it has in the expansion.
+    pub fn map_id_down(&self, id: tt::TokenId) -> tt::TokenId {
+        self.shift.shift(id)
+    }
+
+    /// Maps the id of an expansion token back to the id of the token it was
+    /// produced from, either in the definition or in the invocation.
+    pub fn map_id_up(&self, id: tt::TokenId) -> (tt::TokenId, Origin) {
+        match self.shift.unshift(id) {
+            Some(id) => (id, Origin::Call),
+            None => (id, Origin::Def),
+        }
+    }
+}
+
+/// Both the definition and the invocation token trees number their
+/// identifiers from zero. During expansion, invocation ids are moved past the
+/// largest id of the definition, so the origin of every expanded token can be
+/// recovered from its id alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Shift(u32);
+
+impl Shift {
+    pub(crate) fn new(tt: &tt::Subtree) -> Shift {
+        fn max_id(subtree: &tt::Subtree) -> Option<u32> {
+            subtree
+                .token_trees
+                .iter()
+                .filter_map(|tt| match tt {
+                    tt::TokenTree::Leaf(tt::Leaf::Ident(ident))
+                        if ident.id != tt::TokenId::unspecified() =>
+                    {
+                        Some(ident.id.0)
+                    }
+                    tt::TokenTree::Subtree(subtree) => max_id(subtree),
+                    _ => None,
+                })
+                .max()
+        }
+        Shift(max_id(tt).map_or(0, |it| it + 1))
+    }
+
+    fn shift_all(self, tt: &mut tt::Subtree) {
+        for tt in tt.token_trees.iter_mut() {
+            match tt {
+                tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => ident.id = self.shift(ident.id),
+                tt::TokenTree::Subtree(subtree) => self.shift_all(subtree),
+                tt::TokenTree::Leaf(_) => (),
+            }
+        }
+    }
+
+    fn shift(self, id: tt::TokenId) -> tt::TokenId {
+        if id == tt::TokenId::unspecified() {
+            return id;
+        }
+        tt::TokenId(id.0 + self.0)
+    }
+
+    fn unshift(self, id: tt::TokenId) -> Option<tt::TokenId> {
+        if id == tt::TokenId::unspecified() || id.0 < self.0 {
+            return None;
+        }
+        Some(tt::TokenId(id.0 - self.0))
     }
 }
 
@@ -95,6 +234,7 @@ pub(crate) struct Literal {
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct Ident {
     pub(crate) text: SmolStr,
+    pub(crate) id: tt::TokenId,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -105,7 +245,10 @@ pub(crate) struct Var {
 
 #[cfg(test)]
 mod tests {
-    use ra_syntax::{ast, AstNode};
+    use ra_syntax::{
+        ast::{self, NameOwner},
+        AstNode, TextRange,
+    };
 
     use super::*;
 
@@ -155,6 +298,51 @@ impl_froms!(TokenTree: Leaf, Subtree);
     )
     }
 
+    #[test]
+    fn test_token_tree_to_ast_item_list() {
+        let rules = create_rules(
+            r#"
+        macro_rules! foo {
+            ($ i:ident) => {
+                fn $ i() -> Vec<Vec<u32>> { a::b(..=1) }
+            }
+        }
+"#,
+        );
+        let source_file = ast::SourceFile::parse("foo! { bar }");
+        let macro_invocation =
+            source_file.syntax().descendants().find_map(ast::MacroCall::cast).unwrap();
+        let (invocation_tt, _) = ast_to_token_tree(macro_invocation.token_tree().unwrap()).unwrap();
+        let expansion = rules.expand(&invocation_tt).unwrap();
+
+        let (file, token_map) = token_tree_to_ast_item_list(&expansion);
+        assert!(file.errors().is_empty());
+        assert_eq!(
+            file.syntax().text().to_string(),
+            "fn bar () -> Vec < Vec < u32 >> {a :: b (..= 1)}"
+        );
+
+        let origins = token_map
+            .ranges
+            .iter()
+            .map(|&(range, id)| {
+                (file.syntax().text().slice(range).to_string(), rules.map_id_up(id).1)
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("fn", Origin::Def),
+            ("bar", Origin::Call),
+            ("Vec", Origin::Def),
+            ("Vec", Origin::Def),
+            ("u32", Origin::Def),
+            ("a", Origin::Def),
+            ("b", Origin::Def),
+        ];
+        let expected =
+            expected.iter().map(|&(text, origin)| (text.to_string(), origin)).collect::<Vec<_>>();
+        assert_eq!(origins, expected);
+    }
+
     fn create_rules(macro_definition: &str) -> MacroRules {
         let source_file = ast::SourceFile::parse(macro_definition);
         let macro_definition =
@@ -175,6 +363,188 @@ impl_froms!(TokenTree: Leaf, Subtree);
         assert_eq!(expanded.to_string(), expansion);
     }
 
+    #[test]
+    fn test_text_to_token_tree() {
+        let tt = text_to_token_tree("impl<'a> Foo for Bar { fn f() -> u32 { 92 } }").unwrap();
+        assert_eq!(tt.to_string(), "impl < 'a > Foo for Bar {fn f () -> u32 {92}}");
+        assert!(text_to_token_tree("fn f() {").is_none());
+    }
+
+    #[test]
+    fn test_token_tree_round_trip() {
+        let tt = text_to_token_tree("struct S { f: u32 }").unwrap();
+        let (file, _) = token_tree_to_ast_item_list(&tt);
+        assert!(file.errors().is_empty());
+        assert_eq!(file.syntax().text().to_string(), "struct S {f : u32}");
+        let field = file.syntax().descendants().find_map(ast::NamedFieldDef::cast).unwrap();
+        assert_eq!(field.name().unwrap().text(), "f");
+        assert!(field.type_ref().is_some());
+
+        let tt = text_to_token_tree("fn f() { let x = 1; }").unwrap();
+        let (file, _) = token_tree_to_ast_item_list(&tt);
+        assert!(file.errors().is_empty());
+        assert_eq!(file.syntax().text().to_string(), "fn f () {let x = 1 ;}");
+        let let_stmt = file.syntax().descendants().find_map(ast::LetStmt::cast).unwrap();
+        assert!(let_stmt.pat().is_some());
+        assert!(let_stmt.initializer().is_some());
+    }
+
+    #[test]
+    fn test_syntax_node_to_token_tree() {
+        let source_file = ast::SourceFile::parse("#[derive(Foo)] struct S<'a> { x: &'a u32 }");
+        let struct_def = source_file.syntax().descendants().find_map(ast::StructDef::cast).unwrap();
+        let (tt, token_map) = syntax_node_to_token_tree(struct_def.syntax()).unwrap();
+        assert_eq!(tt.to_string(), "# [derive (Foo)] struct S < 'a > {x : & 'a u32}");
+        // `derive`, `Foo` and `struct` come before `S`.
+        let range = token_map.relative_range_of(tt::TokenId(3)).unwrap();
+        assert_eq!(range, TextRange::offset_len(22.into(), 1.into()));
+    }
+
+    #[test]
+    fn test_expansion_keeps_token_origin() {
+        let rules = create_rules(
+            r#"
+        macro_rules! foo {
+            ($ i:ident) => (
+                fn $ i() { let x = 1; }
+            )
+        }
+"#,
+        );
+        let source_file = ast::SourceFile::parse("foo! { x }");
+        let macro_invocation =
+            source_file.syntax().descendants().find_map(ast::MacroCall::cast).unwrap();
+        let (invocation_tt, _) = ast_to_token_tree(macro_invocation.token_tree().unwrap()).unwrap();
+        let expanded = rules.expand(&invocation_tt).unwrap();
+
+        let idents = expanded
+            .token_trees
+            .iter()
+            .flat_map(|tt| match tt {
+                tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => vec![it.clone()],
+                tt::TokenTree::Subtree(subtree) => subtree
+                    .token_trees
+                    .iter()
+                    .filter_map(|tt| match tt {
+                        tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => Some(it.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .map(|it| (it.text.to_string(), rules.map_id_up(it.id).1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            idents,
+            vec![
+                ("fn".to_string(), Origin::Def),
+                ("x".to_string(), Origin::Call),
+                ("let".to_string(), Origin::Def),
+                ("x".to_string(), Origin::Def),
+            ]
+        );
+        assert_eq!(
+            rules.map_id_up(rules.map_id_down(tt::TokenId(0))),
+            (tt::TokenId(0), Origin::Call)
+        );
+    }
+
+    #[test]
+    fn test_dollar_crate() {
+        let rules = create_rules(
+            r#"
+        macro_rules! foo {
+            () => (
+                $ crate::bar()
+            )
+        }
+"#,
+        );
+        assert_expansion(&rules, "foo! {}", "$crate :: bar ()");
+    }
+
+    fn expand_err(rules: &MacroRules, invocation: &str) -> ExpandError {
+        let source_file = ast::SourceFile::parse(invocation);
+        let macro_invocation =
+            source_file.syntax().descendants().find_map(ast::MacroCall::cast).unwrap();
+
+        let (invocation_tt, _) = ast_to_token_tree(macro_invocation.token_tree().unwrap()).unwrap();
+
+        rules.expand(&invocation_tt).unwrap_err()
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let rules = create_rules(
+            r#"
+        macro_rules! foo {
+            (struct $ i:ident) => (
+                struct $ j;
+            );
+            ($ ($ i:ident),*) => (
+                fn $ i() {}
+            )
+        }
+"#,
+        );
+        assert_eq!(
+            expand_err(&rules, "foo! { struct Foo }"),
+            ExpandError::BindingError("could not find binding `j`".to_string())
+        );
+        assert_eq!(
+            expand_err(&rules, "foo! { foo, bar }"),
+            ExpandError::BindingError(
+                "expected simple binding, found nested binding `i`".to_string()
+            )
+        );
+        assert_eq!(expand_err(&rules, "foo! { 92 }"), ExpandError::NoMatchingRule(None));
+        // The first rule gets furthest, up to the unexpected `Bar`.
+        assert_eq!(
+            expand_err(&rules, "foo! { struct Foo Bar }"),
+            ExpandError::NoMatchingRule(Some(tt::TokenId(2)))
+        );
+    }
+
+    #[test]
+    fn test_expand_errors_in_repetition() {
+        let rules = create_rules(
+            r#"
+        macro_rules! foo {
+            (struct $ ($ i:ident)*) => (
+                $ (struct $ k;)*
+            );
+            ($ ($ i:ident)*) => (
+                $ (fn $ i() { $ ($ i)* })*
+            )
+        }
+"#,
+        );
+        assert_eq!(
+            expand_err(&rules, "foo! { struct a b }"),
+            ExpandError::BindingError(
+                "repetition contains no meta-variables repeating at this depth".to_string()
+            )
+        );
+        assert_eq!(
+            expand_err(&rules, "foo! { a b }"),
+            ExpandError::BindingError(
+                "repetition contains no meta-variables repeating at this depth".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let source_file = ast::SourceFile::parse("macro_rules! foo { ($ i:ident) => }");
+        let macro_definition =
+            source_file.syntax().descendants().find_map(ast::MacroCall::cast).unwrap();
+        let (definition_tt, _) = ast_to_token_tree(macro_definition.token_tree().unwrap()).unwrap();
+        assert_eq!(
+            crate::MacroRules::parse(&definition_tt),
+            Err(ParseError::Expected("macro body".to_string()))
+        );
+    }
+
     #[test]
     fn test_fail_match_pattern_by_first_token() {
         let rules = create_rules(
@@ -267,5 +637,4 @@ impl_froms!(TokenTree: Leaf, Subtree);
         assert_expansion(&rules, "foo! { foo# bar }", "fn foo () {} fn bar () {}");
         assert_expansion(&rules, "foo! { Foo,# Bar }", "struct Foo ; struct Bar ;");
     }
-
 }
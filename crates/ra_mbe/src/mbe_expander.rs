@@ -3,21 +3,43 @@
 /// `tt::TokenTree` for the result of the expansion.
 use rustc_hash::FxHashMap;
 use ra_syntax::SmolStr;
-use tt::TokenId;
 
-use crate::tt_cursor::TtCursor;
+use crate::{ExpandError, tt_cursor::TtCursor};
 
-pub(crate) fn exapnd(rules: &crate::MacroRules, input: &tt::Subtree) -> Option<tt::Subtree> {
-    rules.rules.iter().find_map(|it| expand_rule(it, input))
+pub(crate) fn exapnd(
+    rules: &crate::MacroRules,
+    input: &tt::Subtree,
+) -> Result<tt::Subtree, ExpandError> {
+    // Ids of the invocation increase from left to right, so the largest id
+    // marks the furthest point any rule has matched up to.
+    let mut failed_at: Option<tt::TokenId> = None;
+    for rule in rules.rules.iter() {
+        let mut input = TtCursor::new(input);
+        let id = match match_lhs(&rule.lhs, &mut input) {
+            Ok(_) if !input.is_eof() => current_id(&input),
+            // The first rule which matches is used, so the errors during
+            // transcription are reported instead of trying the other rules.
+            Ok(bindings) => return expand_subtree(&rule.rhs, &bindings, &mut Vec::new()),
+            Err(ExpandError::UnexpectedToken(id)) => id,
+            Err(_) => None,
+        };
+        if id.map(|it| it.0) > failed_at.map(|it| it.0) {
+            failed_at = id;
+        }
+    }
+    Err(ExpandError::NoMatchingRule(failed_at))
 }
 
-fn expand_rule(rule: &crate::Rule, input: &tt::Subtree) -> Option<tt::Subtree> {
-    let mut input = TtCursor::new(input);
-    let bindings = match_lhs(&rule.lhs, &mut input)?;
-    if !input.is_eof() {
-        return None;
+/// The id of the token under the cursor, if it is an identifier.
+fn current_id(input: &TtCursor) -> Option<tt::TokenId> {
+    match input.current() {
+        Some(tt::TokenTree::Leaf(tt::Leaf::Ident(ident))) => Some(ident.id),
+        _ => None,
     }
-    expand_subtree(&rule.rhs, &bindings, &mut Vec::new())
+}
+
+fn unexpected(input: &TtCursor) -> ExpandError {
+    ExpandError::UnexpectedToken(current_id(input))
 }
 
 /// The actual algorithm for expansion is not too hard, but is pretty tricky.
@@ -77,70 +99,100 @@ enum Binding {
 }
 
 impl Bindings {
-    fn get(&self, name: &SmolStr, nesting: &[usize]) -> Option<&tt::TokenTree> {
-        let mut b = self.inner.get(name)?;
+    fn get(&self, name: &SmolStr, nesting: &[usize]) -> Result<&tt::TokenTree, ExpandError> {
+        let mut b = self.inner.get(name).ok_or_else(|| {
+            ExpandError::BindingError(format!("could not find binding `{}`", name))
+        })?;
         for &idx in nesting.iter() {
             b = match b {
                 Binding::Simple(_) => break,
-                Binding::Nested(bs) => bs.get(idx)?,
+                Binding::Nested(bs) => bs.get(idx).ok_or_else(|| {
+                    ExpandError::BindingError(format!("could not find nested binding `{}`", name))
+                })?,
             };
         }
         match b {
-            Binding::Simple(it) => Some(it),
-            Binding::Nested(_) => None,
+            Binding::Simple(it) => Ok(it),
+            Binding::Nested(_) => Err(ExpandError::BindingError(format!(
+                "expected simple binding, found nested binding `{}`",
+                name
+            ))),
         }
     }
-    fn push_nested(&mut self, nested: Bindings) -> Option<()> {
+    fn push_nested(&mut self, nested: Bindings) -> Result<(), ExpandError> {
         for (key, value) in nested.inner {
             if !self.inner.contains_key(&key) {
                 self.inner.insert(key.clone(), Binding::Nested(Vec::new()));
             }
             match self.inner.get_mut(&key) {
                 Some(Binding::Nested(it)) => it.push(value),
-                _ => return None,
+                _ => {
+                    return Err(ExpandError::BindingError(format!(
+                        "could not find binding `{}`",
+                        key
+                    )));
+                }
             }
         }
-        Some(())
+        Ok(())
     }
 }
 
-fn match_lhs(pattern: &crate::Subtree, input: &mut TtCursor) -> Option<Bindings> {
+fn match_lhs(pattern: &crate::Subtree, input: &mut TtCursor) -> Result<Bindings, ExpandError> {
     let mut res = Bindings::default();
     for pat in pattern.token_trees.iter() {
         match pat {
             crate::TokenTree::Leaf(leaf) => match leaf {
                 crate::Leaf::Var(crate::Var { text, kind }) => {
-                    let kind = kind.clone()?;
+                    let kind = kind.clone().ok_or_else(|| {
+                        ExpandError::BindingError(format!(
+                            "missing fragment specifier for `{}`",
+                            text
+                        ))
+                    })?;
                     match kind.as_str() {
                         "ident" => {
-                            let ident = input.eat_ident()?.clone();
+                            let ident = input.eat_ident().ok_or_else(|| unexpected(input))?.clone();
                             res.inner.insert(
                                 text.clone(),
                                 Binding::Simple(tt::Leaf::from(ident).into()),
                             );
                         }
-                        _ => return None,
+                        _ => return Err(ExpandError::UnsupportedFragment(kind.clone())),
                     }
                 }
                 crate::Leaf::Punct(punct) => {
-                    if input.eat_punct()? != punct {
-                        return None;
+                    if input.eat_punct().ok_or_else(|| unexpected(input))?.char != punct.char {
+                        return Err(ExpandError::UnexpectedToken(None));
                     }
                 }
                 crate::Leaf::Ident(ident) => {
-                    if input.eat_ident()?.text != ident.text {
-                        return None;
+                    let actual = input.eat_ident().ok_or_else(|| unexpected(input))?;
+                    if actual.text != ident.text {
+                        return Err(ExpandError::UnexpectedToken(Some(actual.id)));
                     }
                 }
-                _ => return None,
+                _ => return Err(unexpected(input)),
             },
             crate::TokenTree::Repeat(crate::Repeat { subtree, kind: _, separator }) => {
-                while let Some(nested) = match_lhs(subtree, input) {
+                loop {
+                    // The repetition ends at the first token its pattern can't
+                    // match; everything else is a genuine error.
+                    let start = input.clone();
+                    let nested = match match_lhs(subtree, input) {
+                        Ok(nested) => nested,
+                        Err(ExpandError::UnexpectedToken(_)) => {
+                            *input = start;
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    };
                     res.push_nested(nested)?;
                     if let Some(separator) = *separator {
                         if !input.is_eof() {
-                            if input.eat_punct()?.char != separator {
-                                return None;
+                            let punct = input.eat_punct().ok_or_else(|| unexpected(input))?;
+                            if punct.char != separator {
+                                return Err(ExpandError::UnexpectedToken(None));
                             }
                         }
                     }
@@ -149,50 +201,106 @@ fn match_lhs(pattern: &crate::Subtree, input: &mut TtCursor) -> Option<Bindings>
             _ => {}
         }
     }
-    Some(res)
+    Ok(res)
 }
 
 fn expand_subtree(
     template: &crate::Subtree,
     bindings: &Bindings,
     nesting: &mut Vec<usize>,
-) -> Option<tt::Subtree> {
+) -> Result<tt::Subtree, ExpandError> {
     let token_trees = template
         .token_trees
         .iter()
         .map(|it| expand_tt(it, bindings, nesting))
-        .collect::<Option<Vec<_>>>()?;
+        .collect::<Result<Vec<_>, ExpandError>>()?;
 
-    Some(tt::Subtree { token_trees, delimiter: template.delimiter })
+    Ok(tt::Subtree { token_trees, delimiter: template.delimiter })
 }
 
 fn expand_tt(
     template: &crate::TokenTree,
     bindings: &Bindings,
     nesting: &mut Vec<usize>,
-) -> Option<tt::TokenTree> {
+) -> Result<tt::TokenTree, ExpandError> {
     let res: tt::TokenTree = match template {
         crate::TokenTree::Subtree(subtree) => expand_subtree(subtree, bindings, nesting)?.into(),
         crate::TokenTree::Repeat(repeat) => {
+            let count = repeat_count(&repeat.subtree, bindings, nesting)?;
             let mut token_trees = Vec::new();
-            nesting.push(0);
-            while let Some(t) = expand_subtree(&repeat.subtree, bindings, nesting) {
-                let idx = nesting.pop().unwrap();
-                nesting.push(idx + 1);
-                token_trees.push(t.into())
+            for idx in 0..count {
+                nesting.push(idx);
+                let t = expand_subtree(&repeat.subtree, bindings, nesting);
+                nesting.pop().unwrap();
+                token_trees.push(t?.into())
             }
-            nesting.pop().unwrap();
             tt::Subtree { token_trees, delimiter: tt::Delimiter::None }.into()
         }
         crate::TokenTree::Leaf(leaf) => match leaf {
             crate::Leaf::Ident(ident) => {
-                tt::Leaf::from(tt::Ident { text: ident.text.clone(), id: TokenId::unspecified() })
-                    .into()
+                tt::Leaf::from(tt::Ident { text: ident.text.clone(), id: ident.id }).into()
             }
             crate::Leaf::Punct(punct) => tt::Leaf::from(punct.clone()).into(),
             crate::Leaf::Var(v) => bindings.get(&v.text, nesting)?.clone(),
             crate::Leaf::Literal(l) => tt::Leaf::from(tt::Literal { text: l.text.clone() }).into(),
         },
     };
-    Some(res)
+    Ok(res)
+}
+
+/// Determines how many times a repetition in the template is expanded: all
+/// meta variables inside it that repeat at the current depth must have been
+/// matched the same number of times.
+fn repeat_count(
+    template: &crate::Subtree,
+    bindings: &Bindings,
+    nesting: &[usize],
+) -> Result<usize, ExpandError> {
+    let mut vars = Vec::new();
+    collect_vars(template, &mut vars);
+    let mut res: Option<(&SmolStr, usize)> = None;
+    for var in vars {
+        let mut b = match bindings.inner.get(var) {
+            Some(it) => it,
+            None => continue,
+        };
+        for &idx in nesting.iter() {
+            b = match b {
+                Binding::Nested(bs) => match bs.get(idx) {
+                    Some(it) => it,
+                    None => break,
+                },
+                Binding::Simple(_) => break,
+            };
+        }
+        let len = match b {
+            Binding::Nested(bs) => bs.len(),
+            Binding::Simple(_) => continue,
+        };
+        match res {
+            Some((other, count)) if count != len => {
+                return Err(ExpandError::BindingError(format!(
+                    "meta-variable `{}` repeats {} times, but `{}` repeats {} times",
+                    other, count, var, len
+                )));
+            }
+            _ => res = Some((var, len)),
+        }
+    }
+    res.map(|(_, count)| count).ok_or_else(|| {
+        ExpandError::BindingError(
+            "repetition contains no meta-variables repeating at this depth".to_string(),
+        )
+    })
+}
+
+fn collect_vars<'a>(template: &'a crate::Subtree, acc: &mut Vec<&'a SmolStr>) {
+    for tt in template.token_trees.iter() {
+        match tt {
+            crate::TokenTree::Leaf(crate::Leaf::Var(v)) => acc.push(&v.text),
+            crate::TokenTree::Subtree(subtree) => collect_vars(subtree, acc),
+            crate::TokenTree::Repeat(repeat) => collect_vars(&repeat.subtree, acc),
+            crate::TokenTree::Leaf(_) => (),
+        }
+    }
 }
@@ -1,48 +1,52 @@
 /// This module parses a raw `tt::TokenStream` into macro-by-example token
 /// stream. This is a *mostly* identify function, expect for handling of
 /// `$var:tt_kind` and `$(repeat),*` constructs.
+use crate::ParseError;
 use crate::tt_cursor::TtCursor;
 
-pub(crate) fn parse(tt: &tt::Subtree) -> Option<crate::MacroRules> {
+pub(crate) fn parse(tt: &tt::Subtree) -> Result<crate::MacroRules, ParseError> {
     let mut parser = TtCursor::new(tt);
     let mut rules = Vec::new();
     while !parser.is_eof() {
         rules.push(parse_rule(&mut parser)?);
         if parser.expect_char(';') == None {
             if !parser.is_eof() {
-                return None;
+                return Err(ParseError::Expected("`;`".to_string()));
             }
             break;
         }
     }
-    Some(crate::MacroRules { rules })
+    Ok(crate::MacroRules { rules, shift: crate::Shift::new(tt) })
 }
 
-fn parse_rule(p: &mut TtCursor) -> Option<crate::Rule> {
-    let lhs = parse_subtree(p.eat_subtree()?)?;
-    p.expect_char('=')?;
-    p.expect_char('>')?;
-    let mut rhs = parse_subtree(p.eat_subtree()?)?;
+fn parse_rule(p: &mut TtCursor) -> Result<crate::Rule, ParseError> {
+    let lhs = parse_subtree(p.eat_subtree().ok_or_else(|| expected("macro matcher"))?)?;
+    p.expect_char('=').ok_or_else(|| expected("`=>`"))?;
+    p.expect_char('>').ok_or_else(|| expected("`=>`"))?;
+    let mut rhs = parse_subtree(p.eat_subtree().ok_or_else(|| expected("macro body"))?)?;
     rhs.delimiter = crate::Delimiter::None;
-    Some(crate::Rule { lhs, rhs })
+    Ok(crate::Rule { lhs, rhs })
 }
 
-fn parse_subtree(tt: &tt::Subtree) -> Option<crate::Subtree> {
+fn parse_subtree(tt: &tt::Subtree) -> Result<crate::Subtree, ParseError> {
     let mut token_trees = Vec::new();
     let mut p = TtCursor::new(tt);
     while let Some(tt) = p.eat() {
         let child: crate::TokenTree = match tt {
             tt::TokenTree::Leaf(leaf) => match leaf {
-                tt::Leaf::Punct(tt::Punct { char: '$', .. }) => {
-                    if p.at_ident().is_some() {
-                        crate::Leaf::from(parse_var(&mut p)?).into()
-                    } else {
-                        parse_repeat(&mut p)?.into()
+                tt::Leaf::Punct(tt::Punct { char: '$', .. }) => match p.at_ident() {
+                    // `$crate` is kept as a single identifier, which carries the
+                    // id of the definition site.
+                    Some(tt::Ident { text, id }) if text.as_str() == "crate" => {
+                        p.bump();
+                        crate::Leaf::from(crate::Ident { text: "$crate".into(), id: *id }).into()
                     }
-                }
+                    Some(_) => crate::Leaf::from(parse_var(&mut p)).into(),
+                    None => parse_repeat(&mut p)?.into(),
+                },
                 tt::Leaf::Punct(punct) => crate::Leaf::from(*punct).into(),
-                tt::Leaf::Ident(tt::Ident { text, id: _ }) => {
-                    crate::Leaf::from(crate::Ident { text: text.clone() }).into()
+                tt::Leaf::Ident(tt::Ident { text, id }) => {
+                    crate::Leaf::from(crate::Ident { text: text.clone(), id: *id }).into()
                 }
                 tt::Leaf::Literal(tt::Literal { text }) => {
                     crate::Leaf::from(crate::Literal { text: text.clone() }).into()
@@ -52,10 +56,10 @@ fn parse_subtree(tt: &tt::Subtree) -> Option<crate::Subtree> {
         };
         token_trees.push(child);
     }
-    Some(crate::Subtree { token_trees, delimiter: tt.delimiter })
+    Ok(crate::Subtree { token_trees, delimiter: tt.delimiter })
 }
 
-fn parse_var(p: &mut TtCursor) -> Option<crate::Var> {
+fn parse_var(p: &mut TtCursor) -> crate::Var {
     let ident = p.eat_ident().unwrap();
     let text = ident.text.clone();
     let kind = if p.at_char(':') {
@@ -69,25 +73,29 @@ fn parse_var(p: &mut TtCursor) -> Option<crate::Var> {
     } else {
         None
     };
-    Some(crate::Var { text, kind })
+    crate::Var { text, kind }
 }
 
-fn parse_repeat(p: &mut TtCursor) -> Option<crate::Repeat> {
-    let subtree = p.eat_subtree().unwrap();
+fn parse_repeat(p: &mut TtCursor) -> Result<crate::Repeat, ParseError> {
+    let subtree = p.eat_subtree().ok_or_else(|| expected("`(` or an identifier after `$`"))?;
     let mut subtree = parse_subtree(subtree)?;
     subtree.delimiter = crate::Delimiter::None;
-    let sep = p.eat_punct()?;
+    let sep = p.eat_punct().ok_or_else(|| expected("repetition operator"))?;
     let (separator, rep) = match sep.char {
         '*' | '+' | '?' => (None, sep.char),
-        char => (Some(char), p.eat_punct()?.char),
+        char => (Some(char), p.eat_punct().ok_or_else(|| expected("repetition operator"))?.char),
     };
 
     let kind = match rep {
         '*' => crate::RepeatKind::ZeroOrMore,
         '+' => crate::RepeatKind::OneOrMore,
         '?' => crate::RepeatKind::ZeroOrOne,
-        _ => return None,
+        _ => return Err(ParseError::InvalidRepeat(rep)),
     };
     p.bump();
-    Some(crate::Repeat { subtree, kind, separator })
+    Ok(crate::Repeat { subtree, kind, separator })
+}
+
+fn expected(what: &str) -> ParseError {
+    ParseError::Expected(what.to_string())
 }
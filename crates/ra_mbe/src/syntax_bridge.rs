@@ -1,6 +1,7 @@
+use ra_parser::{TokenSource, TreeSink, ParseError};
 use ra_syntax::{
-    AstNode, SyntaxNode, TextRange,
-    ast, SyntaxKind::*, TextUnit
+    AstNode, SyntaxNode, TextRange, SyntaxKind, SmolStr, SyntaxTreeBuilder, TreeArc, ast,
+    SyntaxKind::*, TextUnit, tokenize,
 };
 
 /// Maps `tt::TokenId` to the relative range of the original token.
@@ -10,6 +11,13 @@ pub struct TokenMap {
     toknes: Vec<TextRange>,
 }
 
+/// Maps the ranges of identifiers in a syntax tree built from a token tree
+/// back to their `tt::TokenId`s.
+#[derive(Debug, Default)]
+pub struct RevTokenMap {
+    pub ranges: Vec<(TextRange, tt::TokenId)>,
+}
+
 /// Convert the syntax tree (what user has written) to a `TokenTree` (what macro
 /// will consume).
 pub fn ast_to_token_tree(ast: &ast::TokenTree) -> Option<(tt::Subtree, TokenMap)> {
@@ -19,6 +27,136 @@ pub fn ast_to_token_tree(ast: &ast::TokenTree) -> Option<(tt::Subtree, TokenMap)
     Some((tt, token_map))
 }
 
+/// Converts an arbitrary syntax node, like an item a derive is applied to, to
+/// a `tt::Subtree` with `Delimiter::None`. Like `ast_to_token_tree`, this
+/// records the ranges of the identifiers, relative to the start of the node.
+pub fn syntax_node_to_token_tree(node: &SyntaxNode) -> Option<(tt::Subtree, TokenMap)> {
+    let mut token_map = TokenMap::default();
+    let global_offset = node.range().start();
+    let mut stack = vec![tt::Subtree { delimiter: tt::Delimiter::None, token_trees: Vec::new() }];
+    let leaves = node.descendants().filter(|it| it.leaf_text().is_some()).collect::<Vec<_>>();
+    for (idx, &leaf) in leaves.iter().enumerate() {
+        let kind = leaf.kind();
+        if kind.is_trivia() {
+            continue;
+        }
+        let open = match kind {
+            L_PAREN => Some(tt::Delimiter::Parenthesis),
+            L_CURLY => Some(tt::Delimiter::Brace),
+            L_BRACK => Some(tt::Delimiter::Bracket),
+            _ => None,
+        };
+        if let Some(delimiter) = open {
+            stack.push(tt::Subtree { delimiter, token_trees: Vec::new() });
+            continue;
+        }
+        match kind {
+            R_PAREN | R_CURLY | R_BRACK => {
+                if stack.len() == 1 {
+                    return None;
+                }
+                let subtree = stack.pop().unwrap();
+                stack.last_mut().unwrap().token_trees.push(subtree.into());
+                continue;
+            }
+            _ => (),
+        }
+        let text = leaf.leaf_text().unwrap();
+        let token_trees = &mut stack.last_mut().unwrap().token_trees;
+        if kind.is_punct() {
+            let is_joint_to_next = leaves.get(idx + 1).map_or(false, |it| is_glueable(it.kind()));
+            push_punct(token_trees, text, is_joint_to_next);
+        } else if kind.is_keyword() || kind == IDENT {
+            let id = token_map.alloc(leaf.range() - global_offset);
+            token_trees.push(tt::Leaf::from(tt::Ident { text: text.clone(), id }).into());
+        } else if kind == LIFETIME {
+            let quote = tt::Punct { char: '\'', spacing: tt::Spacing::Joint };
+            token_trees.push(tt::Leaf::from(quote).into());
+            let id = token_map.alloc(leaf.range() - global_offset);
+            token_trees.push(tt::Leaf::from(tt::Ident { text: text[1..].into(), id }).into());
+        } else if kind.is_literal() {
+            token_trees.push(tt::Leaf::from(tt::Literal { text: text.clone() }).into());
+        } else {
+            return None;
+        }
+    }
+    if stack.len() != 1 {
+        return None;
+    }
+    Some((stack.pop().unwrap(), token_map))
+}
+
+/// Parses the token tree (result of macro expansion) as a sequence of items.
+pub fn token_tree_to_ast_item_list(tt: &tt::Subtree) -> (TreeArc<ast::SourceFile>, RevTokenMap) {
+    let token_source = TtTokenSource::new(tt);
+    let mut tree_sink = TtTreeSink::new(&token_source.tokens);
+    ra_parser::parse(&token_source, &mut tree_sink);
+    let (syntax, token_map) = tree_sink.finish();
+    let file = ast::SourceFile::cast(&syntax).unwrap().to_owned();
+    (file, token_map)
+}
+
+/// Lexes `text` into a `tt::Subtree` with `Delimiter::None`. Unlike
+/// `ast_to_token_tree`, this works on arbitrary text (for example, the output of
+/// a procedural macro), so no `TokenMap` is produced and identifiers get
+/// `TokenId::unspecified()`.
+pub fn text_to_token_tree(text: &str) -> Option<tt::Subtree> {
+    let mut stack = vec![tt::Subtree { delimiter: tt::Delimiter::None, token_trees: Vec::new() }];
+    let mut offset = TextUnit::from(0);
+    let tokens = tokenize(text);
+    for (idx, token) in tokens.iter().enumerate() {
+        let range = TextRange::offset_len(offset, token.len);
+        offset += token.len;
+        let token_text = &text[range];
+        let kind = token.kind;
+        if kind.is_trivia() {
+            continue;
+        }
+        let open = match kind {
+            L_PAREN => Some(tt::Delimiter::Parenthesis),
+            L_CURLY => Some(tt::Delimiter::Brace),
+            L_BRACK => Some(tt::Delimiter::Bracket),
+            _ => None,
+        };
+        if let Some(delimiter) = open {
+            stack.push(tt::Subtree { delimiter, token_trees: Vec::new() });
+            continue;
+        }
+        match kind {
+            R_PAREN | R_CURLY | R_BRACK => {
+                if stack.len() == 1 {
+                    return None;
+                }
+                let subtree = stack.pop().unwrap();
+                stack.last_mut().unwrap().token_trees.push(subtree.into());
+                continue;
+            }
+            _ => (),
+        }
+        let token_trees = &mut stack.last_mut().unwrap().token_trees;
+        if kind.is_punct() {
+            let is_joint_to_next = tokens.get(idx + 1).map_or(false, |it| is_glueable(it.kind));
+            push_punct(token_trees, token_text, is_joint_to_next);
+        } else if kind.is_keyword() || kind == IDENT {
+            let ident = tt::Ident { text: token_text.into(), id: tt::TokenId::unspecified() };
+            token_trees.push(tt::Leaf::from(ident).into());
+        } else if kind == LIFETIME {
+            let quote = tt::Punct { char: '\'', spacing: tt::Spacing::Joint };
+            token_trees.push(tt::Leaf::from(quote).into());
+            let ident = tt::Ident { text: token_text[1..].into(), id: tt::TokenId::unspecified() };
+            token_trees.push(tt::Leaf::from(ident).into());
+        } else if kind.is_literal() {
+            token_trees.push(tt::Leaf::from(tt::Literal { text: token_text.into() }).into());
+        } else {
+            return None;
+        }
+    }
+    if stack.len() != 1 {
+        return None;
+    }
+    stack.pop()
+}
+
 impl TokenMap {
     pub fn relative_range_of(&self, tt: tt::TokenId) -> Option<TextRange> {
         let idx = tt.0 as usize;
@@ -51,19 +189,11 @@ fn convert_tt(
             continue;
         }
         if child.kind().is_punct() {
-            let mut prev = None;
-            for char in child.leaf_text().unwrap().chars() {
-                if let Some(char) = prev {
-                    token_trees.push(
-                        tt::Leaf::from(tt::Punct { char, spacing: tt::Spacing::Joint }).into(),
-                    );
-                }
-                prev = Some(char)
-            }
-            if let Some(char) = prev {
-                token_trees
-                    .push(tt::Leaf::from(tt::Punct { char, spacing: tt::Spacing::Alone }).into());
-            }
+            let is_joint_to_next = match child.next_sibling() {
+                Some(next) => next != last_child && is_glueable(next.kind()),
+                None => false,
+            };
+            push_punct(&mut token_trees, child.leaf_text().unwrap(), is_joint_to_next);
         } else {
             let child: tt::TokenTree = if child.kind() == TOKEN_TREE {
                 convert_tt(token_map, global_offset, child)?.into()
@@ -84,3 +214,274 @@ fn convert_tt(
     let res = tt::Subtree { delimiter, token_trees };
     Some(res)
 }
+
+/// Splits a (possibly compound) punctuation token into single-character
+/// `tt::Punct`s, marking all but the last one as `Joint`. The last one is
+/// `Joint` if the token is immediately followed by another punctuation token,
+/// as in `>>`.
+fn push_punct(token_trees: &mut Vec<tt::TokenTree>, text: &str, is_joint_to_next: bool) {
+    let mut prev = None;
+    for char in text.chars() {
+        if let Some(char) = prev {
+            token_trees
+                .push(tt::Leaf::from(tt::Punct { char, spacing: tt::Spacing::Joint }).into());
+        }
+        prev = Some(char)
+    }
+    if let Some(char) = prev {
+        let spacing = if is_joint_to_next { tt::Spacing::Joint } else { tt::Spacing::Alone };
+        token_trees.push(tt::Leaf::from(tt::Punct { char, spacing }).into());
+    }
+}
+
+/// Whether a punct immediately followed by a token of `kind` is `Joint`. `$`
+/// is excluded: in `$e::$v`, the second `:` is not joint with whatever gets
+/// substituted for `$v`.
+fn is_glueable(kind: SyntaxKind) -> bool {
+    kind.is_punct() && kind != DOLLAR && !is_delimiter(kind)
+}
+
+fn is_delimiter(kind: SyntaxKind) -> bool {
+    match kind {
+        L_PAREN | R_PAREN | L_CURLY | R_CURLY | L_BRACK | R_BRACK => true,
+        _ => false,
+    }
+}
+
+fn is_opening(kind: SyntaxKind) -> bool {
+    match kind {
+        L_PAREN | L_CURLY | L_BRACK => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+struct TtToken {
+    kind: SyntaxKind,
+    is_joint_to_next: bool,
+    text: SmolStr,
+    id: Option<tt::TokenId>,
+}
+
+/// Flattens a `tt::Subtree` into the sequence of tokens the parser expects.
+/// Compound punctuation, which the lexer produces as a single token, is glued
+/// back together here.
+struct TtTokenSource {
+    tokens: Vec<TtToken>,
+}
+
+impl TtTokenSource {
+    fn new(tt: &tt::Subtree) -> TtTokenSource {
+        let mut res = TtTokenSource { tokens: Vec::new() };
+        res.convert_subtree(tt);
+        res
+    }
+
+    fn convert_subtree(&mut self, subtree: &tt::Subtree) {
+        let (l, r) = match subtree.delimiter {
+            tt::Delimiter::Parenthesis => (Some((L_PAREN, "(")), Some((R_PAREN, ")"))),
+            tt::Delimiter::Brace => (Some((L_CURLY, "{")), Some((R_CURLY, "}"))),
+            tt::Delimiter::Bracket => (Some((L_BRACK, "[")), Some((R_BRACK, "]"))),
+            tt::Delimiter::None => (None, None),
+        };
+        if let Some((kind, text)) = l {
+            self.push(kind, false, text.into(), None);
+        }
+        let mut idx = 0;
+        while idx < subtree.token_trees.len() {
+            idx += self.convert_tt(&subtree.token_trees[idx..]);
+        }
+        if let Some((kind, text)) = r {
+            self.push(kind, false, text.into(), None);
+        }
+    }
+
+    /// Converts the first token tree of `tts`, returning the number of token
+    /// trees consumed.
+    fn convert_tt(&mut self, tts: &[tt::TokenTree]) -> usize {
+        let leaf = match &tts[0] {
+            tt::TokenTree::Subtree(subtree) => {
+                self.convert_subtree(subtree);
+                return 1;
+            }
+            tt::TokenTree::Leaf(leaf) => leaf,
+        };
+        match leaf {
+            tt::Leaf::Ident(ident) => {
+                let kind = SyntaxKind::from_keyword(&ident.text).unwrap_or(IDENT);
+                self.push(kind, false, ident.text.clone(), Some(ident.id));
+                1
+            }
+            tt::Leaf::Literal(lit) => {
+                let kind = tokenize(&lit.text).first().map_or(ERROR, |it| it.kind);
+                self.push(kind, false, lit.text.clone(), None);
+                1
+            }
+            tt::Leaf::Punct(punct) => {
+                if punct.char == '\'' && punct.spacing == tt::Spacing::Joint {
+                    if let Some(tt::TokenTree::Leaf(tt::Leaf::Ident(ident))) = tts.get(1) {
+                        let text = format!("'{}", ident.text);
+                        self.push(LIFETIME, false, text.into(), None);
+                        return 2;
+                    }
+                }
+                self.convert_punct(tts)
+            }
+        }
+    }
+
+    fn convert_punct(&mut self, tts: &[tt::TokenTree]) -> usize {
+        let puncts = tts
+            .iter()
+            .take(3)
+            .map(|it| match it {
+                tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => Some(*punct),
+                _ => None,
+            })
+            .take_while(|it| it.is_some())
+            .map(|it| it.unwrap())
+            .collect::<Vec<_>>();
+        // Only a prefix of puncts joint to each other can form a compound token.
+        let n_joint = puncts.iter().take_while(|it| it.spacing == tt::Spacing::Joint).count();
+        let puncts = &puncts[..(n_joint + 1).min(puncts.len())];
+
+        for len in (2..=puncts.len()).rev() {
+            let text = puncts[..len].iter().map(|it| it.char).collect::<String>();
+            let kind = match text.as_str() {
+                "..." => DOTDOTDOT,
+                "..=" => DOTDOTEQ,
+                ".." => DOTDOT,
+                "::" => COLONCOLON,
+                "==" => EQEQ,
+                "=>" => FAT_ARROW,
+                "!=" => NEQ,
+                "->" => THIN_ARROW,
+                _ => continue,
+            };
+            let is_joint_to_next = puncts[len - 1].spacing == tt::Spacing::Joint;
+            self.push(kind, is_joint_to_next, text.into(), None);
+            return len;
+        }
+
+        let punct = puncts[0];
+        let kind = match punct.char {
+            ';' => SEMI,
+            ',' => COMMA,
+            '.' => DOT,
+            ':' => COLON,
+            '=' => EQ,
+            '!' => EXCL,
+            '-' => MINUS,
+            '<' => L_ANGLE,
+            '>' => R_ANGLE,
+            '@' => AT,
+            '#' => POUND,
+            '~' => TILDE,
+            '?' => QUESTION,
+            '$' => DOLLAR,
+            '&' => AMP,
+            '|' => PIPE,
+            '+' => PLUS,
+            '*' => STAR,
+            '/' => SLASH,
+            '^' => CARET,
+            '%' => PERCENT,
+            '_' => UNDERSCORE,
+            _ => ERROR,
+        };
+        let is_joint_to_next = punct.spacing == tt::Spacing::Joint;
+        self.push(kind, is_joint_to_next, punct.char.to_string().into(), None);
+        1
+    }
+
+    fn push(
+        &mut self,
+        kind: SyntaxKind,
+        is_joint_to_next: bool,
+        text: SmolStr,
+        id: Option<tt::TokenId>,
+    ) {
+        self.tokens.push(TtToken { kind, is_joint_to_next, text, id })
+    }
+}
+
+impl TokenSource for TtTokenSource {
+    fn token_kind(&self, pos: usize) -> SyntaxKind {
+        self.tokens.get(pos).map_or(EOF, |it| it.kind)
+    }
+    fn is_token_joint_to_next(&self, pos: usize) -> bool {
+        self.tokens.get(pos).map_or(true, |it| it.is_joint_to_next)
+    }
+    fn is_keyword(&self, pos: usize, kw: &str) -> bool {
+        self.tokens.get(pos).map_or(false, |it| it.text.as_str() == kw)
+    }
+}
+
+/// Builds a `SyntaxNode` from the tokens of `TtTokenSource`, remembering the
+/// ranges of the identifiers. Tokens are separated by a single space, the same
+/// way `tt::Subtree` is displayed.
+struct TtTreeSink<'a> {
+    tokens: &'a [TtToken],
+    token_pos: usize,
+    text_pos: TextUnit,
+    needs_space: bool,
+    token_map: RevTokenMap,
+    inner: SyntaxTreeBuilder,
+}
+
+impl<'a> TtTreeSink<'a> {
+    fn new(tokens: &'a [TtToken]) -> TtTreeSink<'a> {
+        TtTreeSink {
+            tokens,
+            token_pos: 0,
+            text_pos: 0.into(),
+            needs_space: false,
+            token_map: RevTokenMap::default(),
+            inner: SyntaxTreeBuilder::default(),
+        }
+    }
+
+    fn finish(self) -> (TreeArc<SyntaxNode>, RevTokenMap) {
+        (self.inner.finish(), self.token_map)
+    }
+
+    fn add_space(&mut self) {
+        let next_kind = self.tokens.get(self.token_pos).map(|it| it.kind);
+        let is_closing = next_kind.map_or(false, |kind| is_delimiter(kind) && !is_opening(kind));
+        if self.needs_space && !is_closing {
+            self.needs_space = false;
+            self.text_pos += TextUnit::of_char(' ');
+            self.inner.leaf(WHITESPACE, " ".into());
+        }
+    }
+}
+
+impl<'a> TreeSink for TtTreeSink<'a> {
+    fn leaf(&mut self, kind: SyntaxKind, n_tokens: u8) {
+        self.add_space();
+        let tokens = &self.tokens[self.token_pos..self.token_pos + n_tokens as usize];
+        let text: SmolStr = tokens.iter().map(|it| it.text.as_str()).collect::<String>().into();
+        let range = TextRange::offset_len(self.text_pos, TextUnit::of_str(&text));
+        if let [TtToken { id: Some(id), .. }] = tokens {
+            self.token_map.ranges.push((range, *id));
+        }
+        self.token_pos += tokens.len();
+        self.text_pos = range.end();
+        self.needs_space =
+            tokens.last().map_or(false, |it| !it.is_joint_to_next && !is_opening(it.kind));
+        self.inner.leaf(kind, text);
+    }
+
+    fn start_branch(&mut self, kind: SyntaxKind) {
+        self.add_space();
+        self.inner.start_branch(kind);
+    }
+
+    fn finish_branch(&mut self) {
+        self.inner.finish_branch();
+    }
+
+    fn error(&mut self, error: ParseError) {
+        self.inner.error(error, self.text_pos)
+    }
+}
@@ -0,0 +1,95 @@
+//! Client-side proc-macro crate
+//!
+//! We separate proc-macro expanding logic to an extern program to allow
+//! different implementations (e.g. wasm or dylib loading). And this crate
+//! is used to provide basic infrastructure for communication between two
+//! processes: Client (RA itself), Server (the external program)
+
+pub mod msg;
+mod process;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{msg::ExpansionTask, process::ProcMacroProcessSrv};
+
+pub use tt::ProcMacroKind;
+
+#[derive(Debug, Clone)]
+pub struct ProcMacroProcessExpander {
+    process: Arc<ProcMacroProcessSrv>,
+    dylib_path: PathBuf,
+    name: String,
+}
+
+impl tt::TokenExpander for ProcMacroProcessExpander {
+    fn expand(
+        &self,
+        subtree: &tt::Subtree,
+        attributes: Option<&tt::Subtree>,
+    ) -> Result<tt::Subtree, tt::ExpansionError> {
+        let task = ExpansionTask {
+            macro_body: subtree.clone(),
+            macro_name: self.name.clone(),
+            attributes: attributes.cloned(),
+            lib: self.dylib_path.to_string_lossy().to_string(),
+        };
+        self.process.expand(task)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ProcMacroClientKind {
+    Process { process: Arc<ProcMacroProcessSrv> },
+    Dummy,
+}
+
+/// Entry point for proc-macro expansion: knows how to list the macros exported
+/// by a compiled proc-macro crate and produces an expander for each of them.
+#[derive(Debug, Clone)]
+pub struct ProcMacroClient {
+    kind: ProcMacroClientKind,
+}
+
+impl ProcMacroClient {
+    /// Spawns the proc-macro server at `process_path`.
+    pub fn extern_process(process_path: &Path) -> std::io::Result<ProcMacroClient> {
+        let process = ProcMacroProcessSrv::run(process_path)?;
+        Ok(ProcMacroClient { kind: ProcMacroClientKind::Process { process: Arc::new(process) } })
+    }
+
+    /// A client which doesn't know about any proc macros.
+    pub fn dummy() -> ProcMacroClient {
+        ProcMacroClient { kind: ProcMacroClientKind::Dummy }
+    }
+
+    pub fn by_dylib_path(
+        &self,
+        dylib_path: &Path,
+    ) -> Vec<(String, ProcMacroKind, Arc<dyn tt::TokenExpander>)> {
+        let process = match &self.kind {
+            ProcMacroClientKind::Dummy => return Vec::new(),
+            ProcMacroClientKind::Process { process } => process,
+        };
+        let macros = match process.find_proc_macros(dylib_path) {
+            Ok(it) => it,
+            Err(err) => {
+                log::error!("failed to load proc macros from {}: {}", dylib_path.display(), err);
+                return Vec::new();
+            }
+        };
+        macros
+            .into_iter()
+            .map(|(name, kind)| {
+                let expander: Arc<dyn tt::TokenExpander> = Arc::new(ProcMacroProcessExpander {
+                    process: Arc::clone(process),
+                    dylib_path: dylib_path.to_path_buf(),
+                    name: name.clone(),
+                });
+                (name, kind, expander)
+            })
+            .collect()
+    }
+}
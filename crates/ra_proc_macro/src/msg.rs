@@ -0,0 +1,153 @@
+//! Defines messages for the proc-macro server protocol.
+//!
+//! The protocol is deliberately simple: each message is a single line of JSON,
+//! the client writes a `Request` to the stdin of the server and reads exactly
+//! one `Response` from its stdout.
+
+use std::io::{self, BufRead, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tt::ProcMacroKind;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Request {
+    ListMacro(ListMacrosTask),
+    ExpansionMacro(ExpansionTask),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Response {
+    Error(ResponseError),
+    ListMacro(ListMacrosResult),
+    ExpansionMacro(ExpansionResult),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResponseError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    ServerErrorEnd,
+    ExpansionError,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListMacrosTask {
+    /// Path to the proc-macro dylib (`.so`, `.dylib` or `.dll`) to load.
+    pub lib: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListMacrosResult {
+    pub macros: Vec<(String, ProcMacroKind)>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExpansionTask {
+    /// Argument of macro call.
+    ///
+    /// In custom derive this will be a struct or enum; in attribute-like macro -
+    /// underlying item; in function-like macro - the macro body.
+    pub macro_body: tt::Subtree,
+    /// Name of macro to expand.
+    ///
+    /// In custom derive this is the name of the derived trait (`Serialize`,
+    /// `Getters`, etc.); in attribute-like and function-like macros - the
+    /// function name (`get`, `post`, etc.).
+    pub macro_name: String,
+    /// Possible attributes for the attribute-like macros.
+    pub attributes: Option<tt::Subtree>,
+    /// Path to the dylib the macro lives in.
+    pub lib: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExpansionResult {
+    pub expansion: tt::Subtree,
+}
+
+pub trait Message: Serialize + DeserializeOwned {
+    fn read(r: &mut impl BufRead) -> io::Result<Option<Self>> {
+        let text = match read_json(r)? {
+            None => return Ok(None),
+            Some(text) => text,
+        };
+        let msg = serde_json::from_str(&text)?;
+        Ok(Some(msg))
+    }
+    fn write(self, w: &mut impl Write) -> io::Result<()> {
+        let text = serde_json::to_string(&self)?;
+        write_json(w, &text)
+    }
+}
+
+impl Message for Request {}
+impl Message for Response {}
+
+fn read_json(inp: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        if inp.read_line(&mut buf)? == 0 {
+            return Ok(None);
+        }
+        // Some ill behaved macro (e.g. one which prints to stdout) may break the
+        // protocol; skip lines which are clearly not ours.
+        let line = buf.trim();
+        if line.starts_with('{') {
+            return Ok(Some(line.to_string()));
+        }
+        log::warn!("proc-macro server: ignoring stray output {:?}", line);
+    }
+}
+
+fn write_json(out: &mut impl Write, msg: &str) -> io::Result<()> {
+    log::trace!("> {}", msg);
+    out.write_all(msg.as_bytes())?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_subtree() -> tt::Subtree {
+        let ident = tt::Ident { text: "Foo".into(), id: tt::TokenId(0) };
+        let punct = tt::Punct { char: ';', spacing: tt::Spacing::Alone };
+        tt::Subtree {
+            delimiter: tt::Delimiter::None,
+            token_trees: vec![
+                tt::Leaf::from(tt::Ident { text: "struct".into(), id: tt::TokenId::unspecified() })
+                    .into(),
+                tt::Leaf::from(ident).into(),
+                tt::Leaf::from(punct).into(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_proc_macro_rpc_roundtrip() {
+        let task = ExpansionTask {
+            macro_body: fixture_subtree(),
+            macro_name: "Derive".to_string(),
+            attributes: None,
+            lib: "libfoo.so".to_string(),
+        };
+
+        let mut buf = Vec::new();
+        Request::ExpansionMacro(task.clone()).write(&mut buf).unwrap();
+        let back = Request::read(&mut buf.as_slice()).unwrap().unwrap();
+        match back {
+            Request::ExpansionMacro(back) => {
+                assert_eq!(back.macro_body, task.macro_body);
+                assert_eq!(back.macro_name, task.macro_name);
+            }
+            _ => panic!("unexpected request: {:?}", back),
+        }
+    }
+}
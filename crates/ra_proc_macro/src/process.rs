@@ -0,0 +1,113 @@
+//! Handles the lifetime of the external proc-macro server process.
+
+use std::{
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::Mutex,
+};
+
+use tt::ProcMacroKind;
+
+use crate::msg::{
+    ErrorCode, ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask, Message, Request,
+    Response,
+};
+
+#[derive(Debug)]
+pub(crate) struct ProcMacroProcessSrv {
+    path: PathBuf,
+    inner: Mutex<Option<Process>>,
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Process {
+    fn spawn(path: &Path) -> io::Result<Process> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Process { child, stdin, stdout })
+    }
+
+    fn send_request(&mut self, req: Request) -> io::Result<Option<Response>> {
+        req.write(&mut self.stdin)?;
+        Response::read(&mut self.stdout)
+    }
+}
+
+impl ProcMacroProcessSrv {
+    pub(crate) fn run(path: &Path) -> io::Result<ProcMacroProcessSrv> {
+        let process = Process::spawn(path)?;
+        Ok(ProcMacroProcessSrv { path: path.to_path_buf(), inner: Mutex::new(Some(process)) })
+    }
+
+    pub(crate) fn find_proc_macros(
+        &self,
+        dylib_path: &Path,
+    ) -> Result<Vec<(String, ProcMacroKind)>, tt::ExpansionError> {
+        let task = ListMacrosTask { lib: dylib_path.to_string_lossy().to_string() };
+        match self.send_task(Request::ListMacro(task))? {
+            Response::ListMacro(ListMacrosResult { macros }) => Ok(macros),
+            it => Err(unexpected_response(it)),
+        }
+    }
+
+    pub(crate) fn expand(&self, task: ExpansionTask) -> Result<tt::Subtree, tt::ExpansionError> {
+        match self.send_task(Request::ExpansionMacro(task))? {
+            Response::ExpansionMacro(ExpansionResult { expansion }) => Ok(expansion),
+            it => Err(unexpected_response(it)),
+        }
+    }
+
+    fn send_task(&self, req: Request) -> Result<Response, tt::ExpansionError> {
+        // std's `Mutex` (unlike parking_lot's) is `RefUnwindSafe`, which
+        // `tt::TokenExpander` requires.
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        // The server might have died (a proc macro can abort the process), so
+        // respawn it lazily instead of failing every subsequent expansion.
+        if inner.is_none() {
+            let process = Process::spawn(&self.path)
+                .map_err(|err| tt::ExpansionError::IOError(err.to_string()))?;
+            *inner = Some(process);
+        }
+        let res = inner.as_mut().unwrap().send_request(req);
+        match res {
+            Ok(Some(Response::Error(err))) => {
+                if err.code == ErrorCode::ServerErrorEnd {
+                    *inner = None;
+                }
+                Err(tt::ExpansionError::ExpansionError(err.message))
+            }
+            Ok(Some(res)) => Ok(res),
+            Ok(None) => {
+                *inner = None;
+                Err(tt::ExpansionError::Unknown("proc-macro server exited".to_string()))
+            }
+            Err(err) => {
+                *inner = None;
+                Err(tt::ExpansionError::IOError(err.to_string()))
+            }
+        }
+    }
+}
+
+fn unexpected_response(res: Response) -> tt::ExpansionError {
+    tt::ExpansionError::Unknown(format!("unexpected response: {:?}", res))
+}
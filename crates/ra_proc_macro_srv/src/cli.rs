@@ -0,0 +1,27 @@
+//! The main loop of the server.
+
+use std::io;
+
+use ra_proc_macro::msg::{ErrorCode, Message, Request, Response, ResponseError};
+
+use crate::ProcMacroSrv;
+
+pub fn run() -> io::Result<()> {
+    let mut srv = ProcMacroSrv::default();
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    while let Some(req) = Request::read(&mut stdin)? {
+        let res = match req {
+            Request::ListMacro(task) => srv.list_macros(&task).map(Response::ListMacro),
+            Request::ExpansionMacro(task) => srv.expand(&task).map(Response::ExpansionMacro),
+        };
+        let res = res.unwrap_or_else(|message| {
+            Response::Error(ResponseError { code: ErrorCode::ExpansionError, message })
+        });
+        res.write(&mut stdout)?;
+    }
+    Ok(())
+}
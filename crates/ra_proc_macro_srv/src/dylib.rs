@@ -0,0 +1,126 @@
+//! Handles dynamic library loading for proc macros.
+
+use std::path::Path;
+
+use goblin::{mach::Mach, Object};
+use libloading::Library;
+use proc_macro::bridge::{self, client::ProcMacro};
+use ra_proc_macro::ProcMacroKind;
+
+use crate::rustc_server::{Rustc, TokenStream};
+
+/// Proc-macro dylibs export a single symbol, `__rustc_proc_macro_decls_{hash}__`,
+/// which points to the slice of all macros defined by the crate.
+const REGISTRAR_SYMBOL_PREFIX: &str = "__rustc_proc_macro_decls_";
+
+fn invalid_data_err(e: impl ToString) -> String {
+    e.to_string()
+}
+
+fn get_symbols_from_lib(file: &Path) -> Result<Vec<String>, String> {
+    let buffer = std::fs::read(file).map_err(invalid_data_err)?;
+    let object = Object::parse(&buffer).map_err(invalid_data_err)?;
+
+    let res = match object {
+        Object::Elf(elf) => elf
+            .dynsyms
+            .iter()
+            .filter_map(|sym| elf.dynstrtab.get(sym.st_name)?.ok())
+            .map(String::from)
+            .collect(),
+        Object::PE(pe) => pe.exports.iter().filter_map(|it| it.name).map(String::from).collect(),
+        Object::Mach(Mach::Binary(binary)) => binary
+            .exports()
+            .map_err(invalid_data_err)?
+            .into_iter()
+            // Mach-O symbols carry an extra leading underscore, which `dlsym`
+            // adds back itself.
+            .map(|it| it.name.trim_start_matches('_').to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok(res)
+}
+
+fn find_registrar_symbol(file: &Path) -> Result<String, String> {
+    let symbols = get_symbols_from_lib(file)?;
+    symbols.into_iter().find(|it| it.starts_with(REGISTRAR_SYMBOL_PREFIX)).ok_or_else(|| {
+        format!("{} is not a proc-macro library: can't find registrar symbol", file.display())
+    })
+}
+
+struct ProcMacroLibrary {
+    // Must outlive `exported_macros`, which point into the library.
+    _lib: Library,
+    exported_macros: Vec<ProcMacro>,
+}
+
+impl ProcMacroLibrary {
+    fn open(file: &Path) -> Result<ProcMacroLibrary, String> {
+        let symbol_name = find_registrar_symbol(file)?;
+        let lib = Library::new(file).map_err(invalid_data_err)?;
+        let exported_macros = {
+            let macros: libloading::Symbol<&&[ProcMacro]> =
+                unsafe { lib.get(symbol_name.as_bytes()) }.map_err(invalid_data_err)?;
+            macros.to_vec()
+        };
+        Ok(ProcMacroLibrary { _lib: lib, exported_macros })
+    }
+}
+
+pub(crate) struct Expander {
+    lib: ProcMacroLibrary,
+}
+
+impl Expander {
+    pub(crate) fn new(lib: &Path) -> Result<Expander, String> {
+        let lib = ProcMacroLibrary::open(lib)?;
+        Ok(Expander { lib })
+    }
+
+    pub(crate) fn expand(
+        &self,
+        macro_name: &str,
+        macro_body: &tt::Subtree,
+        attributes: Option<&tt::Subtree>,
+    ) -> Result<tt::Subtree, bridge::PanicMessage> {
+        let parsed_body = TokenStream::with_subtree(macro_body.clone());
+        let parsed_attributes = attributes
+            .map_or_else(TokenStream::new, |attr| TokenStream::with_subtree(attr.clone()));
+
+        for proc_macro in self.lib.exported_macros.iter() {
+            let res = match proc_macro {
+                ProcMacro::CustomDerive { trait_name, client, .. } if *trait_name == macro_name => {
+                    client.run(&bridge::server::SameThread, Rustc::default(), parsed_body)
+                }
+                ProcMacro::Bang { name, client } if *name == macro_name => {
+                    client.run(&bridge::server::SameThread, Rustc::default(), parsed_body)
+                }
+                ProcMacro::Attr { name, client } if *name == macro_name => client.run(
+                    &bridge::server::SameThread,
+                    Rustc::default(),
+                    parsed_attributes,
+                    parsed_body,
+                ),
+                _ => continue,
+            };
+            return res.map(|it| it.subtree);
+        }
+
+        Err(bridge::PanicMessage::String(format!("no proc macro named {}", macro_name)))
+    }
+
+    pub(crate) fn list_macros(&self) -> Vec<(String, ProcMacroKind)> {
+        self.lib
+            .exported_macros
+            .iter()
+            .map(|proc_macro| match proc_macro {
+                ProcMacro::CustomDerive { trait_name, .. } => {
+                    (trait_name.to_string(), ProcMacroKind::CustomDerive)
+                }
+                ProcMacro::Bang { name, .. } => (name.to_string(), ProcMacroKind::FuncLike),
+                ProcMacro::Attr { name, .. } => (name.to_string(), ProcMacroKind::Attr),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,62 @@
+//! RA Proc Macro Server
+//!
+//! This library is able to call compiled Rust procedural macros (custom derives,
+//! attribute-like and function-like macros) from dynamic libraries on arbitrary
+//! token trees.
+//!
+//! The dylib is loaded with `libloading`, and the macros are invoked through
+//! the `proc_macro` bridge, with `rustc_server::Rustc` playing the role of the
+//! compiler. Because the bridge is unstable, this crate must be built with the
+//! same nightly compiler the proc macros were compiled with.
+#![feature(proc_macro_internals, proc_macro_diagnostic, proc_macro_span)]
+
+extern crate proc_macro;
+
+mod dylib;
+mod rustc_server;
+pub mod cli;
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use ra_proc_macro::msg::{ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask};
+
+/// Keeps the loaded dylibs alive between requests: loading a library is
+/// expensive, and the client usually asks to expand many macros from a handful
+/// of libraries.
+#[derive(Default)]
+pub struct ProcMacroSrv {
+    expanders: HashMap<PathBuf, dylib::Expander>,
+}
+
+impl ProcMacroSrv {
+    pub fn list_macros(&mut self, task: &ListMacrosTask) -> Result<ListMacrosResult, String> {
+        let expander = self.expander(Path::new(&task.lib))?;
+        Ok(ListMacrosResult { macros: expander.list_macros() })
+    }
+
+    pub fn expand(&mut self, task: &ExpansionTask) -> Result<ExpansionResult, String> {
+        let expander = self.expander(Path::new(&task.lib))?;
+        match expander.expand(&task.macro_name, &task.macro_body, task.attributes.as_ref()) {
+            Ok(expansion) => Ok(ExpansionResult { expansion }),
+            Err(msg) => Err(format!(
+                "cannot perform expansion for {}: {}",
+                task.macro_name,
+                msg.as_str().unwrap_or("<unknown panic>")
+            )),
+        }
+    }
+
+    fn expander(&mut self, path: &Path) -> Result<&dylib::Expander, String> {
+        if !self.expanders.contains_key(path) {
+            let expander = dylib::Expander::new(path)?;
+            self.expanders.insert(path.to_path_buf(), expander);
+        }
+        Ok(&self.expanders[path])
+    }
+}
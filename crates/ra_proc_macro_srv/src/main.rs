@@ -0,0 +1,9 @@
+//! Driver for the proc-macro server: reads requests from stdin and writes
+//! responses to stdout, see `ra_proc_macro::msg` for the protocol.
+
+fn main() {
+    if let Err(err) = ra_proc_macro_srv::cli::run() {
+        eprintln!("proc-macro server failed: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,443 @@
+//! Implementation of the `proc_macro` bridge "server".
+//!
+//! When a proc macro runs, every operation on `proc_macro::TokenStream` and
+//! friends is forwarded over the bridge to the compiler. Here, we play the role
+//! of the compiler: `Rustc` implements the server traits on top of `tt` data
+//! structures. Spans are `tt::TokenId`s, so that tokens which came from the
+//! macro input keep their identity in the output.
+
+use std::{ascii, collections::HashMap, ops::Bound, vec::IntoIter};
+
+use proc_macro::{
+    bridge::{self, server},
+    Delimiter, Level, LineColumn, Spacing,
+};
+
+type Group = tt::Subtree;
+type Punct = tt::Punct;
+type Literal = tt::Literal;
+type Span = tt::TokenId;
+
+#[derive(Debug, Clone)]
+pub(crate) struct TokenStream {
+    pub(crate) subtree: tt::Subtree,
+}
+
+impl TokenStream {
+    pub(crate) fn new() -> TokenStream {
+        TokenStream::from_trees(Vec::new())
+    }
+
+    /// Creates a stream from a macro input. A delimited subtree becomes a
+    /// single group, a `Delimiter::None` subtree is used as is.
+    pub(crate) fn with_subtree(subtree: tt::Subtree) -> TokenStream {
+        if subtree.delimiter == tt::Delimiter::None {
+            TokenStream { subtree }
+        } else {
+            TokenStream::from_trees(vec![subtree.into()])
+        }
+    }
+
+    fn from_trees(token_trees: Vec<tt::TokenTree>) -> TokenStream {
+        TokenStream { subtree: tt::Subtree { delimiter: tt::Delimiter::None, token_trees } }
+    }
+}
+
+pub(crate) struct TokenStreamBuilder {
+    acc: Vec<tt::TokenTree>,
+}
+
+#[derive(Clone)]
+pub(crate) struct TokenStreamIter {
+    trees: IntoIter<tt::TokenTree>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct SourceFile {
+    path: String,
+}
+
+pub(crate) struct Diagnostic {
+    level: Level,
+    message: String,
+    children: Vec<Diagnostic>,
+}
+
+/// `proc_macro` requires idents to be `Copy`, so we intern them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct IdentId(u32);
+
+#[derive(Default)]
+struct IdentInterner {
+    ids: HashMap<tt::Ident, u32>,
+    idents: Vec<tt::Ident>,
+}
+
+impl IdentInterner {
+    fn intern(&mut self, ident: tt::Ident) -> IdentId {
+        if let Some(&id) = self.ids.get(&ident) {
+            return IdentId(id);
+        }
+        let id = self.idents.len() as u32;
+        self.idents.push(ident.clone());
+        self.ids.insert(ident, id);
+        IdentId(id)
+    }
+
+    fn get(&self, id: IdentId) -> &tt::Ident {
+        &self.idents[id.0 as usize]
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Rustc {
+    ident_interner: IdentInterner,
+}
+
+impl server::Types for Rustc {
+    type TokenStream = TokenStream;
+    type TokenStreamBuilder = TokenStreamBuilder;
+    type TokenStreamIter = TokenStreamIter;
+    type Group = Group;
+    type Punct = Punct;
+    type Ident = IdentId;
+    type Literal = Literal;
+    type SourceFile = SourceFile;
+    type Diagnostic = Diagnostic;
+    type Span = Span;
+    type MultiSpan = Vec<Span>;
+}
+
+impl server::TokenStream for Rustc {
+    fn new(&mut self) -> Self::TokenStream {
+        TokenStream::new()
+    }
+
+    fn is_empty(&mut self, stream: &Self::TokenStream) -> bool {
+        stream.subtree.token_trees.is_empty()
+    }
+
+    fn from_str(&mut self, src: &str) -> Self::TokenStream {
+        // `proc_macro` has no way to report an error here, and rustc panics
+        // as well.
+        let subtree = ra_mbe::text_to_token_tree(src)
+            .unwrap_or_else(|| panic!("can't parse token stream: {:?}", src));
+        TokenStream { subtree }
+    }
+
+    fn to_string(&mut self, stream: &Self::TokenStream) -> String {
+        stream.subtree.to_string()
+    }
+
+    fn from_token_tree(
+        &mut self,
+        tree: bridge::TokenTree<Self::Group, Self::Punct, Self::Ident, Self::Literal>,
+    ) -> Self::TokenStream {
+        let tree: tt::TokenTree = match tree {
+            bridge::TokenTree::Group(group) => group.into(),
+            bridge::TokenTree::Ident(ident) => {
+                tt::Leaf::from(self.ident_interner.get(ident).clone()).into()
+            }
+            bridge::TokenTree::Literal(literal) => tt::Leaf::from(literal).into(),
+            bridge::TokenTree::Punct(punct) => tt::Leaf::from(punct).into(),
+        };
+        TokenStream::from_trees(vec![tree])
+    }
+
+    fn into_iter(&mut self, stream: Self::TokenStream) -> Self::TokenStreamIter {
+        TokenStreamIter { trees: stream.subtree.token_trees.into_iter() }
+    }
+}
+
+impl server::TokenStreamBuilder for Rustc {
+    fn new(&mut self) -> Self::TokenStreamBuilder {
+        TokenStreamBuilder { acc: Vec::new() }
+    }
+
+    fn push(&mut self, builder: &mut Self::TokenStreamBuilder, stream: Self::TokenStream) {
+        builder.acc.extend(stream.subtree.token_trees)
+    }
+
+    fn build(&mut self, builder: Self::TokenStreamBuilder) -> Self::TokenStream {
+        TokenStream::from_trees(builder.acc)
+    }
+}
+
+impl server::TokenStreamIter for Rustc {
+    fn next(
+        &mut self,
+        iter: &mut Self::TokenStreamIter,
+    ) -> Option<bridge::TokenTree<Self::Group, Self::Punct, Self::Ident, Self::Literal>> {
+        let tree = iter.trees.next()?;
+        let res = match tree {
+            tt::TokenTree::Subtree(group) => bridge::TokenTree::Group(group),
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => {
+                bridge::TokenTree::Ident(self.ident_interner.intern(ident))
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => bridge::TokenTree::Literal(literal),
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => bridge::TokenTree::Punct(punct),
+        };
+        Some(res)
+    }
+}
+
+fn delim_to_internal(d: Delimiter) -> tt::Delimiter {
+    match d {
+        Delimiter::Parenthesis => tt::Delimiter::Parenthesis,
+        Delimiter::Brace => tt::Delimiter::Brace,
+        Delimiter::Bracket => tt::Delimiter::Bracket,
+        Delimiter::None => tt::Delimiter::None,
+    }
+}
+
+fn delim_to_external(d: tt::Delimiter) -> Delimiter {
+    match d {
+        tt::Delimiter::Parenthesis => Delimiter::Parenthesis,
+        tt::Delimiter::Brace => Delimiter::Brace,
+        tt::Delimiter::Bracket => Delimiter::Bracket,
+        tt::Delimiter::None => Delimiter::None,
+    }
+}
+
+fn spacing_to_internal(spacing: Spacing) -> tt::Spacing {
+    match spacing {
+        Spacing::Alone => tt::Spacing::Alone,
+        Spacing::Joint => tt::Spacing::Joint,
+    }
+}
+
+fn spacing_to_external(spacing: tt::Spacing) -> Spacing {
+    match spacing {
+        tt::Spacing::Alone => Spacing::Alone,
+        tt::Spacing::Joint => Spacing::Joint,
+    }
+}
+
+impl server::Group for Rustc {
+    fn new(&mut self, delimiter: Delimiter, stream: Self::TokenStream) -> Self::Group {
+        tt::Subtree {
+            delimiter: delim_to_internal(delimiter),
+            token_trees: stream.subtree.token_trees,
+        }
+    }
+
+    fn delimiter(&mut self, group: &Self::Group) -> Delimiter {
+        delim_to_external(group.delimiter)
+    }
+
+    fn stream(&mut self, group: &Self::Group) -> Self::TokenStream {
+        TokenStream::from_trees(group.token_trees.clone())
+    }
+
+    fn span(&mut self, _group: &Self::Group) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn set_span(&mut self, _group: &mut Self::Group, _span: Self::Span) {}
+
+    fn span_open(&mut self, _group: &Self::Group) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn span_close(&mut self, _group: &Self::Group) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+}
+
+impl server::Punct for Rustc {
+    fn new(&mut self, ch: char, spacing: Spacing) -> Self::Punct {
+        tt::Punct { char: ch, spacing: spacing_to_internal(spacing) }
+    }
+
+    fn as_char(&mut self, punct: Self::Punct) -> char {
+        punct.char
+    }
+
+    fn spacing(&mut self, punct: Self::Punct) -> Spacing {
+        spacing_to_external(punct.spacing)
+    }
+
+    fn span(&mut self, _punct: Self::Punct) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn with_span(&mut self, punct: Self::Punct, _span: Self::Span) -> Self::Punct {
+        punct
+    }
+}
+
+impl server::Ident for Rustc {
+    fn new(&mut self, string: &str, span: Self::Span, _is_raw: bool) -> Self::Ident {
+        self.ident_interner.intern(tt::Ident { text: string.into(), id: span })
+    }
+
+    fn span(&mut self, ident: Self::Ident) -> Self::Span {
+        self.ident_interner.get(ident).id
+    }
+
+    fn with_span(&mut self, ident: Self::Ident, span: Self::Span) -> Self::Ident {
+        let text = self.ident_interner.get(ident).text.clone();
+        self.ident_interner.intern(tt::Ident { text, id: span })
+    }
+}
+
+impl server::Literal for Rustc {
+    fn debug(&mut self, literal: &Self::Literal) -> String {
+        format!("Literal {{ lit: {} }}", literal.text)
+    }
+
+    fn integer(&mut self, n: &str) -> Self::Literal {
+        tt::Literal { text: n.into() }
+    }
+
+    fn typed_integer(&mut self, n: &str, kind: &str) -> Self::Literal {
+        tt::Literal { text: format!("{}{}", n, kind).into() }
+    }
+
+    fn float(&mut self, n: &str) -> Self::Literal {
+        let mut text = n.to_string();
+        if !text.contains('.') {
+            text += ".0";
+        }
+        tt::Literal { text: text.into() }
+    }
+
+    fn f32(&mut self, n: &str) -> Self::Literal {
+        tt::Literal { text: format!("{}f32", n).into() }
+    }
+
+    fn f64(&mut self, n: &str) -> Self::Literal {
+        tt::Literal { text: format!("{}f64", n).into() }
+    }
+
+    fn string(&mut self, string: &str) -> Self::Literal {
+        tt::Literal { text: format!("\"{}\"", string.escape_debug()).into() }
+    }
+
+    fn character(&mut self, ch: char) -> Self::Literal {
+        tt::Literal { text: format!("'{}'", ch.escape_debug()).into() }
+    }
+
+    fn byte_string(&mut self, bytes: &[u8]) -> Self::Literal {
+        let string = bytes
+            .iter()
+            .cloned()
+            .flat_map(ascii::escape_default)
+            .map(char::from)
+            .collect::<String>();
+        tt::Literal { text: format!("b\"{}\"", string).into() }
+    }
+
+    fn span(&mut self, _literal: &Self::Literal) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn set_span(&mut self, _literal: &mut Self::Literal, _span: Self::Span) {}
+
+    fn subspan(
+        &mut self,
+        _literal: &Self::Literal,
+        _start: Bound<usize>,
+        _end: Bound<usize>,
+    ) -> Option<Self::Span> {
+        None
+    }
+}
+
+impl server::SourceFile for Rustc {
+    fn eq(&mut self, file1: &Self::SourceFile, file2: &Self::SourceFile) -> bool {
+        file1 == file2
+    }
+
+    fn path(&mut self, file: &Self::SourceFile) -> String {
+        file.path.clone()
+    }
+
+    fn is_real(&mut self, _file: &Self::SourceFile) -> bool {
+        false
+    }
+}
+
+impl server::Diagnostic for Rustc {
+    fn new(&mut self, level: Level, msg: &str, _spans: Self::MultiSpan) -> Self::Diagnostic {
+        Diagnostic { level, message: msg.to_string(), children: Vec::new() }
+    }
+
+    fn sub(
+        &mut self,
+        diag: &mut Self::Diagnostic,
+        level: Level,
+        msg: &str,
+        _spans: Self::MultiSpan,
+    ) {
+        diag.children.push(Diagnostic { level, message: msg.to_string(), children: Vec::new() })
+    }
+
+    fn emit(&mut self, diag: Self::Diagnostic) {
+        // There's no channel to report diagnostics back to the client yet, so
+        // just make them visible in the server's stderr.
+        fn emit(diag: &Diagnostic, indent: usize) {
+            eprintln!("{:indent$}{:?}: {}", "", diag.level, diag.message, indent = indent);
+            for child in diag.children.iter() {
+                emit(child, indent + 2);
+            }
+        }
+        emit(&diag, 0)
+    }
+}
+
+impl server::Span for Rustc {
+    fn debug(&mut self, span: Self::Span) -> String {
+        format!("{:?}", span.0)
+    }
+
+    fn def_site(&mut self) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn call_site(&mut self) -> Self::Span {
+        tt::TokenId::unspecified()
+    }
+
+    fn source_file(&mut self, _span: Self::Span) -> Self::SourceFile {
+        SourceFile { path: String::new() }
+    }
+
+    fn parent(&mut self, _span: Self::Span) -> Option<Self::Span> {
+        None
+    }
+
+    fn source(&mut self, span: Self::Span) -> Self::Span {
+        span
+    }
+
+    fn start(&mut self, _span: Self::Span) -> LineColumn {
+        LineColumn { line: 0, column: 0 }
+    }
+
+    fn end(&mut self, _span: Self::Span) -> LineColumn {
+        LineColumn { line: 0, column: 0 }
+    }
+
+    fn join(&mut self, first: Self::Span, _second: Self::Span) -> Option<Self::Span> {
+        Some(first)
+    }
+
+    fn resolved_at(&mut self, _span: Self::Span, at: Self::Span) -> Self::Span {
+        at
+    }
+
+    fn source_text(&mut self, _span: Self::Span) -> Option<String> {
+        None
+    }
+}
+
+impl server::MultiSpan for Rustc {
+    fn new(&mut self) -> Self::MultiSpan {
+        Vec::new()
+    }
+
+    fn push(&mut self, spans: &mut Self::MultiSpan, span: Self::Span) {
+        spans.push(span)
+    }
+}
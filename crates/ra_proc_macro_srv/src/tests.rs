@@ -0,0 +1,100 @@
+//! Tests which build `test_data/proc_macro_test` and expand its macros.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ra_proc_macro::{
+    msg::{ExpansionTask, ListMacrosTask},
+    ProcMacroKind,
+};
+
+use crate::ProcMacroSrv;
+
+fn build_test_proc_macro() -> PathBuf {
+    let manifest =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/proc_macro_test/Cargo.toml");
+    let output = Command::new("cargo")
+        .arg("build")
+        .arg("--message-format=json")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .output()
+        .expect("failed to run cargo");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    for line in String::from_utf8(output.stdout).unwrap().lines() {
+        let message: serde_json::Value = match serde_json::from_str(line) {
+            Ok(it) => it,
+            Err(_) => continue,
+        };
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        if message["target"]["name"] != "proc_macro_test" {
+            continue;
+        }
+        let dylib = message["filenames"][0].as_str().unwrap();
+        return PathBuf::from(dylib);
+    }
+    panic!("can't find proc_macro_test dylib")
+}
+
+fn expand(srv: &mut ProcMacroSrv, lib: &Path, macro_name: &str, body: &str) -> String {
+    let task = ExpansionTask {
+        macro_body: ra_mbe::text_to_token_tree(body).unwrap(),
+        macro_name: macro_name.to_string(),
+        attributes: None,
+        lib: lib.to_string_lossy().to_string(),
+    };
+    srv.expand(&task).unwrap().expansion.to_string()
+}
+
+#[test]
+fn test_proc_macro_srv() {
+    let lib = build_test_proc_macro();
+    let mut srv = ProcMacroSrv::default();
+
+    let mut macros =
+        srv.list_macros(&ListMacrosTask { lib: lib.to_string_lossy().to_string() }).unwrap().macros;
+    macros.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        macros,
+        vec![
+            ("Named".to_string(), ProcMacroKind::CustomDerive),
+            ("count_tokens".to_string(), ProcMacroKind::FuncLike),
+            ("identity".to_string(), ProcMacroKind::Attr),
+        ]
+    );
+
+    assert_eq!(expand(&mut srv, &lib, "count_tokens", "a b c"), "fn count () -> usize {3}");
+    assert_eq!(
+        expand(&mut srv, &lib, "Named", "struct Foo;"),
+        "impl Foo {fn name () -> & 'static str {\"Foo\"}}"
+    );
+}
+
+#[test]
+fn test_proc_macro_srv_keeps_token_ids() {
+    let lib = build_test_proc_macro();
+    let mut srv = ProcMacroSrv::default();
+
+    let ident =
+        |text: &str, id| tt::Leaf::from(tt::Ident { text: text.into(), id: tt::TokenId(id) });
+    let item = tt::Subtree {
+        delimiter: tt::Delimiter::None,
+        token_trees: vec![
+            ident("struct", 0).into(),
+            ident("Foo", 1).into(),
+            tt::Leaf::from(tt::Punct { char: ';', spacing: tt::Spacing::Alone }).into(),
+        ],
+    };
+    let task = ExpansionTask {
+        macro_body: item.clone(),
+        macro_name: "identity".to_string(),
+        attributes: Some(tt::Subtree { delimiter: tt::Delimiter::None, token_trees: Vec::new() }),
+        lib: lib.to_string_lossy().to_string(),
+    };
+    assert_eq!(srv.expand(&task).unwrap().expansion, item);
+}
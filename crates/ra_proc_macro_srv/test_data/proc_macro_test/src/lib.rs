@@ -0,0 +1,36 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+
+/// Replaces the body of the macro call with a `fn` returning the number of
+/// tokens in the body.
+#[proc_macro]
+pub fn count_tokens(input: TokenStream) -> TokenStream {
+    let n = input.into_iter().count();
+    format!("fn count() -> usize {{ {} }}", n).parse().unwrap()
+}
+
+/// Derives `fn name() -> &'static str` for the type.
+#[proc_macro_derive(Named)]
+pub fn derive_named(input: TokenStream) -> TokenStream {
+    let name = input
+        .into_iter()
+        .skip_while(|tt| match tt {
+            proc_macro::TokenTree::Ident(it) => {
+                let text = it.to_string();
+                text != "struct" && text != "enum"
+            }
+            _ => true,
+        })
+        .nth(1)
+        .unwrap();
+    format!("impl {name} {{ fn name() -> &'static str {{ \"{name}\" }} }}", name = name)
+        .parse()
+        .unwrap()
+}
+
+/// Does nothing, used to test attribute-like macros.
+#[proc_macro_attribute]
+pub fn identity(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
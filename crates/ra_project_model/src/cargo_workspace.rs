@@ -1,12 +1,17 @@
-use std::path::{Path, PathBuf};
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
 
-use cargo_metadata::{MetadataCommand, CargoOpt};
+use cargo_metadata::{Metadata, Dependency, DependencyKind};
 use ra_arena::{Arena, RawId, impl_arena_id};
 use rustc_hash::FxHashMap;
 use failure::format_err;
+use serde::Deserialize;
 use ra_db::Edition;
 
-use crate::Result;
+use crate::{Result, cfg::HostTarget, sysroot::SysrootConfig};
 
 /// `CargoWorkspace` represents the logical structure of, well, a Cargo
 /// workspace. It pretty closely mirrors `cargo metadata` output.
@@ -19,6 +24,53 @@ use crate::Result;
 pub struct CargoWorkspace {
     packages: Arena<Package, PackageData>,
     targets: Arena<Target, TargetData>,
+    host: HostTarget,
+}
+
+/// Knobs for loading a Cargo workspace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CargoConfig {
+    /// Run `cargo check` to learn the paths of the compiled proc-macro
+    /// dylibs. This is potentially slow, as the whole dependency graph has to
+    /// be built.
+    pub load_proc_macros: bool,
+    /// Run `cargo check` to learn the `OUT_DIR`s of the build scripts and the
+    /// cfg options and environment variables they set.
+    pub load_out_dirs_from_check: bool,
+    pub features: CargoFeatures,
+    pub sysroot: SysrootConfig,
+}
+
+/// Which features of the workspace members are enabled, the same as the
+/// corresponding command line flags of Cargo.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CargoFeatures {
+    /// Do not activate the `default` feature.
+    pub no_default_features: bool,
+    /// Activate all available features.
+    pub all_features: bool,
+    /// Features to activate. Ignored if `all_features` is set.
+    pub features: Vec<String>,
+}
+
+impl CargoFeatures {
+    /// The command line flags of Cargo which select these features.
+    fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.all_features {
+            args.push("--all-features".to_string());
+            return args;
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(" "));
+        }
+        args
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -37,12 +89,65 @@ struct PackageData {
     is_member: bool,
     dependencies: Vec<PackageDependency>,
     edition: Edition,
+    /// Features enabled for this package by the resolver.
+    features: Vec<String>,
+    build_data: BuildData,
+}
+
+/// What we learn about a package by compiling it with `cargo check`.
+#[derive(Debug, Clone, Default)]
+struct BuildData {
+    /// Path to the compiled dylib, for `proc-macro = true` packages.
+    proc_macro_dylib_path: Option<PathBuf>,
+    /// The directory where the build script puts generated files.
+    out_dir: Option<PathBuf>,
+    /// Options set with `cargo:rustc-cfg`.
+    cfgs: Vec<String>,
+    /// Variables set with `cargo:rustc-env`.
+    envs: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PackageDependency {
     pub pkg: Package,
+    /// The name under which the dependency is visible in the source code, with
+    /// renames and `-` to `_` conversion applied.
     pub name: String,
+    pub kind: DepKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepKind {
+    /// Available to the library, binary, and dev targets in the package (but
+    /// not the build script).
+    Normal,
+    /// Available only to test, bench, and example targets.
+    Dev,
+    /// Available only to the build script target.
+    Build,
+}
+
+impl DepKind {
+    fn new(kind: &DependencyKind) -> DepKind {
+        match kind {
+            DependencyKind::Development => DepKind::Dev,
+            DependencyKind::Build => DepKind::Build,
+            _ => DepKind::Normal,
+        }
+    }
+
+    /// Checks if a target of the given kind can use dependencies of this kind.
+    pub fn applies_to(self, target: TargetKind) -> bool {
+        match (self, target) {
+            (DepKind::Build, TargetKind::BuildScript) => true,
+            (DepKind::Build, _) | (_, TargetKind::BuildScript) => false,
+            (DepKind::Dev, TargetKind::Test)
+            | (DepKind::Dev, TargetKind::Bench)
+            | (DepKind::Dev, TargetKind::Example) => true,
+            (DepKind::Dev, _) => false,
+            (DepKind::Normal, _) => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +156,7 @@ struct TargetData {
     name: String,
     root: PathBuf,
     kind: TargetKind,
+    is_proc_macro: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -60,6 +166,8 @@ pub enum TargetKind {
     Example,
     Test,
     Bench,
+    /// `build.rs`
+    BuildScript,
     Other,
 }
 
@@ -71,6 +179,8 @@ impl TargetKind {
                 "test" => TargetKind::Test,
                 "bench" => TargetKind::Bench,
                 "example" => TargetKind::Example,
+                "proc-macro" => TargetKind::Lib,
+                "custom-build" => TargetKind::BuildScript,
                 _ if kind.contains("lib") => TargetKind::Lib,
                 _ => continue,
             };
@@ -89,10 +199,12 @@ impl Package {
     pub fn edition(self, ws: &CargoWorkspace) -> Edition {
         ws.packages[self].edition
     }
+    pub fn features(self, ws: &CargoWorkspace) -> &[String] {
+        ws.packages[self].features.as_slice()
+    }
     pub fn targets<'a>(self, ws: &'a CargoWorkspace) -> impl Iterator<Item = Target> + 'a {
         ws.packages[self].targets.iter().cloned()
     }
-    #[allow(unused)]
     pub fn is_member(self, ws: &CargoWorkspace) -> bool {
         ws.packages[self].is_member
     }
@@ -102,6 +214,18 @@ impl Package {
     ) -> impl Iterator<Item = &'a PackageDependency> + 'a {
         ws.packages[self].dependencies.iter()
     }
+    pub fn proc_macro_dylib_path(self, ws: &CargoWorkspace) -> Option<&Path> {
+        ws.packages[self].build_data.proc_macro_dylib_path.as_ref().map(PathBuf::as_path)
+    }
+    pub fn out_dir(self, ws: &CargoWorkspace) -> Option<&Path> {
+        ws.packages[self].build_data.out_dir.as_ref().map(PathBuf::as_path)
+    }
+    pub fn build_cfgs(self, ws: &CargoWorkspace) -> &[String] {
+        ws.packages[self].build_data.cfgs.as_slice()
+    }
+    pub fn build_envs(self, ws: &CargoWorkspace) -> &[(String, String)] {
+        ws.packages[self].build_data.envs.as_slice()
+    }
 }
 
 impl Target {
@@ -117,24 +241,35 @@ impl Target {
     pub fn kind(self, ws: &CargoWorkspace) -> TargetKind {
         ws.targets[self].kind
     }
+    pub fn is_proc_macro(self, ws: &CargoWorkspace) -> bool {
+        ws.targets[self].is_proc_macro
+    }
 }
 
 impl CargoWorkspace {
-    pub fn from_cargo_metadata(cargo_toml: &Path) -> Result<CargoWorkspace> {
-        let mut meta = MetadataCommand::new();
-        meta.manifest_path(cargo_toml).features(CargoOpt::AllFeatures);
-        if let Some(parent) = cargo_toml.parent() {
-            meta.current_dir(parent);
-        }
-        let meta = meta.exec().map_err(|e| format_err!("cargo metadata failed: {}", e))?;
+    pub fn from_cargo_metadata(cargo_toml: &Path, config: &CargoConfig) -> Result<CargoWorkspace> {
+        let meta = cargo_metadata(cargo_toml, &config.features)?;
         let mut pkg_by_id = FxHashMap::default();
         let mut packages = Arena::default();
         let mut targets = Arena::default();
 
+        let mut build_data = if config.load_proc_macros || config.load_out_dirs_from_check {
+            load_build_data(cargo_toml, config)
+        } else {
+            FxHashMap::default()
+        };
+
+        let host = HostTarget::discover();
+        // `resolve` tells us which packages are used, but to learn the kinds
+        // of the dependencies and whether they are specific to some target, we
+        // need the dependency declarations from the manifests.
+        let mut declared_deps = FxHashMap::default();
+
         let ws_members = &meta.workspace_members;
 
         for meta_pkg in meta.packages {
             let is_member = ws_members.contains(&meta_pkg.id);
+            declared_deps.insert(meta_pkg.id.clone(), meta_pkg.dependencies.clone());
             let pkg = packages.alloc(PackageData {
                 name: meta_pkg.name.into(),
                 manifest: meta_pkg.manifest_path.clone(),
@@ -142,6 +277,8 @@ impl CargoWorkspace {
                 is_member,
                 edition: Edition::from_string(&meta_pkg.edition),
                 dependencies: Vec::new(),
+                features: Vec::new(),
+                build_data: build_data.remove(&meta_pkg.id.repr).unwrap_or_default(),
             });
             let pkg_data = &mut packages[pkg];
             pkg_by_id.insert(meta_pkg.id.clone(), pkg);
@@ -151,6 +288,7 @@ impl CargoWorkspace {
                     name: meta_tgt.name.into(),
                     root: meta_tgt.src_path.clone(),
                     kind: TargetKind::new(meta_tgt.kind.as_slice()),
+                    is_proc_macro: meta_tgt.kind.iter().any(|it| it == "proc-macro"),
                 });
                 pkg_data.targets.push(tgt);
             }
@@ -158,21 +296,293 @@ impl CargoWorkspace {
         let resolve = meta.resolve.expect("metadata executed with deps");
         for node in resolve.nodes {
             let source = pkg_by_id[&node.id];
+            let declarations = declared_deps.get(&node.id).map(Vec::as_slice).unwrap_or(&[]);
             for dep_node in node.deps {
-                let dep =
-                    PackageDependency { name: dep_node.name.into(), pkg: pkg_by_id[&dep_node.pkg] };
-                packages[source].dependencies.push(dep);
+                let pkg = pkg_by_id[&dep_node.pkg];
+                let kinds = dep_kinds(&host, declarations, &dep_node.name, &packages[pkg].name);
+                for kind in kinds {
+                    let dep = PackageDependency { name: dep_node.name.clone(), pkg, kind };
+                    packages[source].dependencies.push(dep);
+                }
             }
+            packages[source].features = node.features;
         }
 
-        Ok(CargoWorkspace { packages, targets })
+        Ok(CargoWorkspace { packages, targets, host })
     }
 
     pub fn packages<'a>(&'a self) -> impl Iterator<Item = Package> + 'a {
         self.packages.iter().map(|(id, _pkg)| id)
     }
 
+    /// The target we are analyzing the workspace for.
+    pub fn host(&self) -> &HostTarget {
+        &self.host
+    }
+
     pub fn target_by_root(&self, root: &Path) -> Option<Target> {
         self.packages().filter_map(|pkg| pkg.targets(self).find(|it| it.root(self) == root)).next()
     }
 }
+
+/// Determines the kinds of the dependency edge to the library `lib_name` of
+/// the package `pkg_name`, from the dependency declarations in the manifest.
+fn dep_kinds(
+    host: &HostTarget,
+    declarations: &[Dependency],
+    lib_name: &str,
+    pkg_name: &str,
+) -> Vec<DepKind> {
+    // `lib_name` might differ from the name of the package. A package can be
+    // declared both with and without a rename, for example once as a normal
+    // and once as a build dependency, and each of them is a separate edge.
+    let is_rename = |decl: &Dependency| match &decl.rename {
+        Some(rename) => rename.replace('-', "_") == lib_name,
+        None => false,
+    };
+    let is_renamed = declarations.iter().any(is_rename);
+    let declarations = declarations.iter().filter(|decl| match &decl.rename {
+        Some(_) => is_rename(decl),
+        None => !is_renamed && decl.name == pkg_name,
+    });
+    let mut kinds = Vec::new();
+    let mut is_declared = false;
+    for decl in declarations {
+        is_declared = true;
+        // Skip `[target.'cfg(windows)'.dependencies]` on Linux.
+        let kind = DepKind::new(&decl.kind);
+        if decl.target.as_ref().map_or(true, |it| host.matches(&it.to_string()))
+            && !kinds.contains(&kind)
+        {
+            kinds.push(kind);
+        }
+    }
+    if !is_declared {
+        kinds.push(DepKind::Normal);
+    }
+    kinds
+}
+
+/// Runs `cargo metadata`. The command is built by hand, because
+/// `cargo_metadata::MetadataCommand` can't combine `--no-default-features`
+/// with a list of features.
+fn cargo_metadata(cargo_toml: &Path, features: &CargoFeatures) -> Result<Metadata> {
+    let mut cmd = Command::new("cargo");
+    cmd.args(&["metadata", "--format-version", "1", "--manifest-path"])
+        .arg(cargo_toml)
+        .args(features.cargo_args());
+    if let Some(parent) = cargo_toml.parent() {
+        cmd.current_dir(parent);
+    }
+    let output = cmd.output().map_err(|e| format_err!("cargo metadata failed: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format_err!("cargo metadata failed: {}", stderr.trim()));
+    }
+    let meta = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format_err!("invalid cargo metadata: {}", e))?;
+    Ok(meta)
+}
+
+/// Builds the workspace with `cargo check` and collects the paths of the
+/// compiled proc-macro dylibs and the outputs of the build scripts, keyed by
+/// package id.
+fn load_build_data(cargo_toml: &Path, config: &CargoConfig) -> FxHashMap<String, BuildData> {
+    let mut res: FxHashMap<String, BuildData> = FxHashMap::default();
+    let mut cmd = Command::new("cargo");
+    cmd.args(&["check", "--message-format=json", "--all-targets", "--manifest-path"])
+        .arg(cargo_toml)
+        .args(config.features.cargo_args())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = match cmd.spawn() {
+        Ok(it) => it,
+        Err(e) => {
+            log::error!("failed to run cargo check: {}", e);
+            return res;
+        }
+    };
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        let line = match line {
+            Ok(it) => it,
+            Err(_) => break,
+        };
+        let message: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(it) => it,
+            Err(_) => continue,
+        };
+        let package_id = match message["package_id"].as_str() {
+            Some(it) => it.to_string(),
+            None => continue,
+        };
+        match message["reason"].as_str() {
+            Some("compiler-artifact") if config.load_proc_macros => {
+                let is_proc_macro = match message["target"]["kind"].as_array() {
+                    Some(kinds) => kinds.iter().any(|it| it == "proc-macro"),
+                    None => false,
+                };
+                if !is_proc_macro {
+                    continue;
+                }
+                let dylib = message["filenames"].as_array().and_then(|it| it.first()?.as_str());
+                if let Some(dylib) = dylib {
+                    res.entry(package_id).or_default().proc_macro_dylib_path =
+                        Some(PathBuf::from(dylib));
+                }
+            }
+            Some("build-script-executed") if config.load_out_dirs_from_check => {
+                record_build_script_outputs(res.entry(package_id).or_default(), &message);
+            }
+            _ => (),
+        }
+    }
+    let _ = child.wait();
+    res
+}
+
+/// Records the outputs of a build script, reported by cargo in a
+/// `build-script-executed` message.
+fn record_build_script_outputs(data: &mut BuildData, message: &serde_json::Value) {
+    data.out_dir = message["out_dir"].as_str().map(PathBuf::from);
+    data.cfgs = message["cfgs"]
+        .as_array()
+        .map(|cfgs| cfgs.iter().filter_map(|it| Some(it.as_str()?.to_string())).collect())
+        .unwrap_or_default();
+    data.envs = message["env"]
+        .as_array()
+        .map(|envs| {
+            envs.iter()
+                .filter_map(|it| {
+                    let key = it.get(0)?.as_str()?.to_string();
+                    let value = it.get(1)?.as_str()?.to_string();
+                    Some((key, value))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_are_passed_to_cargo() {
+        let features = CargoFeatures {
+            no_default_features: true,
+            all_features: false,
+            features: vec!["foo".to_string(), "bar".to_string()],
+        };
+        assert_eq!(features.cargo_args(), vec!["--no-default-features", "--features", "foo bar"]);
+
+        let features = CargoFeatures { all_features: true, ..features };
+        assert_eq!(features.cargo_args(), vec!["--all-features"]);
+
+        assert!(CargoFeatures::default().cargo_args().is_empty());
+    }
+
+    #[test]
+    fn records_build_script_outputs() {
+        let message = r#"{
+            "reason": "build-script-executed",
+            "package_id": "foo 0.1.0 (path+file:///foo)",
+            "linked_libs": [],
+            "linked_paths": [],
+            "cfgs": ["has_foo", "foo_version=\"2\""],
+            "env": [["FOO_GENERATED", "1"], ["BAR", "baz"]],
+            "out_dir": "/foo/target/debug/build/foo-1234/out"
+        }"#;
+        let message: serde_json::Value = serde_json::from_str(message).unwrap();
+        let mut data = BuildData::default();
+        record_build_script_outputs(&mut data, &message);
+
+        assert_eq!(data.out_dir, Some(PathBuf::from("/foo/target/debug/build/foo-1234/out")));
+        assert_eq!(data.cfgs, vec!["has_foo".to_string(), "foo_version=\"2\"".to_string()]);
+        assert_eq!(
+            data.envs,
+            vec![
+                ("FOO_GENERATED".to_string(), "1".to_string()),
+                ("BAR".to_string(), "baz".to_string()),
+            ]
+        );
+        assert_eq!(data.proc_macro_dylib_path, None);
+    }
+
+    #[test]
+    fn dep_kind_applies_to_targets() {
+        let all_targets = [
+            TargetKind::Lib,
+            TargetKind::Bin,
+            TargetKind::Example,
+            TargetKind::Test,
+            TargetKind::Bench,
+            TargetKind::BuildScript,
+            TargetKind::Other,
+        ];
+        let applies = |kind: DepKind| {
+            all_targets.iter().cloned().filter(|&it| kind.applies_to(it)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            applies(DepKind::Normal),
+            vec![
+                TargetKind::Lib,
+                TargetKind::Bin,
+                TargetKind::Example,
+                TargetKind::Test,
+                TargetKind::Bench,
+                TargetKind::Other,
+            ]
+        );
+        assert_eq!(
+            applies(DepKind::Dev),
+            vec![TargetKind::Example, TargetKind::Test, TargetKind::Bench]
+        );
+        assert_eq!(applies(DepKind::Build), vec![TargetKind::BuildScript]);
+    }
+
+    fn dependency(
+        name: &str,
+        kind: &str,
+        target: Option<&str>,
+        rename: Option<&str>,
+    ) -> Dependency {
+        let json = serde_json::json!({
+            "name": name,
+            "source": null,
+            "req": "^1.0",
+            "kind": kind,
+            "optional": false,
+            "uses_default_features": true,
+            "features": [],
+            "target": target,
+            "rename": rename,
+            "registry": null,
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn dep_kinds_of_renamed_dependencies() {
+        let mut host = HostTarget::default();
+        host.triple = Some("x86_64-unknown-linux-gnu".to_string());
+        host.cfg_options.insert_atom("unix".into());
+        let declarations = vec![
+            dependency("serde", "normal", None, None),
+            dependency("serde", "build", None, Some("serde-build")),
+            dependency("log", "dev", None, Some("logging")),
+            dependency("log", "normal", Some("cfg(windows)"), None),
+            dependency("winapi", "normal", Some("cfg(windows)"), None),
+        ];
+
+        assert_eq!(dep_kinds(&host, &declarations, "serde", "serde"), vec![DepKind::Normal]);
+        assert_eq!(dep_kinds(&host, &declarations, "serde_build", "serde"), vec![DepKind::Build]);
+        // The renamed edge is a dev-dependency, and the windows-only edge
+        // under the original name is dropped.
+        assert_eq!(dep_kinds(&host, &declarations, "logging", "log"), vec![DepKind::Dev]);
+        assert_eq!(dep_kinds(&host, &declarations, "log", "log"), Vec::new());
+        assert_eq!(dep_kinds(&host, &declarations, "winapi", "winapi"), Vec::new());
+        // Dependencies unknown to the manifest are assumed to be normal.
+        assert_eq!(dep_kinds(&host, &declarations, "rand", "rand"), vec![DepKind::Normal]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::process::Command;
+
+use ra_db::CfgOptions;
+
+/// Adds an option in the format of `rustc --cfg`, like `unix` or
+/// `feature="std"`, to `cfg_options`.
+pub(crate) fn insert_cfg_option(cfg_options: &mut CfgOptions, text: &str) {
+    match text.find('=') {
+        Some(idx) => {
+            let key = text[..idx].trim();
+            let value = text[idx + 1..].trim().trim_matches('"');
+            cfg_options.insert_key_value(key.into(), value.into());
+        }
+        None => cfg_options.insert_atom(text.trim().into()),
+    }
+}
+
+/// The host target, as reported by `rustc`.
+#[derive(Debug, Clone, Default)]
+pub struct HostTarget {
+    /// Target triple, like `x86_64-unknown-linux-gnu`.
+    pub triple: Option<String>,
+    /// Options like `unix` or `target_os="linux"`.
+    pub cfg_options: CfgOptions,
+}
+
+impl HostTarget {
+    /// Runs `rustc` to learn the host target. If `rustc` is not available,
+    /// the host target is unknown and no cfg options are set.
+    pub fn discover() -> HostTarget {
+        let mut res = HostTarget::default();
+        if let Some(output) = run_rustc(&["-vV"]) {
+            res.triple = output
+                .lines()
+                .find(|it| it.starts_with("host: "))
+                .map(|it| it["host: ".len()..].trim().to_string());
+        }
+        if let Some(output) = run_rustc(&["--print", "cfg"]) {
+            for line in output.lines() {
+                insert_cfg_option(&mut res.cfg_options, line);
+            }
+        }
+        res
+    }
+
+    /// Checks if a target-specific dependency applies to this target. `target`
+    /// is either a triple or a `cfg(...)` expression, as in
+    /// `[target.'cfg(unix)'.dependencies]`. If we can't tell, the dependency is
+    /// assumed to apply.
+    pub fn matches(&self, target: &str) -> bool {
+        let target = target.trim();
+        if target.starts_with("cfg(") {
+            let mut tokens = tokenize(target).into_iter().peekable();
+            let res = parse_expr(&mut tokens).and_then(|it| it.eval(&self.cfg_options));
+            return res.unwrap_or(true);
+        }
+        match &self.triple {
+            Some(triple) => triple == target,
+            None => true,
+        }
+    }
+}
+
+fn run_rustc(args: &[&str]) -> Option<String> {
+    let output = match Command::new("rustc").args(args).output() {
+        Ok(it) => it,
+        Err(e) => {
+            log::error!("failed to run rustc: {}", e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+#[derive(Debug)]
+enum CfgExpr {
+    Atom(String),
+    KeyValue { key: String, value: String },
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    fn eval(&self, cfg_options: &CfgOptions) -> Option<bool> {
+        let res = match self {
+            CfgExpr::Atom(name) => cfg_options.check_atom(name),
+            CfgExpr::KeyValue { key, value } => cfg_options.check_key_value(key, value),
+            CfgExpr::All(exprs) => {
+                let mut res = true;
+                for expr in exprs {
+                    res &= expr.eval(cfg_options)?;
+                }
+                res
+            }
+            CfgExpr::Any(exprs) => {
+                let mut res = false;
+                for expr in exprs {
+                    res |= expr.eval(cfg_options)?;
+                }
+                res
+            }
+            CfgExpr::Not(expr) => !expr.eval(cfg_options)?,
+        };
+        Some(res)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut res = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ')' | ',' | '=' => res.push(Token::Punct(c)),
+            '"' => {
+                let mut value = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '"' {
+                        break;
+                    }
+                    value.push(c);
+                }
+                res.push(Token::Str(value));
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                res.push(Token::Ident(ident));
+            }
+        }
+    }
+    res
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
+
+fn parse_expr(tokens: &mut Tokens) -> Option<CfgExpr> {
+    let name = match tokens.next()? {
+        Token::Ident(it) => it,
+        _ => return None,
+    };
+    match tokens.peek() {
+        Some(Token::Punct('=')) => {
+            tokens.next();
+            match tokens.next()? {
+                Token::Str(value) => Some(CfgExpr::KeyValue { key: name, value }),
+                _ => None,
+            }
+        }
+        Some(Token::Punct('(')) => {
+            tokens.next();
+            let mut args = Vec::new();
+            loop {
+                if tokens.peek() == Some(&Token::Punct(')')) {
+                    tokens.next();
+                    break;
+                }
+                args.push(parse_expr(tokens)?);
+                match tokens.next()? {
+                    Token::Punct(',') => (),
+                    Token::Punct(')') => break,
+                    _ => return None,
+                }
+            }
+            match name.as_str() {
+                "cfg" if args.len() == 1 => args.pop(),
+                "all" => Some(CfgExpr::All(args)),
+                "any" => Some(CfgExpr::Any(args)),
+                "not" if args.len() == 1 => Some(CfgExpr::Not(Box::new(args.pop()?))),
+                _ => None,
+            }
+        }
+        _ => Some(CfgExpr::Atom(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linux_host() -> HostTarget {
+        let mut cfg_options = CfgOptions::default();
+        cfg_options.insert_atom("unix".into());
+        cfg_options.insert_key_value("target_os".into(), "linux".into());
+        cfg_options.insert_key_value("target_pointer_width".into(), "64".into());
+        HostTarget { triple: Some("x86_64-unknown-linux-gnu".to_string()), cfg_options }
+    }
+
+    #[test]
+    fn matches_target_specific_dependencies() {
+        let host = linux_host();
+        assert!(host.matches("cfg(unix)"));
+        assert!(!host.matches("cfg(windows)"));
+        assert!(host.matches(r#"cfg(target_os = "linux")"#));
+        assert!(!host.matches(r#"cfg(target_os = "macos")"#));
+        assert!(host.matches(r#"cfg(all(unix, target_pointer_width = "64"))"#));
+        assert!(!host.matches(r#"cfg(any(windows, target_os = "macos"))"#));
+        assert!(host.matches("cfg(not(windows))"));
+        assert!(host.matches("x86_64-unknown-linux-gnu"));
+        assert!(!host.matches("x86_64-pc-windows-msvc"));
+        // Malformed expressions are not filtered out.
+        assert!(host.matches("cfg(all(unix"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
+
+use ra_db::{CfgOptions, Env};
+
+use crate::{Result, cfg::insert_cfg_option};
+
+/// A project described by a `rust-project.json` file, for build systems other
+/// than Cargo. Unlike `CargoWorkspace`, which is computed from `cargo metadata`,
+/// the crate graph is specified directly, crates refer to their dependencies
+/// by index in the `crates` array.
+///
+/// Relative paths are relative to the directory of the `rust-project.json`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JsonProject {
+    pub(crate) roots: Vec<Root>,
+    pub(crate) crates: Vec<Crate>,
+}
+
+/// A directory with source files, which is watched for changes.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Root {
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Crate {
+    pub(crate) root_module: PathBuf,
+    pub(crate) edition: Edition,
+    pub(crate) deps: Vec<Dep>,
+    /// Options like `unix` or `feature="std"`.
+    #[serde(default)]
+    pub(crate) cfg: FxHashSet<String>,
+    #[serde(default)]
+    pub(crate) env: FxHashMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Edition {
+    #[serde(rename = "2015")]
+    Edition2015,
+    #[serde(rename = "2018")]
+    Edition2018,
+}
+
+/// Index of a crate in `JsonProject::crates`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub struct CrateId(pub usize);
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Dep {
+    #[serde(rename = "crate")]
+    pub(crate) krate: CrateId,
+    pub(crate) name: String,
+}
+
+impl JsonProject {
+    pub fn load(path: &Path) -> Result<JsonProject> {
+        let file = File::open(path)?;
+        let mut res: JsonProject = serde_json::from_reader(BufReader::new(file))?;
+        let base = path.parent().unwrap_or(path);
+        for root in res.roots.iter_mut() {
+            root.path = base.join(&root.path);
+        }
+        for krate in res.crates.iter_mut() {
+            krate.root_module = base.join(&krate.root_module);
+        }
+        Ok(res)
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &Path> {
+        self.roots.iter().map(|it| it.path.as_path())
+    }
+}
+
+impl Crate {
+    pub(crate) fn cfg_options(&self) -> CfgOptions {
+        let mut res = CfgOptions::default();
+        for cfg in self.cfg.iter() {
+            insert_cfg_option(&mut res, cfg);
+        }
+        res
+    }
+
+    pub(crate) fn env(&self) -> Env {
+        let mut res = Env::default();
+        for (key, value) in self.env.iter() {
+            res.set(key, value.clone());
+        }
+        res
+    }
+}
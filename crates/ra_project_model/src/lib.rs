@@ -1,4 +1,6 @@
 mod cargo_workspace;
+mod cfg;
+mod json_project;
 mod sysroot;
 
 use std::path::{Path, PathBuf};
@@ -6,127 +8,308 @@ use std::path::{Path, PathBuf};
 use failure::bail;
 use rustc_hash::FxHashMap;
 
-use ra_db::{CrateGraph, FileId, Edition};
+use ra_db::{CrateGraph, CrateKind, FileId, Edition, ProcMacro, Env};
 
 pub use crate::{
-    cargo_workspace::{CargoWorkspace, Package, Target, TargetKind},
-    sysroot::Sysroot,
+    cargo_workspace::{
+        CargoWorkspace, CargoConfig, CargoFeatures, Package, PackageDependency, DepKind, Target,
+        TargetKind,
+    },
+    cfg::HostTarget,
+    json_project::JsonProject,
+    sysroot::{Sysroot, SysrootConfig},
 };
+pub use ra_proc_macro::ProcMacroClient;
 
 // TODO use proper error enum
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
 #[derive(Debug, Clone)]
-pub struct ProjectWorkspace {
-    pub cargo: CargoWorkspace,
-    pub sysroot: Sysroot,
+pub enum ProjectWorkspace {
+    /// Project workspace was discovered by running `cargo metadata` and `rustc --print sysroot`.
+    Cargo { cargo: CargoWorkspace, sysroot: Sysroot },
+    /// Project workspace was specified manually, using a `rust-project.json` file.
+    Json { project: JsonProject },
 }
 
 impl ProjectWorkspace {
-    pub fn discover(path: &Path) -> Result<ProjectWorkspace> {
-        let cargo_toml = find_cargo_toml(path)?;
-        let cargo = CargoWorkspace::from_cargo_metadata(&cargo_toml)?;
-        let sysroot = Sysroot::discover(&cargo_toml)?;
-        let res = ProjectWorkspace { cargo, sysroot };
+    /// Loads the project described by the `rust-project.json` or `Cargo.toml`
+    /// nearest to `path`.
+    pub fn discover(path: &Path, config: &CargoConfig) -> Result<ProjectWorkspace> {
+        let manifest = find_manifest(path)?;
+        if manifest.ends_with("rust-project.json") {
+            let project = JsonProject::load(&manifest)?;
+            return Ok(ProjectWorkspace::Json { project });
+        }
+        let cargo_toml = manifest;
+        let cargo = CargoWorkspace::from_cargo_metadata(&cargo_toml, config)?;
+        let sysroot = Sysroot::discover(&cargo_toml, &config.sysroot)?;
+        let res = ProjectWorkspace::Cargo { cargo, sysroot };
         Ok(res)
     }
 
-    pub fn to_crate_graph(&self, load: &mut dyn FnMut(&Path) -> Option<FileId>) -> CrateGraph {
-        let mut crate_graph = CrateGraph::default();
-        let mut sysroot_crates = FxHashMap::default();
-        for krate in self.sysroot.crates() {
-            if let Some(file_id) = load(krate.root(&self.sysroot)) {
-                sysroot_crates
-                    .insert(krate, crate_graph.add_crate_root(file_id, Edition::Edition2015));
+    /// Returns the directories which should be watched for changes.
+    pub fn to_roots(&self) -> Vec<PathBuf> {
+        match self {
+            ProjectWorkspace::Cargo { cargo, sysroot } => {
+                let mut roots = Vec::new();
+                for pkg in cargo.packages() {
+                    roots.push(pkg.root(cargo).to_path_buf());
+                    // Code generated by the build script.
+                    if let Some(out_dir) = pkg.out_dir(cargo) {
+                        roots.push(out_dir.to_path_buf());
+                    }
+                }
+                for krate in sysroot.crates() {
+                    roots.push(krate.root_dir(sysroot).to_path_buf())
+                }
+                roots
+            }
+            ProjectWorkspace::Json { project } => {
+                project.roots().map(|it| it.to_path_buf()).collect()
             }
         }
-        for from in self.sysroot.crates() {
-            for to in from.deps(&self.sysroot) {
-                let name = to.name(&self.sysroot);
-                if let (Some(&from), Some(&to)) =
-                    (sysroot_crates.get(&from), sysroot_crates.get(&to))
-                {
-                    if let Err(_) = crate_graph.add_dep(from, name.into(), to) {
-                        log::error!("cyclic dependency between sysroot crates")
-                    }
+    }
+
+    pub fn n_packages(&self) -> usize {
+        match self {
+            ProjectWorkspace::Cargo { cargo, .. } => cargo.packages().count(),
+            ProjectWorkspace::Json { project } => project.crates.len(),
+        }
+    }
+
+    pub fn to_crate_graph(
+        &self,
+        load: &mut dyn FnMut(&Path) -> Option<FileId>,
+        proc_macro_client: &ProcMacroClient,
+    ) -> CrateGraph {
+        match self {
+            ProjectWorkspace::Cargo { cargo, sysroot } => {
+                cargo_to_crate_graph(cargo, sysroot, load, proc_macro_client)
+            }
+            ProjectWorkspace::Json { project } => json_to_crate_graph(project, load),
+        }
+    }
+}
+
+fn json_to_crate_graph(
+    project: &JsonProject,
+    load: &mut dyn FnMut(&Path) -> Option<FileId>,
+) -> CrateGraph {
+    let mut crate_graph = CrateGraph::default();
+    let mut crates = FxHashMap::default();
+    for (idx, krate) in project.crates.iter().enumerate() {
+        if let Some(file_id) = load(&krate.root_module) {
+            let edition = match krate.edition {
+                json_project::Edition::Edition2015 => Edition::Edition2015,
+                json_project::Edition::Edition2018 => Edition::Edition2018,
+            };
+            let crate_id = crate_graph.add_crate_root(file_id, edition);
+            crate_graph.set_cfg_options(crate_id, krate.cfg_options());
+            crate_graph.set_env(crate_id, krate.env());
+            crates.insert(json_project::CrateId(idx), crate_id);
+        }
+    }
+
+    for (idx, krate) in project.crates.iter().enumerate() {
+        let from_id = json_project::CrateId(idx);
+        for dep in krate.deps.iter() {
+            if let (Some(&from), Some(&to)) = (crates.get(&from_id), crates.get(&dep.krate)) {
+                if let Err(_) = crate_graph.add_dep(from, dep.name.as_str().into(), to) {
+                    log::error!("cyclic dependency {:?} -> {:?}", from_id, dep.krate);
                 }
             }
         }
+    }
+    crate_graph
+}
 
-        let libstd = self.sysroot.std().and_then(|it| sysroot_crates.get(&it).map(|&it| it));
-
-        let mut pkg_to_lib_crate = FxHashMap::default();
-        let mut pkg_crates = FxHashMap::default();
-        // Next, create crates for each package, target pair
-        for pkg in self.cargo.packages() {
-            let mut lib_tgt = None;
-            for tgt in pkg.targets(&self.cargo) {
-                let root = tgt.root(&self.cargo);
-                if let Some(file_id) = load(root) {
-                    let edition = pkg.edition(&self.cargo);
-                    let crate_id = crate_graph.add_crate_root(file_id, edition);
-                    if tgt.kind(&self.cargo) == TargetKind::Lib {
-                        lib_tgt = Some(crate_id);
-                        pkg_to_lib_crate.insert(pkg, crate_id);
-                    }
-                    pkg_crates.entry(pkg).or_insert_with(Vec::new).push(crate_id);
+fn cargo_to_crate_graph(
+    cargo: &CargoWorkspace,
+    sysroot: &Sysroot,
+    load: &mut dyn FnMut(&Path) -> Option<FileId>,
+    proc_macro_client: &ProcMacroClient,
+) -> CrateGraph {
+    let mut crate_graph = CrateGraph::default();
+    let mut sysroot_crates = FxHashMap::default();
+    for krate in sysroot.crates() {
+        if let Some(file_id) = load(krate.root(sysroot)) {
+            let crate_id = crate_graph.add_crate_root(file_id, krate.edition(sysroot));
+            crate_graph.set_display_name(crate_id, krate.name(sysroot).to_string());
+            crate_graph.set_cfg_options(crate_id, cargo.host().cfg_options.clone());
+            sysroot_crates.insert(krate, crate_id);
+        }
+    }
+    for from in sysroot.crates() {
+        for to in from.deps(sysroot) {
+            let name = to.name(sysroot);
+            if let (Some(&from), Some(&to)) = (sysroot_crates.get(&from), sysroot_crates.get(&to)) {
+                if let Err(_) = crate_graph.add_dep(from, name.into(), to) {
+                    log::error!("cyclic dependency between sysroot crates")
                 }
             }
+        }
+    }
 
-            // Set deps to the std and to the lib target of the current package
-            for &from in pkg_crates.get(&pkg).into_iter().flatten() {
-                if let Some(to) = lib_tgt {
-                    if to != from {
-                        if let Err(_) = crate_graph.add_dep(from, pkg.name(&self.cargo).into(), to)
-                        {
-                            log::error!(
-                                "cyclic dependency between targets of {}",
-                                pkg.name(&self.cargo)
-                            )
-                        }
-                    }
+    // Without std, in `no_std` mode, `core` and `alloc` are linked directly.
+    let sysroot_deps: Vec<_> = {
+        let public = match sysroot.std() {
+            Some(std) => vec![Some(std), sysroot.core()],
+            None => vec![sysroot.core(), sysroot.alloc()],
+        };
+        public
+            .into_iter()
+            .flatten()
+            .filter_map(|it| Some((it.name(sysroot).to_string(), *sysroot_crates.get(&it)?)))
+            .collect()
+    };
+
+    let mut pkg_to_lib_crate = FxHashMap::default();
+    let mut pkg_crates = FxHashMap::default();
+    // Next, create crates for each package, target pair
+    for pkg in cargo.packages() {
+        let cfg_options = {
+            let mut res = cargo.host().cfg_options.clone();
+            for feature in pkg.features(cargo) {
+                res.insert_key_value("feature".into(), feature.as_str().into());
+            }
+            for cfg in pkg.build_cfgs(cargo) {
+                cfg::insert_cfg_option(&mut res, cfg);
+            }
+            res
+        };
+        let env = {
+            let mut res = Env::default();
+            if let Some(out_dir) = pkg.out_dir(cargo) {
+                res.set("OUT_DIR", out_dir.to_string_lossy().into_owned());
+            }
+            for (key, value) in pkg.build_envs(cargo) {
+                res.set(key, value.clone());
+            }
+            res
+        };
+        let mut lib_tgt = None;
+        for tgt in pkg.targets(cargo) {
+            let root = tgt.root(cargo);
+            let kind = tgt.kind(cargo);
+            if let Some(file_id) = load(root) {
+                let edition = pkg.edition(cargo);
+                let crate_id = crate_graph.add_crate_root(file_id, edition);
+                crate_graph.set_display_name(crate_id, tgt.name(cargo).to_string());
+                crate_graph.set_kind(crate_id, crate_kind(kind));
+                crate_graph.set_cfg_options(crate_id, cfg_options.clone());
+                crate_graph.set_env(crate_id, env.clone());
+                if tgt.is_proc_macro(cargo) {
+                    crate_graph.set_kind(crate_id, CrateKind::ProcMacro);
+                    let proc_macros = match pkg.proc_macro_dylib_path(cargo) {
+                        Some(dylib) => load_proc_macros(proc_macro_client, dylib),
+                        None => Vec::new(),
+                    };
+                    crate_graph.set_proc_macros(crate_id, proc_macros);
+                }
+                if kind == TargetKind::Lib {
+                    lib_tgt = Some((crate_id, tgt.name(cargo).replace('-', "_")));
+                    pkg_to_lib_crate.insert(pkg, crate_id);
+                }
+                pkg_crates.entry(pkg).or_insert_with(Vec::new).push((crate_id, kind));
+
+                // `cargo test` compiles the library of a workspace member once
+                // more, with `--cfg test`, as the unit test harness.
+                if kind == TargetKind::Lib && pkg.is_member(cargo) {
+                    let harness = crate_graph.add_crate_root(file_id, edition);
+                    let mut cfg_options = cfg_options.clone();
+                    cfg_options.insert_atom("test".into());
+                    crate_graph.set_display_name(harness, tgt.name(cargo).to_string());
+                    crate_graph.set_kind(harness, CrateKind::Test);
+                    crate_graph.set_cfg_options(harness, cfg_options);
+                    crate_graph.set_env(harness, env.clone());
+                    pkg_crates
+                        .entry(pkg)
+                        .or_insert_with(Vec::new)
+                        .push((harness, TargetKind::Test));
                 }
-                if let Some(std) = libstd {
-                    if let Err(_) = crate_graph.add_dep(from, "std".into(), std) {
-                        log::error!("cyclic dependency on std for {}", pkg.name(&self.cargo))
+            }
+        }
+
+        // Set deps to the sysroot and to the lib target of the current package
+        for &(from, kind) in pkg_crates.get(&pkg).into_iter().flatten() {
+            if let Some((to, name)) = &lib_tgt {
+                // The build script can't use the library it builds, and the
+                // test harness of the library is the library itself.
+                let is_harness = crate_graph.crate_root(from) == crate_graph.crate_root(*to);
+                if !is_harness && kind != TargetKind::BuildScript {
+                    if let Err(_) = crate_graph.add_dep(from, name.as_str().into(), *to) {
+                        log::error!("cyclic dependency between targets of {}", pkg.name(cargo))
                     }
                 }
             }
+            for (name, to) in sysroot_deps.iter() {
+                if let Err(_) = crate_graph.add_dep(from, name.as_str().into(), *to) {
+                    log::error!("cyclic dependency on {} for {}", name, pkg.name(cargo))
+                }
+            }
         }
+    }
 
-        // Now add a dep ednge from all targets of upstream to the lib
-        // target of downstream.
-        for pkg in self.cargo.packages() {
-            for dep in pkg.dependencies(&self.cargo) {
-                if let Some(&to) = pkg_to_lib_crate.get(&dep.pkg) {
-                    for &from in pkg_crates.get(&pkg).into_iter().flatten() {
-                        if let Err(_) = crate_graph.add_dep(from, dep.name.clone().into(), to) {
-                            log::error!(
-                                "cyclic dependency {} -> {}",
-                                pkg.name(&self.cargo),
-                                dep.pkg.name(&self.cargo)
-                            )
-                        }
+    // Now add a dep edge from the targets of upstream to the lib target of
+    // downstream, according to the kind of the dependency: dev-dependencies
+    // are only visible to tests, benches and examples, build-dependencies
+    // only to the build script.
+    for pkg in cargo.packages() {
+        for dep in pkg.dependencies(cargo) {
+            if let Some(&to) = pkg_to_lib_crate.get(&dep.pkg) {
+                for &(from, kind) in pkg_crates.get(&pkg).into_iter().flatten() {
+                    if !dep.kind.applies_to(kind) {
+                        continue;
+                    }
+                    // A dependency might be both a normal and a dev one.
+                    if crate_graph.dependencies(from).any(|it| it.crate_id() == to) {
+                        continue;
+                    }
+                    if let Err(_) = crate_graph.add_dep(from, dep.name.clone().into(), to) {
+                        log::error!(
+                            "cyclic dependency {} -> {}",
+                            pkg.name(cargo),
+                            dep.pkg.name(cargo)
+                        )
                     }
                 }
             }
         }
+    }
+
+    crate_graph
+}
 
-        crate_graph
+fn crate_kind(kind: TargetKind) -> CrateKind {
+    match kind {
+        TargetKind::Lib | TargetKind::Other => CrateKind::Lib,
+        TargetKind::Bin | TargetKind::Example | TargetKind::BuildScript => CrateKind::Bin,
+        TargetKind::Test | TargetKind::Bench => CrateKind::Test,
     }
 }
 
-fn find_cargo_toml(path: &Path) -> Result<PathBuf> {
-    if path.ends_with("Cargo.toml") {
+fn load_proc_macros(client: &ProcMacroClient, dylib: &Path) -> Vec<ProcMacro> {
+    client
+        .by_dylib_path(dylib)
+        .into_iter()
+        .map(|(name, kind, expander)| ProcMacro { name: name.into(), kind, expander })
+        .collect()
+}
+
+/// Finds the manifest nearest to `path`. If a directory has both, a
+/// `rust-project.json` takes precedence over `Cargo.toml`.
+fn find_manifest(path: &Path) -> Result<PathBuf> {
+    if path.ends_with("rust-project.json") || path.ends_with("Cargo.toml") {
         return Ok(path.to_path_buf());
     }
-    let mut curr = Some(path);
-    while let Some(path) = curr {
-        let candidate = path.join("Cargo.toml");
-        if candidate.exists() {
-            return Ok(candidate);
+    for dir in path.ancestors() {
+        for name in ["rust-project.json", "Cargo.toml"].iter() {
+            let candidate = dir.join(name);
+            if candidate.exists() {
+                return Ok(candidate);
+            }
         }
-        curr = path.parent();
     }
-    bail!("can't find Cargo.toml at {}", path.display())
+    bail!("can't find Cargo.toml or rust-project.json at {}", path.display())
 }
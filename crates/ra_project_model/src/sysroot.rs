@@ -1,17 +1,32 @@
 use std::{
+    env, fs,
     path::{Path, PathBuf},
     process::Command,
 };
 
 use ra_arena::{Arena, RawId, impl_arena_id};
+use ra_db::Edition;
+use rustc_hash::FxHashMap;
 
 use crate::Result;
 
+/// The standard library crates, which are not described by `cargo metadata`.
 #[derive(Debug, Clone)]
 pub struct Sysroot {
     crates: Arena<SysrootCrate, SysrootCrateData>,
 }
 
+/// Knobs for locating the standard library.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SysrootConfig {
+    /// Directory with the sources of the standard library, like
+    /// `$(rustc --print sysroot)/lib/rustlib/src/rust/src`. Takes precedence
+    /// over the `RUST_SRC_PATH` environment variable.
+    pub src: Option<PathBuf>,
+    /// Link only `core` and `alloc`, for `#![no_std]` projects.
+    pub no_std: bool,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SysrootCrate(RawId);
 impl_arena_id!(SysrootCrate);
@@ -20,6 +35,7 @@ impl_arena_id!(SysrootCrate);
 struct SysrootCrateData {
     name: String,
     root: PathBuf,
+    edition: Edition,
     deps: Vec<SysrootCrate>,
 }
 
@@ -28,51 +44,72 @@ impl Sysroot {
         self.by_name("std")
     }
 
+    pub fn core(&self) -> Option<SysrootCrate> {
+        self.by_name("core")
+    }
+
+    pub fn alloc(&self) -> Option<SysrootCrate> {
+        self.by_name("alloc")
+    }
+
     pub fn crates<'a>(&'a self) -> impl Iterator<Item = SysrootCrate> + 'a {
         self.crates.iter().map(|(id, _data)| id)
     }
 
-    pub fn discover(cargo_toml: &Path) -> Result<Sysroot> {
-        let rustc_output = Command::new("rustc")
-            .current_dir(cargo_toml.parent().unwrap())
-            .args(&["--print", "sysroot"])
-            .output()?;
-        if !rustc_output.status.success() {
-            failure::bail!("failed to locate sysroot")
-        }
-        let stdout = String::from_utf8(rustc_output.stdout)?;
-        let sysroot_path = Path::new(stdout.trim());
-        let src = sysroot_path.join("lib/rustlib/src/rust/src");
+    pub fn discover(cargo_toml: &Path, config: &SysrootConfig) -> Result<Sysroot> {
+        let src = find_sysroot_src(cargo_toml, config)?;
         if !src.exists() {
             failure::bail!(
                 "can't load standard library from sysroot\n\
                  {:?}\n\
-                 try running `rustup component add rust-src`",
+                 try running `rustup component add rust-src` or setting `RUST_SRC_PATH`",
                 src,
             );
         }
 
+        let roots: &[&str] = if config.no_std {
+            &["core", "alloc"]
+        } else {
+            &["std", "core", "alloc", "proc_macro", "test"]
+        };
         let mut sysroot = Sysroot { crates: Arena::default() };
-        for name in SYSROOT_CRATES.trim().lines() {
-            let root = src.join(format!("lib{}", name)).join("lib.rs");
-            if root.exists() {
-                sysroot.crates.alloc(SysrootCrateData {
-                    name: name.into(),
-                    root,
-                    deps: Vec::new(),
-                });
-            }
-        }
-        if let Some(std) = sysroot.std() {
-            for dep in STD_DEPS.trim().lines() {
-                if let Some(dep) = sysroot.by_name(dep) {
-                    sysroot.crates[std].deps.push(dep)
-                }
-            }
+        let mut visited = FxHashMap::default();
+        for name in roots {
+            sysroot.load_crate(&src, &src.join(format!("lib{}", name)), &mut visited);
         }
         Ok(sysroot)
     }
 
+    /// Loads the crate from `dir`, together with all of its dependencies from
+    /// the sysroot, according to its `Cargo.toml`.
+    fn load_crate(
+        &mut self,
+        src: &Path,
+        dir: &Path,
+        visited: &mut FxHashMap<PathBuf, Option<SysrootCrate>>,
+    ) -> Option<SysrootCrate> {
+        let dir = dir.canonicalize().ok()?;
+        if let Some(&res) = visited.get(&dir) {
+            return res;
+        }
+        visited.insert(dir.clone(), None);
+
+        let manifest = match Manifest::read(&dir) {
+            Some(it) => it,
+            None => Manifest::fallback(src, &dir)?,
+        };
+        let deps =
+            manifest.deps.iter().filter_map(|dep| self.load_crate(src, dep, visited)).collect();
+        let res = self.crates.alloc(SysrootCrateData {
+            name: manifest.name,
+            root: manifest.root,
+            edition: manifest.edition,
+            deps,
+        });
+        visited.insert(dir, Some(res));
+        Some(res)
+    }
+
     fn by_name(&self, name: &str) -> Option<SysrootCrate> {
         self.crates.iter().find(|(_id, data)| data.name == name).map(|(id, _data)| id)
     }
@@ -88,49 +125,197 @@ impl SysrootCrate {
     pub fn root_dir(self, sysroot: &Sysroot) -> &Path {
         self.root(sysroot).parent().unwrap()
     }
+    pub fn edition(self, sysroot: &Sysroot) -> Edition {
+        sysroot.crates[self].edition
+    }
     pub fn deps<'a>(self, sysroot: &'a Sysroot) -> impl Iterator<Item = SysrootCrate> + 'a {
         sysroot.crates[self].deps.iter().map(|&it| it)
     }
 }
 
-const SYSROOT_CRATES: &str = "
-std
-core
-alloc
-collections
-libc
-panic_unwind
-proc_macro
-rustc_unicode
-std_unicode
-test
-alloc_jemalloc
-alloc_system
-compiler_builtins
-getopts
-panic_unwind
-panic_abort
-rand
-term
-unwind
-build_helper
-rustc_asan
-rustc_lsan
-rustc_msan
-rustc_tsan
-syntax";
-
-const STD_DEPS: &str = "
-alloc
-alloc_jemalloc
-alloc_system
-core
-panic_abort
-rand
-compiler_builtins
-unwind
-rustc_asan
-rustc_lsan
-rustc_msan
-rustc_tsan
-build_helper";
+fn find_sysroot_src(cargo_toml: &Path, config: &SysrootConfig) -> Result<PathBuf> {
+    if let Some(src) = &config.src {
+        return Ok(src.clone());
+    }
+    if let Ok(path) = env::var("RUST_SRC_PATH") {
+        return Ok(PathBuf::from(path));
+    }
+    let rustc_output = Command::new("rustc")
+        .current_dir(cargo_toml.parent().unwrap())
+        .args(&["--print", "sysroot"])
+        .output()
+        .map_err(|e| failure::format_err!("failed to run rustc to locate sysroot: {}", e))?;
+    if !rustc_output.status.success() {
+        failure::bail!("failed to locate sysroot")
+    }
+    let stdout = String::from_utf8(rustc_output.stdout)?;
+    let sysroot_path = Path::new(stdout.trim());
+    Ok(sysroot_path.join("lib/rustlib/src/rust/src"))
+}
+
+/// The parts of the `Cargo.toml` of a sysroot crate we care about.
+struct Manifest {
+    name: String,
+    root: PathBuf,
+    edition: Edition,
+    /// Directories of the `path` dependencies. Dependencies from crates.io,
+    /// like `libc`, are not part of the sysroot sources.
+    deps: Vec<PathBuf>,
+}
+
+impl Manifest {
+    fn read(dir: &Path) -> Option<Manifest> {
+        let text = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+        let manifest = text.parse::<toml::Value>().ok()?;
+        let package = manifest.get("package")?;
+        let lib = manifest.get("lib");
+        let name = lib
+            .and_then(|it| it.get("name"))
+            .or_else(|| package.get("name"))?
+            .as_str()?
+            .replace('-', "_");
+        let root = match lib.and_then(|it| it.get("path")).and_then(|it| it.as_str()) {
+            Some(path) => dir.join(path),
+            None if dir.join("lib.rs").exists() => dir.join("lib.rs"),
+            None => dir.join("src/lib.rs"),
+        };
+        let edition = match package.get("edition").and_then(|it| it.as_str()) {
+            Some(edition) => Edition::from_string(edition),
+            None => Edition::Edition2015,
+        };
+
+        let mut deps = Vec::new();
+        let mut tables = vec![manifest.get("dependencies")];
+        if let Some(targets) = manifest.get("target").and_then(|it| it.as_table()) {
+            tables.extend(targets.values().map(|it| it.get("dependencies")));
+        }
+        for table in tables.into_iter().filter_map(|it| it?.as_table()) {
+            for dep in table.values() {
+                if let Some(path) = dep.get("path").and_then(|it| it.as_str()) {
+                    deps.push(dir.join(path));
+                }
+            }
+        }
+        Some(Manifest { name, root, edition, deps })
+    }
+
+    /// For the sources without manifests, we only know about the most
+    /// important crates.
+    fn fallback(src: &Path, dir: &Path) -> Option<Manifest> {
+        let name = dir.file_name()?.to_str()?;
+        let name = if name.starts_with("lib") { &name["lib".len()..] } else { name };
+        let deps: &[&str] = match name {
+            "core" => &[],
+            "alloc" => &["core"],
+            "std" => &["core", "alloc"],
+            "proc_macro" => &["std"],
+            "test" => &["std", "proc_macro"],
+            _ => return None,
+        };
+        let root = dir.join("lib.rs");
+        if !root.exists() {
+            return None;
+        }
+        let deps = deps.iter().map(|it| src.join(format!("lib{}", it))).collect();
+        Some(Manifest { name: name.to_string(), root, edition: Edition::Edition2015, deps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lays out a sysroot in which `libstd` and `liballoc` have manifests,
+    /// while `libcore` and `libproc_macro` only have sources and `libtest`
+    /// is missing.
+    fn fixture_sysroot(dir: &Path) -> PathBuf {
+        let src = dir.join("src");
+        let files = [
+            ("libcore/lib.rs", ""),
+            ("libproc_macro/lib.rs", ""),
+            ("liballoc/lib.rs", ""),
+            (
+                "liballoc/Cargo.toml",
+                r#"
+[package]
+name = "alloc"
+edition = "2018"
+
+[lib]
+path = "lib.rs"
+
+[dependencies]
+core = { path = "../libcore" }
+"#,
+            ),
+            ("libstd/src/lib.rs", ""),
+            (
+                "libstd/Cargo.toml",
+                r#"
+[package]
+name = "std"
+
+[dependencies]
+alloc = { path = "../liballoc" }
+libc = "0.2"
+
+[target.'cfg(unix)'.dependencies]
+core = { path = "../libcore" }
+"#,
+            ),
+        ];
+        for (path, text) in files.iter() {
+            let path = src.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        src.canonicalize().unwrap()
+    }
+
+    fn deps(sysroot: &Sysroot, krate: SysrootCrate) -> Vec<&str> {
+        let mut res = krate.deps(sysroot).map(|it| it.name(sysroot)).collect::<Vec<_>>();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn discovers_sysroot() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = fixture_sysroot(dir.path());
+        let cargo_toml = dir.path().join("Cargo.toml");
+
+        let config = SysrootConfig { src: Some(src.clone()), no_std: false };
+        let sysroot = Sysroot::discover(&cargo_toml, &config).unwrap();
+        let mut names = sysroot.crates().map(|it| it.name(&sysroot)).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["alloc", "core", "proc_macro", "std"]);
+
+        let std = sysroot.std().unwrap();
+        assert_eq!(std.root(&sysroot), src.join("libstd/src/lib.rs").as_path());
+        assert_eq!(std.edition(&sysroot), Edition::Edition2015);
+        assert_eq!(deps(&sysroot, std), vec!["alloc", "core"]);
+
+        let alloc = sysroot.alloc().unwrap();
+        assert_eq!(alloc.root(&sysroot), src.join("liballoc/lib.rs").as_path());
+        assert_eq!(alloc.edition(&sysroot), Edition::Edition2018);
+        assert_eq!(deps(&sysroot, alloc), vec!["core"]);
+
+        // Crates without a manifest use the built-in list of dependencies.
+        let core = sysroot.core().unwrap();
+        assert_eq!(core.root(&sysroot), src.join("libcore/lib.rs").as_path());
+        assert!(deps(&sysroot, core).is_empty());
+        let proc_macro = sysroot.crates().find(|it| it.name(&sysroot) == "proc_macro").unwrap();
+        assert_eq!(deps(&sysroot, proc_macro), vec!["std"]);
+
+        let config = SysrootConfig { src: Some(src.clone()), no_std: true };
+        let sysroot = Sysroot::discover(&cargo_toml, &config).unwrap();
+        let mut names = sysroot.crates().map(|it| it.name(&sysroot)).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["alloc", "core"]);
+        assert!(sysroot.std().is_none());
+
+        // The configured directory is used even if it doesn't exist.
+        let missing = SysrootConfig { src: Some(dir.path().join("missing")), no_std: false };
+        assert!(Sysroot::discover(&cargo_toml, &missing).is_err());
+    }
+}
@@ -151,6 +151,10 @@ impl FnDef {
     pub fn has_atom_attr(&self, atom: &str) -> bool {
         self.attrs().filter_map(|x| x.as_atom()).any(|x| x == atom)
     }
+
+    pub fn is_unsafe(&self) -> bool {
+        self.syntax().children().any(|n| n.kind() == UNSAFE_KW)
+    }
 }
 
 impl Attr {
@@ -724,6 +728,12 @@ impl LiteralExpr {
     }
 }
 
+impl StaticDef {
+    pub fn is_mutable(&self) -> bool {
+        self.syntax().children().any(|n| n.kind() == MUT_KW)
+    }
+}
+
 impl BindPat {
     pub fn is_mutable(&self) -> bool {
         self.syntax().children().any(|n| n.kind() == MUT_KW)
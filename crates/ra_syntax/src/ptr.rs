@@ -18,6 +18,12 @@ impl SyntaxNodePtr {
         SyntaxNodePtr { range: node.range(), kind: node.kind() }
     }
 
+    /// Recreates a pointer from its parts, for example when loading it from a
+    /// cache on disk.
+    pub fn from_parts(range: TextRange, kind: SyntaxKind) -> SyntaxNodePtr {
+        SyntaxNodePtr { range, kind }
+    }
+
     pub fn to_node(self, source_file: &SourceFile) -> &SyntaxNode {
         generate(Some(source_file.syntax()), |&node| {
             node.children().find(|it| self.range.is_subrange(&it.range()))
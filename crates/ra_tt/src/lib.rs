@@ -14,8 +14,9 @@ macro_rules! impl_froms {
     }
 }
 
-use std::fmt;
+use std::{fmt, panic::RefUnwindSafe};
 
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 
 /// Represents identity of the token.
@@ -24,7 +25,7 @@ use smol_str::SmolStr;
 /// which source tokens. We do it by assigning an distinct identity to each
 /// source token and making sure that identities are preserved during macro
 /// expansion.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TokenId(pub u32);
 
 impl TokenId {
@@ -33,14 +34,14 @@ impl TokenId {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenTree {
     Leaf(Leaf),
     Subtree(Subtree),
 }
 impl_froms!(TokenTree: Leaf, Subtree);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Leaf {
     Literal(Literal),
     Punct(Punct),
@@ -48,13 +49,13 @@ pub enum Leaf {
 }
 impl_froms!(Leaf: Literal, Punct, Ident);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Subtree {
     pub delimiter: Delimiter,
     pub token_trees: Vec<TokenTree>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Delimiter {
     Parenthesis,
     Brace,
@@ -62,29 +63,70 @@ pub enum Delimiter {
     None,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Literal {
     pub text: SmolStr,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Punct {
     pub char: char,
     pub spacing: Spacing,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Spacing {
     Alone,
     Joint,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Ident {
     pub text: SmolStr,
     pub id: TokenId,
 }
 
+/// The kinds of procedural macros. This is part of the proc-macro server
+/// protocol as well, so it is shared by the server and the analyzer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProcMacroKind {
+    CustomDerive,
+    FuncLike,
+    Attr,
+}
+
+/// Something which can transform one token tree into another. This is the
+/// interface for macros whose implementation lives outside of the analyzer,
+/// like procedural macros, which are expanded by a separate process.
+pub trait TokenExpander: fmt::Debug + Send + Sync + RefUnwindSafe {
+    /// `attributes` is `Some` only for attribute-like macros, where it holds the
+    /// arguments of the attribute itself.
+    fn expand(
+        &self,
+        subtree: &Subtree,
+        attributes: Option<&Subtree>,
+    ) -> Result<Subtree, ExpansionError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExpansionError {
+    IOError(String),
+    JsonError(String),
+    Unknown(String),
+    ExpansionError(String),
+}
+
+impl fmt::Display for ExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpansionError::IOError(it) => write!(f, "io error: {}", it),
+            ExpansionError::JsonError(it) => write!(f, "invalid message: {}", it),
+            ExpansionError::Unknown(it) => write!(f, "unknown error: {}", it),
+            ExpansionError::ExpansionError(it) => write!(f, "expansion failed: {}", it),
+        }
+    }
+}
+
 impl fmt::Display for TokenTree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -0,0 +1,244 @@
+use std::{
+    fmt, fs,
+    path::Path,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{never, select, unbounded, Receiver, Sender};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher as _};
+use relative_path::RelativePathBuf;
+use walkdir::WalkDir;
+
+use crate::{
+    roots::{RootConfig, VfsRoot},
+    Watch,
+};
+
+pub(crate) enum Task {
+    AddRoot {
+        root: VfsRoot,
+        config: Arc<RootConfig>,
+    },
+    /// A nested root was added inside of the root, which excludes it now.
+    UpdateRoot {
+        root: VfsRoot,
+        config: Arc<RootConfig>,
+    },
+}
+
+/// The results of the IO thread, which the `Vfs` applies in `handle_task`.
+pub enum TaskResult {
+    /// All files of a freshly added root.
+    BulkLoadRoot { root: VfsRoot, files: Vec<(RelativePathBuf, String)> },
+    /// A file was created, modified or, if `text` is `None`, removed.
+    SingleFile { root: VfsRoot, path: RelativePathBuf, text: Option<String> },
+}
+
+impl fmt::Debug for TaskResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskResult::BulkLoadRoot { root, files } => f
+                .debug_struct("BulkLoadRoot")
+                .field("root", root)
+                .field("n_files", &files.len())
+                .finish(),
+            TaskResult::SingleFile { root, path, text } => f
+                .debug_struct("SingleFile")
+                .field("root", root)
+                .field("path", path)
+                .field("removed", &text.is_none())
+                .finish(),
+        }
+    }
+}
+
+pub(crate) type Worker = thread_worker::Worker<Task, TaskResult>;
+
+pub(crate) fn start(watch: Watch) -> Worker {
+    thread_worker::Worker::spawn("vfs", 128, move |input_receiver, output_sender| {
+        let watcher = if watch.0 { Watcher::start() } else { None };
+        let mut io = Io { roots: Vec::new(), watcher, sender: output_sender };
+        let watcher_receiver = match &io.watcher {
+            Some(watcher) => watcher.receiver.clone(),
+            None => never(),
+        };
+        loop {
+            select! {
+                recv(input_receiver) -> task => match task {
+                    Ok(task) => io.handle_task(task),
+                    // The `Vfs` is dropped.
+                    Err(_) => break,
+                },
+                recv(watcher_receiver) -> event => match event {
+                    Ok(event) => io.handle_event(event),
+                    Err(_) => {
+                        log::error!("the file watcher has stopped");
+                        break;
+                    }
+                },
+            }
+        }
+    })
+}
+
+struct Io {
+    roots: Vec<(VfsRoot, Arc<RootConfig>)>,
+    watcher: Option<Watcher>,
+    sender: Sender<TaskResult>,
+}
+
+impl Io {
+    fn handle_task(&mut self, task: Task) {
+        match task {
+            Task::AddRoot { root, config } => {
+                let files = self.load_dir(&config, &config.root);
+                self.send(TaskResult::BulkLoadRoot { root, files });
+                self.roots.push((root, config));
+            }
+            Task::UpdateRoot { root, config } => {
+                if let Some(it) = self.roots.iter_mut().find(|(it, _)| *it == root) {
+                    it.1 = config;
+                }
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: DebouncedEvent) {
+        match event {
+            DebouncedEvent::Create(path) => {
+                if path.is_dir() {
+                    self.handle_new_dir(&path);
+                } else {
+                    self.refresh(&path);
+                }
+            }
+            DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => self.refresh(&path),
+            DebouncedEvent::Rename(from, to) => {
+                self.refresh(&from);
+                if to.is_dir() {
+                    self.handle_new_dir(&to);
+                } else {
+                    self.refresh(&to);
+                }
+            }
+            DebouncedEvent::Error(err, path) => {
+                log::warn!("vfs watcher error at {:?}: {}", path, err);
+            }
+            DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_)
+            | DebouncedEvent::Rescan => (),
+        }
+    }
+
+    /// Reports all files of a directory which appeared after its root was
+    /// loaded.
+    fn handle_new_dir(&mut self, dir: &Path) {
+        let root = self.roots.iter().find(|(_, config)| config.contains_dir(dir)).cloned();
+        if let Some((root, config)) = root {
+            for (path, text) in self.load_dir(&config, dir) {
+                self.send(TaskResult::SingleFile { root, path, text: Some(text) });
+            }
+        }
+    }
+
+    /// Reads the file at `path` again and reports its new state.
+    fn refresh(&mut self, path: &Path) {
+        let (root, rel_path) = match self.find(path) {
+            Some(it) => it,
+            None => return,
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => Some(text),
+            Err(_) if !path.exists() => None,
+            Err(e) => {
+                log::warn!("failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        self.send(TaskResult::SingleFile { root, path: rel_path, text });
+    }
+
+    /// Reads the files of `dir` which belong to the root and watches the
+    /// directories.
+    fn load_dir(&mut self, config: &RootConfig, dir: &Path) -> Vec<(RelativePathBuf, String)> {
+        let mut files = Vec::new();
+        let entries =
+            WalkDir::new(dir).into_iter().filter_entry(|entry| config.contains_dir(entry.path()));
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::warn!("vfs walk error: {}", e);
+                    continue;
+                }
+            };
+            if entry.file_type().is_dir() {
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.watch(entry.path());
+                }
+                continue;
+            }
+            let path = match config.contains(entry.path()) {
+                Some(path) => path,
+                None => continue,
+            };
+            match fs::read_to_string(entry.path()) {
+                Ok(text) => files.push((path, text)),
+                Err(e) => log::warn!("failed to read {}: {}", entry.path().display(), e),
+            }
+        }
+        files
+    }
+
+    fn find(&self, path: &Path) -> Option<(VfsRoot, RelativePathBuf)> {
+        self.roots.iter().find_map(|(root, config)| Some((*root, config.contains(path)?)))
+    }
+
+    fn send(&self, result: TaskResult) {
+        // The receiving side is only gone when the `Vfs` is being dropped.
+        let _ = self.sender.send(result);
+    }
+}
+
+const WATCHER_DELAY: Duration = Duration::from_millis(250);
+
+/// Watches the directories of the roots one by one, so that ignored
+/// directories, like `target`, are not watched.
+struct Watcher {
+    inner: RecommendedWatcher,
+    receiver: Receiver<DebouncedEvent>,
+}
+
+impl Watcher {
+    fn start() -> Option<Watcher> {
+        // `notify` sends events to a std channel, which can't be selected on,
+        // so they are forwarded to a crossbeam one. The forwarding thread
+        // exits when the watcher is dropped.
+        let (notify_sender, notify_receiver) = mpsc::channel();
+        let inner = match notify::watcher(notify_sender, WATCHER_DELAY) {
+            Ok(it) => it,
+            Err(e) => {
+                log::error!("failed to start the file watcher: {}", e);
+                return None;
+            }
+        };
+        let (sender, receiver) = unbounded();
+        thread::spawn(move || {
+            for event in notify_receiver {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Watcher { inner, receiver })
+    }
+
+    fn watch(&mut self, dir: &Path) {
+        if let Err(e) = self.inner.watch(dir, RecursiveMode::NonRecursive) {
+            log::warn!("failed to watch {}: {}", dir.display(), e);
+        }
+    }
+}
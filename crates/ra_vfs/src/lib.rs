@@ -0,0 +1,310 @@
+//! VFS stands for Virtual File System.
+//!
+//! When doing analysis, we don't want to do any IO, we want to keep all source
+//! code in memory. However, the actual source code is stored on disk, so you
+//! need to get it into the memory in the first place somehow. VFS is the
+//! component which does this.
+//!
+//! It is also responsible for watching the disk for changes, and for merging
+//! editor state (modified, unsaved files) with disk state. Watching can be
+//! turned off, if something else, like the editor, watches the files and
+//! reports the changes through `Vfs::notify_changed`.
+//!
+//! The files are grouped into roots: directories which are scanned and
+//! watched as a whole. Roots are scanned on a separate IO thread, the results
+//! are sent back as `VfsTask`s and must be passed to `Vfs::handle_task`. The
+//! accumulated changes are then retrieved with `Vfs::commit_changes`.
+mod io;
+mod roots;
+
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crossbeam_channel::Receiver;
+use ra_arena::{Arena, RawId, impl_arena_id};
+use relative_path::{RelativePath, RelativePathBuf};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    io::{Task, Worker},
+    roots::Roots,
+};
+
+pub use crate::{io::TaskResult as VfsTask, roots::VfsRoot};
+
+/// Whether the `Vfs` watches the roots for changes on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watch(pub bool);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VfsFile(pub RawId);
+impl_arena_id!(VfsFile);
+
+struct VfsFileData {
+    root: VfsRoot,
+    path: RelativePathBuf,
+    is_overlayed: bool,
+    text: Arc<String>,
+}
+
+pub struct Vfs {
+    roots: Roots,
+    files: Arena<VfsFile, VfsFileData>,
+    root2files: FxHashMap<VfsRoot, FxHashMap<RelativePathBuf, VfsFile>>,
+    pending_changes: Vec<VfsChange>,
+    worker: Worker,
+}
+
+impl std::fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Vfs")
+            .field("n_roots", &self.roots.len())
+            .field("n_files", &self.files.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum VfsChange {
+    AddRoot { root: VfsRoot, files: Vec<(VfsFile, RelativePathBuf, Arc<String>)> },
+    AddFile { root: VfsRoot, file: VfsFile, path: RelativePathBuf, text: Arc<String> },
+    RemoveFile { root: VfsRoot, file: VfsFile, path: RelativePathBuf },
+    ChangeFile { file: VfsFile, text: Arc<String> },
+}
+
+impl Vfs {
+    /// Creates a `Vfs` for the directories in `roots`, and starts scanning
+    /// them. Returns the created roots, there may be fewer of them than paths
+    /// if some of the paths are the same.
+    pub fn new(roots: Vec<PathBuf>, watch: Watch) -> (Vfs, Vec<VfsRoot>) {
+        let mut vfs = Vfs {
+            roots: Roots::new(roots),
+            files: Arena::default(),
+            root2files: FxHashMap::default(),
+            pending_changes: Vec::new(),
+            worker: io::start(watch),
+        };
+        let res = vfs.roots.iter().collect::<Vec<_>>();
+        for &root in res.iter() {
+            vfs.start_scanning(root);
+        }
+        (vfs, res)
+    }
+
+    /// Adds a new root for the directory at `path`, which is scanned like the
+    /// initial ones. If the directory is inside of an existing root, its files
+    /// move to the new root, as if it was there from the start. Returns `None`
+    /// if there already is a root for the directory.
+    pub fn add_root(&mut self, path: PathBuf) -> Option<VfsRoot> {
+        let (root, outer) = self.roots.add(path)?;
+        let mut overlays = Vec::new();
+        for outer in outer {
+            let config = self.roots.config(outer);
+            let task = Task::UpdateRoot { root: outer, config: Arc::clone(&config) };
+            self.worker.sender().send(task).unwrap();
+            let moved = self.root2files[&outer]
+                .iter()
+                .map(|(path, &file)| (path.clone(), file))
+                .filter(|(path, _)| config.contains(&path.to_path(&config.root)).is_none())
+                .collect::<Vec<_>>();
+            for (path, file) in moved {
+                if self.files[file].is_overlayed {
+                    let text = Arc::clone(&self.files[file].text);
+                    overlays.push((path.to_path(&config.root), text));
+                }
+                self.remove_file(outer, path, file);
+            }
+        }
+        self.start_scanning(root);
+        // The scan reads the moved files from disk again, but the ones open
+        // in the editor keep their texts.
+        for (path, text) in overlays {
+            if let Some((root, rel_path)) = self.roots.find(&path) {
+                self.add_file(root, rel_path, text, true);
+            }
+        }
+        Some(root)
+    }
+
+    fn start_scanning(&mut self, root: VfsRoot) {
+        self.root2files.insert(root, FxHashMap::default());
+        let task = Task::AddRoot { root, config: self.roots.config(root) };
+        self.worker.sender().send(task).unwrap();
+    }
+
+    pub fn root2path(&self, root: VfsRoot) -> PathBuf {
+        self.roots.path(root).to_path_buf()
+    }
+
+    pub fn path2file(&self, path: &Path) -> Option<VfsFile> {
+        let (_, _, file) = self.find_root(path)?;
+        file
+    }
+
+    pub fn file2path(&self, file: VfsFile) -> PathBuf {
+        let data = &self.files[file];
+        data.path.to_path(self.roots.path(data.root))
+    }
+
+    pub fn n_roots(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Reads the file at `path` right away, without waiting for its root to
+    /// be scanned.
+    pub fn load(&mut self, path: &Path) -> Option<VfsFile> {
+        let (root, rel_path, file) = self.find_root(path)?;
+        if let Some(file) = file {
+            return Some(file);
+        }
+        let text = fs::read_to_string(path).unwrap_or_default();
+        Some(self.add_file(root, rel_path, Arc::new(text), false))
+    }
+
+    pub fn add_file_overlay(&mut self, path: &Path, text: String) -> Option<VfsFile> {
+        let (root, rel_path, file) = self.find_root(path)?;
+        let text = Arc::new(text);
+        let file = match file {
+            Some(file) => {
+                self.change_file(file, text, true);
+                file
+            }
+            None => self.add_file(root, rel_path, text, true),
+        };
+        Some(file)
+    }
+
+    pub fn change_file_overlay(&mut self, path: &Path, new_text: String) {
+        if let Some((_, _, Some(file))) = self.find_root(path) {
+            self.change_file(file, Arc::new(new_text), true);
+        }
+    }
+
+    /// Removes the overlay of the file, which reverts it to the text on disk.
+    pub fn remove_file_overlay(&mut self, path: &Path) -> Option<VfsFile> {
+        let (root, rel_path, file) = self.find_root(path)?;
+        let file = file?;
+        match fs::read_to_string(path) {
+            Ok(text) => self.change_file(file, Arc::new(text), false),
+            Err(_) => self.remove_file(root, rel_path, file),
+        }
+        Some(file)
+    }
+
+    /// Reports that the file at `path` was created, modified or removed on
+    /// disk. Like `load`, this reads the file right away, and the change is
+    /// applied as if the vfs watcher had reported it.
+    pub fn notify_changed(&mut self, path: &Path) {
+        let (root, rel_path, _) = match self.find_root(path) {
+            Some(it) => it,
+            None => return,
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => Some(text),
+            Err(_) if !path.exists() => None,
+            Err(e) => {
+                log::warn!("failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        self.handle_task(VfsTask::SingleFile { root, path: rel_path, text });
+    }
+
+    pub fn commit_changes(&mut self) -> Vec<VfsChange> {
+        mem::replace(&mut self.pending_changes, Vec::new())
+    }
+
+    pub fn task_receiver(&self) -> &Receiver<VfsTask> {
+        self.worker.receiver()
+    }
+
+    pub fn handle_task(&mut self, task: VfsTask) {
+        match task {
+            VfsTask::BulkLoadRoot { root, files } => {
+                let mut cur_files = Vec::new();
+                for (path, text) in files {
+                    // Files may have been loaded or opened in the editor
+                    // while the root was scanned, their texts take precedence.
+                    let file = match self.find_file(root, &path) {
+                        Some(file) => file,
+                        None => {
+                            let data = VfsFileData {
+                                root,
+                                path: path.clone(),
+                                is_overlayed: false,
+                                text: Arc::new(text),
+                            };
+                            let file = self.files.alloc(data);
+                            self.root2files.entry(root).or_default().insert(path.clone(), file);
+                            file
+                        }
+                    };
+                    cur_files.push((file, path, Arc::clone(&self.files[file].text)));
+                }
+                self.pending_changes.push(VfsChange::AddRoot { root, files: cur_files });
+            }
+            VfsTask::SingleFile { root, path, text } => {
+                let file = self.find_file(root, &path);
+                if file.map_or(false, |file| self.files[file].is_overlayed) {
+                    return;
+                }
+                match (file, text) {
+                    (Some(file), Some(text)) => {
+                        if *self.files[file].text != text {
+                            self.change_file(file, Arc::new(text), false);
+                        }
+                    }
+                    (Some(file), None) => self.remove_file(root, path, file),
+                    (None, Some(text)) => {
+                        self.add_file(root, path, Arc::new(text), false);
+                    }
+                    (None, None) => (),
+                }
+            }
+        }
+    }
+
+    fn add_file(
+        &mut self,
+        root: VfsRoot,
+        path: RelativePathBuf,
+        text: Arc<String>,
+        is_overlayed: bool,
+    ) -> VfsFile {
+        let data = VfsFileData { root, path: path.clone(), is_overlayed, text: Arc::clone(&text) };
+        let file = self.files.alloc(data);
+        self.root2files.entry(root).or_default().insert(path.clone(), file);
+        self.pending_changes.push(VfsChange::AddFile { root, file, path, text });
+        file
+    }
+
+    fn change_file(&mut self, file: VfsFile, text: Arc<String>, is_overlayed: bool) {
+        let data = &mut self.files[file];
+        data.text = Arc::clone(&text);
+        data.is_overlayed = is_overlayed;
+        self.pending_changes.push(VfsChange::ChangeFile { file, text });
+    }
+
+    fn remove_file(&mut self, root: VfsRoot, path: RelativePathBuf, file: VfsFile) {
+        if let Some(files) = self.root2files.get_mut(&root) {
+            files.remove(&path);
+        }
+        let data = &mut self.files[file];
+        data.text = Default::default();
+        data.is_overlayed = false;
+        self.pending_changes.push(VfsChange::RemoveFile { root, file, path });
+    }
+
+    fn find_root(&self, path: &Path) -> Option<(VfsRoot, RelativePathBuf, Option<VfsFile>)> {
+        let (root, rel_path) = self.roots.find(path)?;
+        let file = self.find_file(root, &rel_path);
+        Some((root, rel_path, file))
+    }
+
+    fn find_file(&self, root: VfsRoot, path: &RelativePath) -> Option<VfsFile> {
+        self.root2files.get(&root)?.get(path).cloned()
+    }
+}
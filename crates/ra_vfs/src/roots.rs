@@ -0,0 +1,115 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use ra_arena::{Arena, RawId, impl_arena_id};
+use relative_path::RelativePathBuf;
+
+/// A directory which is scanned and watched as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VfsRoot(pub RawId);
+impl_arena_id!(VfsRoot);
+
+/// Describes the contents of a single root: all `.rs` files under `root`,
+/// except for the ones in hidden directories, `target` and nested roots.
+#[derive(Debug, Clone)]
+pub(crate) struct RootConfig {
+    pub(crate) root: PathBuf,
+    excluded_dirs: Vec<PathBuf>,
+}
+
+/// The set of roots. Roots may be nested, but every path belongs to at most
+/// one root: the innermost one.
+#[derive(Debug, Default)]
+pub(crate) struct Roots {
+    roots: Arena<VfsRoot, Arc<RootConfig>>,
+}
+
+impl Roots {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Roots {
+        let mut roots = Roots::default();
+        for path in paths {
+            roots.add(path);
+        }
+        roots
+    }
+
+    /// Adds a root for `path`, unless there already is one. The new root is
+    /// excluded from the existing roots around it, and the existing roots
+    /// inside of it are excluded from the new one, so it doesn't matter which
+    /// one is added first. Returns the new root and the roots around it.
+    pub(crate) fn add(&mut self, path: PathBuf) -> Option<(VfsRoot, Vec<VfsRoot>)> {
+        if self.roots.iter().any(|(_, config)| config.root == path) {
+            return None;
+        }
+        let outer = self
+            .roots
+            .iter()
+            .filter(|(_, config)| config.contains_dir(&path))
+            .map(|(root, _)| root)
+            .collect::<Vec<_>>();
+        for &root in outer.iter() {
+            Arc::make_mut(&mut self.roots[root]).excluded_dirs.push(path.clone());
+        }
+        let excluded_dirs = self
+            .roots
+            .iter()
+            .map(|(_, config)| config.root.clone())
+            .filter(|it| it.starts_with(&path))
+            .collect();
+        let config = RootConfig { root: path, excluded_dirs };
+        Some((self.roots.alloc(Arc::new(config)), outer))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub(crate) fn iter<'a>(&'a self) -> impl Iterator<Item = VfsRoot> + 'a {
+        self.roots.iter().map(|(id, _)| id)
+    }
+
+    pub(crate) fn path(&self, root: VfsRoot) -> &Path {
+        self.roots[root].root.as_path()
+    }
+
+    pub(crate) fn config(&self, root: VfsRoot) -> Arc<RootConfig> {
+        Arc::clone(&self.roots[root])
+    }
+
+    /// Finds the root of the file at `path`.
+    pub(crate) fn find(&self, path: &Path) -> Option<(VfsRoot, RelativePathBuf)> {
+        self.roots.iter().find_map(|(root, config)| Some((root, config.contains(path)?)))
+    }
+}
+
+impl RootConfig {
+    /// Checks if the file at `path` belongs to the root and returns its path
+    /// relative to the root.
+    pub(crate) fn contains(&self, path: &Path) -> Option<RelativePathBuf> {
+        if !self.contains_dir(path) || path.extension().map_or(true, |it| it != "rs") {
+            return None;
+        }
+        let rel_path = path.strip_prefix(&self.root).ok()?;
+        RelativePathBuf::from_path(rel_path).ok()
+    }
+
+    /// Checks if the directory at `path` is scanned as a part of the root.
+    pub(crate) fn contains_dir(&self, path: &Path) -> bool {
+        let rel_path = match path.strip_prefix(&self.root) {
+            Ok(it) => it,
+            Err(_) => return false,
+        };
+        if self.excluded_dirs.iter().any(|it| path.starts_with(it)) {
+            return false;
+        }
+        rel_path.components().enumerate().all(|(i, component)| match component {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                !(name.starts_with('.') || name == "node_modules" || (i == 0 && name == "target"))
+            }
+            _ => true,
+        })
+    }
+}
@@ -0,0 +1,259 @@
+use std::{collections::HashSet, fs, time::Duration};
+
+use crossbeam_channel::RecvTimeoutError;
+use ra_vfs::{Vfs, VfsChange, Watch};
+use tempfile::tempdir;
+
+fn process_tasks(vfs: &mut Vfs, num_tasks: u32) {
+    for _ in 0..num_tasks {
+        let task = vfs
+            .task_receiver()
+            .recv_timeout(Duration::from_secs(3))
+            .expect("no task was sent to the vfs");
+        vfs.handle_task(task);
+    }
+}
+
+fn assert_no_tasks(vfs: &Vfs) {
+    match vfs.task_receiver().recv_timeout(Duration::from_millis(300)) {
+        Err(RecvTimeoutError::Timeout) => (),
+        res => panic!("unexpected task: {:?}", res),
+    }
+}
+
+#[test]
+fn test_vfs_works() -> std::io::Result<()> {
+    let files = [("a/foo.rs", "hello"), ("a/bar.rs", "world"), ("a/b/baz.rs", "nested hello")];
+
+    let dir = tempdir().unwrap();
+    for (path, text) in files.iter() {
+        let file_path = dir.path().join(path);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, text)?
+    }
+
+    let a_root = dir.path().join("a");
+    let b_root = dir.path().join("a/b");
+
+    let (mut vfs, roots) = Vfs::new(vec![a_root.clone(), b_root.clone()], Watch(true));
+    assert_eq!(roots.len(), 2);
+    process_tasks(&mut vfs, 2);
+    {
+        let files = vfs
+            .commit_changes()
+            .into_iter()
+            .flat_map(|change| match change {
+                VfsChange::AddRoot { files, .. } => files,
+                _ => panic!("unexpected change"),
+            })
+            .map(|(_file, path, text)| (path.to_string(), text.to_string()))
+            .collect::<HashSet<_>>();
+
+        let expected_files = [("foo.rs", "hello"), ("bar.rs", "world"), ("baz.rs", "nested hello")]
+            .iter()
+            .map(|(path, text)| (path.to_string(), text.to_string()))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(files, expected_files);
+    }
+
+    fs::write(dir.path().join("a/b/baz.rs"), "quux").unwrap();
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "quux"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    vfs.add_file_overlay(&dir.path().join("a/b/baz.rs"), "m".to_string());
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "m"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    // Changes on disk are ignored while the file is overlayed.
+    fs::write(dir.path().join("a/b/baz.rs"), "spam").unwrap();
+    process_tasks(&mut vfs, 1);
+    assert!(vfs.commit_changes().is_empty());
+
+    vfs.remove_file_overlay(&dir.path().join("a/b/baz.rs"));
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "spam"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    vfs.add_file_overlay(&dir.path().join("a/b/spam.rs"), "spam".to_string());
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::AddFile { text, path, .. }] => {
+            assert_eq!(text.as_str(), "spam");
+            assert_eq!(path, "spam.rs");
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    vfs.remove_file_overlay(&dir.path().join("a/b/spam.rs"));
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::RemoveFile { path, .. }] => assert_eq!(path, "spam.rs"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    fs::create_dir_all(dir.path().join("a/c")).unwrap();
+    fs::write(dir.path().join("a/c/new.rs"), "new hello").unwrap();
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::AddFile { text, path, .. }] => {
+            assert_eq!(text.as_str(), "new hello");
+            assert_eq!(path, "c/new.rs");
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    fs::remove_file(dir.path().join("a/c/new.rs")).unwrap();
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::RemoveFile { path, .. }] => assert_eq!(path, "c/new.rs"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    // Ignored directories are neither scanned nor watched.
+    fs::create_dir_all(dir.path().join("a/target")).unwrap();
+    fs::write(dir.path().join("a/target/new.rs"), "ignore me").unwrap();
+    assert_no_tasks(&vfs);
+
+    Ok(())
+}
+
+#[test]
+fn test_add_root() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a")).unwrap();
+    fs::create_dir_all(dir.path().join("b/src")).unwrap();
+    fs::write(dir.path().join("a/foo.rs"), "foo").unwrap();
+    fs::write(dir.path().join("b/src/bar.rs"), "bar").unwrap();
+
+    let (mut vfs, roots) = Vfs::new(vec![dir.path().join("a")], Watch(true));
+    assert_eq!(roots.len(), 1);
+    process_tasks(&mut vfs, 1);
+    vfs.commit_changes();
+    assert_eq!(vfs.path2file(&dir.path().join("b/src/bar.rs")), None);
+
+    // Existing roots are not added again.
+    assert_eq!(vfs.add_root(dir.path().join("a")), None);
+
+    let root = vfs.add_root(dir.path().join("b")).unwrap();
+    assert_eq!(vfs.n_roots(), 2);
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::AddRoot { root: added, files }] => {
+            assert_eq!(*added, root);
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].1, "src/bar.rs");
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+    assert!(vfs.path2file(&dir.path().join("b/src/bar.rs")).is_some());
+
+    // The new root is watched as well.
+    fs::write(dir.path().join("b/src/bar.rs"), "baz").unwrap();
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "baz"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+}
+
+#[test]
+fn test_add_nested_root() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::write(dir.path().join("a/foo.rs"), "foo").unwrap();
+    fs::write(dir.path().join("a/b/bar.rs"), "bar").unwrap();
+    fs::write(dir.path().join("a/b/baz.rs"), "baz").unwrap();
+
+    let (mut vfs, _roots) = Vfs::new(vec![dir.path().join("a")], Watch(true));
+    process_tasks(&mut vfs, 1);
+    vfs.commit_changes();
+    vfs.add_file_overlay(&dir.path().join("a/b/baz.rs"), "overlay".to_string());
+    vfs.commit_changes();
+
+    // Like at startup, the files of the nested root belong to it and not to
+    // the root around it.
+    let root = vfs.add_root(dir.path().join("a/b")).unwrap();
+    process_tasks(&mut vfs, 1);
+    let changes = vfs.commit_changes();
+    let removed = changes
+        .iter()
+        .filter_map(|change| match change {
+            VfsChange::RemoveFile { path, .. } => Some(path.to_string()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let expected_removed =
+        ["b/bar.rs", "b/baz.rs"].iter().map(|it| it.to_string()).collect::<HashSet<_>>();
+    assert_eq!(removed, expected_removed);
+    let added = changes
+        .iter()
+        .filter_map(|change| match change {
+            VfsChange::AddRoot { root: added, files } if *added == root => Some(files),
+            _ => None,
+        })
+        .flatten()
+        .map(|(_file, path, text)| (path.to_string(), text.to_string()))
+        .collect::<HashSet<_>>();
+    let expected_added = [("bar.rs", "bar"), ("baz.rs", "overlay")]
+        .iter()
+        .map(|(path, text)| (path.to_string(), text.to_string()))
+        .collect::<HashSet<_>>();
+    assert_eq!(added, expected_added);
+    assert_eq!(
+        vfs.file2path(vfs.path2file(&dir.path().join("a/b/bar.rs")).unwrap()),
+        dir.path().join("a/b/bar.rs")
+    );
+
+    // Changes on disk are reported for the nested root now.
+    fs::write(dir.path().join("a/b/bar.rs"), "quux").unwrap();
+    process_tasks(&mut vfs, 1);
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { file, text }] => {
+            assert_eq!(text.as_str(), "quux");
+            assert_eq!(vfs.path2file(&dir.path().join("a/b/bar.rs")), Some(*file));
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+}
+
+#[test]
+fn test_notify_changed_without_watching() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a")).unwrap();
+    fs::write(dir.path().join("a/foo.rs"), "foo").unwrap();
+
+    let (mut vfs, _roots) = Vfs::new(vec![dir.path().join("a")], Watch(false));
+    process_tasks(&mut vfs, 1);
+    vfs.commit_changes();
+
+    fs::write(dir.path().join("a/foo.rs"), "bar").unwrap();
+    assert_no_tasks(&vfs);
+    vfs.notify_changed(&dir.path().join("a/foo.rs"));
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "bar"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::write(dir.path().join("a/b/baz.rs"), "baz").unwrap();
+    vfs.notify_changed(&dir.path().join("a/b/baz.rs"));
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::AddFile { text, path, .. }] => {
+            assert_eq!(text.as_str(), "baz");
+            assert_eq!(path, "b/baz.rs");
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+
+    fs::remove_file(dir.path().join("a/foo.rs")).unwrap();
+    vfs.notify_changed(&dir.path().join("a/foo.rs"));
+    match vfs.commit_changes().as_slice() {
+        [VfsChange::RemoveFile { path, .. }] => assert_eq!(path, "foo.rs"),
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+}
@@ -0,0 +1,48 @@
+import * as vscode from 'vscode';
+import { Position, TextDocumentIdentifier } from 'vscode-languageclient';
+import { Server } from '../server';
+
+interface ExpandMacroParams {
+    textDocument: TextDocumentIdentifier;
+    position?: Position;
+}
+
+interface ExpandedMacro {
+    name: string;
+    expansion: string;
+}
+
+// Shows the recursive expansion of the macro call under the cursor in a new
+// editor.
+export async function handle() {
+    const editor = vscode.window.activeTextEditor;
+    if (editor == null || editor.document.languageId !== 'rust') {
+        return;
+    }
+    const request: ExpandMacroParams = {
+        textDocument: { uri: editor.document.uri.toString() },
+        position: Server.client.code2ProtocolConverter.asPosition(
+            editor.selection.active
+        )
+    };
+    const expanded = await Server.client.sendRequest<ExpandedMacro | null>(
+        'rust-analyzer/expandMacro',
+        request
+    );
+    if (expanded == null) {
+        vscode.window.showInformationMessage('No macro call at the cursor');
+        return;
+    }
+    const content = `// Recursive expansion of ${expanded.name}! macro\n\n${
+        expanded.expansion
+    }\n`;
+    const document = await vscode.workspace.openTextDocument({
+        language: 'rust',
+        content
+    });
+    return vscode.window.showTextDocument(
+        document,
+        vscode.ViewColumn.Two,
+        true
+    );
+}
@@ -4,10 +4,33 @@ import { Server } from './server';
 
 const RA_LSP_DEBUG = process.env.__RA_LSP_SERVER_DEBUG;
 
+export interface CargoFeatures {
+    noDefaultFeatures: boolean;
+    allFeatures: boolean;
+    features: string[];
+}
+
 export class Config {
     public highlightingOn = true;
     public enableEnhancedTyping = true;
     public raLspServerPath = RA_LSP_DEBUG || 'ra_lsp_server';
+    public procMacroSrvPath: null | string = null;
+    public loadOutDirsFromCheck = false;
+    public rustSrcPath: null | string = null;
+    public noStd = false;
+    public libraryCache = true;
+    public lruCapacity: null | number = null;
+    public excludeDirs: string[] = [];
+    public enableDiagnostics = true;
+    public rustfmtPath: null | string = null;
+    public rustfmtArgs: string[] = [];
+    public completionSnippets = true;
+    public useClientWatching = false;
+    public cargoFeatures: CargoFeatures = {
+        noDefaultFeatures: false,
+        allFeatures: false,
+        features: []
+    };
 
     private prevEnhancedTyping: null | boolean = null;
 
@@ -61,5 +84,78 @@ export class Config {
             this.raLspServerPath =
                 RA_LSP_DEBUG || (config.get('raLspServerPath') as string);
         }
+
+        if (config.has('procMacroSrvPath')) {
+            this.procMacroSrvPath = config.get('procMacroSrvPath') as
+                | null
+                | string;
+        }
+
+        if (config.has('loadOutDirsFromCheck')) {
+            this.loadOutDirsFromCheck = config.get(
+                'loadOutDirsFromCheck'
+            ) as boolean;
+        }
+
+        if (config.has('rustSrcPath')) {
+            this.rustSrcPath = config.get('rustSrcPath') as null | string;
+        }
+
+        if (config.has('noStd')) {
+            this.noStd = config.get('noStd') as boolean;
+        }
+
+        if (config.has('libraryCache')) {
+            this.libraryCache = config.get('libraryCache') as boolean;
+        }
+
+        if (config.has('lruCapacity')) {
+            this.lruCapacity = config.get('lruCapacity') as null | number;
+        }
+
+        if (config.has('excludeDirs')) {
+            this.excludeDirs = config.get('excludeDirs') as string[];
+        }
+
+        if (config.has('enableDiagnostics')) {
+            this.enableDiagnostics = config.get('enableDiagnostics') as boolean;
+        }
+
+        if (config.has('rustfmtPath')) {
+            this.rustfmtPath = config.get('rustfmtPath') as null | string;
+        }
+
+        if (config.has('rustfmtArgs')) {
+            this.rustfmtArgs = config.get('rustfmtArgs') as string[];
+        }
+
+        if (config.has('completionSnippets')) {
+            this.completionSnippets = config.get(
+                'completionSnippets'
+            ) as boolean;
+        }
+
+        if (config.has('useClientWatching')) {
+            this.useClientWatching = config.get('useClientWatching') as boolean;
+        }
+
+        if (config.has('cargoFeatures.noDefaultFeatures')) {
+            this.cargoFeatures.noDefaultFeatures = config.get(
+                'cargoFeatures.noDefaultFeatures',
+                false
+            );
+        }
+        if (config.has('cargoFeatures.allFeatures')) {
+            this.cargoFeatures.allFeatures = config.get(
+                'cargoFeatures.allFeatures',
+                false
+            );
+        }
+        if (config.has('cargoFeatures.features')) {
+            this.cargoFeatures.features = config.get(
+                'cargoFeatures.features',
+                []
+            );
+        }
     }
 }
@@ -53,6 +53,7 @@ export function activate(context: vscode.ExtensionContext) {
         Server.client.sendRequest<null>('rust-analyzer/collectGarbage', null)
     );
     registerCommand('rust-analyzer.syntaxTree', commands.syntaxTree.handle);
+    registerCommand('rust-analyzer.expandMacro', commands.expandMacro.handle);
     registerCommand(
         'rust-analyzer.extendSelection',
         commands.extendSelection.handle
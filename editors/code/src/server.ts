@@ -1,6 +1,6 @@
 import * as lc from 'vscode-languageclient';
 
-import { window } from 'vscode';
+import { window, workspace } from 'vscode';
 import { Config } from './config';
 import { Highlighter } from './highlighting';
 
@@ -26,7 +26,29 @@ export class Server {
         const clientOptions: lc.LanguageClientOptions = {
             documentSelector: [{ scheme: 'file', language: 'rust' }],
             initializationOptions: {
-                publishDecorations: true
+                publishDecorations: true,
+                procMacroSrv: this.config.procMacroSrvPath,
+                cargoFeatures: this.config.cargoFeatures,
+                loadOutDirsFromCheck: this.config.loadOutDirsFromCheck,
+                rustSrcPath: this.config.rustSrcPath,
+                noStd: this.config.noStd,
+                libraryCache: this.config.libraryCache,
+                lruCapacity: this.config.lruCapacity,
+                excludeDirs: this.config.excludeDirs,
+                enableDiagnostics: this.config.enableDiagnostics,
+                rustfmtPath: this.config.rustfmtPath,
+                rustfmtArgs: this.config.rustfmtArgs,
+                completionSnippets: this.config.completionSnippets,
+                useClientWatching: this.config.useClientWatching
+            },
+            synchronize: {
+                // Changed settings are sent to the server, which applies them
+                // without a restart.
+                configurationSection: 'rust-analyzer',
+                // The server reloads the workspace when these change.
+                fileEvents: workspace.createFileSystemWatcher(
+                    '**/{Cargo.toml,Cargo.lock,rust-project.json}'
+                )
             },
             traceOutputChannel
         };